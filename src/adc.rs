@@ -0,0 +1,194 @@
+//! Internal temperature sensor and supply voltage, measured with ADC1.
+//!
+//! VDDA is derived from VREFINT and its factory calibration,
+//! the temperature from the sensor's two-point factory calibration at 30 and 110 °C.
+//! [`run`] samples both every [`INTERVAL`] and publishes smoothed [`Readings`]
+//! to [`READINGS`].
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+use embassy_time::Duration;
+
+use crate::util::Cursor;
+
+pub const INTERVAL: Duration = Duration::from_secs(1);
+/// receivers [`READINGS`] can have
+pub const RECEIVERS: usize = 4;
+
+/// calibration values of the STM32F76x, measured at VDDA = 3.3 V
+const VREFINT_CAL: u32 = 0x1FF0_F44A;
+/// raw temperature at 30 °C
+const TS_CAL1: u32 = 0x1FF0_F44C;
+/// raw temperature at 110 °C
+const TS_CAL2: u32 = 0x1FF0_F44E;
+const CAL_VDDA_MV: u32 = 3300;
+const CAL1_MILLI_C: i32 = 30_000;
+const CAL2_MILLI_C: i32 = 110_000;
+/// weight of a new sample is 1 / 2^SMOOTHING
+const SMOOTHING: u32 = 3;
+
+pub static READINGS: Watch<CriticalSectionRawMutex, Readings, RECEIVERS> = Watch::new();
+
+/// Factory calibration values.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Calibration {
+    pub vrefint: u16,
+    /// at 30 °C
+    pub ts_cal1: u16,
+    /// at 110 °C
+    pub ts_cal2: u16,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Readings {
+    /// mV
+    pub vdda: u32,
+    /// m°C
+    pub temperature: i32,
+}
+
+/// Exponential moving average of raw samples.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+struct Smoother {
+    /// scaled by 2^SMOOTHING
+    average: Option<u32>,
+}
+
+impl Calibration {
+    #[cfg(feature = "cross")]
+    pub fn read() -> Self {
+        let read = |address: u32| {
+            // Safety: the calibration values are readable system memory
+            unsafe { core::ptr::read_volatile(address as *const u16) }
+        };
+        Self {
+            vrefint: read(VREFINT_CAL),
+            ts_cal1: read(TS_CAL1),
+            ts_cal2: read(TS_CAL2),
+        }
+    }
+
+    /// Convert raw VREFINT and temperature sensor samples.
+    pub fn convert(&self, vrefint: u16, temperature: u16) -> Readings {
+        let vdda = CAL_VDDA_MV * u32::from(self.vrefint) / u32::from(vrefint.max(1));
+        // the sample at calibration voltage
+        let raw =
+            (i64::from(temperature) * i64::from(vdda) / i64::from(CAL_VDDA_MV)) as i32;
+        let (cal1, cal2) = (i32::from(self.ts_cal1), i32::from(self.ts_cal2));
+        let temperature = (raw - cal1) * (CAL2_MILLI_C - CAL1_MILLI_C)
+            / (cal2 - cal1).max(1)
+            + CAL1_MILLI_C;
+        Readings { vdda, temperature }
+    }
+}
+
+impl Smoother {
+    const fn new() -> Self {
+        Self { average: None }
+    }
+
+    fn update(&mut self, sample: u16) -> u16 {
+        let scaled = u32::from(sample) << SMOOTHING;
+        let average = match self.average {
+            | None => scaled,
+            | Some(average) => average - (average >> SMOOTHING) + u32::from(sample),
+        };
+        self.average = Some(average);
+        (average >> SMOOTHING) as u16
+    }
+}
+
+/// Sample every [`INTERVAL`], publishing to [`READINGS`].
+#[cfg(feature = "cross")]
+pub async fn run(
+    adc: embassy_stm32::adc::Adc<'_, embassy_stm32::peripherals::ADC1>,
+) -> ! {
+    use embassy_stm32::adc::SampleTime;
+    use embassy_time::Ticker;
+
+    let mut adc = adc;
+    // the sensors need at least 10 µs of sampling
+    adc.set_sample_time(SampleTime::CYCLES480);
+    let mut vrefint_channel = adc.enable_vrefint();
+    let mut temperature_channel = adc.enable_temperature();
+    let calibration = Calibration::read();
+    let sender = READINGS.sender();
+
+    let mut vrefint = Smoother::new();
+    let mut temperature = Smoother::new();
+    let mut ticker = Ticker::every(INTERVAL);
+    loop {
+        let raw_vrefint = vrefint.update(adc.blocking_read(&mut vrefint_channel));
+        let raw_temperature =
+            temperature.update(adc.blocking_read(&mut temperature_channel));
+        sender.send(calibration.convert(raw_vrefint, raw_temperature));
+        ticker.next().await;
+    }
+}
+
+/// Write the latest readings as a JSON telemetry payload, for [`mqtt::run`](crate::net::mqtt::run).
+pub fn telemetry(buf: &mut [u8]) -> Option<usize> {
+    let readings = READINGS.try_get()?;
+    let mut writer = Cursor::new(buf);
+    write!(
+        writer,
+        r#"{{"vdda_mv":{},"temperature_mc":{}}}"#,
+        readings.vdda, readings.temperature
+    )
+    .ok()?;
+    Some(writer.written())
+}
+
+impl Display for Readings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.temperature < 0 { "-" } else { "" };
+        let tenths = self.temperature.unsigned_abs() / 100;
+        write!(
+            f,
+            "temperature: {sign}{}.{} °C, VDDA: {} mV",
+            tenths / 10,
+            tenths % 10,
+            self.vdda
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let calibration = Calibration {
+            vrefint: 1500,
+            ts_cal1: 940,
+            ts_cal2: 1200,
+        };
+        // at calibration voltage
+        let readings = calibration.convert(1500, 940);
+        assert_eq!(
+            readings,
+            Readings {
+                vdda: 3300,
+                temperature: 30_000,
+            }
+        );
+        // VDDA of 3.0 V: VREFINT reads higher, the sensor reads higher for the same voltage
+        let readings = calibration.convert(1650, 1320);
+        assert_eq!(readings.vdda, 3000);
+        assert_eq!(readings.temperature, 110_000);
+
+        let mut smoother = Smoother::new();
+        assert_eq!(smoother.update(800), 800);
+        assert_eq!(smoother.update(1600), 900);
+    }
+}
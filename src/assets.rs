@@ -0,0 +1,293 @@
+//! Bundles of named assets, such as fonts and images.
+//!
+//! A bundle is a [`Header`], a table of contents of one [`Entry`] per asset,
+//! and the asset data. It is read in place, e.g., from memory-mapped QSPI flash
+//! or a copy loaded from an SD card, and [`Asset`]s borrow their data from it.
+//! Asset data is word-aligned relative to the bundle, so as long as the bundle is
+//! word-aligned, pixel data can be used as a DMA2D source directly.
+//!
+//! All multi-byte values are stored little-endian.
+
+use core::fmt;
+use core::fmt::Display;
+use core::mem::size_of;
+use core::str;
+
+use bytemuck::Zeroable;
+
+use crate::graphics::testcard;
+use crate::mem;
+
+/// magic number of a bundle header
+pub const MAGIC: u32 = u32::from_le_bytes(*b"ASET");
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = size_of::<Header>();
+pub const ENTRY_LEN: usize = size_of::<Entry>();
+pub const NAME_LEN: usize = 16;
+
+/// [`Entry::format`] of pixel data, plus its DMA2D color mode
+const FORMAT_PIXELS: u32 = 0x10;
+
+#[repr(C)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Header {
+    pub magic: u32,
+    pub version: u16,
+    /// number of entries in the table of contents following the header
+    pub count: u16,
+    /// bytes of the whole bundle, including header and table of contents
+    pub len: u32,
+}
+
+/// Table of contents entry describing one asset.
+#[repr(C)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Entry {
+    /// UTF-8, padded with NULs
+    pub name: [u8; NAME_LEN],
+    /// from the start of the bundle; word-aligned
+    pub offset: u32,
+    pub len: u32,
+    /// 0 for raw data, 1 for a QOI image, `0x10 + CM` for pixels in DMA2D color mode CM
+    pub format: u32,
+    /// pixels, for images
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Format {
+    /// anything else, e.g., a font
+    Raw,
+    /// a [QOI](crate::graphics::qoi) image
+    Qoi,
+    /// uncompressed pixels, row by row without padding
+    Pixels(testcard::Format),
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Asset<'a> {
+    pub name: &'a str,
+    pub format: Format,
+    pub width: u16,
+    pub height: u16,
+    pub data: &'a [u8],
+}
+
+/// A bundle whose table of contents has been checked.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub struct Bundle<'a> {
+    data: &'a [u8],
+    count: usize,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the input is shorter than the header or table of contents say
+    Truncated,
+    BadMagic,
+    BadVersion(u16),
+    /// the entry at this index has an invalid name or format,
+    /// or its data is misaligned or out of bounds
+    BadEntry(usize),
+    Memory(mem::Error),
+}
+
+/// The bundle at `address`, e.g., in memory-mapped QSPI flash.
+pub fn at(address: u32) -> Result<Bundle<'static>, Error> {
+    let header = mem::slice(address, HEADER_LEN as u32).map_err(Error::Memory)?;
+    let len = Header::parse(header)?.len;
+    Bundle::parse(mem::slice(address, len).map_err(Error::Memory)?)
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut header = Header::zeroed();
+        bytemuck::bytes_of_mut(&mut header)
+            .copy_from_slice(data.get(..HEADER_LEN).ok_or(Error::Truncated)?);
+        if header.magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if header.version != VERSION {
+            return Err(Error::BadVersion(header.version));
+        }
+        Ok(header)
+    }
+}
+
+impl Format {
+    /// The format stored as [`Entry::format`].
+    pub fn from_raw(format: u32) -> Option<Self> {
+        match format {
+            | 0 => Some(Format::Raw),
+            | 1 => Some(Format::Qoi),
+            | _ => {
+                let cm = format.checked_sub(FORMAT_PIXELS)?;
+                testcard::Format::from_ltdc(cm).map(Format::Pixels)
+            }
+        }
+    }
+}
+
+impl<'a> Bundle<'a> {
+    /// Parse a bundle, checking every entry of its table of contents.
+    ///
+    /// `data` may extend past the end of the bundle.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::parse(data)?;
+        let data = data.get(..header.len as usize).ok_or(Error::Truncated)?;
+        let count = usize::from(header.count);
+        if data.len() < HEADER_LEN + count * ENTRY_LEN {
+            return Err(Error::Truncated);
+        }
+        let bundle = Self { data, count };
+        for index in 0..count {
+            bundle.entry(index).ok_or(Error::BadEntry(index))?;
+        }
+        Ok(bundle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The assets in table of contents order.
+    pub fn iter(&self) -> impl Iterator<Item = Asset<'a>> + '_ {
+        (0..self.count)
+            .map(|index| self.entry(index).expect("entries are checked by parse"))
+    }
+
+    /// The asset called `name`.
+    pub fn get(&self, name: &str) -> Option<Asset<'a>> {
+        self.iter().find(|asset| asset.name == name)
+    }
+
+    fn entry(&self, index: usize) -> Option<Asset<'a>> {
+        let raw = &self.data[HEADER_LEN + index * ENTRY_LEN..][..ENTRY_LEN];
+        let mut entry = Entry::zeroed();
+        bytemuck::bytes_of_mut(&mut entry).copy_from_slice(raw);
+
+        let name = &raw[..NAME_LEN];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN)];
+        let format = Format::from_raw(entry.format)?;
+        if entry.offset % 4 != 0 {
+            return None;
+        }
+        let start = entry.offset as usize;
+        let data = self.data.get(start..start.checked_add(entry.len as usize)?)?;
+        if let Format::Pixels(pixels) = format {
+            let len =
+                usize::from(entry.width) * usize::from(entry.height) * pixels.bytes();
+            if data.len() < len {
+                return None;
+            }
+        }
+        Some(Asset {
+            name: str::from_utf8(name).ok()?,
+            format,
+            width: entry.width,
+            height: entry.height,
+            data,
+        })
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Format::Raw => write!(f, "raw"),
+            | Format::Qoi => write!(f, "qoi"),
+            | Format::Pixels(pixels) => write!(f, "{pixels:?}"),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Truncated => write!(f, "bundle is truncated"),
+            | Error::BadMagic => write!(f, "not an asset bundle"),
+            | Error::BadVersion(version) => {
+                write!(f, "unsupported bundle version {version}")
+            }
+            | Error::BadEntry(index) => write!(f, "invalid entry {index}"),
+            | Error::Memory(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, offset: u32, len: u32, format: u32) -> Entry {
+        let mut entry = Entry::zeroed();
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        entry.offset = offset;
+        entry.len = len;
+        entry.format = format;
+        entry
+    }
+
+    #[test]
+    fn test_parse() {
+        const LEN: usize = HEADER_LEN + 2 * ENTRY_LEN + 12;
+        let data_start = (HEADER_LEN + 2 * ENTRY_LEN) as u32;
+        let mut bundle = [0; LEN];
+        let header = Header {
+            magic: MAGIC,
+            version: VERSION,
+            count: 2,
+            len: LEN as u32,
+        };
+        bundle[..HEADER_LEN].copy_from_slice(bytemuck::bytes_of(&header));
+        let font = entry("font", data_start, 4, 0);
+        // 2x2 RGB565
+        let mut icon = entry("icon", data_start + 4, 8, FORMAT_PIXELS + 2);
+        icon.width = 2;
+        icon.height = 2;
+        for (i, entry) in [font, icon].iter().enumerate() {
+            bundle[HEADER_LEN + i * ENTRY_LEN..][..ENTRY_LEN]
+                .copy_from_slice(bytemuck::bytes_of(entry));
+        }
+        bundle[data_start as usize..].copy_from_slice(b"FONTpixelsxx");
+
+        let parsed = Bundle::parse(&bundle).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.iter().map(|asset| asset.name).eq(["font", "icon"]));
+        let icon = parsed.get("icon").unwrap();
+        assert_eq!(icon.format, Format::Pixels(testcard::Format::Rgb565));
+        assert_eq!(icon.data, b"pixelsxx");
+        assert_eq!(parsed.get("font").unwrap().data, b"FONT");
+        assert_eq!(parsed.get("missing"), None);
+
+        // misaligned data
+        bundle[HEADER_LEN + ENTRY_LEN + NAME_LEN] += 1;
+        assert_eq!(Bundle::parse(&bundle).err(), Some(Error::BadEntry(1)));
+        assert_eq!(
+            Bundle::parse(&bundle[..LEN - 1]).err(),
+            Some(Error::Truncated)
+        );
+        bundle[0] = b'a';
+        assert_eq!(Bundle::parse(&bundle).err(), Some(Error::BadMagic));
+    }
+}
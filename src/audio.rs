@@ -0,0 +1,231 @@
+//! Audio playback through the WM8994 codec.
+//!
+//! A [`Mixer`] sums up to `VOICES` queued [`Voice`]s, clips from memory or tones,
+//! and streams the result to an [`Output`], e.g. SAI1 block A feeding the codec via DMA.
+//! All audio is 16-bit mono PCM at [`RATE`], played on both channels.
+//! While no voice is queued, the mixer streams silence to keep the DMA running.
+
+pub mod beeper;
+pub mod wm8994;
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Display;
+
+#[cfg(feature = "cross")]
+use embassy_stm32::sai;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use heapless::Vec;
+
+pub const RATE: wm8994::Rate = wm8994::Rate::Hz48000;
+/// samples mixed at once
+pub const BLOCK: usize = 256;
+/// amplitude of tones at full volume
+const TONE_AMPLITUDE: i32 = i16::MAX as i32 / 4;
+
+/// Sink of interleaved 16-bit stereo samples.
+#[allow(async_fn_in_trait)]
+pub trait Output {
+    type Error;
+
+    /// Queue `samples`, waiting for room.
+    async fn write(&mut self, samples: &[i16]) -> Result<(), Self::Error>;
+}
+
+/// A sound queued on a [`Mixer`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Voice {
+    source: Source,
+    /// percent
+    volume: u8,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum Source {
+    Clip {
+        samples: &'static [i16],
+        position: usize,
+    },
+    /// square wave
+    Tone {
+        /// samples per half period
+        half_period: u32,
+        remaining: u32,
+    },
+}
+
+/// All voices of the mixer are busy.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Busy;
+
+pub struct Mixer<const VOICES: usize> {
+    voices: Mutex<CriticalSectionRawMutex, RefCell<Vec<Voice, VOICES>>>,
+}
+
+impl Voice {
+    /// Play `samples` at `volume` percent.
+    pub const fn clip(samples: &'static [i16], volume: u8) -> Self {
+        Self {
+            source: Source::Clip {
+                samples,
+                position: 0,
+            },
+            volume,
+        }
+    }
+
+    /// A square wave of `frequency` Hz lasting `duration`.
+    pub fn tone(frequency: u32, duration: Duration, volume: u8) -> Self {
+        let rate = RATE.hz();
+        Self {
+            source: Source::Tone {
+                half_period: (rate / frequency.clamp(1, rate / 2) / 2).max(1),
+                remaining: (duration.as_micros() * u64::from(rate) / 1_000_000) as u32,
+            },
+            volume,
+        }
+    }
+
+    /// Add the next samples to `block`. Returns whether the voice has finished.
+    fn mix(&mut self, block: &mut [i32]) -> bool {
+        let volume = i32::from(self.volume.min(100));
+        match &mut self.source {
+            | Source::Clip { samples, position } => {
+                let rest = &samples[*position..];
+                for (acc, &sample) in block.iter_mut().zip(rest) {
+                    *acc += i32::from(sample) * volume / 100;
+                }
+                *position += rest.len().min(block.len());
+                *position == samples.len()
+            }
+            | Source::Tone {
+                half_period,
+                remaining,
+            } => {
+                let len = block.len().min(*remaining as usize);
+                for acc in &mut block[..len] {
+                    let high = *remaining / *half_period % 2 == 0;
+                    let sample = if high {
+                        TONE_AMPLITUDE
+                    } else {
+                        -TONE_AMPLITUDE
+                    };
+                    *acc += sample * volume / 100;
+                    *remaining -= 1;
+                }
+                *remaining == 0
+            }
+        }
+    }
+}
+
+impl<const VOICES: usize> Mixer<VOICES> {
+    pub const fn new() -> Self {
+        Self {
+            voices: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn play(&self, voice: Voice) -> Result<(), Busy> {
+        self.voices.lock(|voices| voices.borrow_mut().push(voice).map_err(|_| Busy))
+    }
+
+    /// Drop all queued voices.
+    pub fn stop(&self) {
+        self.voices.lock(|voices| voices.borrow_mut().clear());
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.voices.lock(|voices| voices.borrow().is_empty())
+    }
+
+    /// Mix the next samples of all voices into `block`, dropping finished voices.
+    pub fn mix(&self, block: &mut [i16]) {
+        let mut acc = [0i32; BLOCK];
+        for chunk in block.chunks_mut(BLOCK) {
+            let acc = &mut acc[..chunk.len()];
+            acc.fill(0);
+            self.voices.lock(|voices| {
+                voices.borrow_mut().retain_mut(|voice| !voice.mix(acc));
+            });
+            for (sample, &acc) in chunk.iter_mut().zip(acc.iter()) {
+                *sample = acc.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+            }
+        }
+    }
+
+    /// Stream the mix to `output`.
+    pub async fn run<O: Output>(&self, output: &mut O) -> ! {
+        let mut mono = [0; BLOCK];
+        let mut stereo = [0; 2 * BLOCK];
+        loop {
+            self.mix(&mut mono);
+            for (frame, &sample) in stereo.chunks_exact_mut(2).zip(mono.iter()) {
+                frame.fill(sample);
+            }
+            // an underrun only causes a short gap
+            let _ = output.write(&stereo).await;
+        }
+    }
+}
+
+impl<const VOICES: usize> Default for Mixer<VOICES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all voices busy")
+    }
+}
+
+impl core::error::Error for Busy {}
+
+#[cfg(feature = "cross")]
+impl<T: sai::Instance> Output for sai::Sai<'_, T, u16> {
+    type Error = sai::Error;
+
+    async fn write(&mut self, samples: &[i16]) -> Result<(), sai::Error> {
+        sai::Sai::write(self, bytemuck::cast_slice(samples)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        static CLIP: [i16; 4] = [1000, -1000, i16::MAX, i16::MIN];
+        let mixer = Mixer::<2>::new();
+        mixer.play(Voice::clip(&CLIP, 100)).unwrap();
+        mixer.play(Voice::clip(&CLIP, 50)).unwrap();
+        assert_eq!(mixer.play(Voice::clip(&CLIP, 50)), Err(Busy));
+
+        let mut block = [1; 6];
+        mixer.mix(&mut block);
+        // saturated, then silence after the clips end
+        assert_eq!(block, [1500, -1500, i16::MAX, i16::MIN, 0, 0]);
+        assert!(mixer.is_idle());
+    }
+
+    #[test]
+    fn test_tone() {
+        // 12 kHz: two samples high, two low
+        let mut voice = Voice::tone(12_000, Duration::from_micros(125), 100);
+        let mut block = [0; 8];
+        assert!(voice.mix(&mut block));
+        let a = TONE_AMPLITUDE;
+        assert_eq!(block, [-a, a, a, -a, -a, a, 0, 0]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Square-wave beeps on a timer PWM output, e.g. driving a piezo buzzer.
+//!
+//! Independent of the codec, so feedback works without SAI set up.
+//! Any code may [`cue`] a [`Cue`]; [`Beeper::feedback`] plays the cues,
+//! dropping those that arrive while another is still playing.
+
+#[cfg(feature = "cross")]
+use embassy_stm32::time::Hertz;
+#[cfg(feature = "cross")]
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+#[cfg(feature = "cross")]
+use embassy_stm32::timer::Channel;
+#[cfg(feature = "cross")]
+use embassy_stm32::timer::GeneralInstance4Channel;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use embassy_time::Timer;
+
+static CUES: Signal<CriticalSectionRawMutex, Cue> = Signal::new();
+
+/// Output toggling at a given frequency.
+pub trait Tone {
+    fn start(&mut self, frequency: u32);
+    fn stop(&mut self);
+}
+
+/// Audible feedback for events.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Cue {
+    /// e.g. a touch on a GUI element
+    Click,
+    /// e.g. a failed CLI command
+    Error,
+}
+
+pub struct Beeper<T> {
+    tone: Mutex<CriticalSectionRawMutex, T>,
+}
+
+/// [`Tone`] on one channel of a timer, at 50 % duty cycle.
+#[cfg(feature = "cross")]
+pub struct Pwm<'d, T: GeneralInstance4Channel> {
+    pwm: SimplePwm<'d, T>,
+    channel: Channel,
+}
+
+/// Have [`Beeper::feedback`] play `cue`.
+pub fn cue(cue: Cue) {
+    CUES.signal(cue);
+}
+
+impl Cue {
+    /// Frequencies in Hz and durations in ms.
+    pub const fn tones(self) -> &'static [(u32, u64)] {
+        match self {
+            | Cue::Click => &[(4000, 5)],
+            | Cue::Error => &[(880, 80), (440, 160)],
+        }
+    }
+}
+
+impl<T: Tone> Beeper<T> {
+    pub const fn new(tone: T) -> Self {
+        Self {
+            tone: Mutex::new(tone),
+        }
+    }
+
+    /// Sound `frequency` Hz for `duration`, after any beep in progress.
+    pub async fn beep(&self, frequency: u32, duration: Duration) {
+        let mut tone = self.tone.lock().await;
+        tone.start(frequency);
+        Timer::after(duration).await;
+        tone.stop();
+    }
+
+    /// Play [cued](cue) events.
+    pub async fn feedback(&self) -> ! {
+        loop {
+            let cue = CUES.wait().await;
+            for &(frequency, ms) in cue.tones() {
+                self.beep(frequency, Duration::from_millis(ms)).await;
+            }
+            // cues raised while playing are stale by now
+            CUES.reset();
+        }
+    }
+}
+
+#[cfg(feature = "cross")]
+impl<'d, T: GeneralInstance4Channel> Pwm<'d, T> {
+    pub fn new(pwm: SimplePwm<'d, T>, channel: Channel) -> Self {
+        let mut pwm = pwm;
+        pwm.disable(channel);
+        Self { pwm, channel }
+    }
+}
+
+#[cfg(feature = "cross")]
+impl<T: GeneralInstance4Channel> Tone for Pwm<'_, T> {
+    fn start(&mut self, frequency: u32) {
+        self.pwm.set_frequency(Hertz(frequency));
+        let duty = self.pwm.get_max_duty() / 2;
+        self.pwm.set_duty(self.channel, duty);
+        self.pwm.enable(self.channel);
+    }
+
+    fn stop(&mut self) {
+        self.pwm.disable(self.channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cue() {
+        cue(Cue::Click);
+        cue(Cue::Error);
+        // only the latest pending cue is played
+        assert_eq!(CUES.try_take(), Some(Cue::Error));
+        assert_eq!(CUES.try_take(), None);
+    }
+}
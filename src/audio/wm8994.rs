@@ -0,0 +1,223 @@
+//! Cirrus Logic WM8994 codec, configured for headphone playback from AIF1.
+//!
+//! The control interface uses 16-bit register addresses and values.
+//! The start-up sequence follows the WM8994 datasheet and ST's board support package.
+
+use core::fmt;
+use core::fmt::Display;
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+/// I2C address on the STM32F769I-DISCO on-board bus
+pub const ADDRESS: u8 = 0x1A;
+/// device ID in [`REG_RESET`]
+const ID: u16 = 0x8994;
+
+const REG_RESET: u16 = 0x0000;
+const REG_HPOUT1_LEFT: u16 = 0x001C;
+const REG_HPOUT1_RIGHT: u16 = 0x001D;
+const REG_AIF1_DAC1_FILTERS: u16 = 0x0420;
+
+/// HPOUT1 volume update
+const HPOUT1_VU: u16 = 1 << 8;
+/// HPOUT1 unmute
+const HPOUT1_MUTE_N: u16 = 1 << 6;
+const HPOUT1_MAX: u16 = 0x3F;
+/// AIF1DAC1 soft mute
+const DAC1_MUTE: u16 = 1 << 9;
+
+/// AIF1 sample rate.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Rate {
+    Hz8000,
+    Hz16000,
+    Hz22050,
+    Hz32000,
+    Hz44100,
+    Hz48000,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error<E> {
+    Bus(E),
+    /// an unexpected device answered
+    Id(u16),
+}
+
+pub struct Codec<B> {
+    bus: B,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum Step {
+    Write(u16, u16),
+    DelayMs(u64),
+}
+
+impl Rate {
+    pub const fn hz(self) -> u32 {
+        match self {
+            | Rate::Hz8000 => 8000,
+            | Rate::Hz16000 => 16000,
+            | Rate::Hz22050 => 22050,
+            | Rate::Hz32000 => 32000,
+            | Rate::Hz44100 => 44100,
+            | Rate::Hz48000 => 48000,
+        }
+    }
+
+    /// AIF1 rate register value: sample rate and AIF1CLK = 256 fs
+    const fn register(self) -> u16 {
+        let rate = match self {
+            | Rate::Hz8000 => 0,
+            | Rate::Hz16000 => 3,
+            | Rate::Hz22050 => 4,
+            | Rate::Hz32000 => 6,
+            | Rate::Hz44100 => 7,
+            | Rate::Hz48000 => 8,
+        };
+        rate << 4 | 0x3
+    }
+}
+
+impl<B: I2c> Codec<B> {
+    pub fn new(bus: B) -> Self {
+        Self { bus }
+    }
+
+    /// Reset the codec and power up the headphone output at `volume` percent.
+    ///
+    /// The codec is an I2S slave expecting 16-bit stereo frames at `rate`
+    /// and a master clock of 256 times `rate` on MCLK1.
+    pub async fn init(&mut self, rate: Rate, volume: u8) -> Result<(), Error<B::Error>> {
+        let id = self.read(REG_RESET).await?;
+        if id != ID {
+            return Err(Error::Id(id));
+        }
+        // writing the reset register resets all registers
+        self.write(REG_RESET, 0).await?;
+
+        for step in playback(rate) {
+            match step {
+                | Step::Write(register, value) => self.write(register, value).await?,
+                | Step::DelayMs(ms) => Timer::after_millis(ms).await,
+            }
+        }
+        self.set_volume(volume).await?;
+        self.mute(false).await
+    }
+
+    /// Set the headphone volume in percent, 0 muting the output.
+    pub async fn set_volume(&mut self, volume: u8) -> Result<(), Error<B::Error>> {
+        let value = match u16::from(volume.min(100)) * HPOUT1_MAX / 100 {
+            | 0 => HPOUT1_VU,
+            | level => HPOUT1_VU | HPOUT1_MUTE_N | level,
+        };
+        self.write(REG_HPOUT1_LEFT, value).await?;
+        self.write(REG_HPOUT1_RIGHT, value).await
+    }
+
+    /// Soft-mute the DAC, e.g. to avoid pops while the audio interface stops.
+    pub async fn mute(&mut self, mute: bool) -> Result<(), Error<B::Error>> {
+        let value = if mute { DAC1_MUTE } else { 0 };
+        self.write(REG_AIF1_DAC1_FILTERS, value).await
+    }
+
+    pub async fn read(&mut self, register: u16) -> Result<u16, Error<B::Error>> {
+        let mut value = [0; 2];
+        self.bus
+            .write_read(ADDRESS, &register.to_be_bytes(), &mut value)
+            .await
+            .map_err(Error::Bus)?;
+        Ok(u16::from_be_bytes(value))
+    }
+
+    pub async fn write(
+        &mut self,
+        register: u16,
+        value: u16,
+    ) -> Result<(), Error<B::Error>> {
+        let [register_high, register_low] = register.to_be_bytes();
+        let [value_high, value_low] = value.to_be_bytes();
+        self.bus
+            .write(
+                ADDRESS,
+                &[register_high, register_low, value_high, value_low],
+            )
+            .await
+            .map_err(Error::Bus)
+    }
+}
+
+/// Power-up sequence routing AIF1 DAC1 to HPOUT1.
+fn playback(rate: Rate) -> [Step; 27] {
+    use Step::DelayMs;
+    use Step::Write;
+
+    [
+        // errata work-around
+        Write(0x0102, 0x0003),
+        Write(0x0817, 0x0000),
+        Write(0x0102, 0x0000),
+        // VMID soft start, then bias and VMID
+        Write(0x0039, 0x006C),
+        Write(0x0001, 0x0003),
+        DelayMs(50),
+        // AIF1 DAC1 left/right to DAC1 left/right
+        Write(0x0005, 0x0303),
+        Write(0x0601, 0x0001),
+        Write(0x0602, 0x0001),
+        Write(0x0604, 0x0000),
+        Write(0x0605, 0x0000),
+        // AIF1: slave, I2S, 16 bit, clocked from MCLK1
+        Write(0x0210, rate.register()),
+        Write(0x0300, 0x4010),
+        Write(0x0302, 0x0000),
+        Write(0x0208, 0x000A),
+        Write(0x0200, 0x0001),
+        // DAC1 to the headphone mixers, charge pump on
+        Write(0x002D, 0x0100),
+        Write(0x002E, 0x0100),
+        Write(0x004C, 0x9F25),
+        DelayMs(15),
+        // headphone outputs: enable, DC servo start-up, then remove the clamps
+        Write(0x0001, 0x0303),
+        Write(0x0060, 0x0022),
+        Write(0x0054, 0x0033),
+        DelayMs(257),
+        Write(0x0060, 0x00EE),
+        // DAC1 at 0 dB, unmuted
+        Write(0x0610, 0x00C0),
+        Write(0x0611, 0x01C0),
+    ]
+}
+
+impl<E: fmt::Debug> Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Bus(e) => write!(f, "i2c: {e:?}"),
+            | Error::Id(id) => write!(f, "unexpected device id {id:#06x}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> core::error::Error for Error<E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback() {
+        assert_eq!(Rate::Hz48000.register(), 0x0083);
+        assert_eq!(Rate::Hz44100.register(), 0x0073);
+        assert!(playback(Rate::Hz16000).contains(&Step::Write(0x0210, 0x0033)));
+    }
+}
@@ -0,0 +1,417 @@
+//! Board support for the STM32F769I-DISCO.
+//!
+//! Owns the pin assignments, clock tree and memory geometry of the board.
+//! [`Board::init`] brings up the chip and hands out the on-board peripherals ready to use,
+//! so the application does not need to know which pin goes where.
+//! Another board would get a module with the same interface, selected by a feature.
+
+use core::range::Range;
+
+use embassy_stm32::adc::Adc;
+use embassy_stm32::bind_interrupts;
+use embassy_stm32::eth::PacketQueue;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio;
+use embassy_stm32::i2c;
+use embassy_stm32::i2c::I2c;
+use embassy_stm32::mode::Async;
+use embassy_stm32::peripherals;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::simple_pwm::PwmPin;
+use embassy_stm32::timer::simple_pwm::SimplePwm;
+use embassy_stm32::timer::Channel;
+use heapless::String;
+use static_cell::StaticCell;
+
+use crate::audio::beeper;
+use crate::audio::beeper::Beeper;
+use crate::boot;
+use crate::flash;
+use crate::mem::backup;
+use crate::net::phy::Lan8742;
+use crate::ota;
+use crate::rtc;
+use crate::util::uid::Uid;
+
+pub const NAME: &str = "STM32F769I-DISCO";
+/// prefix of the hostname, followed by a suffix derived from the unique ID
+pub const HOSTNAME: &str = "STM32F7-DISCO";
+/// overrides the MAC address derived from the unique ID
+pub const MAC_ADDR: Option<[u8; 6]> = None;
+/// the IS42S32400F-6 on FMC bank 1
+pub const SDRAM_ADDRESS: u32 = 0xC000_0000;
+pub const SDRAM_SIZE: usize = 16 << 20;
+/// the OTM8009A panel behind the DSI host, in landscape
+pub const DISPLAY: DisplayTimings = DisplayTimings {
+    width: 800,
+    height: 480,
+    hsync: 2,
+    hbp: 34,
+    hfp: 34,
+    vsync: 1,
+    vbp: 15,
+    vfp: 16,
+};
+/// update slots in the upper half of the 64 MiB QSPI flash
+pub const OTA: ota::Layout = ota::Layout {
+    // room for the whole 2 MiB of internal flash plus the header
+    staging: Range {
+        start: 0x0200_0000,
+        end: 0x0220_1000,
+    },
+    state: 0x0220_1000,
+};
+
+/// divides the AHB clock down to the QSPI clock, staying below the flash's 60 MHz limit
+const QSPI_PRESCALER: u8 = 2;
+/// standard mode, which all on-board devices support
+const I2C_FREQ: Hertz = Hertz(100_000);
+/// the beeper's timer frequency until the first beep sets its own
+const BEEPER_FREQ: Hertz = Hertz(1_000);
+
+bind_interrupts!(pub struct Irqs {
+    ETH => embassy_stm32::eth::InterruptHandler;
+    RNG => embassy_stm32::rng::InterruptHandler<peripherals::RNG>;
+    I2C1_EV => i2c::EventInterruptHandler<peripherals::I2C1>;
+    I2C1_ER => i2c::ErrorInterruptHandler<peripherals::I2C1>;
+    I2C4_EV => i2c::EventInterruptHandler<peripherals::I2C4>;
+    I2C4_ER => i2c::ErrorInterruptHandler<peripherals::I2C4>;
+});
+
+/// Video timings in pixels and lines.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct DisplayTimings {
+    pub width: u16,
+    pub height: u16,
+    pub hsync: u16,
+    /// horizontal back porch
+    pub hbp: u16,
+    /// horizontal front porch
+    pub hfp: u16,
+    pub vsync: u16,
+    /// vertical back porch
+    pub vbp: u16,
+    /// vertical front porch
+    pub vfp: u16,
+}
+
+/// The on-board peripherals, ready to use.
+pub struct Board {
+    pub core: cortex_m::Peripherals,
+    /// AHB clock
+    pub hclk: Hertz,
+    pub boot: boot::Info,
+    pub clock: &'static rtc::Clock,
+    /// the blue user button, high while pressed
+    pub button: ExtiInput<'static>,
+    pub ld1: gpio::Output<'static>,
+    pub ld2: gpio::Output<'static>,
+    pub rng: embassy_stm32::rng::Rng<'static, peripherals::RNG>,
+    pub adc: Adc<'static, peripherals::ADC1>,
+    pub ethernet: Ethernet,
+    pub flash: QspiFlash,
+    /// I2C4, connecting the on-board peripherals, see [`crate::i2c::BOARD_DEVICES`]
+    pub i2c: I2c<'static, Async>,
+    /// I2C1, routed to the Arduino connector
+    pub i2c_ext: I2c<'static, Async>,
+    /// TIM3 channel 3 on Arduino D5 (PC8), for a piezo buzzer fitted there
+    pub beeper: Beeper<beeper::Pwm<'static, peripherals::TIM3>>,
+    pub uid: Uid,
+    pub mac_addr: [u8; 6],
+}
+
+/// The RMII interface to the LAN8742A PHY.
+pub struct Ethernet {
+    pub eth: peripherals::ETH,
+    pub ref_clk: peripherals::PA1,
+    pub mdio: peripherals::PA2,
+    pub mdc: peripherals::PC1,
+    pub crs: peripherals::PA7,
+    pub rx_d0: peripherals::PC4,
+    pub rx_d1: peripherals::PC5,
+    pub tx_d0: peripherals::PG13,
+    pub tx_d1: peripherals::PG14,
+    pub tx_en: peripherals::PG11,
+}
+
+/// The MX25L51245G NOR flash on QUADSPI bank 1.
+///
+/// D2 and D3 double as the flash's WP# and RESET#.
+pub struct QspiFlash {
+    pub qspi: peripherals::QUADSPI,
+    pub d0: peripherals::PC9,
+    pub d1: peripherals::PC10,
+    pub d2: peripherals::PE2,
+    pub d3: peripherals::PD13,
+    pub sck: peripherals::PB2,
+    pub nss: peripherals::PB6,
+    pub dma: peripherals::DMA2_CH7,
+}
+
+impl Board {
+    /// Set up clocks, the RTC and the backup domain, and record the boot.
+    ///
+    /// # Panics
+    /// Panics if called more than once.
+    pub fn init() -> Self {
+        let (config, hclk) = config();
+        let p = embassy_stm32::init(config);
+        let core = cortex_m::Peripherals::take().expect("core peripherals taken twice");
+
+        static CLOCK: StaticCell<rtc::Clock> = StaticCell::new();
+        let rtc =
+            embassy_stm32::rtc::Rtc::new(p.RTC, embassy_stm32::rtc::RtcConfig::default());
+        let clock = CLOCK.init(rtc::Clock::new(rtc));
+        backup::init();
+        let boot = boot::init(hclk.0);
+
+        /* SDRAM
+        let memory: &'static mut [core::mem::MaybeUninit<u32>] = {
+            static SDRAM: StaticCell<
+                stm32_fmc::Sdram<
+                    embassy_stm32::fmc::Fmc<'static, embassy_stm32::peripherals::FMC>,
+                    stm32_fmc::devices::is42s32400f_6::Is42s32400f6,
+                >,
+            > = StaticCell::new();
+            let sdram =
+                SDRAM.init(embassy_stm32::fmc::Fmc::sdram_a13bits_d32bits_4banks_bank1(
+                    p.FMC,
+                    p.PF0,
+                    p.PF1,
+                    p.PF2,
+                    p.PF3,
+                    p.PF4,
+                    p.PF5,
+                    p.PF12,
+                    p.PF13,
+                    p.PF14,
+                    p.PF15,
+                    p.PG0,
+                    p.PG1,
+                    p.PG2,
+                    p.PG4,
+                    p.PG5,
+                    p.PD14,
+                    p.PD15,
+                    p.PD0,
+                    p.PD1,
+                    p.PE7,
+                    p.PE8,
+                    p.PE9,
+                    p.PE10,
+                    p.PE11,
+                    p.PE12,
+                    p.PE13,
+                    p.PE14,
+                    p.PE15,
+                    p.PD8,
+                    p.PD9,
+                    p.PD10,
+                    p.PH8,
+                    p.PH9,
+                    p.PH10,
+                    p.PH11,
+                    p.PH12,
+                    p.PH13,
+                    p.PH14,
+                    p.PH15,
+                    p.PI0,
+                    p.PI1,
+                    p.PI2,
+                    p.PI3,
+                    p.PI6,
+                    p.PI7,
+                    p.PI9,
+                    p.PI10,
+                    p.PE0,
+                    p.PE1,
+                    p.PI4,
+                    p.PI5,
+                    p.PH2,
+                    p.PG8,
+                    p.PG15,
+                    p.PH3,
+                    p.PF11,
+                    p.PH5,
+                    stm32_fmc::devices::is42s32400f_6::Is42s32400f6 {},
+                ));
+            let ptr = sdram.init(&mut embassy_time::Delay);
+            crate::mem::Gate::Sdram.set(true);
+            let ptr = ptr.cast::<core::mem::MaybeUninit<u32>>();
+            // Safety: pointee u32: Sized
+            let size = unsafe { core::mem::size_of_val_raw(ptr) };
+            let len = SDRAM_SIZE / size;
+            // Safety:
+            // - I sure hope `embassy_stm32::fmc::Fmc::sdram_a13bits_d32bits_4banks_bank1` returns a read/write valid pointer
+            // - the source ptr does not escape this scope
+            const _: () = assert!(SDRAM_SIZE <= isize::MAX as usize);
+            assert!((ptr as usize).checked_add(SDRAM_SIZE).is_some());
+            unsafe { core::slice::from_raw_parts_mut(ptr, len) }
+        };
+
+        let (head, tail) = memory.split_at_mut(4);
+        let values: &[u32] = &[0x12345678, 0x87654321, 0x89ABCDEF, 0xFEDCBA98];
+        for (src, dst) in values.iter().zip(head.iter_mut()) {
+            dst.write(*src);
+        }
+        let head =
+            unsafe { core::mem::transmute::<&mut [core::mem::MaybeUninit<u32>], &mut [u32]>(head) };
+
+        assert_eq!(head, values);
+        */
+
+        let uid = Uid::read();
+        Self {
+            core,
+            hclk,
+            boot,
+            clock,
+            button: ExtiInput::new(p.PA0, p.EXTI0, gpio::Pull::Down),
+            ld1: gpio::Output::new(p.PJ13, gpio::Level::High, gpio::Speed::Low),
+            ld2: gpio::Output::new(p.PJ5, gpio::Level::High, gpio::Speed::Low),
+            rng: embassy_stm32::rng::Rng::new(p.RNG, Irqs),
+            adc: Adc::new(p.ADC1),
+            ethernet: Ethernet {
+                eth: p.ETH,
+                ref_clk: p.PA1,
+                mdio: p.PA2,
+                mdc: p.PC1,
+                crs: p.PA7,
+                rx_d0: p.PC4,
+                rx_d1: p.PC5,
+                tx_d0: p.PG13,
+                tx_d1: p.PG14,
+                tx_en: p.PG11,
+            },
+            flash: QspiFlash {
+                qspi: p.QUADSPI,
+                d0: p.PC9,
+                d1: p.PC10,
+                d2: p.PE2,
+                d3: p.PD13,
+                sck: p.PB2,
+                nss: p.PB6,
+                dma: p.DMA2_CH7,
+            },
+            i2c: I2c::new(
+                p.I2C4,
+                p.PD12,
+                p.PB7,
+                Irqs,
+                p.DMA1_CH6,
+                p.DMA1_CH2,
+                I2C_FREQ,
+                Default::default(),
+            ),
+            beeper: Beeper::new(beeper::Pwm::new(
+                SimplePwm::new(
+                    p.TIM3,
+                    None,
+                    None,
+                    Some(PwmPin::new_ch3(p.PC8, gpio::OutputType::PushPull)),
+                    None,
+                    BEEPER_FREQ,
+                    CountingMode::EdgeAlignedUp,
+                ),
+                Channel::Ch3,
+            )),
+            i2c_ext: I2c::new(
+                p.I2C1,
+                p.PB8,
+                p.PB9,
+                Irqs,
+                p.DMA1_CH7,
+                p.DMA1_CH0,
+                I2C_FREQ,
+                Default::default(),
+            ),
+            uid,
+            mac_addr: MAC_ADDR.unwrap_or_else(|| uid.mac()),
+        }
+    }
+
+    /// `HOSTNAME-<suffix derived from the unique ID>`
+    pub fn hostname(&self) -> String<32> {
+        self.uid.hostname(HOSTNAME).expect("hostname should fit")
+    }
+}
+
+impl Ethernet {
+    pub fn init<const TX: usize, const RX: usize>(
+        self,
+        queue: &'static mut PacketQueue<TX, RX>,
+        mac_addr: [u8; 6],
+    ) -> embassy_stm32::eth::Ethernet<'static, peripherals::ETH, Lan8742> {
+        embassy_stm32::eth::Ethernet::new(
+            queue,
+            self.eth,
+            Irqs,
+            self.ref_clk,
+            self.mdio,
+            self.mdc,
+            self.crs,
+            self.rx_d0,
+            self.rx_d1,
+            self.tx_d0,
+            self.tx_d1,
+            self.tx_en,
+            Lan8742::new(0),
+            mac_addr,
+        )
+    }
+}
+
+impl QspiFlash {
+    /// Reset the flash and read its geometry; takes over a second.
+    pub async fn init(self, hclk: Hertz) -> flash::Device<'static, peripherals::QUADSPI> {
+        flash::Device::new(
+            None,
+            hclk,
+            QSPI_PRESCALER,
+            self.qspi,
+            self.d0,
+            self.d1,
+            self.d2,
+            self.d3,
+            self.sck,
+            self.nss,
+            self.dma,
+            None::<flash::ExtendedPins>,
+        )
+        .await
+    }
+}
+
+// noinspection ALL
+fn config() -> (embassy_stm32::Config, Hertz) {
+    use embassy_stm32::rcc::*;
+    let mut config = embassy_stm32::Config::default();
+    config.rcc = {
+        let mut rcc = Config::default();
+        // HSI == 16 MHz
+        rcc.hsi = true;
+        rcc.pll = Some(Pll {
+            // PLL in == 16 MHz / 8 == 2 MHz
+            prediv: PllPreDiv::DIV8,
+            // PLL out == 2 MHz * 64 == 128 MHz
+            mul: PllMul(64),
+            // SYSCLK == PLL out / divp == 128 MHz / 2 == 64 MHz
+            divp: Some(PllPDiv::DIV2),
+            divq: None,
+            divr: None,
+        });
+        rcc.pll_src = PllSource::HSI;
+        // the RTC runs from the 32.768 kHz crystal, which keeps going across resets
+        rcc.ls = LsConfig::default_lse();
+        rcc.sys = Sysclk::PLL1_P;
+        // APB1 clock must not be faster than 54 MHz
+        rcc.apb1_pre = APBPrescaler::DIV2;
+        // AHB clock == SYSCLK = 64MHz
+        rcc.ahb_pre = AHBPrescaler::DIV1;
+        rcc
+    };
+    (config, Hertz(64_000_000))
+}
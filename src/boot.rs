@@ -0,0 +1,154 @@
+//! Boot diagnostics: why the last reset happened and how often the board booted.
+//!
+//! [`init`] reads and clears the RCC reset flags, so it has to run once, early.
+//! The flags and a boot counter are kept in the backup SRAM,
+//! so they have to be [initialized](crate::mem::backup::init) first.
+
+use core::cell::Cell;
+use core::fmt;
+use core::fmt::Display;
+
+use bitflags::bitflags;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+#[cfg(feature = "cross")]
+use crate::mem::backup;
+
+pub const NAME: &str = env!("CARGO_PKG_NAME");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static INFO: Mutex<CriticalSectionRawMutex, Cell<Option<Info>>> =
+    Mutex::new(Cell::new(None));
+
+bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    #[derive(bytemuck::Pod, bytemuck::Zeroable)]
+    /// reset flags, as in bits 24 to 31 of RCC_CSR
+    pub struct ResetFlags: u8 {
+        const BROWN_OUT            = 1 << 1;
+        /// NRST pin, also set by all other resets
+        const PIN                  = 1 << 2;
+        /// power-on or power-down
+        const POWER_ON             = 1 << 3;
+        const SOFTWARE             = 1 << 4;
+        const INDEPENDENT_WATCHDOG = 1 << 5;
+        const WINDOW_WATCHDOG      = 1 << 6;
+        /// illegal entry into stop or standby mode
+        const LOW_POWER            = 1 << 7;
+    }
+}
+
+/// What is known about the current boot.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Info {
+    pub reset: ResetFlags,
+    /// boots since the backup domain lost power, this one included
+    pub boots: u32,
+    /// Hz
+    pub hclk: u32,
+}
+
+/// Record the reset flags and count this boot.
+///
+/// Later calls return the recorded info.
+#[cfg(feature = "cross")]
+pub fn init(hclk: u32) -> Info {
+    use embassy_stm32::pac::RCC;
+
+    if let Some(info) = info() {
+        return info;
+    }
+    let reset = ResetFlags::from_csr(RCC.csr().read().0);
+    RCC.csr().modify(|w| w.set_rmvf(true));
+
+    let boots = backup::load::<u32>(backup::Key::BOOT_COUNT).unwrap_or(0).wrapping_add(1);
+    // without backup SRAM, the count stays at 1
+    let _ = backup::store(backup::Key::BOOT_COUNT, boots);
+    let _ = backup::store(backup::Key::RESET_REASON, reset);
+
+    let info = Info { reset, boots, hclk };
+    INFO.lock(|cell| cell.set(Some(info)));
+    info
+}
+
+/// The info recorded by [`init`], if it ran.
+pub fn info() -> Option<Info> {
+    INFO.lock(Cell::get)
+}
+
+impl ResetFlags {
+    pub fn from_csr(csr: u32) -> Self {
+        Self::from_bits_truncate((csr >> 24) as u8)
+    }
+
+    /// The most specific cause among the flags.
+    pub fn cause(self) -> &'static str {
+        // power-on resets set the brown-out and pin flags too,
+        // and all resets set the pin flag
+        [
+            (Self::INDEPENDENT_WATCHDOG, "independent watchdog"),
+            (Self::WINDOW_WATCHDOG, "window watchdog"),
+            (Self::LOW_POWER, "low-power management"),
+            (Self::SOFTWARE, "software"),
+            (Self::POWER_ON, "power-on"),
+            (Self::BROWN_OUT, "brown-out"),
+            (Self::PIN, "reset pin"),
+        ]
+        .into_iter()
+        .find(|&(flag, _)| self.contains(flag))
+        .map_or("unknown", |(_, cause)| cause)
+    }
+}
+
+impl Display for ResetFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cause())?;
+        for (i, (name, _)) in self.iter_names().enumerate() {
+            let separator = if i == 0 { " (" } else { " | " };
+            write!(f, "{separator}{name}")?;
+        }
+        if !self.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "firmware: {NAME} {VERSION}")?;
+        writeln!(f, "reset:    {}", self.reset)?;
+        writeln!(f, "boots:    {}", self.boots)?;
+        write!(f, "hclk:     {} MHz", self.hclk / 1_000_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use heapless::String;
+
+    use super::*;
+
+    #[test]
+    fn test_reset_flags() {
+        // power-on: POR, BOR and pin flags
+        let flags = ResetFlags::from_csr(0x0E00_0000 | 1 << 24);
+        assert_eq!(
+            flags,
+            ResetFlags::POWER_ON | ResetFlags::BROWN_OUT | ResetFlags::PIN
+        );
+        assert_eq!(flags.cause(), "power-on");
+
+        let flags = ResetFlags::INDEPENDENT_WATCHDOG | ResetFlags::PIN;
+        let mut out = String::<64>::new();
+        write!(out, "{flags}").unwrap();
+        assert_eq!(out, "independent watchdog (PIN | INDEPENDENT_WATCHDOG)");
+        assert_eq!(ResetFlags::empty().cause(), "unknown");
+    }
+}
@@ -1,19 +1,1750 @@
+pub mod auth;
+pub mod server;
+pub mod telnet;
+pub mod term;
+
+#[cfg(feature = "cross")]
+use core::ffi::CStr;
+use core::fmt;
+use core::fmt::Display;
+use core::net::Ipv4Addr;
+use core::ops::RangeInclusive;
+use core::str;
+
+#[cfg(feature = "cross")]
+use embassy_net::IpEndpoint;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::Error as _;
+use embedded_hal_async::i2c::I2c as I2cBus;
+use embedded_io_async::Write;
+
+use crate::adc;
+use crate::assets;
+use crate::audio;
+use crate::audio::Voice;
+use crate::boot;
+#[cfg(feature = "cross")]
+use crate::graphics::testcard;
+use crate::graphics::testcard::Pattern;
+use crate::i2c;
+use crate::mem;
+use crate::mem::regs;
+use crate::net::dhcp;
+use crate::net::dns;
+use crate::net::phy;
+use crate::net::ping;
+use crate::net::ping::Pinger;
+#[cfg(feature = "cross")]
+use crate::net::sntp;
+use crate::net::stats;
+use crate::net::wol;
+use crate::ota;
+#[cfg(feature = "cross")]
+use crate::rtc;
+use crate::rtc::DateTime;
+use crate::storage::Programmer;
+use crate::storage::Storage;
+use crate::storage::Verifier;
+use crate::system::events;
+use crate::system::events::Link;
+use crate::system::panic;
+use crate::system::supervisor::Service;
+#[cfg(feature = "cross")]
+use crate::tftp;
+#[cfg(feature = "cross")]
+use crate::tftp::TransferError;
+use crate::util::hash;
+use crate::util::hash::Hasher;
+use crate::util::hash::Sha256;
+use crate::util::lease::LeaseStats;
+use crate::util::profile;
+#[cfg(feature = "cross")]
+use crate::util::Cancel;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Command<'a> {
+pub enum Command<'a> {
     Echo(Echo<'a>),
     Download(Download<'a>),
+    Profile(Profile),
+    Ota(Ota<'a>),
+    Hash(Hash),
+    Mem(Mem<'a>),
+    Regs(Regs),
+    Flash(Flash<'a>),
+    Assets(Assets),
+    I2c(I2c<'a>),
+    Term(Term<'a>),
+    Ping(Ping),
+    Nslookup(Nslookup<'a>),
+    Wol(Wol),
+    Net(Net),
+    Cache(Cache),
+    Screen(Screen),
+    Beep(Beep),
+    Play(Play),
+    Date,
+    Time(Time),
+    Boot(Boot),
+    Passwd(Passwd<'a>),
+    Services,
+    Stats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Echo<'arg> {
+    pub echo: &'arg [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Download<'filename> {
+    pub filename: &'filename [u8],
+}
+
+/// `profile report` or `profile reset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Report,
+    Reset,
+}
+
+/// `ota download <server> <file>`, `ota verify` or `ota activate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ota<'a> {
+    Download {
+        server: Ipv4Addr,
+        filename: &'a [u8],
+    },
+    Verify,
+    Activate,
+}
+
+/// `hash <crc32|sha256> <address> <len>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hash {
+    pub algorithm: Algorithm,
+    pub address: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Crc32,
+    Sha256,
+}
+
+/// `mem read|dump <address> <len> [--periph]` or `mem write <address> <hex> [--periph]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mem<'a> {
+    pub op: MemOp<'a>,
+    pub address: u32,
+    /// allow accessing peripheral space
+    pub peripherals: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemOp<'a> {
+    Read {
+        len: u32,
+    },
+    Dump {
+        len: u32,
+    },
+    /// hex-encoded data, at most [`Mem::MAX_WRITE`] bytes
+    Write {
+        hex: &'a [u8],
+    },
+}
+
+/// `assets ls <address>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assets {
+    /// list the bundle at `address`, e.g., in memory-mapped QSPI flash
+    Ls { address: u32 },
+}
+
+/// `regs dump <ltdc|dsi|dma2d>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Regs {
+    Dump(&'static regs::Block),
+}
+
+/// `flash id`, `flash read <address> <len>`, `flash erase <start> <end>`,
+/// `flash program <address> <payload>`, `flash verify <address> <payload>`,
+/// `flash lock <start> <end>`, `flash unlock <start> <end>`, `flash locked <address>`,
+/// `flash otp status`, `flash otp read <offset> <len>` or `flash otp program <offset> <hex>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flash<'a> {
+    Id,
+    Read {
+        address: u32,
+        len: u32,
+    },
+    /// `end` is inclusive
+    Erase {
+        start: u32,
+        end: u32,
+    },
+    /// the target range has to be erased beforehand
+    Program {
+        address: u32,
+        payload: Payload<'a>,
+    },
+    Verify {
+        address: u32,
+        payload: Payload<'a>,
+    },
+    /// `end` is inclusive
+    Lock {
+        start: u32,
+        end: u32,
+    },
+    /// `end` is inclusive
+    Unlock {
+        start: u32,
+        end: u32,
+    },
+    Locked {
+        address: u32,
+    },
+    OtpStatus,
+    OtpRead {
+        offset: u32,
+        len: u32,
+    },
+    /// hex-encoded data, at most [`Flash::MAX_INLINE`] bytes
+    OtpProgram {
+        offset: u32,
+        hex: &'a [u8],
+    },
+}
+
+/// `tftp <server> <file>` or inline hex of at most [`Flash::MAX_INLINE`] bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload<'a> {
+    Hex(&'a [u8]),
+    Tftp {
+        server: Ipv4Addr,
+        filename: &'a [u8],
+    },
+}
+
+/// `i2c scan`, `i2c read <address> <register> <len>`
+/// or `i2c write <address> <register> <hex>`, followed by `--ext`
+/// to use the Arduino connector instead of the on-board bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2c<'a> {
+    pub op: I2cOp<'a>,
+    /// use the Arduino connector bus
+    pub external: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct Echo<'arg> {
-    echo: &'arg [u8],
+pub enum I2cOp<'a> {
+    Scan,
+    /// at most [`I2c::MAX_TRANSFER`] bytes
+    Read {
+        address: u8,
+        register: u8,
+        len: u8,
+    },
+    /// hex-encoded data, at most [`I2c::MAX_TRANSFER`] bytes
+    Write {
+        address: u8,
+        register: u8,
+        hex: &'a [u8],
+    },
+}
+
+/// `term`, `term color on|off`, `term prompt <text>` or `term page <lines>`
+///
+/// Changes the settings of the current session only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term<'a> {
+    Show,
+    Color(bool),
+    Prompt(&'a [u8]),
+    Page(u8),
+}
+
+/// `ping <host> [count]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping {
+    pub host: Ipv4Addr,
+    /// at most [`Ping::MAX_COUNT`]
+    pub count: u16,
+}
+
+/// `nslookup <name> [a|aaaa|ptr] [--server <address>]`
+///
+/// PTR lookups take an IPv4 address or an `in-addr.arpa` name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nslookup<'a> {
+    pub name: &'a [u8],
+    pub ty: dns::Type,
+    /// ask this server instead of the configured one
+    pub server: Option<Ipv4Addr>,
+}
+
+/// `wol <mac>`, with the MAC address as `aa:bb:cc:dd:ee:ff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wol {
+    pub mac: [u8; 6],
+}
+
+/// `net info`, `net stats` or `net renegotiate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Net {
+    Info,
+    Stats,
+    /// restart autonegotiation of the Ethernet link
+    Renegotiate,
+}
+
+/// `cache`, `cache clean`, `cache invalidate` or `cache probe <address> <len>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cache {
+    Status,
+    /// write back the D-cache
+    Clean,
+    /// write back and discard the D-cache
+    Invalidate,
+    /// time cold and warm reads of a memory range
+    Probe {
+        address: u32,
+        len: u32,
+    },
+}
+
+/// `display test <bars|gradient|checkerboard [cell]|order> [--layer <1|2>]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    /// draw a test card into an LTDC layer's framebuffer
+    Test { pattern: Pattern, layer: u8 },
+}
+
+/// `beep [frequency] [ms]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beep {
+    /// Hz
+    pub frequency: u32,
+    /// at most [`Beep::MAX_MS`]
+    pub ms: u32,
+}
+
+/// `play <address> <len> [volume]`
+///
+/// Plays 16-bit mono PCM at [`audio::RATE`] from memory, e.g. uploaded to SDRAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Play {
+    pub address: u32,
+    pub len: u32,
+    /// percent
+    pub volume: u8,
+}
+
+/// `time set <YYYY-MM-DD> <HH:MM:SS>` or `time sync [server]`, in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Time {
+    Set(DateTime),
+    /// ask this SNTP server instead of [`crate::net::sntp::POOL`]
+    Sync(Option<Ipv4Addr>),
+}
+
+/// `boot info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boot {
+    Info,
+}
+
+/// `passwd <password>` or `passwd --clear`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Passwd<'a> {
+    Set(&'a [u8]),
+    /// allow logins without password
+    Clear,
+}
+
+/// Network state reported by [`Net`] beyond the stack itself.
+#[derive(Clone, Copy)]
+pub struct NetState<'a> {
+    pub dhcp: &'a dhcp::Snooper,
+    pub interface: &'a stats::Interface,
+    pub sockets: &'a [&'a stats::Socket],
+}
+
+/// Downloads files for commands taking a TFTP [`Payload`].
+#[allow(async_fn_in_trait)]
+pub trait Fetch {
+    /// Download `filename` into `file`, reporting progress to `out`.
+    async fn fetch<W: Write>(
+        &mut self,
+        server: Ipv4Addr,
+        filename: &[u8],
+        file: W,
+        out: &mut impl fmt::Write,
+    ) -> Result<(), FetchError<W::Error>>;
+}
+
+/// [`Fetch`] over TFTP, aborted once `cancel` is cancelled.
+#[cfg(feature = "cross")]
+#[derive(Clone, Copy)]
+pub struct Tftp<'a> {
+    pub stack: Stack<'a>,
+    pub cancel: &'a Cancel,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct Download<'filename> {
-    filename: &'filename [u8],
+pub enum FetchError<E> {
+    /// no network to fetch from
+    Unavailable,
+    Filename,
+    Transfer,
+    Cancelled,
+    File(E),
 }
 
+/// Types that can be parsed from a single argument.
+pub trait FromArg<'a>: Sized {
+    fn from_arg(arg: &'a [u8]) -> Option<Self>;
+}
+
+/// Whitespace-separated, optionally quoted arguments of a command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Args<'a> {
+    rest: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<'a> {
+    Empty,
+    UnknownCommand(&'a [u8]),
+    InvalidArgument { name: &'static str, arg: &'a [u8] },
+    MissingArgument(&'static str),
+    UnexpectedArgument(&'a [u8]),
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a [u8]) -> Result<Self, Error<'a>> {
+        let mut args = Args::new(line);
+        let command = match args.next().ok_or(Error::Empty)? {
+            | b"echo" => Command::Echo(Echo { echo: args.rest() }),
+            | b"download" => Command::Download(Download {
+                filename: args.positional("filename")?,
+            }),
+            | b"profile" => Command::Profile(args.positional("subcommand")?),
+            | b"ota" => Command::Ota(match args.subcommand()? {
+                | b"download" => Ota::Download {
+                    server: args.positional("server")?,
+                    filename: args.positional("filename")?,
+                },
+                | b"verify" => Ota::Verify,
+                | b"activate" => Ota::Activate,
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"hash" => Command::Hash(Hash {
+                algorithm: args.positional("algorithm")?,
+                address: args.positional("address")?,
+                len: args.positional("len")?,
+            }),
+            | b"mem" => {
+                let op = args.subcommand()?;
+                let address = args.positional("address")?;
+                let op = match op {
+                    | b"read" => MemOp::Read {
+                        len: args.positional("len")?,
+                    },
+                    | b"dump" => MemOp::Dump {
+                        len: args.positional("len")?,
+                    },
+                    | b"write" => MemOp::Write {
+                        hex: args.hex("data", Mem::MAX_WRITE)?,
+                    },
+                    | other => return Err(Error::invalid("subcommand", other)),
+                };
+                Command::Mem(Mem {
+                    op,
+                    address,
+                    peripherals: args.flag("periph"),
+                })
+            }
+            | b"assets" => Command::Assets(match args.subcommand()? {
+                | b"ls" => Assets::Ls {
+                    address: args.positional("address")?,
+                },
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"regs" => Command::Regs(match args.subcommand()? {
+                | b"dump" => Regs::Dump(args.positional("peripheral")?),
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"flash" => Command::Flash(match args.subcommand()? {
+                | b"id" => Flash::Id,
+                | b"read" => Flash::Read {
+                    address: args.positional("address")?,
+                    len: args.positional("len")?,
+                },
+                | b"erase" => Flash::Erase {
+                    start: args.positional("start")?,
+                    end: args.positional("end")?,
+                },
+                | b"program" => Flash::Program {
+                    address: args.positional("address")?,
+                    payload: Payload::parse(&mut args)?,
+                },
+                | b"verify" => Flash::Verify {
+                    address: args.positional("address")?,
+                    payload: Payload::parse(&mut args)?,
+                },
+                | b"lock" => Flash::Lock {
+                    start: args.positional("start")?,
+                    end: args.positional("end")?,
+                },
+                | b"unlock" => Flash::Unlock {
+                    start: args.positional("start")?,
+                    end: args.positional("end")?,
+                },
+                | b"locked" => Flash::Locked {
+                    address: args.positional("address")?,
+                },
+                | b"otp" => match args.subcommand()? {
+                    | b"status" => Flash::OtpStatus,
+                    | b"read" => Flash::OtpRead {
+                        offset: args.positional("offset")?,
+                        len: args.positional("len")?,
+                    },
+                    | b"program" => Flash::OtpProgram {
+                        offset: args.positional("offset")?,
+                        hex: args.hex("data", Flash::MAX_INLINE)?,
+                    },
+                    | other => return Err(Error::invalid("subcommand", other)),
+                },
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"i2c" => {
+                let op = match args.subcommand()? {
+                    | b"scan" => I2cOp::Scan,
+                    | b"read" => I2cOp::Read {
+                        address: args.positional("address")?,
+                        register: args.positional("register")?,
+                        len: args.positional_in("len", 0..=I2c::MAX_TRANSFER as u8)?,
+                    },
+                    | b"write" => I2cOp::Write {
+                        address: args.positional("address")?,
+                        register: args.positional("register")?,
+                        hex: args.hex("data", I2c::MAX_TRANSFER)?,
+                    },
+                    | other => return Err(Error::invalid("subcommand", other)),
+                };
+                Command::I2c(I2c {
+                    op,
+                    external: args.flag("ext"),
+                })
+            }
+            | b"term" => Command::Term(match args.optional::<&[u8]>("setting")? {
+                | None => Term::Show,
+                | Some(b"color") => Term::Color(args.positional("color")?),
+                | Some(b"prompt") => Term::Prompt(args.positional("prompt")?),
+                | Some(b"page") => Term::Page(args.positional_in("lines", 1..=u8::MAX)?),
+                | Some(other) => return Err(Error::invalid("setting", other)),
+            }),
+            | b"ping" => Command::Ping(Ping {
+                host: args.positional("host")?,
+                count: args
+                    .optional_in("count", 1..=Ping::MAX_COUNT)?
+                    .unwrap_or(Ping::DEFAULT_COUNT),
+            }),
+            | b"nslookup" => Command::Nslookup(Nslookup {
+                name: args.positional("name")?,
+                ty: args.optional("type")?.unwrap_or(dns::Type::A),
+                server: args.option("server")?,
+            }),
+            | b"wol" => Command::Wol(Wol {
+                mac: args.positional("mac")?,
+            }),
+            | b"net" => Command::Net(match args.subcommand()? {
+                | b"info" => Net::Info,
+                | b"stats" => Net::Stats,
+                | b"renegotiate" => Net::Renegotiate,
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"cache" => Command::Cache(match args.optional::<&[u8]>("subcommand")? {
+                | None => Cache::Status,
+                | Some(b"clean") => Cache::Clean,
+                | Some(b"invalidate") => Cache::Invalidate,
+                | Some(b"probe") => Cache::Probe {
+                    address: args.positional("address")?,
+                    len: args.positional("len")?,
+                },
+                | Some(other) => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"display" => Command::Screen(match args.subcommand()? {
+                | b"test" => {
+                    let pattern = match args.positional::<&[u8]>("pattern")? {
+                        | b"bars" => Pattern::Bars,
+                        | b"gradient" => Pattern::Gradient,
+                        | b"checkerboard" => Pattern::Checkerboard {
+                            cell: args
+                                .optional_in("cell", 1..=256)?
+                                .unwrap_or(Screen::DEFAULT_CELL),
+                        },
+                        | b"order" => Pattern::PixelOrder,
+                        | other => return Err(Error::invalid("pattern", other)),
+                    };
+                    let layer = match args.option::<&[u8]>("layer")? {
+                        | None | Some(b"1") => 1,
+                        | Some(b"2") => 2,
+                        | Some(other) => return Err(Error::invalid("layer", other)),
+                    };
+                    Screen::Test { pattern, layer }
+                }
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"beep" => Command::Beep(Beep {
+                frequency: args
+                    .optional_in("frequency", 20..=20_000)?
+                    .unwrap_or(Beep::DEFAULT_FREQUENCY),
+                ms: args.optional_in("ms", 1..=Beep::MAX_MS)?.unwrap_or(Beep::DEFAULT_MS),
+            }),
+            | b"play" => Command::Play(Play {
+                address: args.positional("address")?,
+                len: args.positional("len")?,
+                volume: args.optional_in("volume", 0..=100)?.unwrap_or(100),
+            }),
+            | b"date" => Command::Date,
+            | b"time" => Command::Time(match args.subcommand()? {
+                | b"set" => {
+                    let date = args.positional::<&[u8]>("date")?;
+                    let time = args.positional::<&[u8]>("time")?;
+                    let date_time = DateTime::parse(date, time);
+                    Time::Set(date_time.ok_or(Error::invalid("time", time))?)
+                }
+                | b"sync" => Time::Sync(args.optional("server")?),
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"boot" => Command::Boot(match args.subcommand()? {
+                | b"info" => Boot::Info,
+                | other => return Err(Error::invalid("subcommand", other)),
+            }),
+            | b"passwd" => Command::Passwd(match args.flag("clear") {
+                | true => Passwd::Clear,
+                | false => Passwd::Set(args.positional("password")?),
+            }),
+            | b"services" => Command::Services,
+            | b"stats" => Command::Stats,
+            | other => return Err(Error::UnknownCommand(other)),
+        };
+        args.end()?;
+        Ok(command)
+    }
+}
+
+impl<'a> Payload<'a> {
+    fn parse(args: &mut Args<'a>) -> Result<Self, Error<'a>> {
+        match args.positional::<&[u8]>("payload")? {
+            | b"tftp" => Ok(Payload::Tftp {
+                server: args.positional("server")?,
+                filename: args.positional("filename")?,
+            }),
+            | data => hex("payload", data, Flash::MAX_INLINE).map(Payload::Hex),
+        }
+    }
+}
+
+/// Check that `arg` is a hex string of at most `max` bytes.
+fn hex<'a>(name: &'static str, arg: &'a [u8], max: usize) -> Result<&'a [u8], Error<'a>> {
+    let valid = arg.len() % 2 == 0
+        && arg.len() / 2 <= max
+        && arg.iter().all(u8::is_ascii_hexdigit);
+    valid.then_some(arg).ok_or(Error::invalid(name, arg))
+}
+
+impl Echo<'_> {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(out, "{}", self.echo.escape_ascii())
+    }
+}
+
+impl Profile {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Profile::Report => profile::report(out),
+            | Profile::Reset => {
+                profile::reset();
+                writeln!(out, "profiling statistics reset")
+            }
+        }
+    }
+}
+
+impl Hash {
+    /// Hash the memory range, using `crc` for CRC-32.
+    pub async fn run(
+        self,
+        crc: &mut impl Hasher<Output = u32>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let data = match mem::slice(self.address, self.len) {
+            | Ok(data) => data,
+            | Err(e) => return term::error(out, e),
+        };
+        match self.algorithm {
+            | Algorithm::Crc32 => writeln!(out, "{:08x}", hash::digest(crc, data).await),
+            | Algorithm::Sha256 => {
+                for byte in hash::digest(&mut Sha256::new(), data).await {
+                    write!(out, "{byte:02x}")?;
+                }
+                writeln!(out)
+            }
+        }
+    }
+}
+
+impl Mem<'_> {
+    pub const MAX_WRITE: usize = 64;
+
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        let Mem {
+            op,
+            address,
+            peripherals,
+        } = self;
+
+        let len = match op {
+            | MemOp::Read { len } | MemOp::Dump { len } => len,
+            | MemOp::Write { hex } => {
+                let mut buf = [0; Self::MAX_WRITE];
+                let data = decode_hex(hex, &mut buf);
+                return match mem::write(address, data, peripherals) {
+                    | Ok(()) => writeln!(out, "wrote {} bytes", data.len()),
+                    | Err(e) => term::error(out, e),
+                };
+            }
+        };
+
+        // check the whole range up front instead of failing halfway through
+        if let Err(e) = mem::check(address, len, false, peripherals) {
+            return term::error(out, e);
+        }
+        let mut buf = [0; 16];
+        for offset in (0..len).step_by(buf.len()) {
+            let line = &mut buf[..(len - offset).min(16) as usize];
+            if let Err(e) = mem::read(address + offset, line, peripherals) {
+                return term::error(out, e);
+            }
+            match op {
+                | MemOp::Dump { .. } => mem::dump_line(out, address + offset, line)?,
+                | _ => {
+                    for byte in line.iter() {
+                        write!(out, "{byte:02x}")?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Assets {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Assets::Ls { address } => {
+                let bundle = match assets::at(address) {
+                    | Ok(bundle) => bundle,
+                    | Err(e) => return term::error(out, e),
+                };
+                for asset in bundle.iter() {
+                    write!(
+                        out,
+                        "{:<16} {:>8} {}",
+                        asset.name,
+                        asset.data.len(),
+                        asset.format
+                    )?;
+                    match asset.format {
+                        | assets::Format::Raw => writeln!(out)?,
+                        | _ => writeln!(out, " {}x{}", asset.width, asset.height)?,
+                    }
+                }
+                writeln!(out, "{} assets", bundle.len())
+            }
+        }
+    }
+}
+
+impl Regs {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Regs::Dump(block) => {
+                writeln!(out, "{} @ {:08x}", block.name, block.base)?;
+                for register in block.registers {
+                    match register.read(block) {
+                        | Ok(value) => {
+                            writeln!(out, "{}", regs::Decoded { register, value })?
+                        }
+                        | Err(e) => return term::error(out, e),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Flash<'_> {
+    pub const MAX_INLINE: usize = 64;
+    /// bytes between progress reports
+    const PROGRESS_STEP: u32 = 64 << 10;
+
+    pub async fn run<S: Storage>(
+        self,
+        storage: &mut S,
+        fetch: &mut impl Fetch,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        match self {
+            | Flash::Id => match storage.jedec_id().await {
+                | Some([manufacturer, kind, capacity]) => writeln!(
+                    out,
+                    "JEDEC ID {manufacturer:02x} {kind:02x} {capacity:02x}, {} KiB",
+                    storage.capacity() >> 10
+                ),
+                | None => writeln!(out, "{} KiB", storage.capacity() >> 10),
+            },
+            | Flash::Read { address, len } => {
+                let mut buf = [0; 16];
+                for offset in (0..len).step_by(buf.len()) {
+                    let line = &mut buf[..(len - offset).min(16) as usize];
+                    storage.read(line, address.wrapping_add(offset)).await;
+                    mem::dump_line(out, address.wrapping_add(offset), line)?;
+                }
+                Ok(())
+            }
+            | Flash::Erase { start, end } => {
+                if end < start {
+                    return term::error(out, "end lies before start");
+                }
+                let total = u64::from(end - start) + 1;
+                let mut address = start;
+                loop {
+                    let last = address.saturating_add(Self::PROGRESS_STEP - 1).min(end);
+                    storage.erase((address..=last).into()).await;
+                    let done = u64::from(last - start) + 1;
+                    writeln!(out, "erased {}%", done * 100 / total)?;
+                    if last == end {
+                        return Ok(());
+                    }
+                    address = last + 1;
+                }
+            }
+            | Flash::Program { address, payload } => {
+                let mut programmer = Programmer::new(storage, address);
+                if let Err(e) = payload.write(fetch, &mut programmer, out).await {
+                    return term::error(out, e);
+                }
+                writeln!(out, "programmed {} bytes", programmer.written())
+            }
+            | Flash::Verify { address, payload } => {
+                let mut verifier = Verifier::new(storage, address);
+                if let Err(e) = payload.write(fetch, &mut verifier, out).await {
+                    return term::error(out, e);
+                }
+                write!(out, "verified {} bytes: ", verifier.compared())?;
+                match verifier.first_mismatch() {
+                    | None => writeln!(out, "OK"),
+                    | Some(first) => writeln!(
+                        out,
+                        "{} differing bytes, first at 0x{first:08x}",
+                        verifier.mismatches()
+                    ),
+                }
+            }
+            | Flash::Lock { start, end } | Flash::Unlock { start, end } => {
+                if end < start {
+                    return term::error(out, "end lies before start");
+                }
+                let (result, state) = match self {
+                    | Flash::Lock { .. } => {
+                        (storage.lock_range((start..=end).into()).await, "locked")
+                    }
+                    | _ => (storage.unlock_range((start..=end).into()).await, "unlocked"),
+                };
+                match result {
+                    | Ok(()) => writeln!(out, "{state} 0x{start:08x}..=0x{end:08x}"),
+                    | Err(e) => term::error(out, e),
+                }
+            }
+            | Flash::Locked { address } => match storage.is_locked(address).await {
+                | Ok(true) => writeln!(out, "0x{address:08x} locked"),
+                | Ok(false) => writeln!(out, "0x{address:08x} unlocked"),
+                | Err(e) => term::error(out, e),
+            },
+            | Flash::OtpStatus => match storage.otp_locked().await {
+                | Ok(locked) => writeln!(
+                    out,
+                    "{} bytes OTP, {}",
+                    storage.otp_len(),
+                    if locked { "locked" } else { "programmable" }
+                ),
+                | Err(e) => term::error(out, e),
+            },
+            | Flash::OtpRead { offset, len } => {
+                let mut buf = [0; 16];
+                for line_offset in (0..len).step_by(buf.len()) {
+                    let line = &mut buf[..(len - line_offset).min(16) as usize];
+                    let address = offset.wrapping_add(line_offset);
+                    if let Err(e) = storage.read_otp(line, address).await {
+                        return term::error(out, e);
+                    }
+                    mem::dump_line(out, address, line)?;
+                }
+                Ok(())
+            }
+            | Flash::OtpProgram { offset, hex } => {
+                let mut buf = [0; Flash::MAX_INLINE];
+                let data = decode_hex(hex, &mut buf);
+                match storage.program_otp(data, offset).await {
+                    | Ok(()) => writeln!(out, "programmed {} OTP bytes", data.len()),
+                    | Err(e) => term::error(out, e),
+                }
+            }
+        }
+    }
+}
+
+impl Ota<'_> {
+    /// Run the command on the slots of `layout` in `storage`.
+    pub async fn run<S: Storage>(
+        self,
+        storage: &mut S,
+        layout: &ota::Layout,
+        fetch: &mut impl Fetch,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        match self {
+            | Ota::Download { server, filename } => {
+                let mut staging = ota::Staging::begin(storage, *layout).await;
+                if let Err(e) = fetch.fetch(server, filename, &mut staging, out).await {
+                    return term::error(out, e);
+                }
+                writeln!(out, "staged {} bytes", staging.written())
+            }
+            | Ota::Verify => match ota::verify(storage, layout).await {
+                | Ok(header) => writeln!(
+                    out,
+                    "version {}, {} bytes, crc32 {:08x}",
+                    header.version, header.len, header.crc32
+                ),
+                | Err(e) => term::error(out, e),
+            },
+            | Ota::Activate => match ota::activate(storage, layout).await {
+                | Ok(header) => {
+                    writeln!(out, "version {} is swapped in on next boot", header.version)
+                }
+                | Err(e) => term::error(out, e),
+            },
+        }
+    }
+}
+
+impl I2c<'_> {
+    pub const MAX_TRANSFER: usize = 32;
+
+    /// Run the command on `bus`, which the caller selects according to [`I2c::external`].
+    pub async fn run(
+        self,
+        bus: &mut impl I2cBus,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let mut buf = [0; Self::MAX_TRANSFER];
+        let result = match self.op {
+            | I2cOp::Scan => {
+                let found = i2c::scan(bus).await;
+                out.write_str("    ")?;
+                for column in 0..16 {
+                    write!(out, " {column:2x}")?;
+                }
+                for address in 0..0x80u8 {
+                    if address % 16 == 0 {
+                        write!(out, "\n{address:02x}: ")?;
+                    }
+                    match address {
+                        | _ if !i2c::ADDRESSES.contains(&address) => {
+                            out.write_str("   ")?
+                        }
+                        | _ if found & (1 << address) != 0 => {
+                            write!(out, " {address:02x}")?
+                        }
+                        | _ => out.write_str(" --")?,
+                    }
+                }
+                writeln!(out)?;
+                for address in i2c::ADDRESSES {
+                    let name = i2c::board_device(address).filter(|_| !self.external);
+                    if let (true, Some(name)) = (found & (1 << address) != 0, name) {
+                        writeln!(out, "0x{address:02x}: {name}")?;
+                    }
+                }
+                return Ok(());
+            }
+            | I2cOp::Read {
+                address,
+                register,
+                len,
+            } => {
+                let data = &mut buf[..len as usize];
+                i2c::read_register(bus, address, register, data)
+                    .await
+                    .map(|()| data.len())
+            }
+            | I2cOp::Write {
+                address,
+                register,
+                hex,
+            } => {
+                let data = decode_hex(hex, &mut buf);
+                i2c::write_register(bus, address, register, data).await.map(|()| 0)
+            }
+        };
+
+        match (self.op, result) {
+            | (_, Err(e)) => term::error(out, e.kind()),
+            | (I2cOp::Write { hex, .. }, Ok(_)) => {
+                writeln!(out, "wrote {} bytes", hex.len() / 2)
+            }
+            | (_, Ok(len)) => {
+                for byte in &buf[..len] {
+                    write!(out, "{byte:02x}")?;
+                }
+                writeln!(out)
+            }
+        }
+    }
+}
+
+impl Term<'_> {
+    pub fn run(
+        self,
+        settings: &mut term::Settings,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        match self {
+            | Term::Show => {}
+            | Term::Color(color) => settings.color = color,
+            | Term::Prompt(prompt) => {
+                let prompt = str::from_utf8(prompt)
+                    .ok()
+                    .and_then(|prompt| heapless::String::try_from(prompt).ok());
+                match prompt {
+                    | Some(prompt) => settings.prompt = prompt,
+                    | None => return term::error(out, "invalid or too long prompt"),
+                }
+            }
+            | Term::Page(lines) => settings.page = lines,
+        }
+        let on_off = |on| if on { "on" } else { "off" };
+        writeln!(out, "color: {}", on_off(settings.color))?;
+        writeln!(out, "prompt: {:?}", settings.prompt)?;
+        writeln!(out, "page: {} lines", settings.page)
+    }
+}
+
+impl Ping {
+    pub const DEFAULT_COUNT: u16 = 4;
+    pub const MAX_COUNT: u16 = 100;
+    /// time between requests
+    const INTERVAL: Duration = Duration::from_secs(1);
+    /// time to wait for a reply
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub async fn run(self, stack: Stack<'_>, out: &mut impl fmt::Write) -> fmt::Result {
+        let mut buffers = ping::Buffers::new();
+        let mut pinger = match Pinger::new(stack, &mut buffers) {
+            | Ok(pinger) => pinger,
+            | Err(e) => return term::error(out, e),
+        };
+        let target = Ipv4Address(self.host.octets());
+        let mut stats = ping::Stats::default();
+
+        for i in 0..self.count {
+            let start = Instant::now();
+            match pinger.ping(target, Self::TIMEOUT).await {
+                | Ok(reply) => {
+                    stats.record(Some(reply.rtt));
+                    writeln!(
+                        out,
+                        "reply from {}: seq={} time={} ms",
+                        self.host,
+                        reply.seq,
+                        ping::Millis(reply.rtt)
+                    )?;
+                }
+                | Err(ping::Error::Timeout) => {
+                    stats.record(None);
+                    writeln!(out, "seq={i}: timed out")?;
+                }
+                | Err(e) => return term::error(out, e),
+            }
+            if i + 1 < self.count {
+                Timer::at(start + Self::INTERVAL).await;
+            }
+        }
+        writeln!(out, "{stats}")
+    }
+}
+
+impl Nslookup<'_> {
+    pub async fn run(self, stack: Stack<'_>, out: &mut impl fmt::Write) -> fmt::Result {
+        let configured = dns::servers(stack).first().map(|&(server, _)| server);
+        let server = self.server.map(|server| Ipv4Address(server.octets()));
+        let Some(server) = server.or(configured) else {
+            return term::error(out, "no DNS server configured");
+        };
+        let Ok(name) = str::from_utf8(self.name) else {
+            return term::error(out, dns::Error::Name);
+        };
+        let reverse;
+        let name = match (self.ty, name.parse()) {
+            | (dns::Type::Ptr, Ok(address)) => {
+                reverse = dns::reverse_name(address);
+                reverse.as_str()
+            }
+            | _ => name,
+        };
+
+        writeln!(out, "server: {server}")?;
+        let mut buf = [0; dns::MAX_MESSAGE];
+        let answers = match dns::query(stack, server, name, self.ty, &mut buf).await {
+            | Ok(answers) => answers,
+            | Err(e) => return term::error(out, e),
+        };
+        let mut found = false;
+        for record in answers {
+            found = true;
+            writeln!(out, "{name}: {record}")?;
+        }
+        if !found {
+            writeln!(out, "{name}: no records")?;
+        }
+        Ok(())
+    }
+}
+
+impl Wol {
+    pub async fn run(self, stack: Stack<'_>, out: &mut impl fmt::Write) -> fmt::Result {
+        let [a, b, c, d, e, f] = self.mac;
+        match wol::send(stack, self.mac).await {
+            | Ok(()) => writeln!(
+                out,
+                "sent magic packet for {a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}"
+            ),
+            | Err(e) => term::error(out, e),
+        }
+    }
+}
+
+impl Net {
+    pub fn run(
+        self,
+        stack: Stack<'_>,
+        state: &NetState<'_>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        match self {
+            | Net::Stats => {
+                writeln!(out, "interface: {}", state.interface.get())?;
+                for socket in state.sockets {
+                    writeln!(out, "{}: {}", socket.name, socket.get())?;
+                }
+                Ok(())
+            }
+            | Net::Renegotiate => {
+                phy::renegotiate();
+                writeln!(out, "restarting autonegotiation")
+            }
+            | Net::Info => {
+                // the PHY may not report the details
+                match (stack.is_link_up(), events::LINK.get()) {
+                    | (true, Link::Down) => writeln!(out, "link: up")?,
+                    | (true, link) => writeln!(out, "link: {link}")?,
+                    | (false, _) => writeln!(out, "link: down")?,
+                }
+                match stack.config_v4() {
+                    | Some(config) => {
+                        writeln!(out, "address: {}", config.address)?;
+                        match config.gateway {
+                            | Some(gateway) => writeln!(out, "gateway: {gateway}")?,
+                            | None => writeln!(out, "gateway: none")?,
+                        }
+                    }
+                    | None => writeln!(out, "address: none")?,
+                }
+                for (server, origin) in dns::servers(stack) {
+                    writeln!(out, "dns: {server} ({origin})")?;
+                }
+
+                let Some(lease) = state.dhcp.lease() else {
+                    return writeln!(out, "dhcp: no lease");
+                };
+                write!(out, "dhcp: {} from ", lease.address)?;
+                match lease.server {
+                    | Some(server) => writeln!(out, "{server}")?,
+                    | None => writeln!(out, "unknown server")?,
+                }
+                if let Some(router) = lease.router {
+                    writeln!(out, "router: {router}")?;
+                }
+                match (lease.lease_time, lease.remaining()) {
+                    | (Some(time), Some(remaining)) => writeln!(
+                        out,
+                        "lease: {} s, {} s left",
+                        time.as_secs(),
+                        remaining.as_secs()
+                    ),
+                    | _ => writeln!(out, "lease: infinite"),
+                }
+            }
+        }
+    }
+}
+
+impl Beep {
+    pub const DEFAULT_FREQUENCY: u32 = 1000;
+    pub const DEFAULT_MS: u32 = 200;
+    pub const MAX_MS: u32 = 10_000;
+
+    pub fn run<const V: usize>(
+        self,
+        mixer: &audio::Mixer<V>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let duration = Duration::from_millis(self.ms.into());
+        match mixer.play(Voice::tone(self.frequency, duration, 100)) {
+            | Ok(()) => Ok(()),
+            | Err(e) => term::error(out, e),
+        }
+    }
+}
+
+impl Play {
+    pub fn run<const V: usize>(
+        self,
+        mixer: &audio::Mixer<V>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let data = match mem::slice(self.address, self.len) {
+            | Ok(data) => data,
+            | Err(e) => return term::error(out, e),
+        };
+        let Ok(samples) = bytemuck::try_cast_slice::<u8, i16>(data) else {
+            return term::error(out, "address and length must be even");
+        };
+        match mixer.play(Voice::clip(samples, self.volume)) {
+            | Ok(()) => writeln!(
+                out,
+                "playing {} ms",
+                samples.len() as u64 * 1000 / u64::from(audio::RATE.hz())
+            ),
+            | Err(e) => term::error(out, e),
+        }
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Cache {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Cache::Status => writeln!(out, "{}", mem::cache::status()),
+            | Cache::Clean => {
+                mem::cache::clean();
+                writeln!(out, "dcache cleaned")
+            }
+            | Cache::Invalidate => {
+                mem::cache::clean_invalidate();
+                writeln!(out, "dcache cleaned and invalidated")
+            }
+            | Cache::Probe { address, len } => match mem::slice(address, len) {
+                | Ok(data) => writeln!(out, "{}", mem::cache::probe(data)),
+                | Err(e) => term::error(out, e),
+            },
+        }
+    }
+}
+
+impl Screen {
+    /// pixels per checkerboard square
+    pub const DEFAULT_CELL: u16 = 8;
+}
+
+#[cfg(feature = "cross")]
+impl Screen {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Screen::Test { pattern, layer } => match testcard::Layer::ltdc(layer) {
+                | Ok(mut framebuffer) => {
+                    framebuffer.draw(pattern);
+                    // the LTDC reads memory, not the D-cache
+                    mem::cache::clean();
+                    writeln!(
+                        out,
+                        "{pattern:?} on layer {layer}: {}x{} {:?}",
+                        framebuffer.width, framebuffer.height, framebuffer.format
+                    )
+                }
+                | Err(e) => term::error(out, e),
+            },
+        }
+    }
+}
+
+/// Show the current time.
+#[cfg(feature = "cross")]
+pub fn date(clock: &rtc::Clock, out: &mut impl fmt::Write) -> fmt::Result {
+    match clock.now() {
+        | Ok(now) => writeln!(out, "{now} UTC"),
+        | Err(e) => term::error(out, e),
+    }
+}
+
+/// Show the latest [`adc`] readings and the statistics of shared resources.
+pub fn stats(leases: &[(&str, LeaseStats)], out: &mut impl fmt::Write) -> fmt::Result {
+    match adc::READINGS.try_get() {
+        | Some(readings) => writeln!(out, "{readings}")?,
+        | None => writeln!(out, "temperature: not sampled yet")?,
+    }
+    for (name, stats) in leases {
+        writeln!(out, "{name}: {stats}")?;
+    }
+    if let Some(overlong) = events::OVERLONG.get() {
+        writeln!(out, "last overlong lease: {overlong}")?;
+    }
+    Ok(())
+}
+
+/// Show the status of supervised services.
+pub fn services(services: &[&Service], out: &mut impl fmt::Write) -> fmt::Result {
+    for service in services {
+        writeln!(out, "{}: {}", service.name, service.status())?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cross")]
+impl Time {
+    pub async fn run(
+        self,
+        stack: Stack<'_>,
+        clock: &rtc::Clock,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let now = match self {
+            | Time::Set(now) => now,
+            | Time::Sync(server) => {
+                let server = match server {
+                    | Some(server) => Ok(Ipv4Address(server.octets())),
+                    | None => sntp::pool(stack).await,
+                };
+                let unix = match server {
+                    | Ok(server) => {
+                        writeln!(out, "server: {server}")?;
+                        sntp::query(stack, server).await
+                    }
+                    | Err(e) => Err(e),
+                };
+                match unix {
+                    | Ok(unix) => DateTime::from_unix(unix),
+                    | Err(e) => return term::error(out, e),
+                }
+            }
+        };
+        match clock.set(now) {
+            | Ok(()) => writeln!(out, "{now} UTC"),
+            | Err(e) => term::error(out, e),
+        }
+    }
+}
+
+impl Boot {
+    pub fn run(self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self {
+            | Boot::Info => {
+                match boot::info() {
+                    | Some(info) => writeln!(out, "{info}")?,
+                    | None => term::error(out, "boot info not recorded")?,
+                }
+                match panic::previous() {
+                    | Some(message) => writeln!(out, "previous run {message}"),
+                    | None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Passwd<'_> {
+    /// Store the password, hashed with `salt`.
+    pub fn run(self, salt: [u8; 16], out: &mut impl fmt::Write) -> fmt::Result {
+        let (credential, done) = match self {
+            | Passwd::Set(password) => {
+                (Some(auth::Credential::new(password, salt)), "password set")
+            }
+            | Passwd::Clear => (None, "password cleared"),
+        };
+        match auth::set(credential) {
+            | Ok(()) => writeln!(out, "{done}"),
+            | Err(e) => term::error(out, e),
+        }
+    }
+}
+
+impl Payload<'_> {
+    async fn write<F: Fetch, W: Write>(
+        self,
+        fetch: &mut F,
+        mut file: W,
+        out: &mut impl fmt::Write,
+    ) -> Result<(), FetchError<W::Error>> {
+        match self {
+            | Payload::Hex(hex) => {
+                let mut buf = [0; Flash::MAX_INLINE];
+                file.write_all(decode_hex(hex, &mut buf)).await.map_err(FetchError::File)
+            }
+            | Payload::Tftp { server, filename } => {
+                fetch.fetch(server, filename, file, out).await
+            }
+        }
+    }
+}
+
+impl Fetch for () {
+    async fn fetch<W: Write>(
+        &mut self,
+        _server: Ipv4Addr,
+        _filename: &[u8],
+        _file: W,
+        _out: &mut impl fmt::Write,
+    ) -> Result<(), FetchError<W::Error>> {
+        Err(FetchError::Unavailable)
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Fetch for Tftp<'_> {
+    /// Reports progress every [`Flash::PROGRESS_STEP`] bytes.
+    async fn fetch<W: Write>(
+        &mut self,
+        server: Ipv4Addr,
+        filename: &[u8],
+        file: W,
+        out: &mut impl fmt::Write,
+    ) -> Result<(), FetchError<W::Error>> {
+        let mut buf = [0; 128];
+        let name = buf.get_mut(..filename.len() + 1).ok_or(FetchError::Filename)?;
+        name[..filename.len()].copy_from_slice(filename);
+        let name = CStr::from_bytes_with_nul(name).map_err(|_| FetchError::Filename)?;
+
+        let server = IpEndpoint::new(Ipv4Address(server.octets()).into(), tftp::PORT);
+        let mut reported = 0;
+        let progress = |progress: &tftp::Progress| {
+            let step = progress.transferred / Flash::PROGRESS_STEP;
+            if step == reported {
+                return;
+            }
+            reported = step;
+            // progress is best-effort
+            let _ = write!(out, "{} KiB", progress.transferred >> 10);
+            if let Some(total) = progress.total {
+                let _ = write!(out, " of {} KiB", total >> 10);
+            }
+            let _ = writeln!(out, ", {} retransmits", progress.retransmits);
+        };
+        match tftp::fetch(self.stack, server, name, file, progress, self.cancel).await {
+            | Ok(_) => Ok(()),
+            | Err(TransferError::Filename) => Err(FetchError::Filename),
+            | Err(TransferError::Cancelled) => Err(FetchError::Cancelled),
+            | Err(TransferError::File(e)) => Err(FetchError::File(e)),
+            | Err(_) => Err(FetchError::Transfer),
+        }
+    }
+}
+
+/// Decode a hex string validated by [`hex`] into `buf`.
+fn decode_hex<'b>(hex: &[u8], buf: &'b mut [u8]) -> &'b [u8] {
+    let data = &mut buf[..hex.len() / 2];
+    for (byte, digits) in data.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = (hex_digit(digits[0]) << 4) | hex_digit(digits[1]);
+    }
+    data
+}
+
+fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        | b'0'..=b'9' => digit - b'0',
+        | b'a'..=b'f' => digit - b'a' + 10,
+        | b'A'..=b'F' => digit - b'A' + 10,
+        | _ => unreachable!("hex digits are validated while parsing"),
+    }
+}
+
+impl<'a> Args<'a> {
+    pub fn new(line: &'a [u8]) -> Self {
+        Self { rest: line }
+    }
+
+    /// The next argument, parsed as `T`.
+    ///
+    /// `name` identifies the argument in error messages.
+    pub fn positional<T: FromArg<'a>>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<T, Error<'a>> {
+        self.optional(name)?.ok_or(Error::MissingArgument(name))
+    }
+
+    /// The next argument parsed as `T`, if there is one.
+    ///
+    /// Flags and options (`--<name>`) are left for [`flag`](Self::flag)
+    /// and [`option`](Self::option).
+    pub fn optional<T: FromArg<'a>>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<Option<T>, Error<'a>> {
+        let mut peek = *self;
+        match peek.next() {
+            | Some(arg) if !arg.starts_with(b"--") => {
+                *self = peek;
+                T::from_arg(arg).ok_or(Error::invalid(name, arg)).map(Some)
+            }
+            | _ => Ok(None),
+        }
+    }
+
+    /// Like [`positional`](Self::positional), additionally checking the value against `range`.
+    pub fn positional_in<T: FromArg<'a> + PartialOrd>(
+        &mut self,
+        name: &'static str,
+        range: RangeInclusive<T>,
+    ) -> Result<T, Error<'a>> {
+        self.optional_in(name, range)?.ok_or(Error::MissingArgument(name))
+    }
+
+    /// Like [`optional`](Self::optional), additionally checking the value against `range`.
+    pub fn optional_in<T: FromArg<'a> + PartialOrd>(
+        &mut self,
+        name: &'static str,
+        range: RangeInclusive<T>,
+    ) -> Result<Option<T>, Error<'a>> {
+        let Some(arg): Option<&'a [u8]> = self.optional(name)? else {
+            return Ok(None);
+        };
+        let value = T::from_arg(arg).ok_or(Error::invalid(name, arg))?;
+        match range.contains(&value) {
+            | true => Ok(Some(value)),
+            | false => Err(Error::invalid(name, arg)),
+        }
+    }
+
+    /// The next argument as a hex string of at most `max` bytes.
+    pub fn hex(&mut self, name: &'static str, max: usize) -> Result<&'a [u8], Error<'a>> {
+        hex(name, self.positional(name)?, max)
+    }
+
+    /// The next argument, naming a subcommand.
+    pub fn subcommand(&mut self) -> Result<&'a [u8], Error<'a>> {
+        self.positional("subcommand")
+    }
+
+    /// The remaining line with surrounding whitespace trimmed.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = self.rest.trim_ascii();
+        self.rest = &[];
+        rest
+    }
+
+    /// Consume the next argument if it is `--<name>`.
+    pub fn flag(&mut self, name: &str) -> bool {
+        let mut peek = *self;
+        let found = peek
+            .next()
+            .and_then(|arg| arg.strip_prefix(b"--"))
+            .is_some_and(|arg| arg == name.as_bytes());
+        if found {
+            *self = peek;
+        }
+        found
+    }
+
+    /// Consume `--<name> <value>` if it is next, parsing the value as `T`.
+    pub fn option<T: FromArg<'a>>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<Option<T>, Error<'a>> {
+        match self.flag(name) {
+            | true => self.positional(name).map(Some),
+            | false => Ok(None),
+        }
+    }
+
+    /// Ensure all arguments have been consumed.
+    pub fn end(mut self) -> Result<(), Error<'a>> {
+        match self.next() {
+            | None => Ok(()),
+            | Some(arg) => Err(Error::UnexpectedArgument(arg)),
+        }
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.rest.trim_ascii_start();
+        if input.is_empty() {
+            self.rest = input;
+            return None;
+        }
+        match parser::arg()(input) {
+            | Ok((rest, arg)) => {
+                self.rest = rest;
+                Some(arg)
+            }
+            // the last argument is not followed by whitespace
+            | Err(_) => {
+                self.rest = &[];
+                Some(input.trim_ascii_end())
+            }
+        }
+    }
+}
+
+impl Display for Error<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Empty => write!(f, "empty command"),
+            | Error::UnknownCommand(command) => {
+                write!(f, "unknown command: {}", command.escape_ascii())
+            }
+            | Error::InvalidArgument { name, arg } => {
+                write!(f, "invalid {name}: {}", arg.escape_ascii())
+            }
+            | Error::MissingArgument(name) => write!(f, "missing argument: {name}"),
+            | Error::UnexpectedArgument(arg) => {
+                write!(f, "unexpected argument: {}", arg.escape_ascii())
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error<'_> {}
+
+impl<'a> Error<'a> {
+    pub fn invalid(name: &'static str, arg: &'a [u8]) -> Self {
+        Error::InvalidArgument { name, arg }
+    }
+}
+
+impl<'a> FromArg<'a> for &'a [u8] {
+    fn from_arg(arg: &'a [u8]) -> Option<Self> {
+        Some(arg)
+    }
+}
+
+/// Decimal or `0x`-prefixed hexadecimal numbers.
+macro_rules! from_arg_int {
+    ($($int:ty),*) => {$(
+        impl FromArg<'_> for $int {
+            fn from_arg(arg: &[u8]) -> Option<Self> {
+                let digits = str::from_utf8(arg).ok()?;
+                match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+                    | Some(hex) => <$int>::from_str_radix(hex, 16).ok(),
+                    | None => digits.parse().ok(),
+                }
+            }
+        }
+    )*};
+}
+
+from_arg_int!(u8, u16, u32);
+
+/// MAC addresses, see [`wol::parse_mac`]
+impl FromArg<'_> for [u8; 6] {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        wol::parse_mac(arg)
+    }
+}
+
+impl FromArg<'_> for Ipv4Addr {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        str::from_utf8(arg).ok()?.parse().ok()
+    }
+}
+
+/// `on` or `off`
+impl FromArg<'_> for bool {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        match arg {
+            | b"on" => Some(true),
+            | b"off" => Some(false),
+            | _ => None,
+        }
+    }
+}
+
+impl FromArg<'_> for &'static regs::Block {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        regs::find(arg)
+    }
+}
+
+impl FromArg<'_> for Profile {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        match arg {
+            | b"report" => Some(Profile::Report),
+            | b"reset" => Some(Profile::Reset),
+            | _ => None,
+        }
+    }
+}
+
+impl FromArg<'_> for Algorithm {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        match arg {
+            | b"crc32" => Some(Algorithm::Crc32),
+            | b"sha256" => Some(Algorithm::Sha256),
+            | _ => None,
+        }
+    }
+}
+
+impl FromArg<'_> for dns::Type {
+    fn from_arg(arg: &[u8]) -> Option<Self> {
+        match arg {
+            | b"a" => Some(dns::Type::A),
+            | b"aaaa" => Some(dns::Type::Aaaa),
+            | b"ptr" => Some(dns::Type::Ptr),
+            | _ => None,
+        }
+    }
+}
+
+impl<E: fmt::Debug> Display for FetchError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | FetchError::Unavailable => write!(f, "no network available"),
+            | FetchError::Filename => write!(f, "invalid filename"),
+            | FetchError::Transfer => write!(f, "file transfer failed"),
+            | FetchError::Cancelled => write!(f, "cancelled"),
+            | FetchError::File(e) => write!(f, "writing file failed: {e:?}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> core::error::Error for FetchError<E> {}
+
 mod parser {
     use bytes::streaming::*;
     use character::streaming::multispace0;
@@ -120,3 +1851,323 @@ mod parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use heapless::String;
+
+    use super::*;
+    use crate::storage::sim::MemFlash;
+    use crate::storage::sim::NoLatency;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Command::parse(b"  echo \"hello\"  world\r\n"),
+            Ok(Command::Echo(Echo {
+                echo: b"\"hello\"  world"
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"download \"my file\""),
+            Ok(Command::Download(Download {
+                filename: b"my file"
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"profile reset"),
+            Ok(Command::Profile(Profile::Reset))
+        );
+        assert_eq!(
+            Command::parse(b"profile"),
+            Err(Error::MissingArgument("subcommand"))
+        );
+        assert_eq!(
+            Command::parse(b"profile report now"),
+            Err(Error::UnexpectedArgument(b"now"))
+        );
+        assert_eq!(
+            Command::parse(b"ota download 10.0.0.1 fw.bin"),
+            Ok(Command::Ota(Ota::Download {
+                server: Ipv4Addr::new(10, 0, 0, 1),
+                filename: b"fw.bin"
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"ota download 10.0.0 fw.bin"),
+            Err(Error::invalid("server", b"10.0.0"))
+        );
+        assert_eq!(
+            Command::parse(b"hash sha256 0xC0000000 4096"),
+            Ok(Command::Hash(Hash {
+                algorithm: Algorithm::Sha256,
+                address: 0xC000_0000,
+                len: 4096
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"mem write 0x40021018 01000000 --periph"),
+            Ok(Command::Mem(Mem {
+                op: MemOp::Write { hex: b"01000000" },
+                address: 0x4002_1018,
+                peripherals: true
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"mem write 0x20000000 abc"),
+            Err(Error::invalid("data", b"abc"))
+        );
+        assert_eq!(
+            Command::parse(b"flash program 0x1000 tftp 10.0.0.1 \"fw image.bin\""),
+            Ok(Command::Flash(Flash::Program {
+                address: 0x1000,
+                payload: Payload::Tftp {
+                    server: Ipv4Addr::new(10, 0, 0, 1),
+                    filename: b"fw image.bin"
+                }
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"flash verify 0 c0ffee"),
+            Ok(Command::Flash(Flash::Verify {
+                address: 0,
+                payload: Payload::Hex(b"c0ffee")
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"flash erase 0x1000"),
+            Err(Error::MissingArgument("end"))
+        );
+        assert_eq!(
+            Command::parse(b"flash otp program 0x10 c0ffee"),
+            Ok(Command::Flash(Flash::OtpProgram {
+                offset: 0x10,
+                hex: b"c0ffee"
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"i2c read 0x2a 0xa8 2 --ext"),
+            Ok(Command::I2c(I2c {
+                op: I2cOp::Read {
+                    address: 0x2A,
+                    register: 0xA8,
+                    len: 2
+                },
+                external: true
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"i2c read 0x2a 0x100 2"),
+            Err(Error::invalid("register", b"0x100"))
+        );
+        assert_eq!(
+            Command::parse(b"i2c read 0x2a 0 33"),
+            Err(Error::invalid("len", b"33"))
+        );
+        assert_eq!(
+            Command::parse(b"term prompt \"stm32> \""),
+            Ok(Command::Term(Term::Prompt(b"stm32> ")))
+        );
+        assert_eq!(Command::parse(b"term"), Ok(Command::Term(Term::Show)));
+        assert_eq!(
+            Command::parse(b"term page 0"),
+            Err(Error::invalid("lines", b"0"))
+        );
+        assert_eq!(
+            Command::parse(b"ping 10.0.0.1"),
+            Ok(Command::Ping(Ping {
+                host: Ipv4Addr::new(10, 0, 0, 1),
+                count: Ping::DEFAULT_COUNT
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"ping 10.0.0.1 0"),
+            Err(Error::invalid("count", b"0"))
+        );
+        assert_eq!(
+            Command::parse(b"nslookup example.com --server 9.9.9.9"),
+            Ok(Command::Nslookup(Nslookup {
+                name: b"example.com",
+                ty: dns::Type::A,
+                server: Some(Ipv4Addr::new(9, 9, 9, 9))
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"nslookup 192.0.2.1 ptr --server"),
+            Err(Error::MissingArgument("server"))
+        );
+        assert_eq!(Command::parse(b"net info"), Ok(Command::Net(Net::Info)));
+        assert_eq!(Command::parse(b"net stats"), Ok(Command::Net(Net::Stats)));
+        assert_eq!(
+            Command::parse(b"display test checkerboard --layer 2"),
+            Ok(Command::Screen(Screen::Test {
+                pattern: Pattern::Checkerboard {
+                    cell: Screen::DEFAULT_CELL
+                },
+                layer: 2
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"display test order --layer 3"),
+            Err(Error::invalid("layer", b"3"))
+        );
+        assert_eq!(
+            Command::parse(b"assets ls 0x90100000"),
+            Ok(Command::Assets(Assets::Ls {
+                address: 0x9010_0000
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"regs dump dma2d"),
+            Ok(Command::Regs(Regs::Dump(&regs::DMA2D)))
+        );
+        assert_eq!(
+            Command::parse(b"regs dump usart1"),
+            Err(Error::invalid("peripheral", b"usart1"))
+        );
+        assert_eq!(
+            Command::parse(b"wol 02:00:5e:10:20:30"),
+            Ok(Command::Wol(Wol {
+                mac: [0x02, 0x00, 0x5E, 0x10, 0x20, 0x30]
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"net renegotiate"),
+            Ok(Command::Net(Net::Renegotiate))
+        );
+        assert_eq!(Command::parse(b"cache"), Ok(Command::Cache(Cache::Status)));
+        assert_eq!(
+            Command::parse(b"beep 440"),
+            Ok(Command::Beep(Beep {
+                frequency: 440,
+                ms: Beep::DEFAULT_MS,
+            }))
+        );
+        assert!(Command::parse(b"beep 440 60000").is_err());
+        assert_eq!(
+            Command::parse(b"cache probe 0x08000000 4096"),
+            Ok(Command::Cache(Cache::Probe {
+                address: 0x0800_0000,
+                len: 4096,
+            }))
+        );
+        assert_eq!(
+            Command::parse(b"time set 2024-10-16 08:05:00"),
+            Ok(Command::Time(Time::Set(DateTime::new(
+                2024, 10, 16, 8, 5, 0
+            ))))
+        );
+        assert_eq!(
+            Command::parse(b"time set 2024-10-16 8:60:00"),
+            Err(Error::invalid("time", b"8:60:00"))
+        );
+        assert_eq!(
+            Command::parse(b"time sync"),
+            Ok(Command::Time(Time::Sync(None)))
+        );
+        assert_eq!(Command::parse(b"services"), Ok(Command::Services));
+        assert_eq!(Command::parse(b"stats"), Ok(Command::Stats));
+        assert_eq!(Command::parse(b" \t"), Err(Error::Empty));
+        assert_eq!(Command::parse(b"nope"), Err(Error::UnknownCommand(b"nope")));
+    }
+
+    #[test]
+    fn test_flash() {
+        let mut buf = [0; 2 * MemFlash::<NoLatency>::SECTOR_SIZE as usize];
+        let mut flash = MemFlash::new(&mut buf);
+        let mut out = String::<256>::new();
+
+        block_on(async {
+            let erase = Flash::Erase {
+                start: 0,
+                end: 0xFFF,
+            };
+            erase.run(&mut flash, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "erased 100%\n");
+
+            out.clear();
+            let program = Flash::Program {
+                address: 0x10,
+                payload: Payload::Hex(b"c0ffee"),
+            };
+            program.run(&mut flash, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "programmed 3 bytes\n");
+
+            out.clear();
+            let verify = Flash::Verify {
+                address: 0x0F,
+                payload: Payload::Hex(b"ffc0ffef"),
+            };
+            verify.run(&mut flash, &mut (), &mut out).await.unwrap();
+            assert_eq!(
+                out,
+                "verified 4 bytes: 1 differing bytes, first at 0x00000012\n"
+            );
+
+            out.clear();
+            let fetch = Flash::Verify {
+                address: 0,
+                payload: Payload::Tftp {
+                    server: Ipv4Addr::LOCALHOST,
+                    filename: b"fw.bin",
+                },
+            };
+            fetch.run(&mut flash, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "\x1b[31mno network available\x1b[0m\n");
+
+            out.clear();
+            let lock = Flash::Lock {
+                start: 0,
+                end: 0xFFF,
+            };
+            lock.run(&mut flash, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "\x1b[31mnot supported by the storage\x1b[0m\n");
+        });
+    }
+
+    #[test]
+    fn test_ota() {
+        const SECTOR: u32 = MemFlash::<NoLatency>::SECTOR_SIZE;
+        const LAYOUT: ota::Layout = ota::Layout {
+            staging: core::range::Range {
+                start: 0,
+                end: SECTOR,
+            },
+            state: SECTOR,
+        };
+        let mut buf = [0; 2 * SECTOR as usize];
+        let mut flash = MemFlash::new(&mut buf);
+        let mut out = String::<256>::new();
+
+        block_on(async {
+            let download = Ota::Download {
+                server: Ipv4Addr::LOCALHOST,
+                filename: b"fw.bin",
+            };
+            download.run(&mut flash, &LAYOUT, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "\x1b[31mno network available\x1b[0m\n");
+
+            out.clear();
+            Ota::Verify.run(&mut flash, &LAYOUT, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "\x1b[31mno update image staged\x1b[0m\n");
+
+            // an empty image, whose CRC-32 is 0
+            let header = ota::Header {
+                magic: ota::MAGIC,
+                version: 3,
+                len: 0,
+                crc32: 0,
+            };
+            flash.program(bytemuck::bytes_of(&header), 0).await;
+            out.clear();
+            Ota::Verify.run(&mut flash, &LAYOUT, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "version 3, 0 bytes, crc32 00000000\n");
+
+            out.clear();
+            Ota::Activate.run(&mut flash, &LAYOUT, &mut (), &mut out).await.unwrap();
+            assert_eq!(out, "version 3 is swapped in on next boot\n");
+            assert!(ota::pending(&mut flash, &LAYOUT).await.is_some());
+        });
+    }
+}
@@ -0,0 +1,165 @@
+//! Password check for CLI sessions.
+//!
+//! The password is kept as a salted SHA-256 hash in the backup SRAM,
+//! so it survives resets but not a loss of the backup domain.
+//! Without a stored password, sessions are not asked for one.
+//!
+//! Failed attempts are counted across all sessions: after [`MAX_ATTEMPTS`]
+//! in a row, logins are refused for [`LOCKOUT`], doubling with every further failure.
+
+use core::cell::Cell;
+
+use bytemuck::Pod;
+use bytemuck::Zeroable;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use crate::mem::backup;
+use crate::util::hash::Hasher;
+use crate::util::hash::Sha256;
+
+/// failed attempts in a row before logins are refused
+pub const MAX_ATTEMPTS: u32 = 3;
+pub const LOCKOUT: Duration = Duration::from_secs(10);
+pub const MAX_LOCKOUT: Duration = Duration::from_secs(10 * 60);
+/// time a client has to enter the password
+pub const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+static LIMITER: Mutex<CriticalSectionRawMutex, Cell<Limiter>> =
+    Mutex::new(Cell::new(Limiter::new()));
+
+/// A salted password hash.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Pod, Zeroable)]
+#[repr(C)]
+pub struct Credential {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+/// Rate limit of login attempts.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+struct Limiter {
+    /// failed attempts in a row
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Credential {
+    pub fn new(password: &[u8], salt: [u8; 16]) -> Self {
+        Self {
+            salt,
+            hash: hash(&salt, password),
+        }
+    }
+
+    pub fn verify(&self, password: &[u8]) -> bool {
+        // compare all bytes, so timing does not tell how much of the hash matched
+        let hash = hash(&self.salt, password);
+        hash.iter().zip(self.hash).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+/// The stored credential, if a password is set.
+pub fn credential() -> Option<Credential> {
+    backup::load(backup::Key::CLI_PASSWORD)
+}
+
+/// Set the password, or clear it with `None`.
+pub fn set(credential: Option<Credential>) -> Result<(), backup::Error> {
+    match credential {
+        | Some(credential) => backup::store(backup::Key::CLI_PASSWORD, credential),
+        | None => {
+            backup::remove(backup::Key::CLI_PASSWORD);
+            Ok(())
+        }
+    }
+}
+
+/// Check whether a login may be attempted, returning the time left otherwise.
+pub fn check() -> Result<(), Duration> {
+    LIMITER.lock(|limiter| limiter.get().check(Instant::now()))
+}
+
+/// Record the outcome of a login attempt.
+pub fn record(success: bool) {
+    LIMITER.lock(|limiter| {
+        let mut updated = limiter.get();
+        updated.record(success, Instant::now());
+        limiter.set(updated);
+    });
+}
+
+impl Limiter {
+    const fn new() -> Self {
+        Self {
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    fn check(&self, now: Instant) -> Result<(), Duration> {
+        match self.locked_until {
+            | Some(until) if until > now => Err(until - now),
+            | _ => Ok(()),
+        }
+    }
+
+    fn record(&mut self, success: bool, now: Instant) {
+        if success {
+            *self = Self::new();
+            return;
+        }
+        self.failures += 1;
+        if let Some(excess) = self.failures.checked_sub(MAX_ATTEMPTS) {
+            let lockout = LOCKOUT * 2u32.saturating_pow(excess.min(16));
+            self.locked_until = Some(now + lockout.min(MAX_LOCKOUT));
+        }
+    }
+}
+
+fn hash(salt: &[u8], password: &[u8]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.update(salt);
+    sha.update(password);
+    sha.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential() {
+        let credential = Credential::new(b"hunter2", [7; 16]);
+        assert!(credential.verify(b"hunter2"));
+        assert!(!credential.verify(b"hunter3"));
+        assert_ne!(credential, Credential::new(b"hunter2", [8; 16]));
+    }
+
+    #[test]
+    fn test_limiter() {
+        let start = Instant::from_secs(100);
+        let mut limiter = Limiter::new();
+        for _ in 0..MAX_ATTEMPTS - 1 {
+            limiter.record(false, start);
+            assert_eq!(limiter.check(start), Ok(()));
+        }
+        limiter.record(false, start);
+        assert_eq!(limiter.check(start), Err(LOCKOUT));
+        assert_eq!(limiter.check(start + LOCKOUT), Ok(()));
+
+        // doubling with every further failure
+        limiter.record(false, start + LOCKOUT);
+        assert_eq!(limiter.check(start + LOCKOUT), Err(2 * LOCKOUT));
+
+        limiter.record(true, start + LOCKOUT);
+        assert_eq!(limiter.check(start + LOCKOUT), Ok(()));
+    }
+}
@@ -0,0 +1,587 @@
+//! Command line over TCP.
+//!
+//! Every [`Buffers`] passed to [`serve`] is a slot listening for its own client,
+//! so a stuck or idle client only occupies its own slot instead of locking out the CLI.
+//! Lines are terminated by `\n`; a trailing `\r` is ignored.
+//! Telnet clients are switched into character mode (see [`telnet`](super::telnet)),
+//! in which case the server echoes input and handles backspace.
+//!
+//! If a password is [set](super::auth), clients have to enter it first.
+//! The session handles `term` itself, adjusting its [`Settings`].
+//! A trailing `--more` argument pages the output of any command.
+//! Any input while a command runs [cancels](Cancel) it.
+
+use core::fmt;
+use core::fmt::Write as FmtWrite;
+
+use embassy_futures::select::select;
+use embassy_futures::select::select_array;
+use embassy_futures::select::Either;
+use embassy_net::Stack;
+use embassy_time::with_timeout;
+use embassy_time::Duration;
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+use heapless::String;
+use heapless::Vec;
+
+use super::auth;
+use super::auth::Credential;
+use super::telnet::Telnet;
+use super::term;
+use super::term::Plain;
+use super::term::Settings;
+use super::Command;
+use super::Error;
+use crate::net::stats;
+use crate::net::tcp_server;
+use crate::net::tcp_server::Connection;
+use crate::net::tcp_server::Policy;
+use crate::net::tcp_server::Service;
+use crate::util::Cancel;
+
+/// conventional CLI port
+pub const PORT: u16 = 1234;
+/// time without input at the prompt after which a client is logged out and disconnected
+pub const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// keep-alive interval, freeing the slots of clients that vanished
+pub const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// time without even keep-alive ACKs after which a client is considered gone
+pub const DEAD_PEER_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+/// input a client may send without completing a command, including telnet negotiation
+pub const MAX_REQUEST: usize = 4096;
+
+const MORE: &str = "\x1b[7m-- more --\x1b[0m";
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+
+/// Executes parsed commands.
+#[allow(async_fn_in_trait)]
+pub trait Handler {
+    /// Run `command`, writing its output to `out`.
+    ///
+    /// Called concurrently from all sessions.
+    /// Long-running commands should stop early once `cancel` is cancelled.
+    async fn handle(
+        &self,
+        command: Command<'_>,
+        out: &mut impl FmtWrite,
+        cancel: &Cancel,
+    ) -> fmt::Result;
+}
+
+/// Buffers backing one client slot.
+///
+/// `line` bounds the command length, `out` the output of a single command.
+pub struct Buffers<const SOCKET: usize, const LINE: usize, const OUT: usize> {
+    pub socket: tcp_server::Buffers<SOCKET>,
+    pub line: [u8; LINE],
+    pub out: String<OUT>,
+}
+
+/// State of one client connection.
+struct Session<'s, 'c, 'b> {
+    connection: &'s mut Connection<'c, 'b>,
+    telnet: Telnet,
+    settings: Settings,
+}
+
+/// [`tcp_server::Handler`] running sessions on the buffers of one slot.
+struct Slot<'b, 'h, H, const OUT: usize> {
+    line: &'b mut [u8],
+    out: &'b mut String<OUT>,
+    handler: &'h H,
+}
+
+/// Assembles received bytes into lines.
+struct Lines<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<const SOCKET: usize, const LINE: usize, const OUT: usize>
+    Buffers<SOCKET, LINE, OUT>
+{
+    pub const fn new() -> Self {
+        Self {
+            socket: tcp_server::Buffers::new(),
+            line: [0; LINE],
+            out: String::new(),
+        }
+    }
+}
+
+impl<const SOCKET: usize, const LINE: usize, const OUT: usize> Default
+    for Buffers<SOCKET, LINE, OUT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serve the CLI on `port`, one client per slot in `slots`, counting traffic in `stats`.
+pub async fn serve<
+    const N: usize,
+    const SOCKET: usize,
+    const LINE: usize,
+    const OUT: usize,
+>(
+    stack: Stack<'_>,
+    port: u16,
+    slots: &mut [Buffers<SOCKET, LINE, OUT>; N],
+    handler: &impl Handler,
+    stats: &stats::Socket,
+) -> ! {
+    let service = Service::new(
+        Policy {
+            dead_peer_timeout: Some(DEAD_PEER_TIMEOUT),
+            keep_alive: Some(KEEP_ALIVE),
+            max_request: MAX_REQUEST,
+        },
+        stats,
+    );
+    let slots =
+        slots.each_mut().map(|buffers| slot(&service, stack, port, buffers, handler));
+    select_array(slots).await.0
+}
+
+async fn slot<const SOCKET: usize, const LINE: usize, const OUT: usize>(
+    service: &Service<'_>,
+    stack: Stack<'_>,
+    port: u16,
+    buffers: &mut Buffers<SOCKET, LINE, OUT>,
+    handler: &impl Handler,
+) -> ! {
+    let Buffers { socket, line, out } = buffers;
+    let mut slot = Slot { line, out, handler };
+    service.serve(stack, port, socket, &mut slot).await
+}
+
+impl<H: Handler, const OUT: usize> tcp_server::Handler for Slot<'_, '_, H, OUT> {
+    async fn handle(
+        &mut self,
+        connection: &mut Connection<'_, '_>,
+    ) -> Result<(), tcp_server::Error> {
+        let lines = Lines {
+            buf: self.line,
+            len: 0,
+        };
+        session(connection, lines, self.out, self.handler).await
+    }
+}
+
+async fn session<const OUT: usize>(
+    connection: &mut Connection<'_, '_>,
+    mut lines: Lines<'_>,
+    out: &mut String<OUT>,
+    handler: &impl Handler,
+) -> Result<(), tcp_server::Error> {
+    let mut session = Session {
+        connection,
+        telnet: Telnet::new(true),
+        settings: Settings::new(),
+    };
+    let mut rx = [0; 64];
+
+    if let Some(credential) = auth::credential() {
+        let login = session.login(&mut lines, &credential);
+        match with_timeout(auth::LOGIN_TIMEOUT, login).await {
+            | Ok(Ok(true)) => {}
+            | Ok(Ok(false)) | Err(_) => return Ok(()),
+            | Ok(Err(e)) => return Err(e),
+        }
+    }
+
+    session.prompt().await?;
+    loop {
+        // keep-alives keep the connection up, so idleness is up to the session
+        let Ok(received) = with_timeout(TIMEOUT, session.receive(&mut rx)).await else {
+            let message = "\n\x1b[31midle for too long, logged out\x1b[0m\n";
+            session.send(message, false).await?;
+            return Ok(());
+        };
+        let Some(data) = received? else {
+            return Ok(());
+        };
+
+        // at most three bytes of echo per byte received
+        let mut echo = Vec::<u8, { 3 * 64 }>::new();
+        for &byte in data.iter() {
+            let echoed: &[u8] = match byte {
+                | BACKSPACE | DELETE if lines.erase() => b"\x08 \x08",
+                | BACKSPACE | DELETE => b"",
+                | b'\n' => {
+                    lines.push(byte);
+                    b"\r\n"
+                }
+                | _ => {
+                    lines.push(byte);
+                    core::slice::from_ref(&byte)
+                }
+            };
+            let _ = echo.extend_from_slice(echoed);
+        }
+        if session.telnet.echo() {
+            session.connection.write_all(&echo).await?;
+        }
+
+        while let Some(line) = lines.line() {
+            let (line, paged) = strip_more(line);
+            out.clear();
+            let cancel = Cancel::new();
+            let Session {
+                connection,
+                telnet,
+                settings,
+            } = &mut session;
+            let command = execute(line, out, settings, handler, &cancel);
+            let complete =
+                match select(command, interrupt(connection, telnet, &cancel)).await {
+                    | Either::First(complete) => complete,
+                    | Either::Second(never) => never,
+                };
+            session.send(out, paged).await?;
+            if !complete {
+                session.send("\x1b[31m[output truncated]\x1b[0m\n", false).await?;
+            }
+            session.prompt().await?;
+            session.connection.next_request();
+            lines.consume();
+        }
+
+        if lines.is_full() {
+            lines.len = 0;
+            session.send("\x1b[31mline too long\x1b[0m\n", false).await?;
+            session.prompt().await?;
+        }
+    }
+}
+
+/// Parse and run `line`.
+///
+/// Returns `false` if the output did not fit into `out`.
+async fn execute(
+    line: &[u8],
+    out: &mut impl FmtWrite,
+    settings: &mut Settings,
+    handler: &impl Handler,
+    cancel: &Cancel,
+) -> bool {
+    match Command::parse(line) {
+        | Ok(Command::Term(term)) => term.run(settings, out),
+        | Ok(command) => handler.handle(command, out, cancel).await,
+        | Err(Error::Empty) => Ok(()),
+        | Err(e) => term::error(out, e),
+    }
+    .is_ok()
+}
+
+/// Cancel `cancel` once the client sends anything or disconnects.
+///
+/// The input is discarded.
+async fn interrupt(
+    connection: &mut Connection<'_, '_>,
+    telnet: &mut Telnet,
+    cancel: &Cancel,
+) -> ! {
+    let mut rx = [0; 16];
+    while let Ok(received @ 1..) = connection.read(&mut rx).await {
+        let data = telnet.filter(&mut rx[..received]);
+        let _ = connection.write_all(telnet.replies()).await;
+        telnet.clear_replies();
+        if !data.is_empty() {
+            break;
+        }
+    }
+    cancel.cancel();
+    core::future::pending().await
+}
+
+/// Split off a trailing `--more` argument.
+fn strip_more(line: &[u8]) -> (&[u8], bool) {
+    match line.trim_ascii_end().strip_suffix(b"--more") {
+        | Some(rest)
+            if rest.is_empty() || rest.ends_with(b" ") || rest.ends_with(b"\t") =>
+        {
+            (rest, true)
+        }
+        | _ => (line, false),
+    }
+}
+
+impl Session<'_, '_, '_> {
+    /// Receive data with telnet commands stripped and answered.
+    ///
+    /// Returns `None` once the client closed the connection.
+    async fn receive<'r>(
+        &mut self,
+        rx: &'r mut [u8],
+    ) -> Result<Option<&'r [u8]>, tcp_server::Error> {
+        let received = self.connection.read(rx).await?;
+        if received == 0 {
+            return Ok(None);
+        }
+        let data = self.telnet.filter(&mut rx[..received]);
+        self.connection.write_all(self.telnet.replies()).await?;
+        self.telnet.clear_replies();
+        Ok(Some(data))
+    }
+
+    /// Ask for the password until it is entered correctly or logins are locked out.
+    ///
+    /// Returns whether the client logged in.
+    async fn login(
+        &mut self,
+        lines: &mut Lines<'_>,
+        credential: &Credential,
+    ) -> Result<bool, tcp_server::Error> {
+        loop {
+            if let Err(wait) = auth::check() {
+                let mut message = String::<64>::new();
+                let _ = writeln!(
+                    message,
+                    "\x1b[31mtoo many failed logins; retry in {} s\x1b[0m",
+                    wait.as_secs() + 1
+                );
+                self.send(&message, false).await?;
+                return Ok(false);
+            }
+            self.send("password: ", false).await?;
+            if !self.read_hidden(lines).await? {
+                return Ok(false);
+            }
+            let success = credential.verify(lines.line().unwrap_or_default());
+            lines.consume();
+            auth::record(success);
+            self.send("\n", false).await?;
+            if success {
+                return Ok(true);
+            }
+            self.send("\x1b[31mwrong password\x1b[0m\n", false).await?;
+        }
+    }
+
+    /// Receive a line without echoing it.
+    ///
+    /// Returns `false` once the client closed the connection.
+    async fn read_hidden(
+        &mut self,
+        lines: &mut Lines<'_>,
+    ) -> Result<bool, tcp_server::Error> {
+        let mut rx = [0; 64];
+        while lines.line().is_none() {
+            let Some(data) = self.receive(&mut rx).await? else {
+                return Ok(false);
+            };
+            for &byte in data {
+                match byte {
+                    | BACKSPACE | DELETE => {
+                        lines.erase();
+                    }
+                    | _ => lines.push(byte),
+                }
+            }
+            if lines.is_full() {
+                // far too long to be the password anyway
+                lines.len = 0;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn prompt(&mut self) -> Result<(), tcp_server::Error> {
+        let mut prompt = String::<{ Settings::MAX_PROMPT + 16 }>::new();
+        self.settings.write_prompt(&mut prompt).expect("styled prompt should fit");
+        self.connection.write_all(prompt.as_bytes()).await
+    }
+
+    /// Send `text`, translating `\n` to `\r\n`.
+    ///
+    /// If `paged`, wait for a key press after every page;
+    /// `q` skips the rest of the text.
+    async fn send(&mut self, text: &str, paged: bool) -> Result<(), tcp_server::Error> {
+        // the last line of a page shows the pager prompt
+        let per_page = usize::from(self.settings.page.max(2)) - 1;
+        let mut sent = 0;
+        for line in text.split_inclusive('\n') {
+            if paged && sent > 0 && sent % per_page == 0 && !self.more().await? {
+                return Ok(());
+            }
+            match line.strip_suffix('\n') {
+                | Some(line) => {
+                    self.write_styled(line).await?;
+                    self.connection.write_all(b"\r\n").await?;
+                    sent += 1;
+                }
+                | None => self.write_styled(line).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the pager prompt and wait for a key press.
+    ///
+    /// Returns whether to continue.
+    async fn more(&mut self) -> Result<bool, tcp_server::Error> {
+        self.write_styled(MORE).await?;
+        let mut rx = [0; 16];
+        let more = match self.receive(&mut rx).await? {
+            | Some(data) => !data.contains(&b'q'),
+            | None => false,
+        };
+        // overwrite the pager prompt
+        self.connection.write_all(b"\r          \r").await?;
+        Ok(more)
+    }
+
+    /// Write `text`, stripping ANSI escape sequences unless color is enabled.
+    async fn write_styled(&mut self, text: &str) -> Result<(), tcp_server::Error> {
+        if self.settings.color {
+            return self.connection.write_all(text.as_bytes()).await;
+        }
+        for part in Plain::new(text) {
+            self.connection.write_all(part.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Lines<'_> {
+    /// Append `byte`, dropping it if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.buf.get_mut(self.len) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Remove the last byte of an incomplete line.
+    ///
+    /// Returns whether there was one.
+    fn erase(&mut self) -> bool {
+        let erase = self.len > 0 && self.buf[self.len - 1] != b'\n';
+        if erase {
+            self.len -= 1;
+        }
+        erase
+    }
+
+    /// The first complete line, without its terminator.
+    fn line(&self) -> Option<&[u8]> {
+        let end = memchr::memchr(b'\n', &self.buf[..self.len])?;
+        let line = &self.buf[..end];
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+
+    /// Drop the first complete line.
+    fn consume(&mut self) {
+        if let Some(end) = memchr::memchr(b'\n', &self.buf[..self.len]) {
+            self.buf.copy_within(end + 1..self.len, 0);
+            self.len -= end + 1;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    struct Echo;
+
+    impl Handler for Echo {
+        async fn handle(
+            &self,
+            command: Command<'_>,
+            out: &mut impl FmtWrite,
+            _cancel: &Cancel,
+        ) -> fmt::Result {
+            match command {
+                | Command::Echo(echo) => echo.run(out),
+                | _ => writeln!(out, "unsupported"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut buf = [0; 16];
+        let mut lines = Lines {
+            buf: &mut buf,
+            len: 0,
+        };
+
+        b"echo a\r\necx".iter().for_each(|&byte| lines.push(byte));
+        assert_eq!(lines.line(), Some(b"echo a".as_slice()));
+        lines.consume();
+        assert_eq!(lines.line(), None);
+
+        assert!(lines.erase());
+        b"ho\n\n".iter().for_each(|&byte| lines.push(byte));
+        assert_eq!(lines.line(), Some(b"echo".as_slice()));
+        lines.consume();
+        assert_eq!(lines.line(), Some(b"".as_slice()));
+        lines.consume();
+        assert_eq!(lines.len, 0);
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut settings = Settings::new();
+        let mut out = String::<64>::new();
+        let cancel = Cancel::new();
+        assert!(block_on(execute(
+            b"echo hi",
+            &mut out,
+            &mut settings,
+            &Echo,
+            &cancel
+        )));
+        assert_eq!(out, "hi\n");
+
+        out.clear();
+        assert!(block_on(execute(
+            b"  ",
+            &mut out,
+            &mut settings,
+            &Echo,
+            &cancel
+        )));
+        assert_eq!(out, "");
+
+        out.clear();
+        let line = b"term color off";
+        assert!(block_on(execute(
+            line,
+            &mut out,
+            &mut settings,
+            &Echo,
+            &cancel
+        )));
+        assert!(!settings.color);
+
+        out.clear();
+        let line = [b'e'; 80];
+        assert!(!block_on(execute(
+            &line,
+            &mut out,
+            &mut settings,
+            &Echo,
+            &cancel
+        )));
+
+        assert_eq!(
+            strip_more(b"mem dump 0 4096 --more "),
+            (b"mem dump 0 4096 ".as_slice(), true)
+        );
+        assert_eq!(
+            strip_more(b"echo a--more"),
+            (b"echo a--more".as_slice(), false)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Minimal telnet (RFC 854) support for the CLI.
+//!
+//! [`Telnet::filter`] strips commands from received data and answers option
+//! negotiation. Only [`ECHO`] and [`SUPPRESS_GO_AHEAD`] are supported;
+//! everything else is refused. Once the peer turns out to speak telnet,
+//! character mode is requested: the peer sends every key press right away
+//! and leaves echoing and line editing to the server.
+//!
+//! Replies are only sent when an option changes state, so negotiation cannot loop.
+
+use heapless::Vec;
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+/// option: the sender echoes data it receives
+pub const ECHO: u8 = 1;
+/// option: the sender does not send go-aheads
+pub const SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// Telnet command filter for one connection.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Telnet {
+    state: State,
+    /// request character mode once the peer speaks telnet
+    character_mode: bool,
+    /// whether the peer has sent any command
+    peer_is_telnet: bool,
+    /// we echo
+    echo: bool,
+    /// we don't send go-aheads
+    local_sga: bool,
+    /// the peer doesn't send go-aheads
+    remote_sga: bool,
+    replies: Vec<u8, 32>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum State {
+    Data,
+    /// a CR has been held back
+    Cr,
+    /// a CR ended the previous data
+    CrEmitted,
+    Iac,
+    /// option negotiation verb received
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+impl Telnet {
+    /// `character_mode`: request character mode once the peer speaks telnet.
+    pub const fn new(character_mode: bool) -> Self {
+        Self {
+            state: State::Data,
+            character_mode,
+            peer_is_telnet: false,
+            echo: false,
+            local_sga: false,
+            remote_sga: false,
+            replies: Vec::new(),
+        }
+    }
+
+    /// Whether received data has to be echoed back to the peer.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Replies to send to the peer.
+    pub fn replies(&self) -> &[u8] {
+        &self.replies
+    }
+
+    pub fn clear_replies(&mut self) {
+        self.replies.clear();
+    }
+
+    /// Strip telnet commands from `data` in place and return the remaining data.
+    ///
+    /// CR LF and CR NUL are translated to `\n`,
+    /// or to `\r\n` if split across calls.
+    pub fn filter<'d>(&mut self, data: &'d mut [u8]) -> &'d mut [u8] {
+        let mut len = 0;
+        for i in 0..data.len() {
+            let byte = data[i];
+            if self.state == State::CrEmitted {
+                self.state = State::Data;
+                if matches!(byte, b'\n' | b'\0') {
+                    data[len] = b'\n';
+                    len += 1;
+                    continue;
+                }
+            }
+
+            // never overtakes `i`, as a held back CR has not been emitted
+            let mut emit = |byte| {
+                data[len] = byte;
+                len += 1;
+            };
+            self.state = match (self.state, byte) {
+                | (State::Data, IAC) => State::Iac,
+                | (State::Data, b'\r') => State::Cr,
+                | (State::Data, byte) => {
+                    emit(byte);
+                    State::Data
+                }
+                | (State::Cr, b'\n' | b'\0') => {
+                    emit(b'\n');
+                    State::Data
+                }
+                | (State::Cr, b'\r') => {
+                    emit(b'\r');
+                    State::Cr
+                }
+                | (State::Cr, IAC) => {
+                    emit(b'\r');
+                    State::Iac
+                }
+                | (State::Cr, byte) => {
+                    emit(b'\r');
+                    emit(byte);
+                    State::Data
+                }
+                | (State::Iac, IAC) => {
+                    emit(IAC);
+                    State::Data
+                }
+                | (State::Iac, verb @ (DO | DONT | WILL | WONT)) => {
+                    State::Negotiate(verb)
+                }
+                | (State::Iac, SB) => State::Subnegotiation,
+                // NOP, go-ahead, interrupt etc.
+                | (State::Iac, _) => State::Data,
+                | (State::Negotiate(verb), option) => {
+                    self.negotiate(verb, option);
+                    State::Data
+                }
+                | (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                | (State::Subnegotiation, _) => State::Subnegotiation,
+                | (State::SubnegotiationIac, SE) => State::Data,
+                | (State::SubnegotiationIac, _) => State::Subnegotiation,
+                | (State::CrEmitted, _) => unreachable!("handled above"),
+            };
+
+            if self.state == State::Iac && !self.peer_is_telnet {
+                self.peer_is_telnet = true;
+                if self.character_mode {
+                    self.request_character_mode();
+                }
+            }
+        }
+
+        // the CR held back occupies its own slot
+        if self.state == State::Cr {
+            data[len] = b'\r';
+            len += 1;
+            self.state = State::CrEmitted;
+        }
+        &mut data[..len]
+    }
+
+    /// Ask the peer to let us echo and to suppress go-aheads in both directions.
+    pub fn request_character_mode(&mut self) {
+        if !self.echo {
+            self.echo = true;
+            self.reply(WILL, ECHO);
+        }
+        if !self.local_sga {
+            self.local_sga = true;
+            self.reply(WILL, SUPPRESS_GO_AHEAD);
+        }
+        if !self.remote_sga {
+            self.remote_sga = true;
+            self.reply(DO, SUPPRESS_GO_AHEAD);
+        }
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        let (enabled, accept, refuse) = match (verb, option) {
+            | (DO | DONT, ECHO) => (&mut self.echo, WILL, WONT),
+            | (DO | DONT, SUPPRESS_GO_AHEAD) => (&mut self.local_sga, WILL, WONT),
+            | (WILL | WONT, SUPPRESS_GO_AHEAD) => (&mut self.remote_sga, DO, DONT),
+            | (DO, _) => return self.reply(WONT, option),
+            | (WILL, _) => return self.reply(DONT, option),
+            // unsupported options are never enabled
+            | _ => return,
+        };
+        let enable = matches!(verb, DO | WILL);
+        if *enabled != enable {
+            *enabled = enable;
+            self.reply(if enable { accept } else { refuse }, option);
+        }
+    }
+
+    fn reply(&mut self, verb: u8, option: u8) {
+        // dropping a reply only leaves the option disabled on the peer's side
+        let _ = self.replies.extend_from_slice(&[IAC, verb, option]);
+    }
+}
+
+impl Default for Telnet {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let mut telnet = Telnet::new(false);
+        let mut data = *b"ab\xff\xffc\r\n\xff\xfa\x18\x01\xff\xf0d\r\0e\r";
+        assert_eq!(telnet.filter(&mut data), b"ab\xffc\nd\ne\r");
+        let mut data = *b"\0\rx";
+        assert_eq!(telnet.filter(&mut data), b"\n\rx");
+        assert_eq!(telnet.replies(), b"");
+        assert!(!telnet.echo());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let mut telnet = Telnet::new(true);
+
+        // character mode is requested, terminal type refused
+        assert_eq!(telnet.filter(&mut [IAC, WILL, 24]), b"");
+        assert_eq!(
+            telnet.replies(),
+            [
+                IAC,
+                WILL,
+                ECHO,
+                IAC,
+                WILL,
+                SUPPRESS_GO_AHEAD,
+                IAC,
+                DO,
+                SUPPRESS_GO_AHEAD,
+                IAC,
+                DONT,
+                24
+            ]
+        );
+        assert!(telnet.echo());
+        telnet.clear_replies();
+
+        // acknowledgements are not answered
+        telnet.filter(&mut [IAC, DO, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD]);
+        assert_eq!(telnet.replies(), b"");
+
+        telnet.filter(&mut [IAC, DONT, ECHO, IAC, DONT, ECHO]);
+        assert_eq!(telnet.replies(), [IAC, WONT, ECHO]);
+        assert!(!telnet.echo());
+    }
+}
@@ -0,0 +1,133 @@
+//! Terminal output formatting for CLI sessions.
+//!
+//! Command output may contain ANSI escape sequences, e.g. from [`error`];
+//! sessions with color disabled strip them via [`Plain`] before sending.
+
+use core::fmt;
+use core::fmt::Display;
+use core::str;
+
+use heapless::String;
+
+use crate::audio::beeper;
+use crate::audio::beeper::Cue;
+
+pub const RED: &str = "\x1b[31m";
+pub const BOLD_GREEN: &str = "\x1b[1;32m";
+pub const REVERSE: &str = "\x1b[7m";
+pub const RESET: &str = "\x1b[0m";
+
+const ESC: char = '\x1b';
+
+/// Per-session terminal settings, changed with the `term` command.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq, Eq)]
+pub struct Settings {
+    pub color: bool,
+    pub prompt: String<{ Settings::MAX_PROMPT }>,
+    /// lines per page with `--more`
+    pub page: u8,
+}
+
+/// Iterator over the parts of a string outside of ANSI escape sequences.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Plain<'t> {
+    rest: &'t str,
+}
+
+impl Settings {
+    pub const MAX_PROMPT: usize = 16;
+
+    pub fn new() -> Self {
+        let mut prompt = String::new();
+        prompt.push_str("> ").expect("default prompt should fit");
+        Self {
+            color: true,
+            prompt,
+            page: 24,
+        }
+    }
+
+    /// Write the prompt, styled if color is enabled.
+    pub fn write_prompt(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        match self.color {
+            | true => write!(out, "{BOLD_GREEN}{}{RESET}", self.prompt),
+            | false => out.write_str(&self.prompt),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write an error message, marked as such, and [cue](beeper::cue) an error beep.
+pub fn error(out: &mut impl fmt::Write, message: impl Display) -> fmt::Result {
+    beeper::cue(Cue::Error);
+    writeln!(out, "{RED}{message}{RESET}")
+}
+
+impl<'t> Plain<'t> {
+    pub fn new(text: &'t str) -> Self {
+        Self { rest: text }
+    }
+}
+
+impl<'t> Iterator for Plain<'t> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            let Some(start) = self.rest.find(ESC) else {
+                return Some(core::mem::take(&mut self.rest));
+            };
+            let (plain, sequence) = self.rest.split_at(start);
+
+            // CSI sequences end with a byte in 0x40..=0x7E;
+            // anything else is a two-byte escape
+            let len = match sequence.as_bytes().get(1) {
+                | Some(b'[') => sequence.as_bytes()[2..]
+                    .iter()
+                    .position(|byte| (0x40..=0x7E).contains(byte))
+                    .map_or(sequence.len(), |end| end + 3),
+                | Some(_) => 2,
+                | None => 1,
+            };
+            // all bytes skipped are ASCII, so `len` lies on a char boundary
+            // unless the sequence is cut off by a multi-byte char
+            let len = (len..=sequence.len())
+                .find(|&len| sequence.is_char_boundary(len))
+                .unwrap_or(sequence.len());
+            self.rest = &sequence[len..];
+
+            if !plain.is_empty() {
+                return Some(plain);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        let mut plain = String::<64>::new();
+        let mut styled = String::<64>::new();
+        error(&mut styled, "no").unwrap();
+        styled.push_str("a\x1b[1;32mb\x1bc\x1b[").unwrap();
+        for part in Plain::new(&styled) {
+            plain.push_str(part).unwrap();
+        }
+        assert_eq!(plain, "no\nab");
+        assert_eq!(Plain::new("").next(), None);
+    }
+}
@@ -1,33 +1,91 @@
 use core::mem::forget;
+use core::ops;
 use core::range::RangeInclusive;
-use core::slice;
 
 use bitflags::bitflags;
 use embassy_stm32::gpio;
 use embassy_stm32::mode::Async;
+use embassy_stm32::pac;
+use embassy_stm32::qspi::enums::AddressSize;
+use embassy_stm32::qspi::enums::DummyCycles;
 use embassy_stm32::qspi::enums::QspiWidth;
 use embassy_stm32::qspi::Qspi;
 use embassy_stm32::qspi::{self};
 use embassy_stm32::time::Hertz;
 use embassy_stm32::Peripheral;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_time::Duration;
+use embassy_time::Instant;
 use embassy_time::Timer;
-use num_traits::float::FloatCore;
 
-macro_rules! cast_to_slice {
-    ($ref:expr) => {
-        slice::from_ref(bytemuck::cast_ref($ref))
-    };
-    (mut $ref:expr) => {
-        slice::from_mut(bytemuck::cast_mut($ref))
-    };
-}
+use crate::mem;
+use crate::mem::cache;
+use crate::mem::dma::DmaBuffer;
+use crate::storage::sfdp;
+use crate::storage::sfdp::Geometry;
+use crate::storage::ProtectError;
+use crate::storage::Storage;
+use crate::system::events;
+use crate::util::align::align_down;
+use crate::util::align::align_up;
+use crate::util::align::Blocks;
+use crate::util::lease::Lease;
+use crate::util::lease::LeaseStats;
+use crate::util::lease::Leased;
+use crate::util::until_with;
+use crate::util::Strategy;
 
 pub struct Device<'d, T: qspi::Instance> {
-    size: qspi::enums::MemorySize,
+    geometry: Geometry,
     spi: Qspi<'d, T, Async>,
+    /// bounce buffer for DMA, since callers' buffers may share cache lines
+    page: DmaBuffer<[u8; PAGE_SIZE]>,
+    /// erase running in the background, see [`Device::start_erase`]
+    erasing: Option<Erasing>,
+    /// when the background erase was last resumed
+    resumed: Instant,
+}
+
+/// The flash contents mapped into the address space, see [`Device::map`].
+///
+/// Borrows the device, so it cannot be erased or programmed while mapped.
+pub struct Mapped<'m, 'd, T: qspi::Instance> {
+    device: &'m mut Device<'d, T>,
+    /// the background erase to resume once unmapped
+    suspended: bool,
+}
+
+/// A [`Device`] shared between tasks, e.g. the file system, OTA and the CLI,
+/// each accessing it through a [`Handle`].
+pub struct Shared<'d, T: qspi::Instance> {
+    device: Leased<CriticalSectionRawMutex, Device<'d, T>>,
+    capacity: u32,
+}
+
+/// A task's access to [`Shared`] flash, implementing [`Storage`].
+///
+/// Leases the device per operation, so the tasks take turns;
+/// erases release it while running in the background, letting reads through.
+pub struct Handle<'s, 'd, T: qspi::Instance> {
+    shared: &'s Shared<'d, T>,
+    owner: &'static str,
 }
 
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+struct Erasing {
+    /// the blocks left after the one being erased
+    blocks: Blocks,
+    /// size of the block being erased
+    block: u32,
+}
+
+const PAGE_SIZE: usize = 256;
+/// where the flash appears in memory-mapped mode
+pub const MAPPED_BASE: usize = 0x9000_0000;
+/// `CCR.FMODE` of memory-mapped mode
+const FMODE_MEMORY_MAPPED: u8 = 0b11;
+
 #[derive(Debug)]
 #[derive(Copy, Clone)]
 #[derive(Eq, PartialEq)]
@@ -42,20 +100,51 @@ pub struct ExtendedPins<NWP = gpio::AnyPin, NRESET = gpio::AnyPin> {
 }
 
 impl<'d, T: qspi::Instance> Device<'d, T> {
+    /// minimum time CS stays high between commands, in nanoseconds,
+    /// which is below the resolution of [`Duration`]
     const CS_HIGH_TIME_NS: u64 = 30;
     const MAX_FREQ: Hertz = Hertz(60_000_000);
+    /// size of the secured OTP area
+    pub const OTP_LEN: u32 = 512;
+    /// minimum time between resuming and suspending an erase again,
+    /// for the erase to make progress at all
+    const RESUME_TO_SUSPEND: Duration = Duration::from_micros(100);
+    /// size of the blocks with individual write protection,
+    /// except the first and last one, which are protected per 4 KiB sector
+    const PROTECTION_BLOCK: u32 = 64 << 10;
+    /// polling for a page program or register write to finish
+    const WRITE_POLL: Strategy = Strategy::Backoff {
+        initial: Duration::from_micros(10),
+        max: Duration::from_micros(200),
+    };
+    /// polling for an erase to be suspended
+    const SUSPEND_POLL: Strategy = Strategy::Backoff {
+        initial: Duration::from_micros(5),
+        max: Duration::from_micros(50),
+    };
+    /// polling for a chip erase, which takes minutes
+    const CHIP_ERASE_POLL: Strategy = Strategy::Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+    };
 
-    pub const fn size(&self) -> qspi::enums::MemorySize {
-        self.size
+    pub const fn geometry(&self) -> &Geometry {
+        &self.geometry
     }
 
     pub fn size_in_bytes(&self) -> u32 {
-        1 << u8::from(self.size()) << 1
+        self.geometry.capacity
     }
 
+    /// Reset the flash and detect its [`Geometry`] via SFDP, unless one is given
+    /// for parts without or with a broken SFDP table.
+    ///
+    /// # Panics
+    /// Panics if no `geometry` is given and the flash has no usable SFDP table,
+    /// or if the flash has no 4 KiB erase, i.e., [`Storage::SECTOR_SIZE`].
     #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        size: qspi::enums::MemorySize,
+        geometry: Option<Geometry>,
         ahb_freq: Hertz,
         prescaler: u8,
         spi: impl Peripheral<P = T> + 'd,
@@ -103,62 +192,96 @@ impl<'d, T: qspi::Instance> Device<'d, T> {
         }
 
         let spi_cfg = qspi::Config {
-            memory_size: size,
+            // the whole address space until the geometry is known
+            memory_size: qspi::enums::MemorySize::Other(31),
             address_size: qspi::enums::AddressSize::_32bit,
             prescaler,
             fifo_threshold: qspi::enums::FIFOThresholdLevel::_1Bytes,
-            cs_high_time: match (Self::CS_HIGH_TIME_NS as f32 / 1e9 * spi_freq.0 as f32)
-                .ceil() as u32
-            {
-                | 0 | 1 => qspi::enums::ChipSelectHighTime::_1Cycle,
-                | 2 => qspi::enums::ChipSelectHighTime::_2Cycle,
-                | 3 => qspi::enums::ChipSelectHighTime::_3Cycle,
-                | 4 => qspi::enums::ChipSelectHighTime::_4Cycle,
-                | 5 => qspi::enums::ChipSelectHighTime::_5Cycle,
-                | 6 => qspi::enums::ChipSelectHighTime::_6Cycle,
-                | 7 => qspi::enums::ChipSelectHighTime::_7Cycle,
-                | 8 => qspi::enums::ChipSelectHighTime::_8Cycle,
-                | _ => panic!("spi frequency too high"),
-            },
+            cs_high_time: Self::cs_high_time(spi_freq),
         };
         let mut spi = qspi::Qspi::new_bank1(spi, d0, d1, d2, d3, sck, ncs, dma, spi_cfg);
 
-        // Self::wait_write_done(&mut spi, Duration::from_micros(10)).await;
+        // Self::wait_write_done(&mut spi, Self::WRITE_POLL).await;
 
         spi.command(transfer::rsten(Mode::Single));
         spi.command(transfer::rst(Mode::Single));
         Timer::after_millis(1200).await;
 
+        let geometry = match geometry {
+            | Some(geometry) => geometry,
+            | None => Self::read_geometry(&mut spi)
+                .await
+                .expect("flash without usable SFDP needs its geometry given"),
+        };
+        assert!(geometry.erase_sizes().any(|erase| erase.size == Self::SECTOR_SIZE));
+        // FSIZE + 1 address bits
+        let fsize = geometry.capacity.next_power_of_two().trailing_zeros() - 1;
+        pac::QUADSPI.dcr().modify(|v| v.set_fsize(fsize as u8));
+
         spi.command(transfer::en4b(Mode::Single));
         // spi.command(transfer::eqio());
 
-        let mut id = [0; 3];
-        spi.read_dma(&mut id, transfer::rdid()).await;
-
-        let mut sr = SR::empty();
-        spi.read_dma(cast_to_slice!(mut &mut sr), transfer::rdsr(Mode::Single)).await;
+        let _id: [u8; 3] = Self::read_register(&mut spi, transfer::rdid()).await;
+        let [sr] = Self::read_register(&mut spi, transfer::rdsr(Mode::Single)).await;
+        let _sr = SR::from_bits_retain(sr);
+        let [cr] = Self::read_register(&mut spi, transfer::rdcr(Mode::Single)).await;
+        let _cr = CR::from_bits_retain(cr);
 
-        let mut cr = CR::empty();
-        spi.read_dma(cast_to_slice!(mut &mut cr), transfer::rdcr(Mode::Single)).await;
+        Self {
+            geometry,
+            spi,
+            page: DmaBuffer::new([0; PAGE_SIZE]),
+            erasing: None,
+            resumed: Instant::MIN,
+        }
+    }
 
-        Self { size, spi }
+    /// Read the basic flash parameter table.
+    ///
+    /// Must run before switching to 4-byte addresses, as RDSFDP takes 3-byte ones.
+    async fn read_geometry(
+        spi: &mut Qspi<'d, T, Async>,
+    ) -> Result<Geometry, sfdp::Error> {
+        // RDSFDP wants 3 address bytes and 8 dummy cycles:
+        // the low byte of a 4-byte address provides the latter
+        let rdsfdp = |address: u32| transfer::rdsfdp(Mode::Single, address << 8);
+        let header = Self::read_register(spi, rdsfdp(0)).await;
+        let table = sfdp::basic_table(&header).ok_or(sfdp::Error::Missing)?;
+        let buf: [u8; sfdp::BASIC_TABLE_LEN] =
+            Self::read_register(spi, rdsfdp(table.start)).await;
+        let len = buf.len().min((table.end - table.start) as usize);
+        Geometry::parse(&buf[..len])
     }
 
     /// Read some data from flash.
     ///
+    /// Suspends a background erase for the duration of the read.
     /// Wraps on address or flash size overflow.
     pub async fn read(&mut self, data: &mut [u8], address: u32) {
-        self.spi
-            // .read_dma(data, transfer::qread(address, qspi::enums::DummyCycles::_8))
-            .read_dma(data, transfer::read(address))
-            .await
+        let suspended = self.suspend().await;
+        let mut address = address;
+        for chunk in data.chunks_mut(PAGE_SIZE) {
+            let page = &mut self.page;
+            page.invalidate();
+            self.spi
+                // .read_dma(data, transfer::qread(address, qspi::enums::DummyCycles::_8))
+                .read_dma(&mut page[..chunk.len()], transfer::read(address))
+                .await;
+            page.invalidate();
+            chunk.copy_from_slice(&page[..chunk.len()]);
+            address = address.wrapping_add(chunk.len() as u32);
+        }
+        if suspended {
+            self.resume();
+        }
     }
 
     /// Write some data to flash. Cannot Program 0s back to 1s.
     ///
     /// Wraps on address or flash size overflow.
     pub async fn program(&mut self, data: &[u8], address: u32) {
-        let chunk_size = 256;
+        self.finish_erase().await;
+        let chunk_size = PAGE_SIZE as u32;
 
         let (mut offset, _wrap) = align_up(address, chunk_size);
         let prefix_len = offset.wrapping_sub(address);
@@ -166,126 +289,557 @@ impl<'d, T: qspi::Instance> Device<'d, T> {
 
         if !prefix.is_empty() {
             self.spi.command(transfer::wren(Mode::Single));
-            self.spi.write_dma(prefix, transfer::pp(Mode::Single, address)).await;
-            Self::wait_write_done(&mut self.spi, Duration::from_micros(10)).await;
+            let page = Self::stage(&mut self.page, prefix);
+            self.spi.write_dma(page, transfer::pp(Mode::Single, address)).await;
+            Self::wait_write_done(&mut self.spi, Self::WRITE_POLL).await;
         }
 
         for section in data.chunks(chunk_size as usize) {
             self.spi.command(transfer::wren(Mode::Single));
-            self.spi.write_dma(section, transfer::pp(Mode::Single, offset)).await;
+            let page = Self::stage(&mut self.page, section);
+            self.spi.write_dma(page, transfer::pp(Mode::Single, offset)).await;
 
             offset = offset.overflowing_add(chunk_size).0;
 
-            Self::wait_write_done(&mut self.spi, Duration::from_micros(10)).await;
+            Self::wait_write_done(&mut self.spi, Self::WRITE_POLL).await;
         }
     }
 
     /// Erase some data from flash, i.e., change 0s back to 1s.
     ///
-    /// Wraps on address or flash size overflow.
-    ///
-    /// Erases aligned 4, 32 or 64-KiB blocks.
+    /// Erases aligned blocks of the sizes the [`Geometry`] lists.
     /// The actually erased range is fitted as closely as possible
     /// around the requested range and will always contain it entirely.
     /// Wraps on address or flash size overflow.
     pub async fn erase(&mut self, range: impl Into<RangeInclusive<u32>>) {
-        const ALIGN_4K: u32 = 4 << 10;
-        const ALIGN_32K: u32 = 32 << 10;
-        const ALIGN_64K: u32 = 64 << 10;
-
-        fn waste(pick: RangeInclusive<u32>, target: RangeInclusive<u32>) -> u32 {
-            if pick.is_empty()
-                || target.contains(&pick.start) && target.contains(&pick.end)
-            {
-                0
-            } else if target.contains(&pick.end) {
-                target.start - pick.start
-            } else if target.contains(&pick.start) {
-                pick.end - target.end
-            } else {
-                (pick.end - pick.start).saturating_add(1)
+        self.start_erase(range).await;
+        self.finish_erase().await;
+    }
+
+    /// Start erasing like [`Device::erase`], leaving the erase running in the background
+    /// while [reads](Device::read) suspend and resume it as needed.
+    ///
+    /// The erase advances through [`Device::poll_erase`];
+    /// any other operation finishes it first.
+    pub async fn start_erase(&mut self, range: impl Into<RangeInclusive<u32>>) {
+        self.finish_erase().await;
+        self.erase_next(Blocks::new(range.into()));
+    }
+
+    /// Advance the background erase, returning whether it is done.
+    pub async fn poll_erase(&mut self) -> bool {
+        let Some(erasing) = self.erasing else {
+            return true;
+        };
+        let [sr] = Self::read_register(&mut self.spi, transfer::rdsr(Mode::Single)).await;
+        if SR::from_bits_retain(sr).contains(SR::WIP) {
+            return false;
+        }
+        !self.erase_next(erasing.blocks)
+    }
+
+    /// Wait for the background erase, if any, to finish.
+    pub async fn finish_erase(&mut self) {
+        while !self.poll_erase().await {
+            if let Some(erasing) = self.erasing {
+                Timer::after(Self::erase_poll_interval(erasing.block)).await;
             }
         }
+    }
 
-        fn best_fit(addr: u32, target: RangeInclusive<u32>) -> u32 {
-            [ALIGN_4K, ALIGN_32K, ALIGN_64K]
-                .map(|a| (a, align_down(addr, a)..=align_up(addr, a).0.wrapping_sub(1)))
-                .map(|(a, pick)| (a, RangeInclusive::from(pick)))
-                .map(|(a, pick)| (a, waste(pick, target)))
-                .into_iter()
-                .min_by_key(|(_, waste)| *waste)
-                .expect("array is nonempty")
-                .0
+    /// Issue the erase of the next of `blocks`, returning whether there was one left.
+    fn erase_next(&mut self, mut blocks: Blocks) -> bool {
+        let mut sizes = [0; 4];
+        let mut len = 0;
+        for (size, erase) in sizes.iter_mut().zip(self.geometry.erase_sizes()) {
+            *size = erase.size;
+            len += 1;
         }
-        let range = range.into();
+        let Some((address, block)) = blocks.next_block(&sizes[..len]) else {
+            self.erasing = None;
+            return false;
+        };
+        let erase = self
+            .geometry
+            .erase_sizes()
+            .find(|erase| erase.size == block)
+            .expect("best fit is one of the erase sizes");
 
-        let mut wrapped = false;
-        let mut address = range.start;
+        self.spi.command(transfer::wren(Mode::Single));
+        self.spi.command(transfer::erase(Mode::Single, erase.instruction, address));
+        self.erasing = Some(Erasing { blocks, block });
+        true
+    }
 
-        while range.contains(&address) && !wrapped {
-            self.spi.command(transfer::wren(Mode::Single));
-            let align = best_fit(address.wrapping_add(1), range);
-            let (transfer, t_ms) = match align {
-                | ALIGN_4K => (transfer::se(Mode::Single, address), 20),
-                | ALIGN_32K => (transfer::be32k(Mode::Single, address), 100),
-                | ALIGN_64K => (transfer::be(Mode::Single, address), 200),
-                | _ => unreachable!(),
-            };
-            self.spi.command(transfer);
-            Self::wait_write_done(&mut self.spi, Duration::from_millis(t_ms)).await;
+    /// About 20 ms per 4 KiB sector, less for larger blocks.
+    fn erase_poll_interval(block: u32) -> Duration {
+        Duration::from_millis(20 * u64::from((block >> 12).isqrt().max(1)))
+    }
 
-            (address, wrapped) = align_up(address.wrapping_add(1), align);
+    /// Suspend the background erase, returning whether it has to be resumed.
+    async fn suspend(&mut self) -> bool {
+        if self.erasing.is_none() {
+            return false;
         }
+        Timer::at(self.resumed + Self::RESUME_TO_SUSPEND).await;
+        self.spi.command(transfer::pgm_ers_suspend(Mode::Single));
+        Self::wait_write_done(&mut self.spi, Self::SUSPEND_POLL).await;
+        // the block may have finished before the suspend took effect
+        self.security().await.contains(SCUR::ESB)
+    }
+
+    fn resume(&mut self) {
+        self.spi.command(transfer::pgm_ers_resume(Mode::Single));
+        self.resumed = Instant::now();
+    }
+
+    /// JEDEC manufacturer, memory type and capacity ID.
+    pub async fn id(&mut self) -> [u8; 3] {
+        self.finish_erase().await;
+        Self::read_register(&mut self.spi, transfer::rdid()).await
     }
 
     /// Erase all data from flash, i.e., change all 0s back to 1s.
     pub async fn erase_chip(&mut self) {
+        self.finish_erase().await;
         self.spi.command(transfer::wren(Mode::Single));
 
         self.spi.command(transfer::ce(Mode::Single));
-        Self::wait_write_done(&mut self.spi, Duration::from_secs(100)).await;
+        Self::wait_write_done(&mut self.spi, Self::CHIP_ERASE_POLL).await;
+    }
+
+    /// Security register, holding the OTP lock and the write protection mode.
+    pub async fn security(&mut self) -> SCUR {
+        let [scur] =
+            Self::read_register(&mut self.spi, transfer::rdscur(Mode::Single)).await;
+        SCUR::from_bits_retain(scur)
+    }
+
+    /// Write-protect the blocks overlapping `range` via their dynamic protection bits,
+    /// until unlocked or power cycled.
+    ///
+    /// Needs individual block protection, selected by the one-time [`SCUR::WPSEL`] bit,
+    /// otherwise fails with [`ProtectError::Unsupported`].
+    pub async fn lock_range(
+        &mut self,
+        range: impl Into<RangeInclusive<u32>>,
+    ) -> Result<(), ProtectError> {
+        self.protect(range.into(), true).await
+    }
+
+    /// Clear the dynamic protection bits of the blocks overlapping `range`.
+    ///
+    /// Fails with [`ProtectError::Failed`] for blocks locked by their persistent bits.
+    pub async fn unlock_range(
+        &mut self,
+        range: impl Into<RangeInclusive<u32>>,
+    ) -> Result<(), ProtectError> {
+        self.protect(range.into(), false).await
+    }
+
+    /// Whether the block holding `address` is write-protected by its
+    /// persistent or dynamic protection bit.
+    pub async fn is_locked(&mut self, address: u32) -> Result<bool, ProtectError> {
+        self.finish_erase().await;
+        if !self.security().await.contains(SCUR::WPSEL) {
+            return Err(ProtectError::Unsupported);
+        }
+        let [spb] = Self::read_register(&mut self.spi, transfer::rdspb(address)).await;
+        let [dpb] = Self::read_register(&mut self.spi, transfer::rddpb(address)).await;
+        Ok(spb != 0 || dpb != 0)
+    }
+
+    /// Read from the secured OTP area.
+    pub async fn read_otp(
+        &mut self,
+        data: &mut [u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        Self::check_otp(data.len(), offset)?;
+        self.finish_erase().await;
+        self.spi.command(transfer::enso(Mode::Single));
+        self.read(data, offset).await;
+        self.spi.command(transfer::exso(Mode::Single));
+        Ok(())
+    }
+
+    /// Program the secured OTP area, which cannot be erased.
+    pub async fn program_otp(
+        &mut self,
+        data: &[u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        Self::check_otp(data.len(), offset)?;
+        self.finish_erase().await;
+        if self.security().await.contains(SCUR::LDSO) {
+            return Err(ProtectError::OtpLocked);
+        }
+        self.spi.command(transfer::enso(Mode::Single));
+        self.program(data, offset).await;
+        self.spi.command(transfer::exso(Mode::Single));
+        match self.security().await.contains(SCUR::P_FAIL) {
+            | true => Err(ProtectError::Failed),
+            | false => Ok(()),
+        }
+    }
+
+    /// Lock the secured OTP area against programming. This cannot be undone.
+    pub async fn lock_otp(&mut self) -> Result<(), ProtectError> {
+        self.finish_erase().await;
+        self.spi.command(transfer::wren(Mode::Single));
+        self.spi.command(transfer::wrscur(Mode::Single));
+        Self::wait_write_done(&mut self.spi, Self::WRITE_POLL).await;
+        match self.security().await.contains(SCUR::LDSO) {
+            | true => Ok(()),
+            | false => Err(ProtectError::Failed),
+        }
     }
 
-    async fn wait_write_done(spi: &mut Qspi<'d, T, Async>, delay: Duration) {
-        let mut sr = SR::WIP;
+    /// Enter memory-mapped mode, e.g. to use assets in flash as DMA2D sources in place.
+    ///
+    /// Reads use single-line FAST_READ and go through the D-cache;
+    /// lines cached during earlier mappings are discarded first, since the flash
+    /// may have been rewritten since.
+    /// Dropping the [`Mapped`] returns to indirect mode.
+    /// A background erase is suspended until then.
+    pub async fn map(&mut self) -> Mapped<'_, 'd, T> {
+        let suspended = self.suspend().await;
+        cache::clean_invalidate();
+        pac::QUADSPI.ccr().write(|v| {
+            v.set_fmode(FMODE_MEMORY_MAPPED);
+            v.set_instruction(instruction::FAST_READ);
+            v.set_imode(QspiWidth::SING.into());
+            v.set_admode(QspiWidth::SING.into());
+            v.set_adsize(AddressSize::_32bit.into());
+            v.set_abmode(QspiWidth::NONE.into());
+            v.set_dcyc(DummyCycles::_8.into());
+            v.set_dmode(QspiWidth::SING.into());
+        });
+        mem::Gate::Qspi.set(true);
+        Mapped {
+            device: self,
+            suspended,
+        }
+    }
+
+    /// Copy `data` into the DMA page and write it back to memory.
+    fn stage<'p>(page: &'p mut DmaBuffer<[u8; PAGE_SIZE]>, data: &[u8]) -> &'p [u8] {
+        page[..data.len()].copy_from_slice(data);
+        page.clean();
+        &page[..data.len()]
+    }
+
+    fn check_otp(len: usize, offset: u32) -> Result<(), ProtectError> {
+        let end = u32::try_from(len).ok().and_then(|len| offset.checked_add(len));
+        match end {
+            | Some(end) if end <= Self::OTP_LEN => Ok(()),
+            | _ => Err(ProtectError::OutOfRange),
+        }
+    }
+
+    /// Size of the protection unit holding `address`.
+    fn protection_unit(&self, address: u32) -> u32 {
+        let last = self.size_in_bytes() - Self::PROTECTION_BLOCK;
+        match address < Self::PROTECTION_BLOCK || address >= last {
+            | true => Self::SECTOR_SIZE,
+            | false => Self::PROTECTION_BLOCK,
+        }
+    }
+
+    /// Set or clear the dynamic protection bits of `range` and read them back.
+    async fn protect(
+        &mut self,
+        range: RangeInclusive<u32>,
+        locked: bool,
+    ) -> Result<(), ProtectError> {
+        self.finish_erase().await;
+        if !self.security().await.contains(SCUR::WPSEL) {
+            return Err(ProtectError::Unsupported);
+        }
+        let range: ops::RangeInclusive<u32> = range.into();
+        let value = if locked { 0xFF } else { 0x00 };
+        let mut address =
+            align_down(*range.start(), self.protection_unit(*range.start()));
         loop {
-            spi.read_dma(
-                slice::from_mut(bytemuck::cast_mut(&mut sr)),
-                transfer::rdsr(Mode::Single),
-            )
-            .await;
-            if !sr.contains(SR::WIP) {
-                break;
+            self.spi.command(transfer::wren(Mode::Single));
+            let page = Self::stage(&mut self.page, &[value]);
+            self.spi.write_dma(page, transfer::wrdpb(address)).await;
+            Self::wait_write_done(&mut self.spi, Self::WRITE_POLL).await;
+            if self.is_locked(address).await? != locked {
+                return Err(ProtectError::Failed);
+            }
+
+            let (next, wrapped) =
+                align_up(address.wrapping_add(1), self.protection_unit(address));
+            if wrapped || next > *range.end() {
+                return Ok(());
             }
-            Timer::after(delay).await;
+            address = next;
+        }
+    }
+
+    /// [`CS_HIGH_TIME_NS`](Self::CS_HIGH_TIME_NS) in whole cycles of `spi_freq`.
+    fn cs_high_time(spi_freq: Hertz) -> qspi::enums::ChipSelectHighTime {
+        use qspi::enums::ChipSelectHighTime;
+
+        let cycles =
+            (Self::CS_HIGH_TIME_NS * u64::from(spi_freq.0)).div_ceil(1_000_000_000);
+        match cycles {
+            | 0 | 1 => ChipSelectHighTime::_1Cycle,
+            | 2 => ChipSelectHighTime::_2Cycle,
+            | 3 => ChipSelectHighTime::_3Cycle,
+            | 4 => ChipSelectHighTime::_4Cycle,
+            | 5 => ChipSelectHighTime::_5Cycle,
+            | 6 => ChipSelectHighTime::_6Cycle,
+            | 7 => ChipSelectHighTime::_7Cycle,
+            | 8 => ChipSelectHighTime::_8Cycle,
+            | _ => panic!("spi frequency too high"),
         }
     }
+
+    async fn read_register<const N: usize>(
+        spi: &mut Qspi<'d, T, Async>,
+        transfer: qspi::TransferConfig,
+    ) -> [u8; N] {
+        let mut register = DmaBuffer::new([0; N]);
+        register.invalidate();
+        spi.read_dma(&mut *register, transfer).await;
+        register.invalidate();
+        register.into_inner()
+    }
+
+    async fn wait_write_done(spi: &mut Qspi<'d, T, Async>, strategy: Strategy) {
+        let done = async || {
+            let [sr] = Self::read_register(spi, transfer::rdsr(Mode::Single)).await;
+            (!SR::from_bits_retain(sr).contains(SR::WIP)).then_some(())
+        };
+        until_with(done, strategy).await
+    }
 }
 
-/// Returns the aligned address alongside a `bool` indicating whether the result is wrapped.
-///
-/// `alignment` must be a power of two
-pub const fn align_up(address: u32, alignment: u32) -> (u32, bool) {
-    assert!(alignment.is_power_of_two());
-    if is_aligned_to(address, alignment) {
-        (address, false)
-    } else {
-        (address & !(alignment - 1)).overflowing_add(alignment)
+impl<T: qspi::Instance> Mapped<'_, '_, T> {
+    /// The whole flash, starting at [`MAPPED_BASE`].
+    pub fn as_slice(&self) -> &[u8] {
+        let len = self.device.size_in_bytes() as usize;
+        // Safety: the flash is mapped and cannot be written while `self` lives
+        unsafe { core::slice::from_raw_parts(MAPPED_BASE as *const u8, len) }
     }
 }
 
-/// `alignment` must be a power of two
-pub const fn align_down(address: u32, alignment: u32) -> u32 {
-    assert!(alignment.is_power_of_two());
-    address & !(alignment - 1)
+impl<T: qspi::Instance> Drop for Mapped<'_, '_, T> {
+    fn drop(&mut self) {
+        mem::Gate::Qspi.set(false);
+        // memory-mapped mode is only left by aborting it
+        let regs = pac::QUADSPI;
+        regs.cr().modify(|v| v.set_abort(true));
+        while regs.cr().read().abort() {}
+        while regs.sr().read().busy() {}
+        if self.suspended {
+            self.device.resume();
+        }
+    }
 }
 
-/// `alignment` must be a power of two
-pub const fn is_aligned_to(address: u32, alignment: u32) -> bool {
-    assert!(alignment.is_power_of_two());
-    address & (alignment - 1) == 0
+impl<T: qspi::Instance> Storage for Device<'_, T> {
+    const SECTOR_SIZE: u32 = 4 << 10;
+    const PAGE_SIZE: u32 = PAGE_SIZE as u32;
+
+    fn capacity(&self) -> u32 {
+        self.size_in_bytes()
+    }
+
+    async fn read(&mut self, data: &mut [u8], address: u32) {
+        Device::read(self, data, address).await
+    }
+
+    async fn program(&mut self, data: &[u8], address: u32) {
+        Device::program(self, data, address).await
+    }
+
+    async fn erase(&mut self, range: RangeInclusive<u32>) {
+        Device::erase(self, range).await
+    }
+
+    async fn jedec_id(&mut self) -> Option<[u8; 3]> {
+        Some(self.id().await)
+    }
+
+    async fn lock_range(
+        &mut self,
+        range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        Device::lock_range(self, range).await
+    }
+
+    async fn unlock_range(
+        &mut self,
+        range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        Device::unlock_range(self, range).await
+    }
+
+    async fn is_locked(&mut self, address: u32) -> Result<bool, ProtectError> {
+        Device::is_locked(self, address).await
+    }
+
+    fn otp_len(&self) -> u32 {
+        Self::OTP_LEN
+    }
+
+    async fn read_otp(
+        &mut self,
+        data: &mut [u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        Device::read_otp(self, data, offset).await
+    }
+
+    async fn program_otp(
+        &mut self,
+        data: &[u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        Device::program_otp(self, data, offset).await
+    }
+
+    async fn otp_locked(&mut self) -> Result<bool, ProtectError> {
+        Ok(self.security().await.contains(SCUR::LDSO))
+    }
+}
+
+impl<'d, T: qspi::Instance> Shared<'d, T> {
+    /// Leases held for longer than this are reported in the [stats](Shared::stats)
+    /// and as [`events::OVERLONG`],
+    /// e.g. a chip erase, or a block erase with many reads suspending it.
+    const THRESHOLD: Duration = Duration::from_millis(500);
+
+    pub fn new(device: Device<'d, T>) -> Self {
+        Self {
+            capacity: device.size_in_bytes(),
+            device: Leased::new("flash", Self::THRESHOLD, Some(events::overlong), device),
+        }
+    }
+
+    /// Access for `owner`, named in the lease statistics.
+    pub fn handle(&self, owner: &'static str) -> Handle<'_, 'd, T> {
+        Handle {
+            shared: self,
+            owner,
+        }
+    }
+
+    /// Exclusive access for operations spanning several steps, e.g. [`Device::map`].
+    pub async fn lock(
+        &self,
+        owner: &'static str,
+    ) -> Lease<'_, CriticalSectionRawMutex, Device<'d, T>> {
+        self.device.lease(owner).await
+    }
+
+    pub fn stats(&self) -> LeaseStats {
+        self.device.stats()
+    }
+
+    /// Count leases in the [stats](Shared::stats) as soon as they are held for too long,
+    /// see [`Leased::watch`].
+    pub async fn watch(&self, interval: Duration) -> ! {
+        self.device.watch(interval).await
+    }
+}
+
+impl<'d, T: qspi::Instance> Handle<'_, 'd, T> {
+    async fn lease(&self) -> Lease<'_, CriticalSectionRawMutex, Device<'d, T>> {
+        self.shared.device.lease(self.owner).await
+    }
 }
 
+impl<T: qspi::Instance> Clone for Handle<'_, '_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: qspi::Instance> Copy for Handle<'_, '_, T> {}
+
+impl<T: qspi::Instance> Storage for Handle<'_, '_, T> {
+    const SECTOR_SIZE: u32 = Device::<T>::SECTOR_SIZE;
+    const PAGE_SIZE: u32 = Device::<T>::PAGE_SIZE;
+
+    fn capacity(&self) -> u32 {
+        self.shared.capacity
+    }
+
+    async fn read(&mut self, data: &mut [u8], address: u32) {
+        self.lease().await.read(data, address).await
+    }
+
+    async fn program(&mut self, data: &[u8], address: u32) {
+        self.lease().await.program(data, address).await
+    }
+
+    async fn erase(&mut self, range: RangeInclusive<u32>) {
+        self.lease().await.start_erase(range).await;
+        loop {
+            let mut device = self.lease().await;
+            if device.poll_erase().await {
+                return;
+            }
+            let interval = device.erasing.map(|erasing| erasing.block);
+            drop(device);
+            if let Some(block) = interval {
+                Timer::after(Device::<T>::erase_poll_interval(block)).await;
+            }
+        }
+    }
+
+    async fn jedec_id(&mut self) -> Option<[u8; 3]> {
+        Some(self.lease().await.id().await)
+    }
+
+    async fn lock_range(
+        &mut self,
+        range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        self.lease().await.lock_range(range).await
+    }
+
+    async fn unlock_range(
+        &mut self,
+        range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        self.lease().await.unlock_range(range).await
+    }
+
+    async fn is_locked(&mut self, address: u32) -> Result<bool, ProtectError> {
+        self.lease().await.is_locked(address).await
+    }
+
+    fn otp_len(&self) -> u32 {
+        Device::<T>::OTP_LEN
+    }
+
+    async fn read_otp(
+        &mut self,
+        data: &mut [u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        self.lease().await.read_otp(data, offset).await
+    }
+
+    async fn program_otp(
+        &mut self,
+        data: &[u8],
+        offset: u32,
+    ) -> Result<(), ProtectError> {
+        self.lease().await.program_otp(data, offset).await
+    }
+
+    async fn otp_locked(&mut self) -> Result<bool, ProtectError> {
+        Ok(self.lease().await.security().await.contains(SCUR::LDSO))
+    }
+}
+
+#[allow(unused)]
 async fn reset<'d>(
     ncs: impl Peripheral<P = impl gpio::Pin> + 'd,
     nreset: impl Peripheral<P = impl gpio::Pin> + 'd,
@@ -352,13 +906,21 @@ bitflags! {
     #[repr(transparent)]
     #[derive(Debug, Copy, Clone, Eq, PartialEq)]
     #[derive(bytemuck::Pod, bytemuck::Zeroable)]
+    /// security register
     pub struct SCUR: u8 {
+        /// secured OTP area programmed at the factory
         const OTP    = 1 << 0;
+        /// secured OTP area locked
         const LDSO   = 1 << 1;
+        /// program suspended
         const PSB    = 1 << 2;
+        /// erase suspended
         const ESB    = 1 << 3;
+        /// last program failed
         const P_FAIL = 1 << 5;
+        /// last erase failed
         const E_FAIL = 1 << 6;
+        /// individual block write protection
         const WPSEL  = 1 << 7;
         const _       = !0;
     }
@@ -740,6 +1302,17 @@ pub mod transfer {
         }
     }
 
+    /// An erase of the size `instruction` erases, e.g. from the SFDP geometry.
+    pub fn erase(mode: Mode, instruction: u8, address: u32) -> TransferConfig {
+        TransferConfig {
+            instruction,
+            address: Some(address),
+            iwidth: mode.into(),
+            awidth: mode.into(),
+            ..Default::default()
+        }
+    }
+
     pub fn se(mode: Mode, address: u32) -> TransferConfig {
         TransferConfig {
             instruction: instruction::SE,
@@ -918,7 +1491,7 @@ pub mod transfer {
 
     pub fn wrdpb(address: u32) -> TransferConfig {
         TransferConfig {
-            instruction: instruction::WRSPB,
+            instruction: instruction::WRDPB,
             address: Some(address),
             iwidth: Mode::Single.into(),
             awidth: Mode::Single.into(),
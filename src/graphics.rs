@@ -0,0 +1,2 @@
+pub mod qoi;
+pub mod testcard;
@@ -0,0 +1,355 @@
+//! Streaming decoder for the [QOI image format](https://qoiformat.org/qoi-specification.pdf).
+//!
+//! Decodes straight from a byte slice (e.g., memory-mapped QSPI flash)
+//! without any intermediate buffers; the decoded pixels are produced by an iterator
+//! and can be fed directly into a framebuffer.
+
+use core::error::Error as CoreError;
+use core::fmt::Display;
+
+const MAGIC: [u8; 4] = *b"qoif";
+const HEADER_LEN: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0b0000_0000;
+const OP_DIFF: u8 = 0b0100_0000;
+const OP_LUMA: u8 = 0b1000_0000;
+const OP_RUN: u8 = 0b1100_0000;
+const OP_RGB: u8 = 0b1111_1110;
+const OP_RGBA: u8 = 0b1111_1111;
+const OP_MASK: u8 = 0b1100_0000;
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub channels: Channels,
+    pub colorspace: Colorspace,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Channels {
+    Rgb,
+    Rgba,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Colorspace {
+    /// sRGB with linear alpha
+    Srgb,
+    /// all channels linear
+    Linear,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the input is shorter than a QOI header
+    Truncated,
+    /// the input does not start with the QOI magic
+    BadMagic,
+    BadChannels(u8),
+    BadColorspace(u8),
+    /// `width * height` does not fit into a `usize`
+    TooLarge,
+}
+
+/// Iterator over the pixels of a QOI image, in row-major order.
+///
+/// Stops early if the input ends before all pixels have been decoded;
+/// use [`Pixels::remaining`] to detect truncated images.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Pixels<'a> {
+    data: &'a [u8],
+    index: [Rgba; 64],
+    px: Rgba,
+    run: u8,
+    remaining: usize,
+}
+
+/// Parse the header of a QOI image and return an iterator over its pixels.
+pub fn decode(data: &[u8]) -> Result<(Header, Pixels<'_>), Error> {
+    let header = Header::parse(data)?;
+    let pixels = usize::try_from(header.width)
+        .ok()
+        .zip(usize::try_from(header.height).ok())
+        .and_then(|(width, height)| width.checked_mul(height))
+        .ok_or(Error::TooLarge)?;
+
+    let data = &data[HEADER_LEN..];
+    let data = data.strip_suffix(END_MARKER.as_slice()).unwrap_or(data);
+
+    Ok((
+        header,
+        Pixels {
+            data,
+            index: [Rgba::default(); 64],
+            px: Rgba {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255,
+            },
+            run: 0,
+            remaining: pixels,
+        },
+    ))
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let header: &[u8; HEADER_LEN] = data
+            .get(..HEADER_LEN)
+            .and_then(|header| header.try_into().ok())
+            .ok_or(Error::Truncated)?;
+
+        if header[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let channels = match header[12] {
+            | 3 => Channels::Rgb,
+            | 4 => Channels::Rgba,
+            | other => return Err(Error::BadChannels(other)),
+        };
+        let colorspace = match header[13] {
+            | 0 => Colorspace::Srgb,
+            | 1 => Colorspace::Linear,
+            | other => return Err(Error::BadColorspace(other)),
+        };
+
+        Ok(Self {
+            width,
+            height,
+            channels,
+            colorspace,
+        })
+    }
+}
+
+impl Rgba {
+    pub const fn to_argb8888(self) -> u32 {
+        u32::from_be_bytes([self.a, self.r, self.g, self.b])
+    }
+
+    const fn hash(self) -> usize {
+        (self.r as usize * 3
+            + self.g as usize * 5
+            + self.b as usize * 7
+            + self.a as usize * 11)
+            % 64
+    }
+}
+
+impl Pixels<'_> {
+    /// Number of pixels not yet produced by this iterator.
+    pub const fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (chunk, tail) = self.data.split_first_chunk::<N>()?;
+        self.data = tail;
+        Some(*chunk)
+    }
+
+    fn next_px(&mut self) -> Option<Rgba> {
+        let [tag] = self.take()?;
+        let px = self.px;
+        let px = match tag {
+            | OP_RGB => {
+                let [r, g, b] = self.take()?;
+                Rgba { r, g, b, a: px.a }
+            }
+            | OP_RGBA => {
+                let [r, g, b, a] = self.take()?;
+                Rgba { r, g, b, a }
+            }
+            | _ => match tag & OP_MASK {
+                | OP_INDEX => self.index[usize::from(tag & !OP_MASK)],
+                | OP_DIFF => Rgba {
+                    r: px.r.wrapping_add((tag >> 4) & 0b11).wrapping_sub(2),
+                    g: px.g.wrapping_add((tag >> 2) & 0b11).wrapping_sub(2),
+                    b: px.b.wrapping_add(tag & 0b11).wrapping_sub(2),
+                    a: px.a,
+                },
+                | OP_LUMA => {
+                    let [drb] = self.take()?;
+                    let dg = (tag & !OP_MASK).wrapping_sub(32);
+                    Rgba {
+                        r: px.r.wrapping_add(dg).wrapping_add(drb >> 4).wrapping_sub(8),
+                        g: px.g.wrapping_add(dg),
+                        b: px
+                            .b
+                            .wrapping_add(dg)
+                            .wrapping_add(drb & 0b1111)
+                            .wrapping_sub(8),
+                        a: px.a,
+                    }
+                }
+                | OP_RUN => {
+                    self.run = tag & !OP_MASK;
+                    px
+                }
+                | _ => unreachable!("all two-bit tags are covered"),
+            },
+        };
+
+        self.index[px.hash()] = px;
+        self.px = px;
+        Some(px)
+    }
+}
+
+impl Iterator for Pixels<'_> {
+    type Item = Rgba;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let px = if self.run > 0 {
+            self.run -= 1;
+            self.px
+        } else {
+            let Some(px) = self.next_px() else {
+                self.data = &[];
+                return None;
+            };
+            px
+        };
+
+        self.remaining -= 1;
+        Some(px)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            | Error::Truncated => write!(f, "QOI header truncated"),
+            | Error::BadMagic => write!(f, "not a QOI image"),
+            | Error::BadChannels(channels) => {
+                write!(f, "bad QOI channel count {channels}")
+            }
+            | Error::BadColorspace(colorspace) => {
+                write!(f, "bad QOI colorspace {colorspace}")
+            }
+            | Error::TooLarge => write!(f, "QOI image too large"),
+        }
+    }
+}
+
+impl CoreError for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, chunks: &[u8], out: &mut [u8]) -> usize {
+        let mut len = 0;
+        for part in [
+            MAGIC.as_slice(),
+            &width.to_be_bytes(),
+            &height.to_be_bytes(),
+            &[4, 0],
+            chunks,
+            &END_MARKER,
+        ] {
+            out[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        len
+    }
+
+    const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Rgba {
+        Rgba { r, g, b, a }
+    }
+
+    #[test]
+    fn test_header() {
+        let mut buf = [0; 64];
+        let len = image(3, 2, &[], &mut buf);
+        let (header, _) = decode(&buf[..len]).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                width: 3,
+                height: 2,
+                channels: Channels::Rgba,
+                colorspace: Colorspace::Srgb,
+            }
+        );
+
+        assert_eq!(
+            decode(&buf[..HEADER_LEN - 1]).unwrap_err(),
+            Error::Truncated
+        );
+        buf[0] = b'Q';
+        assert_eq!(decode(&buf[..len]).unwrap_err(), Error::BadMagic);
+    }
+
+    #[test]
+    fn test_ops() {
+        #[rustfmt::skip]
+        let chunks = [
+            // rgb
+            OP_RGB, 10, 20, 30,
+            // diff: r - 1, g + 0, b + 1
+            OP_DIFF | 0b01_10_11,
+            // luma: dg = +5, dr - dg = -2, db - dg = +3
+            OP_LUMA | (5 + 32), (6 << 4) | 11,
+            // run of 2
+            OP_RUN | 1,
+            // rgba
+            OP_RGBA, 1, 2, 3, 4,
+            // index of the rgb pixel
+            OP_INDEX | rgba(10, 20, 30, 255).hash() as u8,
+        ];
+        let mut buf = [0; 64];
+        let len = image(8, 1, &chunks, &mut buf);
+        let (_, mut pixels) = decode(&buf[..len]).unwrap();
+
+        assert_eq!(pixels.next(), Some(rgba(10, 20, 30, 255)));
+        assert_eq!(pixels.next(), Some(rgba(9, 20, 31, 255)));
+        assert_eq!(pixels.next(), Some(rgba(12, 25, 39, 255)));
+        assert_eq!(pixels.next(), Some(rgba(12, 25, 39, 255)));
+        assert_eq!(pixels.next(), Some(rgba(12, 25, 39, 255)));
+        assert_eq!(pixels.next(), Some(rgba(1, 2, 3, 4)));
+        assert_eq!(pixels.next(), Some(rgba(10, 20, 30, 255)));
+        assert_eq!(pixels.remaining(), 1);
+        assert_eq!(pixels.next(), None);
+        assert_eq!(pixels.remaining(), 1);
+    }
+
+    #[test]
+    fn test_argb8888() {
+        assert_eq!(rgba(0x12, 0x34, 0x56, 0x78).to_argb8888(), 0x78123456);
+    }
+}
@@ -0,0 +1,286 @@
+//! Test cards drawn straight into a framebuffer.
+//!
+//! The patterns are simple enough to tell at a glance whether edges are cropped,
+//! color channels swapped or lines skipped, which separates panel and timing
+//! issues from bugs in whatever draws the application.
+//! [`Layer::ltdc`] finds the framebuffer of an enabled LTDC layer from its registers,
+//! so the cards can be drawn no matter what set up the display.
+
+use core::fmt;
+use core::fmt::Display;
+
+use super::qoi::Rgba;
+use crate::mem;
+use crate::mem::regs;
+
+const WHITE: Rgba = rgb(0xFF, 0xFF, 0xFF);
+const BLACK: Rgba = rgb(0x00, 0x00, 0x00);
+/// white, yellow, cyan, green, magenta, red, blue, black
+const BARS: [Rgba; 8] = [
+    WHITE,
+    rgb(0xFF, 0xFF, 0x00),
+    rgb(0x00, 0xFF, 0xFF),
+    rgb(0x00, 0xFF, 0x00),
+    rgb(0xFF, 0x00, 0xFF),
+    rgb(0xFF, 0x00, 0x00),
+    rgb(0x00, 0x00, 0xFF),
+    BLACK,
+];
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Pattern {
+    /// eight vertical bars of full intensity colors, see [`BARS`]
+    Bars,
+    /// horizontal ramps of red, green, blue and gray, stacked top to bottom
+    Gradient,
+    /// black and white squares of `cell` pixels
+    Checkerboard { cell: u16 },
+    /// red, green, blue and black quadrants in reading order,
+    /// framed by a one pixel white border
+    PixelOrder,
+}
+
+/// Pixel formats of LTDC layers, except those using a color lookup table.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Format {
+    Argb8888,
+    Rgb888,
+    Rgb565,
+    Argb1555,
+    Argb4444,
+}
+
+/// A framebuffer of `height` lines `pitch` bytes apart.
+#[derive(Debug)]
+pub struct Layer<'a> {
+    pub buf: &'a mut [u8],
+    pub width: usize,
+    pub height: usize,
+    /// bytes
+    pub pitch: usize,
+    pub format: Format,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the layer is not enabled
+    Disabled,
+    /// the pixel format (LxPFCR) is not supported
+    Format(u32),
+    /// the framebuffer is not in writable memory
+    Memory(mem::Error),
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> Rgba {
+    Rgba { r, g, b, a: 0xFF }
+}
+
+impl Pattern {
+    /// The color of pixel `(x, y)` on a `width` by `height` screen.
+    pub fn color(self, x: usize, y: usize, width: usize, height: usize) -> Rgba {
+        match self {
+            | Pattern::Bars => BARS[x * BARS.len() / width],
+            | Pattern::Gradient => {
+                let level = (x * 0xFF / (width - 1).max(1)) as u8;
+                match y * 4 / height {
+                    | 0 => rgb(level, 0, 0),
+                    | 1 => rgb(0, level, 0),
+                    | 2 => rgb(0, 0, level),
+                    | _ => rgb(level, level, level),
+                }
+            }
+            | Pattern::Checkerboard { cell } => {
+                let cell = usize::from(cell.max(1));
+                match (x / cell + y / cell) % 2 {
+                    | 0 => WHITE,
+                    | _ => BLACK,
+                }
+            }
+            | Pattern::PixelOrder => {
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    return WHITE;
+                }
+                match (x < width / 2, y < height / 2) {
+                    | (true, true) => rgb(0xFF, 0, 0),
+                    | (false, true) => rgb(0, 0xFF, 0),
+                    | (true, false) => rgb(0, 0, 0xFF),
+                    | (false, false) => BLACK,
+                }
+            }
+        }
+    }
+}
+
+impl Format {
+    /// The format encoded in the PF field of LxPFCR.
+    pub fn from_ltdc(pf: u32) -> Option<Self> {
+        match pf {
+            | 0 => Some(Format::Argb8888),
+            | 1 => Some(Format::Rgb888),
+            | 2 => Some(Format::Rgb565),
+            | 3 => Some(Format::Argb1555),
+            | 4 => Some(Format::Argb4444),
+            | _ => None,
+        }
+    }
+
+    pub const fn bytes(self) -> usize {
+        match self {
+            | Format::Argb8888 => 4,
+            | Format::Rgb888 => 3,
+            | Format::Rgb565 | Format::Argb1555 | Format::Argb4444 => 2,
+        }
+    }
+
+    /// Write `color` as the [`bytes`](Self::bytes) of one pixel to `out`.
+    pub fn encode(self, color: Rgba, out: &mut [u8]) {
+        let [r, g, b, a] = [color.r, color.g, color.b, color.a].map(u16::from);
+        match self {
+            | Format::Argb8888 => {
+                out.copy_from_slice(&[color.b, color.g, color.r, color.a])
+            }
+            | Format::Rgb888 => out.copy_from_slice(&[color.b, color.g, color.r]),
+            | Format::Rgb565 => {
+                let pixel = (r >> 3) << 11 | (g >> 2) << 5 | b >> 3;
+                out.copy_from_slice(&pixel.to_le_bytes());
+            }
+            | Format::Argb1555 => {
+                let pixel = (a >> 7) << 15 | (r >> 3) << 10 | (g >> 3) << 5 | b >> 3;
+                out.copy_from_slice(&pixel.to_le_bytes());
+            }
+            | Format::Argb4444 => {
+                let pixel = (a >> 4) << 12 | (r >> 4) << 8 | (g >> 4) << 4 | b >> 4;
+                out.copy_from_slice(&pixel.to_le_bytes());
+            }
+        }
+    }
+}
+
+impl Layer<'static> {
+    /// The framebuffer of LTDC layer `layer`, 1 or 2, as currently configured.
+    ///
+    /// # Panics
+    /// Panics if `layer` is neither 1 nor 2.
+    pub fn ltdc(layer: u8) -> Result<Self, Error> {
+        assert!(matches!(layer, 1 | 2), "the LTDC has two layers");
+        // LxCR of the layer, the other registers relative to it
+        let base = regs::LTDC.base + 0x84 + 0x80 * u32::from(layer - 1);
+        let read = |offset: u32| {
+            let mut value = [0; 4];
+            mem::read(base + offset, &mut value, true).map_err(Error::Memory)?;
+            Ok(u32::from_le_bytes(value))
+        };
+
+        let enabled = read(0x00)? & 1 != 0;
+        let address = read(0x28)?;
+        if !enabled || address == 0 {
+            return Err(Error::Disabled);
+        }
+        let pf = read(0x10)? & 0b111;
+        let format = Format::from_ltdc(pf).ok_or(Error::Format(pf))?;
+        let cfblr = read(0x2C)?;
+        let pitch = (cfblr >> 16 & 0x1FFF) as usize;
+        // the line length is programmed 3 bytes longer
+        let line = ((cfblr & 0x1FFF) as usize).saturating_sub(3);
+        let height = (read(0x30)? & 0x7FF) as usize;
+        let len = (pitch * height.saturating_sub(1) + line) as u32;
+
+        mem::check(address, len, true, false).map_err(Error::Memory)?;
+        // Safety: the range lies within a writable memory region.
+        // The framebuffer is shared with the LTDC, which only reads it;
+        // overwriting what is shown is what the caller asked for.
+        let buf =
+            unsafe { core::slice::from_raw_parts_mut(address as *mut u8, len as usize) };
+        Ok(Self {
+            buf,
+            width: line / format.bytes(),
+            height,
+            pitch,
+            format,
+        })
+    }
+}
+
+impl Layer<'_> {
+    /// Fill the framebuffer with `pattern`.
+    ///
+    /// # Panics
+    /// Panics if the buffer is too short for the lines described.
+    pub fn draw(&mut self, pattern: Pattern) {
+        let bytes = self.format.bytes();
+        for y in 0..self.height {
+            let line = &mut self.buf[y * self.pitch..][..self.width * bytes];
+            for (x, pixel) in line.chunks_exact_mut(bytes).enumerate() {
+                let color = pattern.color(x, y, self.width, self.height);
+                self.format.encode(color, pixel);
+            }
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Disabled => write!(f, "layer is not enabled"),
+            | Error::Format(pf) => write!(f, "unsupported pixel format {pf}"),
+            | Error::Memory(e) => write!(f, "framebuffer: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let mut buf = [0xAA; 8 * 4 * 2];
+        let mut layer = Layer {
+            buf: &mut buf,
+            width: 4,
+            height: 4,
+            pitch: 8 * 2,
+            format: Format::Rgb565,
+        };
+        layer.draw(Pattern::PixelOrder);
+        let pixel = |x: usize, y: usize| {
+            u16::from_le_bytes([buf[y * 16 + x * 2], buf[y * 16 + x * 2 + 1]])
+        };
+        assert_eq!(pixel(0, 0), 0xFFFF);
+        assert_eq!(pixel(1, 1), 0xF800);
+        assert_eq!(pixel(2, 1), 0x07E0);
+        assert_eq!(pixel(1, 2), 0x001F);
+        assert_eq!(pixel(2, 2), 0x0000);
+        // beyond the line length up to the pitch
+        assert_eq!(pixel(4, 0), 0xAAAA);
+    }
+
+    #[test]
+    fn test_color() {
+        let bars = |x| Pattern::Bars.color(x, 0, 800, 480);
+        assert_eq!(
+            [bars(0), bars(99), bars(100), bars(799)],
+            [WHITE, WHITE, BARS[1], BLACK]
+        );
+        let gradient = |x, y| Pattern::Gradient.color(x, y, 800, 480);
+        assert_eq!(gradient(0, 0), BLACK);
+        assert_eq!(gradient(799, 0), rgb(0xFF, 0, 0));
+        assert_eq!(gradient(799, 479), WHITE);
+        let checkerboard = Pattern::Checkerboard { cell: 8 };
+        assert_eq!(checkerboard.color(7, 7, 800, 480), WHITE);
+        assert_eq!(checkerboard.color(8, 7, 800, 480), BLACK);
+        assert_eq!(checkerboard.color(8, 8, 800, 480), WHITE);
+
+        let mut pixel = [0; 4];
+        Format::Argb8888.encode(rgb(0x12, 0x34, 0x56), &mut pixel);
+        assert_eq!(pixel, [0x56, 0x34, 0x12, 0xFF]);
+    }
+}
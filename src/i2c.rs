@@ -0,0 +1,137 @@
+//! I2C bus probing and register access.
+//!
+//! On the STM32F769I-DISCO, I2C4 (SCL = PD12, SDA = PB7) connects the on-board
+//! peripherals ([`BOARD_DEVICES`]), while I2C1 (SCL = PB8, SDA = PB9)
+//! is routed to the Arduino connector.
+
+use core::ops::RangeInclusive;
+
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::i2c::Operation;
+
+/// non-reserved 7-bit addresses
+pub const ADDRESSES: RangeInclusive<u8> = 0x08..=0x77;
+
+/// Known devices on the on-board bus.
+pub const BOARD_DEVICES: &[(u8, &str)] = &[
+    (0x1A, "WM8994 audio codec"),
+    (0x2A, "FT6206 touch controller"),
+];
+
+/// Probe all [`ADDRESSES`] by reading a single byte.
+///
+/// Returns a bitmap with bit `n` set if a device acknowledged address `n`.
+pub async fn scan<B: I2c>(bus: &mut B) -> u128 {
+    let mut found = 0;
+    for address in ADDRESSES {
+        if bus.read(address, &mut [0]).await.is_ok() {
+            found |= 1 << address;
+        }
+    }
+    found
+}
+
+/// Read consecutive registers, starting at `register`.
+pub async fn read_register<B: I2c>(
+    bus: &mut B,
+    address: u8,
+    register: u8,
+    buf: &mut [u8],
+) -> Result<(), B::Error> {
+    bus.write_read(address, &[register], buf).await
+}
+
+/// Write consecutive registers, starting at `register`.
+pub async fn write_register<B: I2c>(
+    bus: &mut B,
+    address: u8,
+    register: u8,
+    data: &[u8],
+) -> Result<(), B::Error> {
+    // adjacent writes are sent without a repeated start
+    bus.transaction(
+        address,
+        &mut [Operation::Write(&[register]), Operation::Write(data)],
+    )
+    .await
+}
+
+/// Name of a known on-board device.
+pub fn board_device(address: u8) -> Option<&'static str> {
+    BOARD_DEVICES.iter().find_map(|&(known, name)| (known == address).then_some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal_async::i2c::ErrorKind;
+    use embedded_hal_async::i2c::ErrorType;
+    use embedded_hal_async::i2c::NoAcknowledgeSource;
+
+    use super::*;
+
+    /// A single device with an auto-incrementing register pointer.
+    struct Device {
+        address: u8,
+        registers: [u8; 16],
+    }
+
+    impl ErrorType for Device {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Device {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if address != self.address {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+            }
+            let mut pointer = None;
+            for operation in operations {
+                match operation {
+                    | Operation::Write(data) => {
+                        for &byte in data.iter() {
+                            match pointer {
+                                | None => pointer = Some(byte as usize),
+                                | Some(ref mut register) => {
+                                    self.registers[*register] = byte;
+                                    *register += 1;
+                                }
+                            }
+                        }
+                    }
+                    | Operation::Read(buf) => {
+                        let register = pointer.get_or_insert(0);
+                        for byte in buf.iter_mut() {
+                            *byte = self.registers[*register];
+                            *register += 1;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut device = Device {
+            address: 0x2A,
+            registers: [0; 16],
+        };
+        block_on(async {
+            assert_eq!(scan(&mut device).await, 1 << 0x2A);
+
+            write_register(&mut device, 0x2A, 4, &[1, 2, 3]).await.unwrap();
+            let mut buf = [0; 4];
+            read_register(&mut device, 0x2A, 3, &mut buf).await.unwrap();
+            assert_eq!(buf, [0, 1, 2, 3]);
+
+            assert!(read_register(&mut device, 0x1A, 0, &mut buf).await.is_err());
+        });
+        assert_eq!(board_device(0x2A), Some("FT6206 touch controller"));
+    }
+}
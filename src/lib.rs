@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(new_range_api)]
+#![feature(async_closure)]
 #![allow(clippy::manual_range_patterns)]
 #![allow(internal_features)]
 #![feature(core_intrinsics)]
@@ -8,9 +9,27 @@
 
 #[cfg(any())]
 pub mod bitbang;
-#[cfg(any())]
+#[cfg(feature = "cross")]
+pub mod board;
+#[cfg(feature = "cross")]
 pub mod flash;
 #[cfg(feature = "cross")]
 pub mod tftp;
 
+pub mod adc;
+pub mod assets;
+pub mod audio;
+pub mod boot;
 pub mod cli;
+pub mod graphics;
+pub mod i2c;
+pub mod mem;
+pub mod net;
+pub mod ota;
+pub mod power;
+pub mod rng;
+pub mod rtc;
+pub mod status_led;
+pub mod storage;
+pub mod system;
+pub mod util;
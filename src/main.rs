@@ -4,54 +4,219 @@
 #![feature(async_closure)]
 #![feature(core_intrinsics)]
 #![feature(layout_for_ptr)]
+#![feature(sync_unsafe_cell)]
 #![allow(internal_features)]
 #![allow(unused)]
 use core::array;
+use core::cell::SyncUnsafeCell;
 use core::fmt::Write as FmtWrite;
 #[allow(unused)]
 use core::intrinsics::breakpoint;
-use core::mem::MaybeUninit;
 use core::str::FromStr;
 
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
-use embassy_futures::yield_now;
-use embassy_stm32::bind_interrupts;
+use embassy_futures::join::join3;
+use embassy_futures::join::join5;
+use embassy_sandbox::adc;
+use embassy_sandbox::board;
+use embassy_sandbox::board::Board;
+use embassy_sandbox::boot;
+use embassy_sandbox::cli;
+use embassy_sandbox::cli::server;
+use embassy_sandbox::cli::Command;
+use embassy_sandbox::cli::NetState;
+use embassy_sandbox::flash;
+use embassy_sandbox::mem::backup;
+use embassy_sandbox::mem::cache;
+use embassy_sandbox::mem::mpu;
+use embassy_sandbox::net::arp;
+use embassy_sandbox::net::dhcp;
+use embassy_sandbox::net::dns;
+use embassy_sandbox::net::http;
+use embassy_sandbox::net::mqtt;
+use embassy_sandbox::net::sntp;
+use embassy_sandbox::net::stats;
+use embassy_sandbox::net::tap;
+use embassy_sandbox::power;
+use embassy_sandbox::rng;
+use embassy_sandbox::rtc;
+use embassy_sandbox::status_led;
+use embassy_sandbox::system::events;
+use embassy_sandbox::system::panic;
+use embassy_sandbox::system::supervisor;
+use embassy_sandbox::system::supervisor::Policy;
+use embassy_sandbox::tftp;
+use embassy_sandbox::tftp::server::Access;
+use embassy_sandbox::tftp::server::Entry;
+use embassy_sandbox::tftp::server::Region;
+use embassy_sandbox::tftp::server::Regions;
+use embassy_sandbox::util;
+use embassy_sandbox::util::hash::Crc32;
+use embassy_sandbox::util::lease::Leased;
+use embassy_sandbox::util::profile;
+use embassy_sandbox::util::Cancel;
 use embassy_stm32::eth::PacketQueue;
-use embassy_stm32::gpio;
-use embassy_stm32::time::Hertz;
-use embassy_stm32::Peripheral;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_stm32::exti::ExtiInput;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_sync::signal::Signal;
-use embassy_time::Delay;
 use embassy_time::Duration;
 use embassy_time::Timer;
 use embedded_io_async::Write as AsyncWrite;
 use heapless::String;
-#[allow(unused_imports)]
-use panic_halt as _;
-use rand_core::RngCore;
 use static_cell::ConstStaticCell;
 use static_cell::StaticCell;
-use stm32_fmc::Sdram;
 
-const HOSTNAME: &str = "STM32F7-DISCO";
-// first octet: locally administered (administratively assigned) unicast address;
-// see https://en.wikipedia.org/wiki/MAC_address#IEEE_802c_local_MAC_address_usage
-const MAC_ADDR: [u8; 6] = [0x02, 0xC7, 0x52, 0x67, 0x83, 0xEF];
-
-bind_interrupts!(struct Irqs {
-    ETH => embassy_stm32::eth::InterruptHandler;
-    RNG => embassy_stm32::rng::InterruptHandler<embassy_stm32::peripherals::RNG>;
-});
+/// asked after the DNS servers obtained via DHCP
+const DNS_SERVERS: [embassy_net::Ipv4Address; 1] =
+    [embassy_net::Ipv4Address([9, 9, 9, 9])];
+/// broker for telemetry and remote commands, on the static network
+const MQTT_BROKER: embassy_net::IpEndpoint = embassy_net::IpEndpoint {
+    addr: embassy_net::IpAddress::Ipv4(embassy_net::Ipv4Address([192, 168, 2, 1])),
+    port: 1883,
+};
+/// time between telemetry messages
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// time between SNTP syncs of the RTC
+const SNTP_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// I2C leases held for longer than this are reported
+const I2C_LEASE_THRESHOLD: Duration = Duration::from_millis(100);
+/// time between checks for leases held for too long
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// time without input after which the system stops, if it is quiet
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// only the button, so the network stays reachable while it is up
+const WAKE: power::Wake = power::Wake {
+    button: true,
+    ethernet: false,
+};
+/// bytes of the staging slot for updates
+const OTA_STAGING_LEN: u32 = board::OTA.staging.end - board::OTA.staging.start;
+/// flash regions served over TFTP
+const TFTP_REGIONS: [Region; 1] = [Region {
+    path: "update.bin",
+    start: board::OTA.staging.start,
+    len: OTA_STAGING_LEN,
+    ota: Some(&board::OTA),
+}];
+/// uploads are staged for `ota verify` and `ota activate`
+const TFTP_WHITELIST: [Entry; 1] = [Entry {
+    path: "update.bin",
+    access: Access::ReadWrite,
+    max_size: OTA_STAGING_LEN,
+}];
+
+type Rng = rng::Rng<embassy_stm32::rng::Rng<'static, embassy_stm32::peripherals::RNG>>;
+
+type Flash = flash::Shared<'static, embassy_stm32::peripherals::QUADSPI>;
+
+type I2cBus = Leased<
+    CriticalSectionRawMutex,
+    embassy_stm32::i2c::I2c<'static, embassy_stm32::mode::Async>,
+>;
 
-type Device = embassy_stm32::eth::Ethernet<
+type Device = tap::Tapped<
     'static,
-    embassy_stm32::peripherals::ETH,
-    embassy_stm32::eth::generic_smi::GenericSMI,
+    arp::Guarded<
+        'static,
+        embassy_stm32::eth::Ethernet<
+            'static,
+            embassy_stm32::peripherals::ETH,
+            embassy_sandbox::net::phy::Lan8742,
+        >,
+    >,
+    (dhcp::Snooper, stats::Interface),
 >;
 
+/// Commands available over the network CLI.
+struct Shell<'d> {
+    stack: embassy_net::Stack<'d>,
+    net: NetState<'d>,
+    clock: &'d rtc::Clock,
+    rng: &'d Rng,
+    flash: &'d Flash,
+    /// the on-board bus
+    i2c: &'d I2cBus,
+    /// the Arduino connector bus
+    i2c_ext: &'d I2cBus,
+}
+
+impl server::Handler for Shell<'_> {
+    async fn handle(
+        &self,
+        command: Command<'_>,
+        out: &mut impl FmtWrite,
+        cancel: &Cancel,
+    ) -> core::fmt::Result {
+        match command {
+            | Command::Echo(echo) => echo.run(out),
+            | Command::Profile(profile) => profile.run(out),
+            | Command::Hash(hash) => hash.run(&mut Crc32::new(), out).await,
+            | Command::Mem(mem) => mem.run(out),
+            | Command::Assets(assets) => assets.run(out),
+            | Command::Regs(regs) => regs.run(out),
+            | Command::Flash(flash) => {
+                let mut tftp = cli::Tftp {
+                    stack: self.stack,
+                    cancel,
+                };
+                flash.run(&mut self.flash.handle("cli"), &mut tftp, out).await
+            }
+            | Command::I2c(i2c) => {
+                let bus = match i2c.external {
+                    | true => self.i2c_ext,
+                    | false => self.i2c,
+                };
+                let mut bus = bus.lease("cli").await;
+                i2c.run(&mut *bus, out).await
+            }
+            | Command::Ota(ota) => {
+                let mut tftp = cli::Tftp {
+                    stack: self.stack,
+                    cancel,
+                };
+                let mut flash = self.flash.handle("ota");
+                ota.run(&mut flash, &board::OTA, &mut tftp, out).await
+            }
+            | Command::Ping(ping) => ping.run(self.stack, out).await,
+            | Command::Nslookup(nslookup) => nslookup.run(self.stack, out).await,
+            | Command::Wol(wol) => wol.run(self.stack, out).await,
+            | Command::Net(net) => net.run(self.stack, &self.net, out),
+            | Command::Cache(cache) => cache.run(out),
+            | Command::Screen(screen) => screen.run(out),
+            | Command::Date => cli::date(self.clock, out),
+            | Command::Time(time) => time.run(self.stack, self.clock, out).await,
+            | Command::Boot(boot) => boot.run(out),
+            | Command::Services => cli::services(&[&SNTP_SERVICE], out),
+            | Command::Stats => {
+                let leases = [
+                    ("flash", self.flash.stats()),
+                    (self.i2c.name(), self.i2c.stats()),
+                    (self.i2c_ext.name(), self.i2c_ext.stats()),
+                ];
+                cli::stats(&leases, out)
+            }
+            | Command::Passwd(passwd) => {
+                let mut salt = [0; 16];
+                match self.rng.fill(&mut salt).await {
+                    | Ok(_) => passwd.run(salt, out),
+                    | Err(e) => cli::term::error(out, e),
+                }
+            }
+            | _ => writeln!(out, "not available on this build"),
+        }
+    }
+}
+
+/// Keep the message for the next boot and halt, like `panic-halt`.
+#[panic_handler]
+fn on_panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    panic::record(info);
+    loop {
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
 #[embassy_executor::task]
 async fn net_task(runner: embassy_net::Runner<'static, Device>) -> ! {
     let mut runner = runner;
@@ -63,171 +228,136 @@ async fn main(spawner: Spawner) -> ! {
     _main(spawner).await
 }
 
-static DHCP_UP: Signal<ThreadModeRawMutex, ()> = Signal::new();
+static ARP_GUARD: arp::ConflictDetector = arp::ConflictDetector::new();
+static TAP: StaticCell<(dhcp::Snooper, stats::Interface)> = StaticCell::new();
+static CLI_STATS: stats::Socket = stats::Socket::new("cli");
+static HTTP_STATS: stats::Socket = stats::Socket::new("http");
+static SNTP_SERVICE: supervisor::Service = supervisor::Service::new("sntp");
+
+/// Ethernet DMA descriptors and buffers, aligned to form an MPU region of their own.
+#[repr(C, align(32768))]
+struct EthDma(PacketQueue<8, 8>);
+const _: () = assert!(core::mem::size_of::<EthDma>() == 32 << 10);
+static ETH_DMA: SyncUnsafeCell<EthDma> = SyncUnsafeCell::new(EthDma(PacketQueue::new()));
+
+/// Enable the caches, keeping the Ethernet DMA memory uncached.
+///
+/// SDRAM would otherwise be device memory, which the heap cannot live in.
+#[cfg(feature = "cache")]
+fn enable_caches(core: &mut cortex_m::Peripherals) {
+    let regions = [
+        mpu::Region::new(
+            board::SDRAM_ADDRESS,
+            board::SDRAM_SIZE as u32,
+            mpu::Memory::WriteBack,
+        ),
+        mpu::Region::new(
+            ETH_DMA.get() as u32,
+            core::mem::size_of::<EthDma>() as u32,
+            mpu::Memory::NonCacheable,
+        ),
+    ];
+    mpu::configure(&mut core.MPU, &regions).expect("invalid MPU map");
+    cache::enable(&mut core.SCB, &mut core.CPUID);
+}
 
 async fn _main(spawner: Spawner) -> ! {
-    let (config, ahb_freq) = config();
-    let p = embassy_stm32::init(config);
-    let mut button =
-        embassy_stm32::exti::ExtiInput::new(p.PA0, p.EXTI0, gpio::Pull::Down);
-
-    /* SDRAM
-    let memory: &'static mut [MaybeUninit<u32>] = {
-        static SDRAM: StaticCell<
-            Sdram<
-                embassy_stm32::fmc::Fmc<'static, embassy_stm32::peripherals::FMC>,
-                stm32_fmc::devices::is42s32400f_6::Is42s32400f6,
-            >,
-        > = StaticCell::new();
-        const SDRAM_SIZE: usize = (128 / 8) << 10;
-        let sdram =
-            SDRAM.init(embassy_stm32::fmc::Fmc::sdram_a13bits_d32bits_4banks_bank1(
-                p.FMC,
-                p.PF0,
-                p.PF1,
-                p.PF2,
-                p.PF3,
-                p.PF4,
-                p.PF5,
-                p.PF12,
-                p.PF13,
-                p.PF14,
-                p.PF15,
-                p.PG0,
-                p.PG1,
-                p.PG2,
-                p.PG4,
-                p.PG5,
-                p.PD14,
-                p.PD15,
-                p.PD0,
-                p.PD1,
-                p.PE7,
-                p.PE8,
-                p.PE9,
-                p.PE10,
-                p.PE11,
-                p.PE12,
-                p.PE13,
-                p.PE14,
-                p.PE15,
-                p.PD8,
-                p.PD9,
-                p.PD10,
-                p.PH8,
-                p.PH9,
-                p.PH10,
-                p.PH11,
-                p.PH12,
-                p.PH13,
-                p.PH14,
-                p.PH15,
-                p.PI0,
-                p.PI1,
-                p.PI2,
-                p.PI3,
-                p.PI6,
-                p.PI7,
-                p.PI9,
-                p.PI10,
-                p.PE0,
-                p.PE1,
-                p.PI4,
-                p.PI5,
-                p.PH2,
-                p.PG8,
-                p.PG15,
-                p.PH3,
-                p.PF11,
-                p.PH5,
-                stm32_fmc::devices::is42s32400f_6::Is42s32400f6 {},
-            ));
-        let ptr = sdram.init(&mut Delay);
-        let ptr = ptr.cast::<MaybeUninit<u32>>();
-        // Safety: pointee u32: Sized
-        let size = unsafe { core::mem::size_of_val_raw(ptr) };
-        let len = SDRAM_SIZE / size;
-        // Safety:
-        // - I sure hope `embassy_stm32::fmc::Fmc::sdram_a13bits_d32bits_4banks_bank1` returns a read/write valid pointer
-        // - the source ptr does not escape this scope
-        const _: () = assert!(SDRAM_SIZE <= isize::MAX as usize);
-        assert!((ptr as usize).checked_add(SDRAM_SIZE).is_some());
-        unsafe { core::slice::from_raw_parts_mut(ptr, len) }
-    };
-
-    let (head, tail) = memory.split_at_mut(4);
-    let values: &[u32] = &[0x12345678, 0x87654321, 0x89ABCDEF, 0xFEDCBA98];
-    for (src, dst) in values.iter().zip(head.iter_mut()) {
-        dst.write(*src);
+    let board = Board::init();
+    let hostname = board.hostname();
+    let mut core = board.core;
+    profile::enable(&mut core.DCB, &mut core.DWT);
+    #[cfg(feature = "cache")]
+    enable_caches(&mut core);
+    let mut ld1 = board.ld1;
+    let mut ld2 = board.ld2;
+
+    static RNG: StaticCell<Rng> = StaticCell::new();
+    let rng = &*RNG.init(rng::Rng::new(board.rng));
+    let mut seeds = [0; 2];
+    for seed in &mut seeds {
+        *seed = rng.next_u64().await.expect("the RNG should work at startup");
     }
-    let head =
-        unsafe { core::mem::transmute::<&mut [MaybeUninit<u32>], &mut [u32]>(head) };
 
-    assert_eq!(head, values);
-    */
-
-    loop {
-        button.wait_for_falling_edge().await;
+    static FLASH: StaticCell<Flash> = StaticCell::new();
+    let flash = &*FLASH.init(flash::Shared::new(board.flash.init(board.hclk).await));
+    static I2C: StaticCell<I2cBus> = StaticCell::new();
+    static I2C_EXT: StaticCell<I2cBus> = StaticCell::new();
+    let i2c = &*I2C.init(Leased::new(
+        "i2c4",
+        I2C_LEASE_THRESHOLD,
+        Some(events::overlong),
+        board.i2c,
+    ));
+    let i2c_ext = &*I2C_EXT.init(Leased::new(
+        "i2c1",
+        I2C_LEASE_THRESHOLD,
+        Some(events::overlong),
+        board.i2c_ext,
+    ));
+
+    if panic::take() {
+        events::HEALTH.set(events::Health::Fault);
     }
-
-    /*
-    let ld1 = gpio::Output::new(p.PJ13, gpio::Level::High, gpio::Speed::Low);
-    let ld2 = gpio::Output::new(p.PJ5, gpio::Level::High, gpio::Speed::Low);
-
-    let mut rng = embassy_stm32::rng::Rng::new(p.RNG, Irqs);
-    let seeds = core::array::from_fn(|_| rng.next_u64());
-
-    let blink = blink(ld1, ld2);
+    let leds = join(
+        status_led::run(&mut ld1, status_led::heartbeat),
+        status_led::run(&mut ld2, status_led::network),
+    );
     let echo = echo(
-        spawner, HOSTNAME, MAC_ADDR, seeds, p.ETH, p.PA1, p.PA2, p.PC1, p.PA7, p.PC4,
-        p.PC5, p.PG13, p.PG14, p.PG11,
+        spawner,
+        hostname,
+        board.mac_addr,
+        seeds,
+        rng,
+        board.clock,
+        board.ethernet,
+        flash,
+        [i2c, i2c_ext],
+    );
+
+    let sensors = adc::run(board.adc);
+    let feedback = board.beeper.feedback();
+    let idle = power::run(&mut core.SCB, IDLE_TIMEOUT, WAKE);
+    let leases = join3(
+        flash.watch(LEASE_CHECK_INTERVAL),
+        i2c.watch(LEASE_CHECK_INTERVAL),
+        i2c_ext.watch(LEASE_CHECK_INTERVAL),
     );
 
-    join(blink, echo).await.0
-    */
+    join5(
+        buttons(board.button),
+        leds,
+        echo,
+        join3(sensors, idle, feedback),
+        leases,
+    )
+    .await
+    .0
 }
 
-async fn blink(ld1: gpio::Output<'_>, ld2: gpio::Output<'_>) -> ! {
-    let mut ld1 = ld1;
-    let mut ld2 = ld2;
+/// Publish the edges of the user button to [`events::INPUT`].
+async fn buttons(button: ExtiInput<'static>) -> ! {
+    let mut button = button;
     loop {
-        ld1.set_high();
-        if DHCP_UP.signaled() {
-            ld2.set_high();
-        }
-
-        Timer::after_millis(500).await;
-        ld1.set_low();
-
-        Timer::after_millis(500).await;
-        ld1.set_high();
-        ld2.set_low();
-
-        Timer::after_millis(500).await;
-        ld1.set_low();
-
-        Timer::after_millis(500).await;
+        button.wait_for_any_edge().await;
+        let input = match button.is_high() {
+            | true => events::Input::ButtonPressed,
+            | false => events::Input::ButtonReleased,
+        };
+        events::INPUT.publish_immediate(input);
     }
 }
 
-#[allow(clippy::upper_case_acronyms)]
-type ETH = embassy_stm32::peripherals::ETH;
 #[allow(clippy::too_many_arguments)]
 async fn echo(
     spawner: Spawner,
-    #[allow(unused)] hostname: impl AsRef<str>,
+    hostname: impl AsRef<str>,
     mac_addr: [u8; 6],
     seeds: [u64; 2],
-    eth: ETH,
-    ref_clk: impl Peripheral<P = impl embassy_stm32::eth::RefClkPin<ETH>> + 'static,
-    mdio: impl Peripheral<P = impl embassy_stm32::eth::MDIOPin<ETH>> + 'static,
-    mdc: impl Peripheral<P = impl embassy_stm32::eth::MDCPin<ETH>> + 'static,
-    crs: impl Peripheral<P = impl embassy_stm32::eth::CRSPin<ETH>> + 'static,
-    rx_d0: impl Peripheral<P = impl embassy_stm32::eth::RXD0Pin<ETH>> + 'static,
-    rx_d1: impl Peripheral<P = impl embassy_stm32::eth::RXD1Pin<ETH>> + 'static,
-    tx_d0: impl Peripheral<P = impl embassy_stm32::eth::TXD0Pin<ETH>> + 'static,
-    tx_d1: impl Peripheral<P = impl embassy_stm32::eth::TXD1Pin<ETH>> + 'static,
-    tx_en: impl Peripheral<P = impl embassy_stm32::eth::TXEnPin<ETH>> + 'static,
+    rng: &'static Rng,
+    clock: &'static rtc::Clock,
+    ethernet: board::Ethernet,
+    flash: &'static Flash,
+    [i2c, i2c_ext]: [&'static I2cBus; 2],
 ) -> ! {
     use embassy_net::*;
     let net_cfg =
@@ -238,116 +368,189 @@ async fn echo(
         dns_servers: Default::default(),
     });
 
-    static PACKET_QUEUE: ConstStaticCell<PacketQueue<8, 8>> =
-        ConstStaticCell::new(PacketQueue::new());
-    let packet_queue = PACKET_QUEUE.take();
+    // Safety: `echo` runs once, so this is the only reference
+    let packet_queue = unsafe { &mut (*ETH_DMA.get()).0 };
 
-    static RESOURCES: ConstStaticCell<StackResources<8>> =
+    static RESOURCES: ConstStaticCell<StackResources<12>> =
         ConstStaticCell::new(StackResources::new());
     let resources = RESOURCES.take();
 
-    let ethernet = embassy_stm32::eth::Ethernet::new(
-        packet_queue,
-        eth,
-        Irqs,
-        ref_clk,
-        mdio,
-        mdc,
-        crs,
-        rx_d0,
-        rx_d1,
-        tx_d0,
-        tx_d1,
-        tx_en,
-        embassy_stm32::eth::generic_smi::GenericSMI::new(0),
-        mac_addr,
-    );
-
-    let mut server_rx_buf = [0; 4096];
-    let mut server_tx_buf = [0; 4096];
+    let ethernet = ethernet.init(packet_queue, mac_addr);
+    let ethernet = arp::Guarded::new(ethernet, &ARP_GUARD);
+    let tap = &*TAP.init((dhcp::Snooper::new(mac_addr), stats::Interface::new()));
+    let ethernet = tap::Tapped::new(ethernet, tap);
+    dns::configure(&DNS_SERVERS, dns::Mode::Augment);
 
     let (stack, runner) = embassy_net::new(ethernet, net_cfg, resources, seeds[0]);
 
     spawner.must_spawn(net_task(runner));
+    events::NETWORK.set(events::Network::Pending);
     stack.wait_config_up().await;
 
-    let config = loop {
-        if let Some(config) = stack.config_v4() {
-            break config;
-        }
-        yield_now().await;
-    };
+    let config = util::until(|| stack.config_v4()).await;
     let addr = config.address.address();
     let _addr = addr;
-    DHCP_UP.signal(());
+    events::NETWORK.set(events::Network::Up);
 
-    let mut server = tcp::TcpSocket::new(stack, &mut server_rx_buf, &mut server_tx_buf);
-    server.set_timeout(Some(Duration::from_secs(120)));
     let config_v4 = stack.config_v4();
     let _config_v4 = config_v4;
 
-    let mut server = async move || loop {
-        if let Err(e) = server.accept(1234).await {
-            let _e = e;
-            Timer::after_secs(1).await;
-            continue;
-        }
+    static CLI_SLOTS: ConstStaticCell<[server::Buffers<1024, 256, 4096>; 3]> =
+        ConstStaticCell::new([const { server::Buffers::new() }; 3]);
+    let cli_slots = CLI_SLOTS.take();
+
+    let shell = Shell {
+        stack,
+        net: NetState {
+            dhcp: &tap.0,
+            interface: &tap.1,
+            sockets: &[&CLI_STATS, &HTTP_STATS],
+        },
+        clock,
+        rng,
+        flash,
+        i2c,
+        i2c_ext,
+    };
 
-        let mut buf = [0; 512];
-        let mut fmt = String::<1026>::new();
-        loop {
-            let len = match server.read(&mut buf).await {
-                | Err(_) | Ok(0) => break,
-                | Ok(len) => len,
-            };
-            let buf = &buf[..len];
-
-            for n in buf {
-                fmt.write_fmt(format_args!("{:02x}", n))
-                    .expect("fmt buffer should fit entire formatted input");
-            }
-            fmt.write_str("\r\n")
-                .expect("fmt buffer should fit formatted input plus crlf");
+    // topics below the hostname, so boards can share a broker
+    let mut command_topic = String::<64>::new();
+    let mut telemetry_topic = String::<64>::new();
+    write!(command_topic, "{}/command", hostname.as_ref()).expect("hostname too long");
+    write!(telemetry_topic, "{}/telemetry", hostname.as_ref())
+        .expect("hostname too long");
+    let mqtt_config = mqtt::Config {
+        broker: MQTT_BROKER,
+        client_id: hostname.as_ref(),
+        keep_alive: Duration::from_secs(60),
+        command_topic: &command_topic,
+        telemetry_topic: &telemetry_topic,
+        telemetry_interval: TELEMETRY_INTERVAL,
+        telemetry_qos: mqtt::QoS::AtMostOnce,
+    };
+    static MQTT_BUFFERS: ConstStaticCell<mqtt::Buffers<1024, 256>> =
+        ConstStaticCell::new(mqtt::Buffers::new());
+    let mqtt = mqtt::run(
+        stack,
+        &mqtt_config,
+        MQTT_BUFFERS.take(),
+        |buf| beacon(&tap.1, buf),
+        remote_command,
+    );
 
-            if server.write_all(fmt.as_bytes()).await.is_err() {
-                break;
-            }
-            fmt.clear();
+    static HTTP_BUFFERS: ConstStaticCell<http::Buffers<1024, 1024>> =
+        ConstStaticCell::new(http::Buffers::new());
+    let mut dashboard = Dashboard { interface: &tap.1 };
+    let http = http::serve(
+        stack,
+        http::PORT,
+        HTTP_BUFFERS.take(),
+        &mut dashboard,
+        &HTTP_STATS,
+    );
+
+    join5(
+        join(
+            server::serve(stack, server::PORT, cli_slots, &shell, &CLI_STATS),
+            http,
+        ),
+        arp::supervise(stack, &ARP_GUARD, arp::Policy::Defend),
+        SNTP_SERVICE.supervise(Policy::DEFAULT, || sync_clock(stack, clock)),
+        serve_files(stack, flash),
+        mqtt,
+    )
+    .await
+    .0
+     .0
+}
+
+/// [`http::Source`] for the status page: socket counters and a log
+/// made of the boot info and the state of the supervised services.
+struct Dashboard<'a> {
+    interface: &'a stats::Interface,
+}
+
+impl http::Source for Dashboard<'_> {
+    fn status(&mut self, status: &mut http::Object<'_>) -> core::fmt::Result {
+        stats::status(status, self.interface, &[&CLI_STATS, &HTTP_STATS])
+    }
+
+    fn log(&mut self, offset: usize, buf: &mut [u8]) -> usize {
+        // rendered anew for every piece; cheap next to sending it
+        let mut log = String::<1024>::new();
+        if let Some(info) = boot::info() {
+            let _ = writeln!(log, "{info}");
         }
-        server.close();
-        let _ = server.flush().await;
-    };
+        let _ = writeln!(log, "sntp: {}", SNTP_SERVICE.status());
+        let rest = log.as_bytes().get(offset..).unwrap_or_default();
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        len
+    }
+}
 
-    server().await
+/// Serve [`TFTP_WHITELIST`] from `flash` on [`tftp::PORT`].
+async fn serve_files(stack: embassy_net::Stack<'_>, flash: &Flash) -> ! {
+    use embassy_net::udp::PacketMetadata;
+    use embassy_net::udp::UdpSocket;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buf = [0; 2 * tftp::PACKET_SIZE];
+    let mut tx_buf = [0; 2 * tftp::PACKET_SIZE];
+    let mut sock =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    sock.bind(tftp::PORT).expect("nothing else should bind the TFTP port");
+
+    let mut fs = Regions::new(flash.handle("tftp"), &TFTP_REGIONS);
+    let mut rx = [0; tftp::PACKET_SIZE];
+    let mut tx = [0; tftp::PACKET_SIZE];
+    tftp::server::serve(&sock, &mut fs, &TFTP_WHITELIST, &mut rx, &mut tx).await
 }
 
-// noinspection ALL
-fn config() -> (embassy_stm32::Config, Hertz) {
-    use embassy_stm32::rcc::*;
-    let mut config = embassy_stm32::Config::default();
-    config.rcc = {
-        let mut rcc = Config::default();
-        // HSI == 16 MHz
-        rcc.hsi = true;
-        rcc.pll = Some(Pll {
-            // PLL in == 16 MHz / 8 == 2 MHz
-            prediv: PllPreDiv::DIV8,
-            // PLL out == 2 MHz * 64 == 128 MHz
-            mul: PllMul(64),
-            // SYSCLK == PLL out / divp == 128 MHz / 2 == 64 MHz
-            divp: Some(PllPDiv::DIV2),
-            divq: None,
-            divr: None,
-        });
-        rcc.pll_src = PllSource::HSI;
-        rcc.sys = Sysclk::PLL1_P;
-        // APB1 clock must not be faster than 54 MHz
-        rcc.apb1_pre = APBPrescaler::DIV2;
-        // AHB clock == SYSCLK = 64MHz
-        rcc.ahb_pre = AHBPrescaler::DIV1;
-        rcc
+/// Write the telemetry payload into `buf`: the counters of `interface`
+/// and the [`adc`] readings as JSON, the latter `null` until sampled.
+fn beacon(interface: &stats::Interface, buf: &mut [u8]) -> Option<usize> {
+    let stats = interface.get();
+    let mut payload = String::<256>::new();
+    write!(
+        payload,
+        r#"{{"interface":{{"rx_packets":{},"rx_bytes":{},"tx_packets":{},"tx_bytes":{}}},"adc":"#,
+        stats.rx_packets, stats.rx_bytes, stats.tx_packets, stats.tx_bytes
+    )
+    .ok()?;
+    let mut json = [0; 64];
+    let readings = match adc::telemetry(&mut json) {
+        | Some(len) => core::str::from_utf8(&json[..len]).ok()?,
+        | None => "null",
     };
-    (config, Hertz(64_000_000))
+    payload.push_str(readings).ok()?;
+    payload.push('}').ok()?;
+    buf.get_mut(..payload.len())?.copy_from_slice(payload.as_bytes());
+    Some(payload.len())
+}
+
+/// Apply a command received on the MQTT command topic, ignoring unknown ones.
+fn remote_command(payload: &[u8]) {
+    match mqtt::RemoteCommand::parse(payload) {
+        | Some(mqtt::RemoteCommand::Reboot) => cortex_m::peripheral::SCB::sys_reset(),
+        // there is no logger whose level could be changed yet
+        | Some(mqtt::RemoteCommand::LogLevel(_)) | None => {}
+    }
+}
+
+/// Keep `clock` in sync with [`sntp::POOL`], returning when a sync fails.
+async fn sync_clock(
+    stack: embassy_net::Stack<'_>,
+    clock: &rtc::Clock,
+) -> Result<(), sntp::Error> {
+    loop {
+        let server = sntp::pool(stack).await?;
+        let unix = sntp::query(stack, server).await?;
+        // a time outside the RTC's range is bogus; try again next interval
+        let _ = clock.set(rtc::DateTime::from_unix(unix));
+        Timer::after(SNTP_INTERVAL).await;
+    }
 }
 
 #[allow(unused)]
@@ -359,10 +562,3 @@ fn dhcp_config(hostname: impl AsRef<str>) -> Result<embassy_net::DhcpConfig, ()>
 
     Ok(config)
 }
-
-// D0  = PC9
-// D1  = PC10
-// D2  = PE2
-// D3  = PD13
-// sck = PB2
-// nss = DMA1
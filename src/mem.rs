@@ -0,0 +1,281 @@
+//! Checked raw memory access for bring-up debugging.
+//!
+//! Accesses are checked against the STM32F769 memory map ([`REGIONS`]),
+//! so a mistyped address yields an error instead of a bus fault.
+//! Peripheral space has side effects on access and has to be opted into.
+//! External memories only count as mapped while they are ([`Gate`]).
+
+pub mod backup;
+pub mod cache;
+pub mod dma;
+pub mod mpu;
+pub mod regs;
+
+use core::fmt;
+use core::fmt::Display;
+use core::ptr;
+use core::range::Range;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+/// Known memory regions of the STM32F769NI on the STM32F769I-DISCO.
+pub const REGIONS: &[Region] = &[
+    Region::new("ITCM", 0x0000_0000, 16 << 10, Access::ReadWrite),
+    Region::new("flash", 0x0800_0000, 2 << 20, Access::ReadOnly),
+    Region::new("DTCM", 0x2000_0000, 128 << 10, Access::ReadWrite),
+    Region::new("SRAM", 0x2002_0000, 384 << 10, Access::ReadWrite),
+    Region::new("peripherals", 0x4000_0000, 0x2000_0000, Access::Peripheral),
+    Region::new("QSPI", 0x9000_0000, 64 << 20, Access::ReadOnly).gated(Gate::Qspi),
+    Region::new("SDRAM", 0xC000_0000, 16 << 20, Access::ReadWrite).gated(Gate::Sdram),
+    Region::new("system", 0xE000_0000, 1 << 20, Access::Peripheral),
+];
+
+static QSPI_MAPPED: AtomicBool = AtomicBool::new(false);
+static SDRAM_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub range: Range<u32>,
+    pub access: Access,
+    pub gate: Option<Gate>,
+}
+
+/// Condition for an external memory to be accessible.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Gate {
+    /// the QSPI flash is in memory-mapped mode
+    Qspi,
+    /// the FMC has initialised the SDRAM
+    Sdram,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+    /// memory-mapped registers; accessed word-wise if aligned
+    Peripheral,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the range is not (entirely) contained in a known region
+    Unmapped,
+    ReadOnly(&'static str),
+    /// peripheral access was not opted into
+    Peripheral(&'static str),
+    /// the region's [`Gate`] is closed
+    Unavailable(&'static str),
+}
+
+impl Region {
+    const fn new(name: &'static str, start: u32, len: u32, access: Access) -> Self {
+        Self {
+            name,
+            range: Range {
+                start,
+                end: start + len,
+            },
+            access,
+            gate: None,
+        }
+    }
+
+    const fn gated(self, gate: Gate) -> Self {
+        Self {
+            gate: Some(gate),
+            ..self
+        }
+    }
+
+    fn contains(&self, start: u32, len: u32) -> bool {
+        start >= self.range.start
+            && start.checked_add(len).is_some_and(|end| end <= self.range.end)
+    }
+}
+
+/// Find the region containing `len` bytes at `address` and check the access.
+pub fn check(
+    address: u32,
+    len: u32,
+    write: bool,
+    peripherals: bool,
+) -> Result<&'static Region, Error> {
+    let region = REGIONS
+        .iter()
+        .find(|region| region.contains(address, len))
+        .ok_or(Error::Unmapped)?;
+    if region.gate.is_some_and(|gate| !gate.is_open()) {
+        return Err(Error::Unavailable(region.name));
+    }
+    match region.access {
+        | Access::ReadOnly if write => Err(Error::ReadOnly(region.name)),
+        | Access::Peripheral if !peripherals => Err(Error::Peripheral(region.name)),
+        | _ => Ok(region),
+    }
+}
+
+/// Checked view of regular (non-peripheral) memory.
+pub fn slice(address: u32, len: u32) -> Result<&'static [u8], Error> {
+    check(address, len, false, false)?;
+    // Safety: the range lies within a readable memory region
+    Ok(unsafe { core::slice::from_raw_parts(address as *const u8, len as usize) })
+}
+
+/// Copy memory at `address` into `buf`.
+pub fn read(address: u32, buf: &mut [u8], peripherals: bool) -> Result<(), Error> {
+    let region = check(address, buf.len() as u32, false, peripherals)?;
+    let src = address as *const u8;
+
+    // Safety: the range lies within a readable memory region
+    unsafe {
+        if region.access == Access::Peripheral && is_word_aligned(address, buf.len()) {
+            for (i, word) in buf.chunks_exact_mut(4).enumerate() {
+                let value = ptr::read_volatile(src.cast::<u32>().add(i));
+                word.copy_from_slice(&value.to_le_bytes());
+            }
+        } else {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = ptr::read_volatile(src.add(i));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy `data` to memory at `address`.
+pub fn write(address: u32, data: &[u8], peripherals: bool) -> Result<(), Error> {
+    let region = check(address, data.len() as u32, true, peripherals)?;
+    let dst = address as *mut u8;
+
+    // Safety: the range lies within a writable memory region.
+    // Overwriting memory in use is what the caller asked for.
+    unsafe {
+        if region.access == Access::Peripheral && is_word_aligned(address, data.len()) {
+            for (i, word) in data.chunks_exact(4).enumerate() {
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                ptr::write_volatile(dst.cast::<u32>().add(i), value);
+            }
+        } else {
+            for (i, byte) in data.iter().enumerate() {
+                ptr::write_volatile(dst.add(i), *byte);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write one line of a hex+ASCII dump of `data` located at `address`.
+pub fn dump_line(out: &mut impl fmt::Write, address: u32, data: &[u8]) -> fmt::Result {
+    write!(out, "{address:08x}:")?;
+    for i in 0..16 {
+        match data.get(i) {
+            | Some(byte) => write!(out, " {byte:02x}")?,
+            | None => out.write_str("   ")?,
+        }
+    }
+    out.write_str("  ")?;
+    for &byte in data {
+        let c = match byte {
+            | 0x20..=0x7E => byte as char,
+            | _ => '.',
+        };
+        out.write_char(c)?;
+    }
+    writeln!(out)
+}
+
+impl Gate {
+    /// Open or close the gate, e.g. when entering or leaving memory-mapped mode.
+    pub fn set(self, open: bool) {
+        self.flag().store(open, Ordering::Release);
+    }
+
+    pub fn is_open(self) -> bool {
+        self.flag().load(Ordering::Acquire)
+    }
+
+    fn flag(self) -> &'static AtomicBool {
+        match self {
+            | Gate::Qspi => &QSPI_MAPPED,
+            | Gate::Sdram => &SDRAM_READY,
+        }
+    }
+}
+
+fn is_word_aligned(address: u32, len: usize) -> bool {
+    address % 4 == 0 && len % 4 == 0
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Unmapped => write!(f, "address range is not mapped"),
+            | Error::ReadOnly(region) => write!(f, "{region} is read-only"),
+            | Error::Peripheral(region) => {
+                write!(
+                    f,
+                    "{region} contains peripherals; pass --periph to access it"
+                )
+            }
+            | Error::Unavailable(region) => write!(f, "{region} is not mapped right now"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(
+            check(0xC000_0000, 16, true, false),
+            Err(Error::Unavailable("SDRAM"))
+        );
+        Gate::Sdram.set(true);
+        assert_eq!(
+            check(0xC000_0000, 16, true, false).map(|r| r.name),
+            Ok("SDRAM")
+        );
+        Gate::Sdram.set(false);
+        assert_eq!(check(0xC0FF_FFFF, 2, false, false), Err(Error::Unmapped));
+        assert_eq!(
+            check(0x9000_0000, 4, false, false),
+            Err(Error::Unavailable("QSPI"))
+        );
+        assert_eq!(
+            check(0x0800_0000, 4, true, false),
+            Err(Error::ReadOnly("flash"))
+        );
+        assert_eq!(
+            check(0x4002_3000, 4, false, false),
+            Err(Error::Peripheral("peripherals"))
+        );
+        assert!(check(0x4002_3000, 4, true, true).is_ok());
+        assert_eq!(check(u32::MAX, 2, false, true), Err(Error::Unmapped));
+    }
+
+    #[test]
+    fn test_dump_line() {
+        let mut line = String::<80>::new();
+        dump_line(&mut line, 0x2000_0010, b"Hi!\x00\xff").unwrap();
+        assert_eq!(
+            line,
+            "20000010: 48 69 21 00 ff                                   Hi!..\n"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Records kept in the 4 KiB backup SRAM.
+//!
+//! The backup SRAM keeps its contents across resets, and on VBAT while the
+//! backup regulator is on, which makes it the place for data about the
+//! previous run: panic messages, reset reasons and boot counters.
+//!
+//! The [`Store`] is a magic word followed by records of a [`Key`], a length,
+//! a CRC-32 and the data padded to whole words, terminated by an end marker.
+//! Records failing their CRC end the store, so a torn write loses only the
+//! records behind it.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Display;
+use core::mem;
+
+use bytemuck::AnyBitPattern;
+use bytemuck::NoUninit;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::util::hash::Crc32;
+use crate::util::hash::Hasher;
+
+pub const ADDRESS: u32 = 0x4002_4000;
+pub const SIZE: usize = 4 << 10;
+
+const MAGIC: [u8; 4] = *b"BKP1";
+/// key, length and CRC
+const HEADER_LEN: usize = 8;
+const END: u16 = 0xFFFF;
+/// the end marker, rounded up to a word
+const END_LEN: usize = 4;
+
+static STORE: Mutex<CriticalSectionRawMutex, RefCell<Option<Store<'static>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Identifies a record; `0xFFFF` is reserved.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Key(pub u16);
+
+/// Records in a region of memory.
+pub struct Store<'m> {
+    memory: &'m mut [u8],
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// [`init`] has not been called
+    Uninitialized,
+    /// not enough space left for the record
+    Full,
+}
+
+/// Iterator over the valid records of a [`Store`].
+#[derive(Clone)]
+pub struct Records<'s> {
+    memory: &'s [u8],
+    offset: usize,
+}
+
+/// A record located in a [`Store`].
+struct Located {
+    offset: usize,
+    /// including header and padding
+    len: usize,
+}
+
+impl Key {
+    /// the message of the last panic, see [`system::panic`](crate::system::panic)
+    pub const PANIC: Self = Self(1);
+    /// the reset flags of the last reset
+    pub const RESET_REASON: Self = Self(2);
+    /// boots since the backup domain lost power
+    pub const BOOT_COUNT: Self = Self(3);
+    /// the [credential](crate::cli::auth::Credential) for CLI logins
+    pub const CLI_PASSWORD: Self = Self(4);
+}
+
+/// Power the backup SRAM, keep it on VBAT and [`install`] a [`Store`] in it.
+///
+/// Backup domain write access has to be enabled already,
+/// which initializing the RTC does.
+#[cfg(feature = "cross")]
+pub fn init() {
+    use embassy_stm32::pac::PWR;
+    use embassy_stm32::pac::RCC;
+
+    RCC.ahb1enr().modify(|w| w.set_bkpsramen(true));
+    PWR.cr1().modify(|w| w.set_dbp(true));
+    PWR.csr1().modify(|w| w.set_bre(true));
+    while !PWR.csr1().read().brr() {}
+
+    // Safety: the backup SRAM is only ever accessed through this store
+    let memory = unsafe { core::slice::from_raw_parts_mut(ADDRESS as *mut u8, SIZE) };
+    install(Store::new(memory));
+}
+
+/// Make `store` the one used by the free functions of this module.
+pub fn install(store: Store<'static>) {
+    STORE.lock(|cell| *cell.borrow_mut() = Some(store));
+}
+
+/// Store `value` under `key`, replacing any previous record.
+pub fn store<T: NoUninit>(key: Key, value: T) -> Result<(), Error> {
+    store_bytes(key, bytemuck::bytes_of(&value))
+}
+
+/// The value stored under `key`, if it has the size of a `T`.
+pub fn load<T: AnyBitPattern>(key: Key) -> Option<T> {
+    with(|store| {
+        let data = store.get(key)?;
+        (data.len() == mem::size_of::<T>()).then(|| bytemuck::pod_read_unaligned(data))
+    })
+    .ok()
+    .flatten()
+}
+
+pub fn store_bytes(key: Key, data: &[u8]) -> Result<(), Error> {
+    with(|store| store.set(key, data))?
+}
+
+/// Copy the data stored under `key` into `buf`, truncating it if need be.
+pub fn load_bytes(key: Key, buf: &mut [u8]) -> Option<&[u8]> {
+    let len = with(|store| {
+        let data = store.get(key)?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Some(len)
+    })
+    .ok()??;
+    Some(&buf[..len])
+}
+
+/// Delete the record under `key`. Returns whether there was one.
+pub fn remove(key: Key) -> bool {
+    with(|store| store.remove(key)).unwrap_or(false)
+}
+
+fn with<R>(f: impl FnOnce(&mut Store<'static>) -> R) -> Result<R, Error> {
+    STORE.lock(|cell| cell.borrow_mut().as_mut().map(f).ok_or(Error::Uninitialized))
+}
+
+impl<'m> Store<'m> {
+    /// Use `memory`, formatting it unless it already holds a store.
+    ///
+    /// Panics unless `memory` is a whole number of words
+    /// with room for at least the magic word and the end marker.
+    pub fn new(memory: &'m mut [u8]) -> Self {
+        assert!(memory.len() % 4 == 0 && memory.len() >= MAGIC.len() + END_LEN);
+        let mut store = Self { memory };
+        if store.memory[..MAGIC.len()] != MAGIC {
+            store.clear();
+        }
+        // drop any corrupt records, so appending cannot revive stale ones behind them
+        let end = store.end();
+        store.terminate(end);
+        store
+    }
+
+    /// Delete all records.
+    pub fn clear(&mut self) {
+        self.memory[..MAGIC.len()].copy_from_slice(&MAGIC);
+        self.terminate(MAGIC.len());
+    }
+
+    pub fn records(&self) -> Records<'_> {
+        Records {
+            memory: self.memory,
+            offset: MAGIC.len(),
+        }
+    }
+
+    pub fn get(&self, key: Key) -> Option<&[u8]> {
+        self.records().find(|&(k, _)| k == key).map(|(_, data)| data)
+    }
+
+    /// Store `data` under `key`, replacing any previous record.
+    pub fn set(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        assert_ne!(key.0, END, "key {END:#06x} is reserved");
+        let len = u16::try_from(data.len()).map_err(|_| Error::Full)?;
+        let existing = self.locate(key);
+        if let Some(located) = &existing {
+            if located.len == record_len(data.len()) {
+                self.write(located.offset, key, len, data);
+                return Ok(());
+            }
+        }
+
+        let reclaimed = existing.as_ref().map_or(0, |located| located.len);
+        if record_len(data.len()) > self.free() + reclaimed {
+            return Err(Error::Full);
+        }
+        if let Some(located) = existing {
+            self.delete(located);
+        }
+        let end = self.end();
+        self.write(end, key, len, data);
+        self.terminate(end + record_len(data.len()));
+        Ok(())
+    }
+
+    /// Delete the record under `key`. Returns whether there was one.
+    pub fn remove(&mut self, key: Key) -> bool {
+        let located = self.locate(key);
+        let found = located.is_some();
+        if let Some(located) = located {
+            self.delete(located);
+        }
+        found
+    }
+
+    /// Bytes available for a new record, including its header.
+    pub fn free(&self) -> usize {
+        self.memory.len() - END_LEN - self.end()
+    }
+
+    fn locate(&self, key: Key) -> Option<Located> {
+        let mut records = self.records();
+        loop {
+            let offset = records.offset;
+            let (k, data) = records.next()?;
+            if k == key {
+                return Some(Located {
+                    offset,
+                    len: record_len(data.len()),
+                });
+            }
+        }
+    }
+
+    /// Offset of the end marker.
+    fn end(&self) -> usize {
+        let mut records = self.records();
+        while records.next().is_some() {}
+        records.offset
+    }
+
+    fn delete(&mut self, located: Located) {
+        let end = self.end();
+        self.memory.copy_within(located.offset + located.len..end, located.offset);
+        self.terminate(end - located.len);
+    }
+
+    fn write(&mut self, offset: usize, key: Key, len: u16, data: &[u8]) {
+        let record = &mut self.memory[offset..offset + record_len(data.len())];
+        record[..2].copy_from_slice(&key.0.to_le_bytes());
+        record[2..4].copy_from_slice(&len.to_le_bytes());
+        record[4..8].copy_from_slice(&crc(key, data).to_le_bytes());
+        record[HEADER_LEN..HEADER_LEN + data.len()].copy_from_slice(data);
+        record[HEADER_LEN + data.len()..].fill(0);
+    }
+
+    fn terminate(&mut self, offset: usize) {
+        self.memory[offset..offset + END_LEN].copy_from_slice(&[0xFF; END_LEN]);
+    }
+}
+
+impl<'s> Iterator for Records<'s> {
+    type Item = (Key, &'s [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.memory.get(self.offset..self.offset + HEADER_LEN)?;
+        let key = Key(u16::from_le_bytes([header[0], header[1]]));
+        let len = usize::from(u16::from_le_bytes([header[2], header[3]]));
+        let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if key.0 == END || self.offset + record_len(len) + END_LEN > self.memory.len() {
+            return None;
+        }
+        let data = &self.memory[self.offset + HEADER_LEN..][..len];
+        if crc(key, data) != checksum {
+            return None;
+        }
+        self.offset += record_len(len);
+        Some((key, data))
+    }
+}
+
+fn record_len(data_len: usize) -> usize {
+    HEADER_LEN + data_len.next_multiple_of(4)
+}
+
+fn crc(key: Key, data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&key.0.to_le_bytes());
+    crc.update(&(data.len() as u16).to_le_bytes());
+    crc.update(data);
+    crc.finish()
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            | Key::PANIC => write!(f, "panic"),
+            | Key::RESET_REASON => write!(f, "reset reason"),
+            | Key::BOOT_COUNT => write!(f, "boot count"),
+            | Key::CLI_PASSWORD => write!(f, "cli password"),
+            | Key(key) => write!(f, "{key:#06x}"),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Uninitialized => write!(f, "backup SRAM not initialized"),
+            | Error::Full => write!(f, "backup SRAM full"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store() {
+        let mut memory = [0; 64];
+        let mut store = Store::new(&mut memory);
+        assert_eq!(store.free(), 64 - 4 - 4);
+        store.set(Key::BOOT_COUNT, &7u32.to_le_bytes()).unwrap();
+        store.set(Key::PANIC, b"oops").unwrap();
+        // same size: in place; other size: moved to the end
+        store.set(Key::BOOT_COUNT, &8u32.to_le_bytes()).unwrap();
+        store.set(Key::PANIC, b"out of memory").unwrap();
+        assert_eq!(store.set(Key::RESET_REASON, &[0; 16]), Err(Error::Full));
+        assert_eq!(store.get(Key::BOOT_COUNT), Some(&8u32.to_le_bytes()[..]));
+
+        // survives a reset
+        let mut store = Store::new(&mut memory);
+        let keys = store.records().map(|(key, _)| key);
+        assert!(keys.eq([Key::BOOT_COUNT, Key::PANIC]));
+        assert_eq!(store.get(Key::PANIC), Some(&b"out of memory"[..]));
+        assert!(store.remove(Key::BOOT_COUNT));
+        assert!(!store.remove(Key::BOOT_COUNT));
+        assert_eq!(store.get(Key::PANIC), Some(&b"out of memory"[..]));
+    }
+
+    #[test]
+    fn test_corruption() {
+        let mut memory = [0xA5; 64];
+        let mut store = Store::new(&mut memory);
+        assert_eq!(store.records().count(), 0);
+        store.set(Key(10), b"first").unwrap();
+        store.set(Key(11), b"second").unwrap();
+
+        // a torn write of the second record loses it, but not the first
+        memory[4 + 16 + HEADER_LEN] ^= 1;
+        let store = Store::new(&mut memory);
+        assert!(store.records().eq([(Key(10), &b"first"[..])]));
+    }
+}
@@ -0,0 +1,233 @@
+//! Status and maintenance of the Cortex-M7 L1 caches.
+//!
+//! The caches are off out of reset. Enabling the D-cache requires
+//! an [MPU map](super::mpu) keeping DMA buffers coherent first.
+
+use core::fmt;
+use core::fmt::Display;
+
+#[cfg(feature = "cross")]
+use cortex_m::asm;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::CBP;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::CPUID;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::DWT;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::SCB;
+
+/// CSSELR value selecting the L1 data cache
+#[cfg(feature = "cross")]
+const SELECT_DCACHE: u32 = 0;
+/// CSSELR value selecting the L1 instruction cache
+#[cfg(feature = "cross")]
+const SELECT_ICACHE: u32 = 1;
+
+/// Organisation of a cache, as reported by CCSIDR.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Geometry {
+    pub sets: u32,
+    pub ways: u32,
+    /// line size in bytes
+    pub line: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Status {
+    pub icache: bool,
+    pub dcache: bool,
+    pub icache_geometry: Geometry,
+    pub dcache_geometry: Geometry,
+}
+
+/// Cycles taken to read the same memory twice, see [`probe`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Probe {
+    pub len: usize,
+    /// right after cleaning and invalidating the D-cache
+    pub cold: u32,
+    pub warm: u32,
+}
+
+impl Geometry {
+    pub fn from_ccsidr(ccsidr: u32) -> Self {
+        Self {
+            sets: (ccsidr >> 13 & 0x7FFF) + 1,
+            ways: (ccsidr >> 3 & 0x3FF) + 1,
+            line: 1 << ((ccsidr & 0b111) + 4),
+        }
+    }
+
+    /// Capacity in bytes.
+    pub fn size(&self) -> u32 {
+        self.sets * self.ways * self.line
+    }
+
+    /// Set/way operands addressing every line, as taken by DCCSW and DCCISW.
+    pub fn set_ways(&self) -> impl Iterator<Item = u32> {
+        let set_shift = self.line.trailing_zeros();
+        let way_shift = 32 - self.ways.next_power_of_two().trailing_zeros();
+        let Self { sets, ways, .. } = *self;
+        (0..ways).flat_map(move |way| {
+            (0..sets).map(move |set| {
+                way.checked_shl(way_shift).unwrap_or(0) | set << set_shift
+            })
+        })
+    }
+}
+
+/// Enable both caches, invalidating them first.
+///
+/// Configure the [MPU](super::mpu::configure) beforehand.
+#[cfg(feature = "cross")]
+pub fn enable(scb: &mut SCB, cpuid: &mut CPUID) {
+    scb.enable_icache();
+    scb.enable_dcache(cpuid);
+}
+
+#[cfg(feature = "cross")]
+pub fn status() -> Status {
+    Status {
+        icache: SCB::icache_enabled(),
+        dcache: SCB::dcache_enabled(),
+        icache_geometry: geometry(SELECT_ICACHE),
+        dcache_geometry: geometry(SELECT_DCACHE),
+    }
+}
+
+#[cfg(feature = "cross")]
+fn geometry(select: u32) -> Geometry {
+    // Safety: CSSELR only selects which cache CCSIDR describes.
+    // Interrupts are masked so no one else selects another cache in between.
+    let ccsidr = cortex_m::interrupt::free(|_| unsafe {
+        let cpuid = &*CPUID::PTR;
+        cpuid.csselr.write(select);
+        asm::dsb();
+        cpuid.ccsidr.read()
+    });
+    Geometry::from_ccsidr(ccsidr)
+}
+
+/// Write all dirty D-cache lines back to memory.
+#[cfg(feature = "cross")]
+pub fn clean() {
+    asm::dsb();
+    for set_way in geometry(SELECT_DCACHE).set_ways() {
+        // Safety: cleaning only writes back data that is already there
+        unsafe { (*CBP::PTR).dccsw.write(set_way) };
+    }
+    asm::dsb();
+    asm::isb();
+}
+
+/// Write all dirty D-cache lines back to memory and discard the whole D-cache.
+#[cfg(feature = "cross")]
+pub fn clean_invalidate() {
+    asm::dsb();
+    for set_way in geometry(SELECT_DCACHE).set_ways() {
+        // Safety: lines are written back before being discarded
+        unsafe { (*CBP::PTR).dccisw.write(set_way) };
+    }
+    asm::dsb();
+    asm::isb();
+}
+
+/// Time reading `data` right after [`clean_invalidate`] and once more.
+///
+/// The difference hints at how many of the accesses hit the cache.
+/// Requires the cycle counter, see [`profile::enable`](crate::util::profile::enable).
+#[cfg(feature = "cross")]
+pub fn probe(data: &[u8]) -> Probe {
+    let read = || {
+        let start = DWT::cycle_count();
+        for word in data.chunks_exact(4) {
+            // Safety: `word` is a valid, readable 4-byte range
+            core::hint::black_box(unsafe {
+                word.as_ptr().cast::<u32>().read_unaligned()
+            });
+        }
+        DWT::cycle_count().wrapping_sub(start)
+    };
+    clean_invalidate();
+    let cold = read();
+    let warm = read();
+    Probe {
+        len: data.len(),
+        cold,
+        warm,
+    }
+}
+
+impl Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} KiB, {} sets, {} ways, {} B lines",
+            self.size() >> 10,
+            self.sets,
+            self.ways,
+            self.line
+        )
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = |enabled| if enabled { "on" } else { "off" };
+        writeln!(
+            f,
+            "icache: {} ({})",
+            state(self.icache),
+            self.icache_geometry
+        )?;
+        write!(
+            f,
+            "dcache: {} ({})",
+            state(self.dcache),
+            self.dcache_geometry
+        )
+    }
+}
+
+impl Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read {} bytes: {} cycles cold, {} cycles warm",
+            self.len, self.cold, self.warm
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geometry() {
+        // STM32F769 L1 D-cache: write-back, 16 KiB, 4 ways, 32-byte lines
+        let geometry = Geometry::from_ccsidr(0xF00F_E019);
+        assert_eq!(
+            geometry,
+            Geometry {
+                sets: 128,
+                ways: 4,
+                line: 32,
+            }
+        );
+        assert_eq!(geometry.size(), 16 << 10);
+
+        let mut set_ways = geometry.set_ways();
+        assert_eq!(set_ways.next(), Some(0x0000_0000));
+        assert_eq!(set_ways.next(), Some(0x0000_0020));
+        assert_eq!(set_ways.nth(127), Some(0x4000_0020));
+        assert_eq!(set_ways.last(), Some(0xC000_0FE0));
+    }
+}
@@ -0,0 +1,80 @@
+//! Buffers shared with DMA masters while the D-cache is enabled.
+
+use core::mem;
+use core::ops::Deref;
+use core::ops::DerefMut;
+
+#[cfg(feature = "cross")]
+use super::mpu;
+
+/// A value occupying whole D-cache lines.
+///
+/// Cache maintenance on the buffer cannot affect neighbouring data,
+/// so it is safe to [invalidate](DmaBuffer::invalidate) at any time.
+///
+/// [`clean`](DmaBuffer::clean) it before a DMA master reads it,
+/// and [`invalidate`](DmaBuffer::invalidate) it before and after a DMA master writes it.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+pub struct DmaBuffer<T> {
+    value: T,
+}
+
+const _: () =
+    assert!(mem::align_of::<DmaBuffer<u8>>() == super::mpu::CACHE_LINE as usize);
+
+impl<T> DmaBuffer<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Write the CPU's changes back to memory.
+    #[cfg(feature = "cross")]
+    pub fn clean(&self) {
+        mpu::clean_range(self as *const Self as u32, mem::size_of::<Self>());
+    }
+
+    /// Discard cached contents, so the CPU reads what is in memory.
+    #[cfg(feature = "cross")]
+    pub fn invalidate(&mut self) {
+        // Safety: the buffer is aligned and padded to whole cache lines,
+        // and unreferenced
+        unsafe {
+            mpu::invalidate_range(self as *const Self as u32, mem::size_of::<Self>())
+        };
+    }
+}
+
+impl<T> Deref for DmaBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(mem::size_of::<DmaBuffer<[u8; 3]>>(), 32);
+        assert_eq!(mem::size_of::<DmaBuffer<[u8; 33]>>(), 64);
+        assert_eq!(mem::size_of::<DmaBuffer<[u32; 64]>>(), 256);
+        let buffer = DmaBuffer::new([0u8; 3]);
+        assert_eq!(&buffer as *const _ as usize % 32, 0);
+    }
+}
@@ -0,0 +1,296 @@
+//! Cortex-M7 MPU regions and D-cache maintenance.
+//!
+//! The MPU decides how the core caches each address range.
+//! Without a region, the default map treats external RAM (SDRAM at `0xC000_0000`)
+//! as device memory, which is neither cached nor allows unaligned accesses.
+//! Buffers shared with a DMA master need either a non-cacheable or write-through region,
+//! or explicit [`clean`]/[`invalidate`] calls around each transfer.
+
+use core::fmt;
+use core::fmt::Display;
+use core::range::Range;
+
+#[cfg(feature = "cross")]
+use cortex_m::asm;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::CBP;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::MPU;
+
+/// size of a D-cache line of the Cortex-M7
+pub const CACHE_LINE: u32 = 32;
+/// number of regions implemented by the STM32F7 MPU
+pub const REGIONS: usize = 8;
+
+const MIN_SIZE: u32 = 32;
+
+const CTRL_ENABLE: u32 = 1 << 0;
+/// keep the default map for privileged accesses outside of all regions
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+const RBAR_VALID: u32 = 1 << 4;
+
+const RASR_ENABLE: u32 = 1 << 0;
+const RASR_SIZE_SHIFT: u32 = 1;
+const RASR_B: u32 = 1 << 16;
+const RASR_C: u32 = 1 << 17;
+const RASR_S: u32 = 1 << 18;
+const RASR_TEX_SHIFT: u32 = 19;
+const RASR_AP_SHIFT: u32 = 24;
+const RASR_XN: u32 = 1 << 28;
+
+/// A memory region with uniform attributes.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Region {
+    /// must be aligned to its size
+    pub base: u32,
+    /// a power of two of at least 32 bytes
+    pub size: u32,
+    pub memory: Memory,
+    pub access: Access,
+    pub execute: bool,
+}
+
+/// Memory type and cache policy.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Memory {
+    /// accesses happen in program order and are never merged
+    StronglyOrdered,
+    /// memory-mapped registers
+    Device,
+    /// normal memory bypassing the cache, e.g. DMA buffers
+    NonCacheable,
+    /// reads are cached, writes go straight to memory, e.g. framebuffers
+    WriteThrough,
+    /// write-back, write-allocate, e.g. the heap
+    WriteBack,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Access {
+    None,
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the size is not a power of two of at least 32 bytes
+    Size(u32),
+    /// the base address is not aligned to the size
+    Misaligned(u32),
+    /// more than [`REGIONS`] regions
+    TooMany,
+    /// the range to invalidate does not consist of whole cache lines
+    PartialLine,
+}
+
+impl Region {
+    pub const fn new(base: u32, size: u32, memory: Memory) -> Self {
+        Self {
+            base,
+            size,
+            memory,
+            access: Access::ReadWrite,
+            execute: false,
+        }
+    }
+
+    pub const fn read_only(self) -> Self {
+        Self {
+            access: Access::ReadOnly,
+            ..self
+        }
+    }
+
+    pub const fn executable(self) -> Self {
+        Self {
+            execute: true,
+            ..self
+        }
+    }
+
+    /// RBAR and RASR values of this region in slot `number`.
+    fn encode(&self, number: usize) -> Result<(u32, u32), Error> {
+        if self.size < MIN_SIZE || !self.size.is_power_of_two() {
+            return Err(Error::Size(self.size));
+        }
+        if self.base % self.size != 0 {
+            return Err(Error::Misaligned(self.base));
+        }
+        let rbar = self.base | RBAR_VALID | number as u32;
+
+        // (TEX, C, B, S) per ARMv7-M ARM, table B3-13
+        let (tex, c, b, s) = match self.memory {
+            | Memory::StronglyOrdered => (0b000, false, false, true),
+            | Memory::Device => (0b000, false, true, true),
+            | Memory::NonCacheable => (0b001, false, false, false),
+            | Memory::WriteThrough => (0b000, true, false, false),
+            | Memory::WriteBack => (0b001, true, true, false),
+        };
+        let ap = match self.access {
+            | Access::None => 0b000,
+            | Access::ReadOnly => 0b110,
+            | Access::ReadWrite => 0b011,
+        };
+        let mut rasr = RASR_ENABLE
+            | (self.size.trailing_zeros() - 1) << RASR_SIZE_SHIFT
+            | tex << RASR_TEX_SHIFT
+            | ap << RASR_AP_SHIFT;
+        for (set, bit) in [
+            (c, RASR_C),
+            (b, RASR_B),
+            (s, RASR_S),
+            (!self.execute, RASR_XN),
+        ] {
+            if set {
+                rasr |= bit;
+            }
+        }
+        Ok((rbar, rasr))
+    }
+}
+
+/// Program `regions` and enable the MPU.
+///
+/// Where regions overlap, the later one takes precedence.
+/// Addresses outside of all regions keep the default map.
+/// Caches holding lines of reconfigured ranges should be cleaned beforehand.
+#[cfg(feature = "cross")]
+pub fn configure(mpu: &mut MPU, regions: &[Region]) -> Result<(), Error> {
+    if regions.len() > REGIONS {
+        return Err(Error::TooMany);
+    }
+    let mut encoded = [(0, 0); REGIONS];
+    for (number, region) in regions.iter().enumerate() {
+        encoded[number] = region.encode(number)?;
+    }
+
+    asm::dmb();
+    // Safety: the MPU is disabled while regions are rewritten,
+    // and every region has been validated
+    unsafe {
+        mpu.ctrl.write(0);
+        for (number, (rbar, rasr)) in encoded.into_iter().enumerate() {
+            mpu.rnr.write(number as u32);
+            mpu.rbar.write(rbar);
+            // unused slots are disabled
+            mpu.rasr.write(rasr);
+        }
+        mpu.ctrl.write(CTRL_ENABLE | CTRL_PRIVDEFENA);
+    }
+    asm::dsb();
+    asm::isb();
+    Ok(())
+}
+
+/// Addresses of the cache lines covering `len` bytes at `address`.
+pub fn lines(address: u32, len: usize) -> impl Iterator<Item = u32> {
+    let start = address & !(CACHE_LINE - 1);
+    let end = address.saturating_add(len as u32);
+    Range { start, end }.into_iter().step_by(CACHE_LINE as usize)
+}
+
+/// Write the cached contents of `data` back to memory,
+/// e.g. before a DMA master reads it.
+#[cfg(feature = "cross")]
+pub fn clean(data: &[u8]) {
+    clean_range(data.as_ptr() as u32, data.len());
+}
+
+/// [`clean`] the cache lines covering `len` bytes at `address`.
+#[cfg(feature = "cross")]
+pub fn clean_range(address: u32, len: usize) {
+    asm::dsb();
+    for line in lines(address, len) {
+        // Safety: cleaning only writes back data that is already there
+        unsafe { (*CBP::PTR).dccmvac.write(line) };
+    }
+    asm::dsb();
+    asm::isb();
+}
+
+/// Discard cached contents of `data`, e.g. after a DMA master wrote it.
+///
+/// `data` has to consist of whole cache lines,
+/// since discarding a partial line also drops pending writes to its other bytes.
+#[cfg(feature = "cross")]
+pub fn invalidate(data: &mut [u8]) -> Result<(), Error> {
+    let address = data.as_ptr() as u32;
+    if address % CACHE_LINE != 0 || data.len() as u32 % CACHE_LINE != 0 {
+        return Err(Error::PartialLine);
+    }
+    // Safety: the lines belong to `data` exclusively
+    unsafe { invalidate_range(address, data.len()) };
+    Ok(())
+}
+
+/// [`invalidate`] the cache lines covering `len` bytes at `address`.
+///
+/// # Safety
+///
+/// Pending writes to any byte of these lines are lost,
+/// including bytes outside of the range.
+#[cfg(feature = "cross")]
+pub unsafe fn invalidate_range(address: u32, len: usize) {
+    asm::dsb();
+    for line in lines(address, len) {
+        (*CBP::PTR).dcimvac.write(line);
+    }
+    asm::dsb();
+    asm::isb();
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Size(size) => write!(f, "invalid region size {size:#x}"),
+            | Error::Misaligned(base) => {
+                write!(f, "region base {base:#010x} is not aligned to its size")
+            }
+            | Error::TooMany => write!(f, "at most {REGIONS} regions are supported"),
+            | Error::PartialLine => write!(f, "range is not cache line aligned"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // 16 MiB of SDRAM, write-back write-allocate
+        let sdram = Region::new(0xC000_0000, 16 << 20, Memory::WriteBack);
+        assert_eq!(sdram.encode(0), Ok((0xC000_0010, 0x130B_002F)));
+        // 256 KiB of read-only, write-through flash
+        let flash = Region::new(0x0800_0000, 256 << 10, Memory::WriteThrough)
+            .read_only()
+            .executable();
+        assert_eq!(flash.encode(7), Ok((0x0800_0017, 0x0602_0023)));
+
+        let region = Region::new(0xC000_0100, 0x200, Memory::NonCacheable);
+        assert_eq!(region.encode(0), Err(Error::Misaligned(0xC000_0100)));
+        let region = Region::new(0xC000_0000, 0x300, Memory::NonCacheable);
+        assert_eq!(region.encode(0), Err(Error::Size(0x300)));
+        let region = Region::new(0xC000_0000, 16, Memory::NonCacheable);
+        assert_eq!(region.encode(0), Err(Error::Size(16)));
+    }
+
+    #[test]
+    fn test_lines() {
+        assert!(lines(0x2000_0010, 0x30).eq([0x2000_0000, 0x2000_0020]));
+        assert!(lines(0x2000_0020, 0x20).eq([0x2000_0020]));
+        assert_eq!(lines(0x2000_0020, 0).count(), 0);
+    }
+}
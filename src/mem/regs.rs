@@ -0,0 +1,367 @@
+//! Field-decoded dumps of the display peripherals' registers.
+//!
+//! The register layouts follow RM0410 and only cover what matters during
+//! panel bring-up: timings, enables, layer setup, PLL and PHY state, and
+//! transfer setup. Registers are read like [`mem::read`] does with peripheral
+//! access opted into; reading these has no side effects.
+
+use core::fmt;
+use core::fmt::Display;
+
+use crate::mem;
+
+/// Peripherals that can be dumped.
+pub const BLOCKS: &[Block] = &[LTDC, DSI, DMA2D];
+
+pub const LTDC: Block = Block {
+    name: "ltdc",
+    base: 0x4001_6800,
+    registers: &[
+        reg("SSCR", 0x08, &[field("HSW", 16, 12), field("VSH", 0, 11)]),
+        reg("BPCR", 0x0C, &[field("AHBP", 16, 12), field("AVBP", 0, 11)]),
+        reg("AWCR", 0x10, &[field("AAW", 16, 12), field("AAH", 0, 11)]),
+        reg(
+            "TWCR",
+            0x14,
+            &[field("TOTALW", 16, 12), field("TOTALH", 0, 11)],
+        ),
+        reg(
+            "GCR",
+            0x18,
+            &[
+                field("HSPOL", 31, 1),
+                field("VSPOL", 30, 1),
+                field("DEPOL", 29, 1),
+                field("PCPOL", 28, 1),
+                field("DEN", 16, 1),
+                field("LTDCEN", 0, 1),
+            ],
+        ),
+        reg("SRCR", 0x24, &[field("VBR", 1, 1), field("IMR", 0, 1)]),
+        reg("BCCR", 0x2C, &[field("BC", 0, 24)]),
+        reg("IER", 0x34, &[]),
+        reg(
+            "ISR",
+            0x38,
+            &[
+                field("RRIF", 3, 1),
+                field("TERRIF", 2, 1),
+                field("FUIF", 1, 1),
+                field("LIF", 0, 1),
+            ],
+        ),
+        reg(
+            "CPSR",
+            0x44,
+            &[field("CXPOS", 16, 16), field("CYPOS", 0, 16)],
+        ),
+        reg(
+            "CDSR",
+            0x48,
+            &[
+                field("HSYNCS", 3, 1),
+                field("VSYNCS", 2, 1),
+                field("HDES", 1, 1),
+                field("VDES", 0, 1),
+            ],
+        ),
+        reg(
+            "L1CR",
+            0x84,
+            &[
+                field("CLUTEN", 4, 1),
+                field("COLKEN", 1, 1),
+                field("LEN", 0, 1),
+            ],
+        ),
+        reg(
+            "L1WHPCR",
+            0x88,
+            &[field("WHSPPOS", 16, 12), field("WHSTPOS", 0, 12)],
+        ),
+        reg(
+            "L1WVPCR",
+            0x8C,
+            &[field("WVSPPOS", 16, 11), field("WVSTPOS", 0, 11)],
+        ),
+        reg("L1PFCR", 0x94, &[field("PF", 0, 3)]),
+        reg("L1CACR", 0x98, &[field("CONSTA", 0, 8)]),
+        reg("L1CFBAR", 0xAC, &[]),
+        reg(
+            "L1CFBLR",
+            0xB0,
+            &[field("CFBP", 16, 13), field("CFBLL", 0, 13)],
+        ),
+        reg("L1CFBLNR", 0xB4, &[field("CFBLNBR", 0, 11)]),
+        reg(
+            "L2CR",
+            0x104,
+            &[
+                field("CLUTEN", 4, 1),
+                field("COLKEN", 1, 1),
+                field("LEN", 0, 1),
+            ],
+        ),
+        reg("L2PFCR", 0x114, &[field("PF", 0, 3)]),
+        reg("L2CFBAR", 0x12C, &[]),
+    ],
+};
+
+pub const DSI: Block = Block {
+    name: "dsi",
+    base: 0x4001_6C00,
+    registers: &[
+        reg("VR", 0x00, &[]),
+        reg("CR", 0x04, &[field("EN", 0, 1)]),
+        reg(
+            "CCR",
+            0x08,
+            &[field("TOCKDIV", 8, 8), field("TXECKDIV", 0, 8)],
+        ),
+        reg("LCOLCR", 0x10, &[field("LPE", 8, 1), field("COLC", 0, 4)]),
+        reg(
+            "LPCR",
+            0x14,
+            &[field("HSP", 2, 1), field("VSP", 1, 1), field("DEP", 0, 1)],
+        ),
+        reg("MCR", 0x34, &[field("CMDM", 0, 1)]),
+        reg(
+            "VMCR",
+            0x38,
+            &[
+                field("PGE", 16, 1),
+                field("LPCE", 15, 1),
+                field("VMT", 0, 2),
+            ],
+        ),
+        reg("VPCR", 0x3C, &[field("VPSIZE", 0, 14)]),
+        reg("CLCR", 0x94, &[field("ACR", 1, 1), field("DPCC", 0, 1)]),
+        reg("PCTLR", 0xA0, &[field("CKE", 2, 1), field("DEN", 1, 1)]),
+        reg(
+            "PSR",
+            0xB0,
+            &[
+                field("UAN1", 8, 1),
+                field("PSS1", 7, 1),
+                field("UAN0", 5, 1),
+                field("PSS0", 4, 1),
+                field("UANC", 3, 1),
+                field("PSSC", 2, 1),
+                field("PD", 1, 1),
+            ],
+        ),
+        reg("ISR0", 0xBC, &[]),
+        reg("ISR1", 0xC0, &[]),
+        reg(
+            "WCR",
+            0x404,
+            &[
+                field("DSIEN", 3, 1),
+                field("LTDCEN", 2, 1),
+                field("SHTDN", 1, 1),
+                field("COLM", 0, 1),
+            ],
+        ),
+        reg(
+            "WISR",
+            0x40C,
+            &[
+                field("RRIF", 13, 1),
+                field("RRS", 12, 1),
+                field("PLLUIF", 10, 1),
+                field("PLLLIF", 9, 1),
+                field("PLLLS", 8, 1),
+                field("BUSY", 2, 1),
+                field("ERIF", 1, 1),
+                field("TEIF", 0, 1),
+            ],
+        ),
+        reg(
+            "WRPCR",
+            0x430,
+            &[
+                field("REGEN", 24, 1),
+                field("ODF", 16, 2),
+                field("IDF", 11, 4),
+                field("NDIV", 2, 7),
+                field("PLLEN", 0, 1),
+            ],
+        ),
+    ],
+};
+
+pub const DMA2D: Block = Block {
+    name: "dma2d",
+    base: 0x4002_B000,
+    registers: &[
+        reg(
+            "CR",
+            0x00,
+            &[
+                field("MODE", 16, 2),
+                field("ABORT", 2, 1),
+                field("SUSP", 1, 1),
+                field("START", 0, 1),
+            ],
+        ),
+        reg(
+            "ISR",
+            0x04,
+            &[
+                field("CEIF", 5, 1),
+                field("CTCIF", 4, 1),
+                field("CAEIF", 3, 1),
+                field("TWIF", 2, 1),
+                field("TCIF", 1, 1),
+                field("TEIF", 0, 1),
+            ],
+        ),
+        reg("FGMAR", 0x0C, &[]),
+        reg("FGOR", 0x10, &[field("LO", 0, 14)]),
+        reg("BGMAR", 0x14, &[]),
+        reg("BGOR", 0x18, &[field("LO", 0, 14)]),
+        reg(
+            "FGPFCCR",
+            0x1C,
+            &[field("ALPHA", 24, 8), field("AM", 16, 2), field("CM", 0, 4)],
+        ),
+        reg(
+            "BGPFCCR",
+            0x24,
+            &[field("ALPHA", 24, 8), field("AM", 16, 2), field("CM", 0, 4)],
+        ),
+        reg("OPFCCR", 0x34, &[field("CM", 0, 3)]),
+        reg("OCOLR", 0x38, &[]),
+        reg("OMAR", 0x3C, &[]),
+        reg("OOR", 0x40, &[field("LO", 0, 14)]),
+        reg("NLR", 0x44, &[field("PL", 16, 14), field("NL", 0, 16)]),
+    ],
+};
+
+/// A peripheral's register block.
+#[derive(Debug)]
+#[derive(PartialEq, Eq)]
+pub struct Block {
+    pub name: &'static str,
+    pub base: u32,
+    pub registers: &'static [Register],
+}
+
+#[derive(Debug)]
+#[derive(PartialEq, Eq)]
+pub struct Register {
+    pub name: &'static str,
+    /// from the block's base
+    pub offset: u32,
+    /// most significant first; empty to only show the whole value
+    pub fields: &'static [Field],
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub lsb: u8,
+    pub width: u8,
+}
+
+/// A register value, displayed with its fields.
+pub struct Decoded<'r> {
+    pub register: &'r Register,
+    pub value: u32,
+}
+
+const fn reg(name: &'static str, offset: u32, fields: &'static [Field]) -> Register {
+    Register {
+        name,
+        offset,
+        fields,
+    }
+}
+
+const fn field(name: &'static str, lsb: u8, width: u8) -> Field {
+    Field { name, lsb, width }
+}
+
+/// The block called `name`, e.g. `ltdc`.
+pub fn find(name: &[u8]) -> Option<&'static Block> {
+    BLOCKS.iter().find(|block| block.name.as_bytes() == name)
+}
+
+impl Register {
+    /// Read the register of `block`.
+    pub fn read(&self, block: &Block) -> Result<u32, mem::Error> {
+        let mut value = [0; 4];
+        mem::read(block.base + self.offset, &mut value, true)?;
+        Ok(u32::from_le_bytes(value))
+    }
+}
+
+impl Field {
+    pub const fn get(&self, value: u32) -> u32 {
+        (value >> self.lsb) & (u32::MAX >> (32 - self.width as u32))
+    }
+}
+
+impl Display for Decoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Register { name, offset, .. } = self.register;
+        write!(f, "{name:<8} +{offset:03x} {:08x}", self.value)?;
+        for field in self.register.fields {
+            match field.width {
+                | 1 => write!(f, " {}={}", field.name, field.get(self.value))?,
+                | _ => write!(f, " {}={:#x}", field.name, field.get(self.value))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use heapless::String;
+
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut out = String::<96>::new();
+        let gcr = &LTDC.registers[4];
+        write!(
+            out,
+            "{}",
+            Decoded {
+                register: gcr,
+                value: 0x8001_2221
+            }
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "GCR      +018 80012221 HSPOL=1 VSPOL=0 DEPOL=0 PCPOL=0 DEN=1 LTDCEN=1"
+        );
+
+        out.clear();
+        let nlr = &DMA2D.registers[12];
+        write!(
+            out,
+            "{}",
+            Decoded {
+                register: nlr,
+                value: 0x0320_01E0
+            }
+        )
+        .unwrap();
+        assert_eq!(out, "NLR      +044 032001e0 PL=0x320 NL=0x1e0");
+
+        assert_eq!(find(b"dsi").map(|block| block.base), Some(0x4001_6C00));
+        assert!(BLOCKS.iter().all(|block| block
+            .registers
+            .iter()
+            .flat_map(|register| register.fields)
+            .all(|field| field.lsb + field.width <= 32)));
+    }
+}
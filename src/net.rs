@@ -0,0 +1,12 @@
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod http;
+pub mod mqtt;
+pub mod phy;
+pub mod ping;
+pub mod sntp;
+pub mod stats;
+pub mod tap;
+pub mod tcp_server;
+pub mod wol;
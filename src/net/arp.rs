@@ -0,0 +1,359 @@
+//! Gratuitous ARP announcements and IPv4 address conflict detection,
+//! loosely following [RFC 5227](https://www.rfc-editor.org/rfc/rfc5227).
+//!
+//! smoltcp handles ARP internally and does not expose it to sockets,
+//! so this is implemented as a [`Driver`] adapter ([`Guarded`])
+//! that sniffs incoming ARP packets and injects outgoing announcements.
+//! [`supervise`] drives announcements whenever an address is acquired
+//! and applies a [`Policy`] when another station claims the same address.
+
+use core::convert::Infallible;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
+use embassy_net::driver::Capabilities;
+use embassy_net::driver::Driver;
+use embassy_net::driver::HardwareAddress;
+use embassy_net::driver::LinkState;
+use embassy_net::driver::RxToken;
+use embassy_net::driver::TxToken;
+use embassy_net::ConfigV4;
+use embassy_net::DhcpConfig;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+use embassy_net::StaticConfigV4;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::Duration;
+use embassy_time::Timer;
+
+/// number of announcements sent after acquiring an address
+pub const ANNOUNCE_NUM: usize = 2;
+/// delay between announcements
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+/// minimum time between reacting to two conflicts
+pub const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+const FRAME_LEN: usize = 42;
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+/// hardware type, protocol type, hardware length, protocol length
+const ARP_ETHERNET_IPV4: [u8; 6] = [0x00, 0x01, 0x08, 0x00, 6, 4];
+const OPER_REQUEST: [u8; 2] = [0x00, 0x01];
+
+/// Shared state between a [`Guarded`] driver and its [`supervise`] task.
+pub struct ConflictDetector {
+    /// the address being defended, or `0` if none
+    address: AtomicU32,
+    /// pending announcements
+    announcements: AtomicU8,
+    conflicts: AtomicU32,
+    conflict: Signal<CriticalSectionRawMutex, Conflict>,
+    runner: AtomicWaker,
+}
+
+/// Another station claiming our address.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Conflict {
+    pub address: Ipv4Address,
+    pub mac: [u8; 6],
+}
+
+/// What to do when an address conflict is detected.
+#[derive(Debug)]
+#[derive(Clone)]
+pub enum Policy {
+    /// only report the conflict and keep defending the address
+    Defend,
+    /// restart DHCP to obtain a different address
+    RestartDhcp(DhcpConfig),
+    /// switch to a fallback static configuration
+    Fallback(StaticConfigV4),
+}
+
+/// [`Driver`] adapter detecting address conflicts and sending gratuitous ARP.
+pub struct Guarded<'d, D> {
+    inner: D,
+    detector: &'d ConflictDetector,
+}
+
+pub struct GuardedRxToken<'d, T> {
+    inner: T,
+    detector: &'d ConflictDetector,
+    mac: Option<[u8; 6]>,
+}
+
+impl ConflictDetector {
+    pub const fn new() -> Self {
+        Self {
+            address: AtomicU32::new(0),
+            announcements: AtomicU8::new(0),
+            conflicts: AtomicU32::new(0),
+            conflict: Signal::new(),
+            runner: AtomicWaker::new(),
+        }
+    }
+
+    /// Set the address to defend, or stop defending if `None`.
+    pub fn watch(&self, address: Option<Ipv4Address>) {
+        let address = address.map_or(0, |address| u32::from_be_bytes(address.0));
+        self.address.store(address, Ordering::Relaxed);
+        self.conflict.reset();
+    }
+
+    /// The address currently being defended.
+    pub fn address(&self) -> Option<Ipv4Address> {
+        match self.address.load(Ordering::Relaxed) {
+            | 0 => None,
+            | address => Some(Ipv4Address(address.to_be_bytes())),
+        }
+    }
+
+    /// Queue a gratuitous ARP announcement for the current address.
+    pub fn announce(&self) {
+        let _ = self.announcements.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |pending| pending.checked_add(1),
+        );
+        self.runner.wake();
+    }
+
+    /// Number of conflicts detected so far.
+    pub fn conflicts(&self) -> u32 {
+        self.conflicts.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the next conflict on the current address.
+    pub async fn wait_conflict(&self) -> Conflict {
+        self.conflict.wait().await
+    }
+
+    fn take_announcement(&self) -> bool {
+        self.announcements
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                pending.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    fn inspect(&self, frame: &[u8], own_mac: [u8; 6]) {
+        let Some(address) = self.address() else {
+            return;
+        };
+        let Some((mac, sender)) = arp_sender(frame) else {
+            return;
+        };
+
+        if sender == address.0 && mac != own_mac {
+            self.conflicts.fetch_add(1, Ordering::Relaxed);
+            self.conflict.signal(Conflict { address, mac });
+        }
+    }
+}
+
+impl Default for ConflictDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d, D: Driver> Guarded<'d, D> {
+    pub fn new(inner: D, detector: &'d ConflictDetector) -> Self {
+        Self { inner, detector }
+    }
+
+    fn mac(&self) -> Option<[u8; 6]> {
+        match self.inner.hardware_address() {
+            | HardwareAddress::Ethernet(mac) => Some(mac),
+            | _ => None,
+        }
+    }
+
+    fn poll_announce(&mut self, cx: &mut Context) {
+        self.detector.runner.register(cx.waker());
+
+        let (Some(address), Some(mac)) = (self.detector.address(), self.mac()) else {
+            return;
+        };
+        if !self.detector.take_announcement() {
+            return;
+        }
+
+        match self.inner.transmit(cx) {
+            | Some(tx) => {
+                tx.consume(FRAME_LEN, |frame| announcement(frame, mac, address.0))
+            }
+            // retry on the next poll
+            | None => self.detector.announce(),
+        }
+    }
+}
+
+impl<D: Driver> Driver for Guarded<'_, D> {
+    type RxToken<'a>
+        = GuardedRxToken<'a, D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        cx: &mut Context,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.poll_announce(cx);
+        let mac = self.mac();
+        let (rx, tx) = self.inner.receive(cx)?;
+        let rx = GuardedRxToken {
+            inner: rx,
+            detector: self.detector,
+            mac,
+        };
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.poll_announce(cx);
+        self.inner.transmit(cx)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+impl<T: RxToken> RxToken for GuardedRxToken<'_, T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let Self {
+            inner,
+            detector,
+            mac,
+        } = self;
+        inner.consume(|frame| {
+            if let Some(mac) = mac {
+                detector.inspect(frame, mac);
+            }
+            f(frame)
+        })
+    }
+}
+
+/// Announce the stack's address whenever it is (re)configured
+/// and apply `policy` on conflicts.
+pub async fn supervise(
+    stack: Stack<'_>,
+    detector: &ConflictDetector,
+    policy: Policy,
+) -> ! {
+    loop {
+        stack.wait_config_up().await;
+        let Some(config) = stack.config_v4() else {
+            Timer::after(ANNOUNCE_INTERVAL).await;
+            continue;
+        };
+        detector.watch(Some(config.address.address()));
+
+        let announce = async {
+            for _ in 0..ANNOUNCE_NUM {
+                detector.announce();
+                Timer::after(ANNOUNCE_INTERVAL).await;
+            }
+            core::future::pending::<Infallible>().await
+        };
+        match select(announce, detector.wait_conflict()).await {
+            | Either::First(never) => match never {},
+            | Either::Second(_conflict) => {}
+        }
+
+        match &policy {
+            | Policy::Defend => detector.announce(),
+            | Policy::RestartDhcp(dhcp) => {
+                detector.watch(None);
+                stack.set_config_v4(ConfigV4::Dhcp(dhcp.clone()));
+            }
+            | Policy::Fallback(fallback) => {
+                detector.watch(None);
+                stack.set_config_v4(ConfigV4::Static(fallback.clone()));
+            }
+        }
+
+        Timer::after(DEFEND_INTERVAL).await;
+    }
+}
+
+/// Returns the sender hardware and protocol address of an Ethernet/IPv4 ARP packet.
+fn arp_sender(frame: &[u8]) -> Option<([u8; 6], [u8; 4])> {
+    let frame = frame.get(..FRAME_LEN)?;
+    if frame[12..14] != ETHERTYPE_ARP || frame[14..20] != ARP_ETHERNET_IPV4 {
+        return None;
+    }
+    let mac = frame[22..28].try_into().ok()?;
+    let address = frame[28..32].try_into().ok()?;
+    Some((mac, address))
+}
+
+/// Write a broadcast gratuitous ARP request for `address` into `frame`.
+fn announcement(frame: &mut [u8], mac: [u8; 6], address: [u8; 4]) {
+    let frame = &mut frame[..FRAME_LEN];
+    // ethernet header
+    frame[0..6].copy_from_slice(&[0xFF; 6]);
+    frame[6..12].copy_from_slice(&mac);
+    frame[12..14].copy_from_slice(&ETHERTYPE_ARP);
+    // ARP payload
+    frame[14..20].copy_from_slice(&ARP_ETHERNET_IPV4);
+    frame[20..22].copy_from_slice(&OPER_REQUEST);
+    frame[22..28].copy_from_slice(&mac);
+    frame[28..32].copy_from_slice(&address);
+    frame[32..38].copy_from_slice(&[0x00; 6]);
+    frame[38..42].copy_from_slice(&address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_roundtrip() {
+        let mac = [0x02, 0xC7, 0x52, 0x67, 0x83, 0xEF];
+        let address = [192, 168, 2, 43];
+        let mut frame = [0; FRAME_LEN];
+        announcement(&mut frame, mac, address);
+
+        assert_eq!(frame[..6], [0xFF; 6]);
+        assert_eq!(frame[6..12], mac);
+        assert_eq!(frame[20..22], OPER_REQUEST);
+        assert_eq!(frame[32..38], [0; 6]);
+        assert_eq!(frame[38..42], address);
+        assert_eq!(arp_sender(&frame), Some((mac, address)));
+    }
+
+    #[test]
+    fn test_arp_sender_rejects_other_frames() {
+        let mut frame = [0; FRAME_LEN];
+        announcement(&mut frame, [1; 6], [10, 0, 0, 1]);
+
+        assert_eq!(arp_sender(&frame[..FRAME_LEN - 1]), None);
+        frame[13] = 0x00;
+        assert_eq!(arp_sender(&frame), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! DHCP lease details.
+//!
+//! embassy-net only exposes the address, gateway and DNS servers of a lease.
+//! [`Snooper`] is a [`Tap`] parsing the DHCPACKs addressed to this station
+//! ([RFC 2131](https://www.rfc-editor.org/rfc/rfc2131),
+//! options per [RFC 2132](https://www.rfc-editor.org/rfc/rfc2132))
+//! to additionally learn the server and lease time.
+
+use core::cell::RefCell;
+
+use embassy_net::Ipv4Address;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use heapless::Vec;
+
+use super::tap::Tap;
+
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const PROTOCOL_UDP: u8 = 17;
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+const OP_REPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+/// fixed BOOTP fields preceding the magic cookie
+const BOOTP_LEN: usize = 236;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+const MESSAGE_ACK: u8 = 5;
+
+/// The last lease acknowledged by a DHCP server.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq, Eq)]
+pub struct Lease {
+    pub server: Option<Ipv4Address>,
+    pub address: Ipv4Address,
+    pub subnet_mask: Option<Ipv4Address>,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address, 3>,
+    /// `None` for infinite leases
+    pub lease_time: Option<Duration>,
+    pub acquired: Instant,
+}
+
+/// [`Tap`] recording the DHCP leases of a station.
+pub struct Snooper {
+    mac: [u8; 6],
+    lease: Mutex<CriticalSectionRawMutex, RefCell<Option<Lease>>>,
+}
+
+impl Lease {
+    /// Time until the lease expires, `None` if it is infinite.
+    pub fn remaining(&self) -> Option<Duration> {
+        let expiry = self.acquired + self.lease_time?;
+        Some(expiry.saturating_duration_since(Instant::now()))
+    }
+}
+
+impl Snooper {
+    /// Record leases of the station with hardware address `mac`.
+    pub const fn new(mac: [u8; 6]) -> Self {
+        Self {
+            mac,
+            lease: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn lease(&self) -> Option<Lease> {
+        self.lease.lock(|lease| lease.borrow().clone())
+    }
+}
+
+impl Tap for Snooper {
+    fn receive(&self, frame: &[u8]) {
+        if let Some(lease) = parse_ack(frame, self.mac, Instant::now()) {
+            self.lease.lock(|current| *current.borrow_mut() = Some(lease));
+        }
+    }
+}
+
+/// Parse an Ethernet frame carrying a DHCPACK for `mac`.
+fn parse_ack(frame: &[u8], mac: [u8; 6], now: Instant) -> Option<Lease> {
+    if frame.get(12..14)? != ETHERTYPE_IPV4 {
+        return None;
+    }
+    let ip = &frame[14..];
+    let header_len = usize::from(ip.first()? & 0x0F) * 4;
+    if *ip.get(9)? != PROTOCOL_UDP {
+        return None;
+    }
+    let udp = ip.get(header_len..)?;
+    let source_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    if (source_port, destination_port) != (SERVER_PORT, CLIENT_PORT) {
+        return None;
+    }
+
+    let bootp = udp.get(8..)?;
+    if *bootp.first()? != OP_REPLY
+        || bootp.get(28..34)? != mac
+        || bootp.get(BOOTP_LEN..BOOTP_LEN + 4)? != MAGIC_COOKIE
+    {
+        return None;
+    }
+    let mut lease = Lease {
+        server: None,
+        address: Ipv4Address(bootp[16..20].try_into().ok()?),
+        subnet_mask: None,
+        router: None,
+        dns_servers: Vec::new(),
+        lease_time: None,
+        acquired: now,
+    };
+
+    let mut ack = false;
+    let mut options = &bootp[BOOTP_LEN + 4..];
+    loop {
+        let (&code, rest) = options.split_first()?;
+        match code {
+            | OPTION_END => break,
+            | OPTION_PAD => {
+                options = rest;
+                continue;
+            }
+            | _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let (data, rest) = rest.split_at_checked(usize::from(len))?;
+        options = rest;
+
+        let address = || Some(Ipv4Address(data.get(..4)?.try_into().ok()?));
+        match code {
+            | OPTION_MESSAGE_TYPE => ack = data == [MESSAGE_ACK],
+            | OPTION_SERVER_ID => lease.server = address(),
+            | OPTION_SUBNET_MASK => lease.subnet_mask = address(),
+            | OPTION_ROUTER => lease.router = address(),
+            | OPTION_DNS => {
+                for server in data.chunks_exact(4) {
+                    let server = Ipv4Address(server.try_into().ok()?);
+                    // further servers are dropped
+                    let _ = lease.dns_servers.push(server);
+                }
+            }
+            | OPTION_LEASE_TIME => {
+                let seconds = u32::from_be_bytes(data.try_into().ok()?);
+                lease.lease_time = (seconds != u32::MAX)
+                    .then(|| Duration::from_secs(u64::from(seconds)));
+            }
+            | _ => {}
+        }
+    }
+    ack.then_some(lease)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0xC7, 0x52, 0x67, 0x83, 0xEF];
+
+    fn ack(options: &[u8]) -> Vec<u8, 400> {
+        let mut frame = Vec::new();
+        // ethernet
+        frame.extend_from_slice(&[0xFF; 6]).unwrap();
+        frame.extend_from_slice(&[0x02; 6]).unwrap();
+        frame.extend_from_slice(&ETHERTYPE_IPV4).unwrap();
+        // IPv4 without options, UDP 67 -> 68
+        let mut ip = [0; 20];
+        ip[0] = 0x45;
+        ip[9] = PROTOCOL_UDP;
+        frame.extend_from_slice(&ip).unwrap();
+        frame.extend_from_slice(&[0, 67, 0, 68, 0, 0, 0, 0]).unwrap();
+        // BOOTP reply offering 192.168.2.43
+        let mut bootp = [0; BOOTP_LEN];
+        bootp[0] = OP_REPLY;
+        bootp[16..20].copy_from_slice(&[192, 168, 2, 43]);
+        bootp[28..34].copy_from_slice(&MAC);
+        frame.extend_from_slice(&bootp).unwrap();
+        frame.extend_from_slice(&MAGIC_COOKIE).unwrap();
+        frame.extend_from_slice(options).unwrap();
+        frame
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let now = Instant::from_secs(100);
+        let frame = ack(&[
+            53, 1, 5, // ACK
+            54, 4, 192, 168, 2, 1, // server
+            0, // pad
+            51, 4, 0, 0, 0x0E, 0x10, // one hour
+            3, 4, 192, 168, 2, 1, // router
+            6, 8, 192, 168, 2, 1, 9, 9, 9, 9, // DNS
+            255,
+        ]);
+        let lease = parse_ack(&frame, MAC, now).unwrap();
+        assert_eq!(lease.server, Some(Ipv4Address([192, 168, 2, 1])));
+        assert_eq!(lease.address, Ipv4Address([192, 168, 2, 43]));
+        assert_eq!(lease.router, Some(Ipv4Address([192, 168, 2, 1])));
+        assert_eq!(
+            lease.dns_servers,
+            [Ipv4Address([192, 168, 2, 1]), Ipv4Address([9, 9, 9, 9])]
+        );
+        assert_eq!(lease.lease_time, Some(Duration::from_secs(3600)));
+        assert_eq!(lease.subnet_mask, None);
+
+        // offers, leases for other stations and truncated options are ignored
+        let offer = ack(&[53, 1, 2, 255]);
+        assert_eq!(parse_ack(&offer, MAC, now), None);
+        assert_eq!(parse_ack(&frame, [0; 6], now), None);
+        assert_eq!(parse_ack(&frame[..frame.len() - 1], MAC, now), None);
+    }
+}
@@ -0,0 +1,460 @@
+//! Minimal DNS stub resolver ([RFC 1035](https://www.rfc-editor.org/rfc/rfc1035)).
+//!
+//! smoltcp's resolver only answers A queries in this IPv4-only build
+//! and always asks the servers of the stack configuration.
+//! This one also handles AAAA and PTR records and can ask any server,
+//! which is what diagnostics like `nslookup` need.
+//!
+//! Servers [`configure`]d at startup override or augment those
+//! of the stack configuration, e.g. the ones obtained via DHCP.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Write as FmtWrite;
+use core::net::Ipv4Addr;
+use core::net::Ipv6Addr;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+use embassy_net::udp::PacketMetadata;
+use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::with_deadline;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use heapless::String;
+use heapless::Vec;
+
+pub const PORT: u16 = 53;
+/// maximum message size over UDP
+pub const MAX_MESSAGE: usize = 512;
+/// time to wait for a response before retrying
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+/// queries sent before giving up
+pub const ATTEMPTS: usize = 3;
+/// servers per source, matching embassy-net's limit
+pub const MAX_SERVERS: usize = 3;
+
+const HEADER_LEN: usize = 12;
+/// header, a name of at most 255 bytes, type and class
+const MAX_QUERY: usize = HEADER_LEN + 255 + 4;
+const MAX_NAME: usize = 255;
+const MAX_LABEL: usize = 63;
+/// compression pointers followed before a name is considered malformed
+const MAX_POINTERS: usize = 16;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+static CONFIGURED: Mutex<CriticalSectionRawMutex, RefCell<Configured>> =
+    Mutex::new(RefCell::new(Configured {
+        servers: Vec::new(),
+        mode: Mode::Augment,
+    }));
+
+/// How [`configure`]d servers combine with those of the stack configuration.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Mode {
+    /// ignore the servers of the stack configuration
+    Override,
+    /// ask the configured servers after those of the stack configuration
+    Augment,
+}
+
+/// Where a server returned by [`servers`] comes from.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Origin {
+    /// the stack configuration, i.e. DHCP or its static configuration
+    Stack,
+    /// [`configure`]
+    Configured,
+}
+
+struct Configured {
+    servers: Vec<Ipv4Address, MAX_SERVERS>,
+    mode: Mode,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Type {
+    A,
+    Aaaa,
+    Ptr,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Record<'m> {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(Name<'m>),
+}
+
+/// A possibly compressed domain name inside a message.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Name<'m> {
+    message: &'m [u8],
+    offset: usize,
+}
+
+/// Answers of [`Type`] in a response; other records, e.g. CNAMEs, are skipped.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Answers<'m> {
+    message: &'m [u8],
+    ty: Type,
+    offset: usize,
+    remaining: u16,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the name to look up is not a valid domain name
+    Name,
+    Send,
+    Timeout,
+    Malformed,
+    /// the server answered with an error code
+    Server(u8),
+}
+
+impl Type {
+    fn code(self) -> u16 {
+        match self {
+            | Type::A => 1,
+            | Type::Aaaa => 28,
+            | Type::Ptr => 12,
+        }
+    }
+}
+
+/// Set the servers combined with those of the stack configuration.
+///
+/// Servers beyond [`MAX_SERVERS`] are ignored.
+pub fn configure(servers: &[Ipv4Address], mode: Mode) {
+    let servers = &servers[..servers.len().min(MAX_SERVERS)];
+    let servers = Vec::from_slice(servers).expect("servers should have been truncated");
+    CONFIGURED.lock(|configured| *configured.borrow_mut() = Configured { servers, mode });
+}
+
+/// The servers to ask, in order of preference.
+pub fn servers(stack: Stack<'_>) -> Vec<(Ipv4Address, Origin), { 2 * MAX_SERVERS }> {
+    let mut servers = Vec::new();
+    CONFIGURED.lock(|configured| {
+        let configured = configured.borrow();
+        if configured.mode == Mode::Augment {
+            let config = stack.config_v4();
+            for &server in config.iter().flat_map(|config| &config.dns_servers) {
+                let _ = servers.push((server, Origin::Stack));
+            }
+        }
+        for &server in &configured.servers {
+            if !servers.iter().any(|&(known, _)| known == server) {
+                let _ = servers.push((server, Origin::Configured));
+            }
+        }
+    });
+    servers
+}
+
+/// Look up `name` on `server`, receiving the response into `buf`.
+pub async fn query<'b>(
+    stack: Stack<'_>,
+    server: Ipv4Address,
+    name: &str,
+    ty: Type,
+    buf: &'b mut [u8; MAX_MESSAGE],
+) -> Result<Answers<'b>, Error> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) ^ Instant::now().as_ticks() as u16;
+    let mut query = [0; MAX_QUERY];
+    let query = encode_query(&mut query, id, name, ty)?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0; MAX_MESSAGE];
+    let mut tx_buf = [0; MAX_QUERY];
+    let mut sock =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    sock.bind(0).expect("binding to an ephemeral port should succeed");
+    let endpoint = IpEndpoint::new(server.into(), PORT);
+
+    let len = 'received: {
+        for _ in 0..ATTEMPTS {
+            sock.send_to(query, endpoint).await.map_err(|_| Error::Send)?;
+            let deadline = Instant::now() + TIMEOUT;
+            while let Ok(received) = with_deadline(deadline, sock.recv_from(buf)).await {
+                match received {
+                    | Ok((len, meta))
+                        if meta.endpoint == endpoint && is_response(&buf[..len], id) =>
+                    {
+                        break 'received len;
+                    }
+                    | _ => {}
+                }
+            }
+        }
+        return Err(Error::Timeout);
+    };
+    answers(&buf[..len], ty)
+}
+
+/// The name to look up the PTR record of `address` under.
+pub fn reverse_name(address: Ipv4Addr) -> String<32> {
+    let [a, b, c, d] = address.octets();
+    let mut name = String::new();
+    write!(name, "{d}.{c}.{b}.{a}.in-addr.arpa").expect("reverse names should fit");
+    name
+}
+
+fn encode_query<'b>(
+    buf: &'b mut [u8; MAX_QUERY],
+    id: u16,
+    name: &str,
+    ty: Type,
+) -> Result<&'b [u8], Error> {
+    buf[..2].copy_from_slice(&id.to_be_bytes());
+    buf[2..4].copy_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // one question, no answer, authority or additional records
+    buf[4..HEADER_LEN].copy_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() + 2 > MAX_NAME {
+        return Err(Error::Name);
+    }
+    let mut len = HEADER_LEN;
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL {
+            return Err(Error::Name);
+        }
+        buf[len] = label.len() as u8;
+        buf[len + 1..][..label.len()].copy_from_slice(label.as_bytes());
+        len += 1 + label.len();
+    }
+    buf[len] = 0;
+    buf[len + 1..len + 3].copy_from_slice(&ty.code().to_be_bytes());
+    buf[len + 3..len + 5].copy_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(&buf[..len + 5])
+}
+
+fn is_response(message: &[u8], id: u16) -> bool {
+    message.len() >= HEADER_LEN
+        && message[..2] == id.to_be_bytes()
+        && u16_at(message, 2).is_some_and(|flags| flags & FLAG_RESPONSE != 0)
+}
+
+/// Check the header of a response and skip its questions.
+fn answers(message: &[u8], ty: Type) -> Result<Answers<'_>, Error> {
+    let flags = u16_at(message, 2).ok_or(Error::Malformed)?;
+    let rcode = (flags & 0xF) as u8;
+    if rcode != 0 {
+        return Err(Error::Server(rcode));
+    }
+    let questions = u16_at(message, 4).ok_or(Error::Malformed)?;
+    let remaining = u16_at(message, 6).ok_or(Error::Malformed)?;
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = skip_name(message, offset).ok_or(Error::Malformed)? + 4;
+    }
+    Ok(Answers {
+        message,
+        ty,
+        offset,
+        remaining,
+    })
+}
+
+impl<'m> Iterator for Answers<'m> {
+    type Item = Record<'m>;
+
+    /// Ends early at malformed records.
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            let start = skip_name(self.message, self.offset)?;
+            let ty = u16_at(self.message, start)?;
+            let class = u16_at(self.message, start + 2)?;
+            let len = usize::from(u16_at(self.message, start + 8)?);
+            let data_start = start + 10;
+            let data = self.message.get(data_start..data_start + len)?;
+            self.offset = data_start + len;
+
+            if class != CLASS_IN || ty != self.ty.code() {
+                continue;
+            }
+            return match self.ty {
+                | Type::A => Some(Record::A(<[u8; 4]>::try_from(data).ok()?.into())),
+                | Type::Aaaa => {
+                    Some(Record::Aaaa(<[u8; 16]>::try_from(data).ok()?.into()))
+                }
+                | Type::Ptr => Some(Record::Ptr(Name {
+                    message: self.message,
+                    offset: data_start,
+                })),
+            };
+        }
+        None
+    }
+}
+
+impl Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Record::A(address) => write!(f, "{address}"),
+            | Record::Aaaa(address) => write!(f, "{address}"),
+            | Record::Ptr(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut offset = self.offset;
+        let mut pointers = 0;
+        let mut first = true;
+        loop {
+            let Some(&len) = self.message.get(offset) else {
+                return f.write_str("<malformed>");
+            };
+            match len {
+                | 0 => return Ok(()),
+                | 0xC0.. => {
+                    pointers += 1;
+                    match u16_at(self.message, offset) {
+                        | Some(pointer) if pointers <= MAX_POINTERS => {
+                            offset = usize::from(pointer & 0x3FFF);
+                        }
+                        | _ => return f.write_str("<malformed>"),
+                    }
+                }
+                | len => {
+                    let start = offset + 1;
+                    let Some(label) = self.message.get(start..start + usize::from(len))
+                    else {
+                        return f.write_str("<malformed>");
+                    };
+                    if !first {
+                        f.write_str(".")?;
+                    }
+                    first = false;
+                    write!(f, "{}", label.escape_ascii())?;
+                    offset = start + usize::from(len);
+                }
+            }
+        }
+    }
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Origin::Stack => write!(f, "stack"),
+            | Origin::Configured => write!(f, "configured"),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Name => write!(f, "invalid name"),
+            | Error::Send => write!(f, "sending query failed"),
+            | Error::Timeout => write!(f, "no response"),
+            | Error::Malformed => write!(f, "malformed response"),
+            | Error::Server(2) => write!(f, "server failure"),
+            | Error::Server(3) => write!(f, "no such name"),
+            | Error::Server(5) => write!(f, "query refused"),
+            | Error::Server(rcode) => write!(f, "server error {rcode}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// The offset past the name starting at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        match *message.get(offset)? {
+            | 0 => return Some(offset + 1),
+            // a pointer ends the name
+            | 0xC0.. => return Some(offset + 2),
+            | len => offset += 1 + usize::from(len),
+        }
+    }
+}
+
+fn u16_at(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let mut buf = [0; MAX_QUERY];
+        let query = encode_query(&mut buf, 0xBEEF, "example.com.", Type::Aaaa).unwrap();
+        assert_eq!(
+            query,
+            b"\xBE\xEF\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+              \x07example\x03com\x00\x00\x1C\x00\x01"
+        );
+        assert_eq!(encode_query(&mut buf, 0, "a..b", Type::A), Err(Error::Name));
+        assert_eq!(
+            reverse_name(Ipv4Addr::new(192, 0, 2, 1)),
+            "1.2.0.192.in-addr.arpa"
+        );
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut buf = [0; MAX_QUERY];
+        let query = encode_query(&mut buf, 7, "a.example", Type::Ptr).unwrap();
+        let mut response = heapless::Vec::<u8, 128>::from_slice(query).unwrap();
+        // response, recursion available, two answers
+        response[2..4].copy_from_slice(&[0x81, 0x80]);
+        response[6..8].copy_from_slice(&[0, 2]);
+        // CNAME a.example -> b.a.example, then PTR b.a.example -> c.a.example
+        response
+            .extend_from_slice(
+                b"\xC0\x0C\x00\x05\x00\x01\x00\x00\x00\x3C\x00\x04\x01b\xC0\x0C",
+            )
+            .unwrap();
+        response
+            .extend_from_slice(
+                b"\xC0\x27\x00\x0C\x00\x01\x00\x00\x00\x3C\x00\x04\x01c\xC0\x0C",
+            )
+            .unwrap();
+
+        assert!(is_response(&response, 7));
+        let mut records = answers(&response, Type::Ptr).unwrap();
+        let Some(Record::Ptr(name)) = records.next() else {
+            panic!("expected a PTR record");
+        };
+        let mut out = String::<32>::new();
+        write!(out, "{name}").unwrap();
+        assert_eq!(out, "c.a.example");
+        assert_eq!(records.next(), None);
+
+        response[3] = 0x83;
+        assert_eq!(answers(&response, Type::Ptr).err(), Some(Error::Server(3)));
+    }
+}
@@ -0,0 +1,443 @@
+//! Tiny HTTP/1.1 server for dashboards polling the device.
+//!
+//! - `GET /status`: uptime, link and IPv4 state plus [`Source::status`] fields as JSON
+//! - `GET /log`: [`Source::log`] as chunked plain text,
+//!   or delimited by closing the connection for HTTP/1.0 clients
+//!
+//! Serves one connection at a time and closes it after every response.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Write as FmtWrite;
+use core::str;
+
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+use heapless::String;
+use memchr::memmem;
+
+use super::stats;
+use super::tcp_server;
+use super::tcp_server::Connection;
+use super::tcp_server::Error;
+use super::tcp_server::Policy;
+use super::tcp_server::Service;
+use crate::util::Cursor;
+
+/// conventional HTTP port
+pub const PORT: u16 = 80;
+/// time a client gets to send its request and to accept the response
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain; charset=utf-8";
+
+/// Provider of device-specific status and log output.
+pub trait Source {
+    /// Add fields to the `/status` object, e.g., memory usage or task stats.
+    fn status(&mut self, status: &mut Object<'_>) -> fmt::Result {
+        let _ = status;
+        Ok(())
+    }
+
+    /// Copy log text starting at `offset` into `buf`.
+    ///
+    /// Returns the number of bytes copied; `0` ends the log.
+    fn log(&mut self, offset: usize, buf: &mut [u8]) -> usize {
+        let _ = (offset, buf);
+        0
+    }
+}
+
+/// Buffers backing the server.
+///
+/// `body` bounds the request head and the `/status` response size.
+pub struct Buffers<const SOCKET: usize, const BODY: usize> {
+    pub socket: tcp_server::Buffers<SOCKET>,
+    pub body: [u8; BODY],
+}
+
+/// Writer for a JSON object.
+pub struct Object<'w> {
+    out: &'w mut dyn FmtWrite,
+    empty: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum Route {
+    Status,
+    Log,
+}
+
+/// HTTP version of a request.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum Version {
+    /// no chunked transfer encoding
+    Http10,
+    Http11,
+}
+
+/// Escapes everything written through it as JSON string content.
+struct Escaped<'w>(&'w mut dyn FmtWrite);
+
+/// [`tcp_server::Handler`] answering a single request per connection.
+struct Server<'b, 's, S> {
+    stack: Stack<'s>,
+    body: &'b mut [u8],
+    source: &'b mut S,
+}
+
+impl Source for () {}
+
+impl<const SOCKET: usize, const BODY: usize> Buffers<SOCKET, BODY> {
+    pub const fn new() -> Self {
+        Self {
+            socket: tcp_server::Buffers::new(),
+            body: [0; BODY],
+        }
+    }
+}
+
+impl<const SOCKET: usize, const BODY: usize> Default for Buffers<SOCKET, BODY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'w> Object<'w> {
+    fn new(out: &'w mut dyn FmtWrite) -> Result<Self, fmt::Error> {
+        out.write_char('{')?;
+        Ok(Self { out, empty: true })
+    }
+
+    pub fn u64(&mut self, key: &str, value: u64) -> fmt::Result {
+        self.key(key)?;
+        write!(self.out, "{value}")
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> fmt::Result {
+        self.key(key)?;
+        write!(self.out, "{value}")
+    }
+
+    pub fn null(&mut self, key: &str) -> fmt::Result {
+        self.key(key)?;
+        self.out.write_str("null")
+    }
+
+    /// Write `value` as a string.
+    pub fn str(&mut self, key: &str, value: impl Display) -> fmt::Result {
+        self.key(key)?;
+        string(self.out, value)
+    }
+
+    /// Write `values` as an array of strings.
+    pub fn strs<I>(&mut self, key: &str, values: I) -> fmt::Result
+    where
+        I: IntoIterator,
+        I::Item: Display,
+    {
+        self.key(key)?;
+        self.out.write_char('[')?;
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.out.write_char(',')?;
+            }
+            string(self.out, value)?;
+        }
+        self.out.write_char(']')
+    }
+
+    pub fn object(
+        &mut self,
+        key: &str,
+        f: impl FnOnce(&mut Object<'_>) -> fmt::Result,
+    ) -> fmt::Result {
+        self.key(key)?;
+        let mut object = Object::new(&mut *self.out)?;
+        f(&mut object)?;
+        object.end()
+    }
+
+    fn key(&mut self, key: &str) -> fmt::Result {
+        if !self.empty {
+            self.out.write_char(',')?;
+        }
+        self.empty = false;
+        string(self.out, key)?;
+        self.out.write_char(':')
+    }
+
+    fn end(self) -> fmt::Result {
+        self.out.write_char('}')
+    }
+}
+
+/// Serve HTTP requests on `port`.
+pub async fn serve<const SOCKET: usize, const BODY: usize>(
+    stack: Stack<'_>,
+    port: u16,
+    buffers: &mut Buffers<SOCKET, BODY>,
+    source: &mut impl Source,
+    stats: &stats::Socket,
+) -> ! {
+    let Buffers { socket, body } = buffers;
+    let service = Service::new(
+        Policy {
+            // without keep-alives, any silence counts
+            dead_peer_timeout: Some(TIMEOUT),
+            keep_alive: None,
+            // there are no request bodies, and the head has to fit into `body`
+            max_request: BODY,
+        },
+        stats,
+    );
+    let mut server = Server {
+        stack,
+        body,
+        source,
+    };
+    service.serve(stack, port, socket, &mut server).await
+}
+
+impl<S: Source> tcp_server::Handler for Server<'_, '_, S> {
+    async fn handle(&mut self, connection: &mut Connection<'_, '_>) -> Result<(), Error> {
+        handle(connection, self.stack, self.body, self.source).await
+    }
+}
+
+async fn handle(
+    socket: &mut Connection<'_, '_>,
+    stack: Stack<'_>,
+    buf: &mut [u8],
+    source: &mut impl Source,
+) -> Result<(), Error> {
+    let mut received = 0;
+    let head = loop {
+        if let Some(end) = memmem::find(&buf[..received], b"\r\n\r\n") {
+            break &buf[..end];
+        }
+        if received == buf.len() {
+            return respond(socket, "431 Request Header Fields Too Large", TEXT, b"")
+                .await;
+        }
+        match socket.read(&mut buf[received..]).await? {
+            | 0 => return Ok(()),
+            | n => received += n,
+        }
+    };
+
+    let (route, version) = match route(head) {
+        | Ok(request) => request,
+        | Err(status) => return respond(socket, status, TEXT, b"").await,
+    };
+
+    match route {
+        | Route::Status => {
+            let mut cursor = Cursor::new(buf);
+            match status(stack, source, &mut cursor) {
+                | Ok(()) => {
+                    let len = cursor.written();
+                    respond(socket, "200 OK", JSON, &buf[..len]).await
+                }
+                | Err(fmt::Error) => {
+                    respond(socket, "500 Internal Server Error", TEXT, b"").await
+                }
+            }
+        }
+        | Route::Log => {
+            let chunked = version == Version::Http11;
+            head_streamed(socket, TEXT, chunked).await?;
+            let mut offset = 0;
+            loop {
+                let len = source.log(offset, buf);
+                if chunked {
+                    let mut size = String::<10>::new();
+                    write!(size, "{len:x}\r\n").expect("chunk size should fit");
+                    socket.write_all(size.as_bytes()).await?;
+                }
+                // without chunks, closing the connection ends the body
+                match (len, chunked) {
+                    | (0, true) => break socket.write_all(b"\r\n").await,
+                    | (0, false) => break Ok(()),
+                    | _ => {}
+                }
+                socket.write_all(&buf[..len]).await?;
+                if chunked {
+                    socket.write_all(b"\r\n").await?;
+                }
+                offset += len;
+            }
+        }
+    }
+}
+
+/// Returns the requested route and the HTTP version of the request,
+/// or the status line to reject the request with.
+fn route(head: &[u8]) -> Result<(Route, Version), &'static str> {
+    const BAD_REQUEST: &str = "400 Bad Request";
+
+    let line_end = memmem::find(head, b"\r\n").unwrap_or(head.len());
+    let line = str::from_utf8(&head[..line_end]).map_err(|_| BAD_REQUEST)?;
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(BAD_REQUEST);
+    };
+    let version = match version {
+        | "HTTP/1.0" => Version::Http10,
+        | version if version.starts_with("HTTP/1.") => Version::Http11,
+        | _ => return Err("505 HTTP Version Not Supported"),
+    };
+
+    let path = target.split('?').next().unwrap_or(target);
+    let route = match path {
+        | "/status" => Route::Status,
+        | "/log" => Route::Log,
+        | _ => return Err("404 Not Found"),
+    };
+    match method {
+        | "GET" => Ok((route, version)),
+        | _ => Err("405 Method Not Allowed"),
+    }
+}
+
+fn status(
+    stack: Stack<'_>,
+    source: &mut impl Source,
+    out: &mut dyn FmtWrite,
+) -> fmt::Result {
+    let mut status = Object::new(out)?;
+    status.u64("uptime_ms", Instant::now().as_millis())?;
+    status.bool("link_up", stack.is_link_up())?;
+    match stack.config_v4() {
+        | Some(config) => status.object("ipv4", |ipv4| {
+            ipv4.str("address", config.address)?;
+            match config.gateway {
+                | Some(gateway) => ipv4.str("gateway", gateway)?,
+                | None => ipv4.null("gateway")?,
+            }
+            ipv4.strs("dns", &config.dns_servers)
+        })?,
+        | None => status.null("ipv4")?,
+    }
+    source.status(&mut status)?;
+    status.end()
+}
+
+async fn respond(
+    socket: &mut Connection<'_, '_>,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), Error> {
+    let mut head = String::<160>::new();
+    write!(
+        head,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )
+    .expect("response head should fit");
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body).await
+}
+
+/// Send the head of a response whose length is not known up front,
+/// its body either `chunked` or ended by closing the connection.
+async fn head_streamed(
+    socket: &mut Connection<'_, '_>,
+    content_type: &str,
+    chunked: bool,
+) -> Result<(), Error> {
+    let encoding = match chunked {
+        | true => "Transfer-Encoding: chunked\r\n",
+        | false => "",
+    };
+    let mut head = String::<128>::new();
+    write!(
+        head,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: {content_type}\r\n\
+         {encoding}\
+         Connection: close\r\n\r\n"
+    )
+    .expect("response head should fit");
+    socket.write_all(head.as_bytes()).await
+}
+
+fn string(out: &mut dyn FmtWrite, value: impl Display) -> fmt::Result {
+    out.write_char('"')?;
+    write!(Escaped(&mut *out), "{value}")?;
+    out.write_char('"')
+}
+
+impl FmtWrite for Escaped<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                | '"' => self.0.write_str("\\\"")?,
+                | '\\' => self.0.write_str("\\\\")?,
+                | c if c.is_control() => write!(self.0, "\\u{:04x}", c as u32)?,
+                | c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(
+            route(b"GET /status HTTP/1.1\r\nHost: x"),
+            Ok((Route::Status, Version::Http11))
+        );
+        assert_eq!(
+            route(b"GET /log?tail HTTP/1.0"),
+            Ok((Route::Log, Version::Http10))
+        );
+        assert_eq!(
+            route(b"GET /log HTTP/2.0"),
+            Err("505 HTTP Version Not Supported")
+        );
+        assert_eq!(route(b"POST /log HTTP/1.1"), Err("405 Method Not Allowed"));
+        assert_eq!(route(b"GET /nope HTTP/1.1"), Err("404 Not Found"));
+        assert_eq!(route(b"GET /status"), Err("400 Bad Request"));
+    }
+
+    #[test]
+    fn test_object() {
+        let mut buf = [0; 128];
+        let mut cursor = Cursor::new(&mut buf);
+        let mut object = Object::new(&mut cursor).unwrap();
+        object.u64("n", 42).unwrap();
+        object.str("s", "a\"b\\\n").unwrap();
+        object.object("o", |o| o.strs("l", [1, 2])).unwrap();
+        object.null("z").unwrap();
+        object.end().unwrap();
+
+        let len = cursor.written();
+        assert_eq!(
+            str::from_utf8(&buf[..len]),
+            Ok(r#"{"n":42,"s":"a\"b\\\u000a","o":{"l":["1","2"]},"z":null}"#)
+        );
+
+        let mut small = [0; 4];
+        let mut cursor = Cursor::new(&mut small);
+        assert_eq!(string(&mut cursor, "long"), Err(fmt::Error));
+    }
+}
@@ -0,0 +1,697 @@
+//! Minimal [MQTT 3.1.1](https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/mqtt-v3.1.1.html) client
+//! for publishing telemetry and receiving remote commands.
+//!
+//! Supports QoS 0 and 1 publishing (QoS 1 messages are not retransmitted),
+//! a single command topic subscription and keep-alive pings.
+//! The subscription asks for QoS 1 at most, so a QoS 2 message from the broker
+//! is a protocol error and ends the session.
+//! [`run`] keeps a session alive, reconnecting with exponential backoff.
+
+use core::error::Error as CoreError;
+use core::fmt::Display;
+use core::str;
+
+use embassy_futures::select::select3;
+use embassy_futures::select::Either3;
+use embassy_net::tcp;
+use embassy_net::tcp::ConnectError;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Stack;
+use embassy_time::with_timeout;
+use embassy_time::Duration;
+use embassy_time::Ticker;
+use embassy_time::Timer;
+use embedded_io_async::Write;
+
+const CONNECT: u8 = 1 << 4;
+const CONNACK: u8 = 2 << 4;
+const PUBLISH: u8 = 3 << 4;
+const PUBACK: u8 = 4 << 4;
+const SUBSCRIBE: u8 = (8 << 4) | 0b0010;
+const SUBACK: u8 = 9 << 4;
+const PINGREQ: u8 = 12 << 4;
+const PINGRESP: u8 = 13 << 4;
+const DISCONNECT: u8 = 14 << 4;
+
+const PROTOCOL_LEVEL: u8 = 4;
+const CLEAN_SESSION: u8 = 1 << 1;
+/// maximum length of a fixed header
+const MAX_HEADER_LEN: usize = 5;
+/// largest value encodable as remaining length
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+pub const MIN_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(64);
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Config<'a> {
+    pub broker: IpEndpoint,
+    pub client_id: &'a str,
+    pub keep_alive: Duration,
+    /// topic the client subscribes to for remote commands
+    pub command_topic: &'a str,
+    /// topic telemetry is published to
+    pub telemetry_topic: &'a str,
+    pub telemetry_interval: Duration,
+    pub telemetry_qos: QoS,
+}
+
+/// Buffers backing an MQTT session.
+pub struct Buffers<const SOCKET: usize, const PACKET: usize> {
+    pub socket_rx: [u8; SOCKET],
+    pub socket_tx: [u8; SOCKET],
+    pub packet_rx: [u8; PACKET],
+    pub packet_tx: [u8; PACKET],
+    pub telemetry: [u8; PACKET],
+}
+
+/// Commands accepted on the command topic, for the caller to apply.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum RemoteCommand {
+    Reboot,
+    LogLevel(u8),
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Packet<'a> {
+    ConnAck {
+        session_present: bool,
+        code: u8,
+    },
+    Publish(Publish<'a>),
+    PubAck {
+        packet_id: u16,
+    },
+    SubAck {
+        packet_id: u16,
+        codes: &'a [u8],
+    },
+    PingResp,
+    /// packets a client does not expect to receive, identified by their type
+    Other(u8),
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Publish<'a> {
+    pub topic: &'a str,
+    /// present for QoS > 0
+    pub packet_id: Option<u16>,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    Connect(ConnectError),
+    Tcp(tcp::Error),
+    /// the broker closed the connection
+    Closed,
+    /// the broker did not respond in time
+    Timeout,
+    /// the broker refused the connection with the given return code
+    Refused(u8),
+    /// malformed packet
+    Protocol,
+    BufferTooSmall,
+}
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<const SOCKET: usize, const PACKET: usize> Buffers<SOCKET, PACKET> {
+    pub const fn new() -> Self {
+        Self {
+            socket_rx: [0; SOCKET],
+            socket_tx: [0; SOCKET],
+            packet_rx: [0; PACKET],
+            packet_tx: [0; PACKET],
+            telemetry: [0; PACKET],
+        }
+    }
+}
+
+impl<const SOCKET: usize, const PACKET: usize> Default for Buffers<SOCKET, PACKET> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RemoteCommand {
+    /// Parses `reboot` and `log-level <n>`.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let payload = str::from_utf8(payload).ok()?;
+        let mut words = payload.split_ascii_whitespace();
+        let command = match (words.next()?, words.next()) {
+            | ("reboot", None) => RemoteCommand::Reboot,
+            | ("log-level", Some(level)) => RemoteCommand::LogLevel(level.parse().ok()?),
+            | _ => return None,
+        };
+        words.next().is_none().then_some(command)
+    }
+}
+
+/// Keep an MQTT session alive, reconnecting with exponential backoff.
+///
+/// `telemetry` writes a payload into the provided buffer and returns its length,
+/// or `None` to skip this interval.
+/// `on_command` is called with every message received on the command topic.
+pub async fn run<const SOCKET: usize, const PACKET: usize>(
+    stack: Stack<'_>,
+    config: &Config<'_>,
+    buffers: &mut Buffers<SOCKET, PACKET>,
+    mut telemetry: impl FnMut(&mut [u8]) -> Option<usize>,
+    mut on_command: impl FnMut(&[u8]),
+) -> ! {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let mut connected = false;
+        let _result = session(
+            stack,
+            config,
+            buffers,
+            &mut connected,
+            &mut telemetry,
+            &mut on_command,
+        )
+        .await;
+
+        if connected {
+            backoff = MIN_BACKOFF;
+        }
+        Timer::after(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session<const SOCKET: usize, const PACKET: usize>(
+    stack: Stack<'_>,
+    config: &Config<'_>,
+    buffers: &mut Buffers<SOCKET, PACKET>,
+    connected: &mut bool,
+    telemetry: &mut impl FnMut(&mut [u8]) -> Option<usize>,
+    on_command: &mut impl FnMut(&[u8]),
+) -> Result<(), Error> {
+    let Buffers {
+        socket_rx,
+        socket_tx,
+        packet_rx,
+        packet_tx,
+        telemetry: payload,
+    } = buffers;
+
+    let mut socket = TcpSocket::new(stack, socket_rx, socket_tx);
+    socket.set_timeout(Some(config.keep_alive * 3 / 2));
+    socket.connect(config.broker).await?;
+
+    let keep_alive = u16::try_from(config.keep_alive.as_secs()).unwrap_or(u16::MAX);
+    let len = encode_connect(packet_tx, config.client_id, keep_alive)?;
+    socket.write_all(&packet_tx[..len]).await?;
+
+    let mut received = 0;
+    let code = with_timeout(config.keep_alive, async {
+        loop {
+            match decode(&packet_rx[..received])? {
+                | Some((Packet::ConnAck { code, .. }, _)) => break Ok(code),
+                | Some(_) => break Err(Error::Protocol),
+                | None => {}
+            }
+            if received == packet_rx.len() {
+                break Err(Error::BufferTooSmall);
+            }
+            match socket.read(&mut packet_rx[received..]).await? {
+                | 0 => break Err(Error::Closed),
+                | n => received += n,
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+
+    if code != 0 {
+        return Err(Error::Refused(code));
+    }
+    *connected = true;
+    received = 0;
+
+    let mut packet_id = 0_u16;
+    let mut next_id = move || {
+        packet_id = packet_id.checked_add(1).unwrap_or(1);
+        packet_id
+    };
+
+    let len =
+        encode_subscribe(packet_tx, next_id(), config.command_topic, QoS::AtLeastOnce)?;
+    socket.write_all(&packet_tx[..len]).await?;
+
+    let mut telemetry_ticker = Ticker::every(config.telemetry_interval);
+    let mut ping_ticker = Ticker::every(config.keep_alive / 2);
+
+    let result = loop {
+        let event = select3(
+            socket.read(&mut packet_rx[received..]),
+            telemetry_ticker.next(),
+            ping_ticker.next(),
+        )
+        .await;
+
+        match event {
+            | Either3::First(Ok(0)) => break Err(Error::Closed),
+            | Either3::First(Ok(n)) => received += n,
+            | Either3::First(Err(e)) => break Err(e.into()),
+            | Either3::Second(()) => {
+                let Some(payload_len) = telemetry(payload) else {
+                    continue;
+                };
+                let id = match config.telemetry_qos {
+                    | QoS::AtMostOnce => None,
+                    | QoS::AtLeastOnce => Some(next_id()),
+                };
+                let len = encode_publish(
+                    packet_tx,
+                    config.telemetry_topic,
+                    id,
+                    &payload[..payload_len],
+                )?;
+                socket.write_all(&packet_tx[..len]).await?;
+                continue;
+            }
+            | Either3::Third(()) => {
+                socket.write_all(&[PINGREQ, 0]).await?;
+                continue;
+            }
+        }
+
+        // process all complete packets received so far
+        let mut consumed = 0;
+        while let Some((packet, len)) = decode(&packet_rx[consumed..received])? {
+            if let Packet::Publish(publish) = packet {
+                let ack = acknowledgement(&publish)?;
+                if publish.topic == config.command_topic {
+                    on_command(publish.payload);
+                }
+                if let Some(ack) = ack {
+                    socket.write_all(&ack).await?;
+                }
+            }
+            consumed += len;
+        }
+        packet_rx.copy_within(consumed..received, 0);
+        received -= consumed;
+        if received == packet_rx.len() {
+            break Err(Error::BufferTooSmall);
+        }
+    };
+
+    let _ = socket.write_all(&[DISCONNECT, 0]).await;
+    socket.close();
+    let _ = socket.flush().await;
+    result
+}
+
+/// Encode a CONNECT packet with a clean session and no credentials.
+pub fn encode_connect(
+    buf: &mut [u8],
+    client_id: &str,
+    keep_alive: u16,
+) -> Result<usize, Error> {
+    let mut body = Writer::body(buf)?;
+    body.str("MQTT")?;
+    body.put(&[PROTOCOL_LEVEL, CLEAN_SESSION])?;
+    body.put(&keep_alive.to_be_bytes())?;
+    body.str(client_id)?;
+    body.finish(CONNECT)
+}
+
+/// Encode a PUBLISH packet; QoS 1 if a `packet_id` is given, otherwise QoS 0.
+pub fn encode_publish(
+    buf: &mut [u8],
+    topic: &str,
+    packet_id: Option<u16>,
+    payload: &[u8],
+) -> Result<usize, Error> {
+    let mut body = Writer::body(buf)?;
+    body.str(topic)?;
+    if let Some(id) = packet_id {
+        body.put(&id.to_be_bytes())?;
+    }
+    body.put(payload)?;
+    let qos = packet_id.map_or(QoS::AtMostOnce, |_| QoS::AtLeastOnce);
+    body.finish(PUBLISH | ((qos as u8) << 1))
+}
+
+/// Encode a SUBSCRIBE packet for a single topic filter.
+pub fn encode_subscribe(
+    buf: &mut [u8],
+    packet_id: u16,
+    filter: &str,
+    qos: QoS,
+) -> Result<usize, Error> {
+    let mut body = Writer::body(buf)?;
+    body.put(&packet_id.to_be_bytes())?;
+    body.str(filter)?;
+    body.put(&[qos as u8])?;
+    body.finish(SUBSCRIBE)
+}
+
+/// Decode the packet at the start of `buf`.
+///
+/// Returns the packet alongside its encoded length,
+/// or `None` if `buf` does not contain a complete packet yet.
+pub fn decode(buf: &[u8]) -> Result<Option<(Packet<'_>, usize)>, Error> {
+    let Some((&header, tail)) = buf.split_first() else {
+        return Ok(None);
+    };
+    let Some((remaining, len_len)) = decode_remaining_length(tail)? else {
+        return Ok(None);
+    };
+    let Some(body) = tail[len_len..].get(..remaining) else {
+        return Ok(None);
+    };
+    let total = 1 + len_len + remaining;
+
+    let u16_at = |offset: usize| -> Result<u16, Error> {
+        body.get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or(Error::Protocol)
+    };
+
+    let packet = match header & 0xF0 {
+        | CONNACK => match body {
+            | &[flags, code] => Packet::ConnAck {
+                session_present: flags & 1 != 0,
+                code,
+            },
+            | _ => return Err(Error::Protocol),
+        },
+        | PUBLISH => {
+            let qos = (header >> 1) & 0b11;
+            let topic_len = usize::from(u16_at(0)?);
+            let topic = body.get(2..2 + topic_len).ok_or(Error::Protocol)?;
+            let topic = str::from_utf8(topic).map_err(|_| Error::Protocol)?;
+            let mut offset = 2 + topic_len;
+            let packet_id = match qos {
+                | 0 => None,
+                | 1 | 2 => {
+                    offset += 2;
+                    Some(u16_at(offset - 2)?)
+                }
+                | _ => return Err(Error::Protocol),
+            };
+            Packet::Publish(Publish {
+                topic,
+                packet_id,
+                qos,
+                retain: header & 1 != 0,
+                payload: &body[offset..],
+            })
+        }
+        | PUBACK => Packet::PubAck {
+            packet_id: u16_at(0)?,
+        },
+        | SUBACK => Packet::SubAck {
+            packet_id: u16_at(0)?,
+            codes: &body[2..],
+        },
+        | PINGRESP => Packet::PingResp,
+        | other => Packet::Other(other >> 4),
+    };
+
+    Ok(Some((packet, total)))
+}
+
+/// The PUBACK owed for `publish`, if any.
+///
+/// QoS 2 would take a PUBREC, PUBREL and PUBCOMP exchange instead,
+/// which the client does not implement, as it never subscribes with QoS 2.
+fn acknowledgement(publish: &Publish<'_>) -> Result<Option<[u8; 4]>, Error> {
+    match (publish.qos, publish.packet_id) {
+        | (0, _) => Ok(None),
+        | (1, Some(id)) => {
+            let [hi, lo] = id.to_be_bytes();
+            Ok(Some([PUBACK, 2, hi, lo]))
+        }
+        | _ => Err(Error::Protocol),
+    }
+}
+
+/// Returns the number of bytes written.
+fn encode_remaining_length(buf: &mut [u8], len: usize) -> Result<usize, Error> {
+    if len > MAX_REMAINING_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+    let mut len = len;
+    let mut written = 0;
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        *buf.get_mut(written).ok_or(Error::BufferTooSmall)? = byte;
+        written += 1;
+        if len == 0 {
+            break Ok(written);
+        }
+    }
+}
+
+/// Returns the remaining length alongside the number of bytes it was encoded in,
+/// or `None` if `buf` ends before the encoded length does.
+fn decode_remaining_length(buf: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+    let mut len = 0;
+    for (i, &byte) in buf.iter().enumerate().take(MAX_HEADER_LEN - 1) {
+        len |= usize::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, i + 1)));
+        }
+    }
+    if buf.len() >= MAX_HEADER_LEN - 1 {
+        Err(Error::Protocol)
+    } else {
+        Ok(None)
+    }
+}
+
+impl<'b> Writer<'b> {
+    /// Start writing a packet body, leaving room for the fixed header.
+    fn body(buf: &'b mut [u8]) -> Result<Self, Error> {
+        if buf.len() < MAX_HEADER_LEN {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(Self {
+            buf,
+            len: MAX_HEADER_LEN,
+        })
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn str(&mut self, s: &str) -> Result<(), Error> {
+        let len = u16::try_from(s.len()).map_err(|_| Error::BufferTooSmall)?;
+        self.put(&len.to_be_bytes())?;
+        self.put(s.as_bytes())
+    }
+
+    /// Write the fixed header and move it directly in front of the body.
+    ///
+    /// Returns the total packet length.
+    fn finish(self, header: u8) -> Result<usize, Error> {
+        let remaining = self.len - MAX_HEADER_LEN;
+        let mut fixed = [0; MAX_HEADER_LEN];
+        fixed[0] = header;
+        let fixed_len = 1 + encode_remaining_length(&mut fixed[1..], remaining)?;
+
+        self.buf.copy_within(MAX_HEADER_LEN..self.len, fixed_len);
+        self.buf[..fixed_len].copy_from_slice(&fixed[..fixed_len]);
+        Ok(fixed_len + remaining)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            | Error::Connect(_) => write!(f, "MQTT: failed to connect to broker"),
+            | Error::Tcp(_) => write!(f, "MQTT: TCP error"),
+            | Error::Closed => write!(f, "MQTT: connection closed by broker"),
+            | Error::Timeout => write!(f, "MQTT: broker timed out"),
+            | Error::Refused(code) => write!(f, "MQTT: connection refused ({code})"),
+            | Error::Protocol => write!(f, "MQTT: malformed packet"),
+            | Error::BufferTooSmall => write!(f, "MQTT: packet exceeds buffer"),
+        }
+    }
+}
+
+impl CoreError for Error {}
+
+impl From<ConnectError> for Error {
+    fn from(connect: ConnectError) -> Self {
+        Error::Connect(connect)
+    }
+}
+
+impl From<tcp::Error> for Error {
+    fn from(tcp: tcp::Error) -> Self {
+        Error::Tcp(tcp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_length() {
+        for (len, encoded) in [
+            (0, [0x00].as_slice()),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xFF, 0x7F]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let mut buf = [0; 4];
+            let written = encode_remaining_length(&mut buf, len).unwrap();
+            assert_eq!(&buf[..written], encoded);
+            assert_eq!(decode_remaining_length(encoded), Ok(Some((len, written))));
+        }
+        assert_eq!(decode_remaining_length(&[0x80, 0x80]), Ok(None));
+        assert_eq!(
+            decode_remaining_length(&[0x80, 0x80, 0x80, 0x80]),
+            Err(Error::Protocol)
+        );
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut buf = [0; 32];
+        let len = encode_connect(&mut buf, "dev", 60).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[
+                CONNECT,
+                15,
+                0,
+                4,
+                b'M',
+                b'Q',
+                b'T',
+                b'T',
+                4,
+                CLEAN_SESSION,
+                0,
+                60,
+                0,
+                3,
+                b'd',
+                b'e',
+                b'v'
+            ]
+        );
+    }
+
+    #[test]
+    fn test_publish_roundtrip() {
+        let mut buf = [0; 32];
+        let len = encode_publish(&mut buf, "a/b", Some(7), b"hi").unwrap();
+        assert_eq!(buf[0], PUBLISH | 0b10);
+
+        let (packet, decoded_len) = decode(&buf[..len]).unwrap().unwrap();
+        assert_eq!(decoded_len, len);
+        assert_eq!(
+            packet,
+            Packet::Publish(Publish {
+                topic: "a/b",
+                packet_id: Some(7),
+                qos: 1,
+                retain: false,
+                payload: b"hi",
+            })
+        );
+        assert_eq!(decode(&buf[..len - 1]), Ok(None));
+    }
+
+    #[test]
+    fn test_acknowledgement() {
+        let publish = |qos, packet_id| Publish {
+            topic: "cmd",
+            packet_id,
+            qos,
+            retain: false,
+            payload: b"reboot",
+        };
+        assert_eq!(acknowledgement(&publish(0, None)), Ok(None));
+        assert_eq!(
+            acknowledgement(&publish(1, Some(0x1234))),
+            Ok(Some([PUBACK, 2, 0x12, 0x34]))
+        );
+        assert_eq!(
+            acknowledgement(&publish(2, Some(0x1234))),
+            Err(Error::Protocol)
+        );
+    }
+
+    #[test]
+    fn test_decode_acks() {
+        assert_eq!(
+            decode(&[CONNACK, 2, 0, 5]),
+            Ok(Some((
+                Packet::ConnAck {
+                    session_present: false,
+                    code: 5
+                },
+                4
+            )))
+        );
+        assert_eq!(
+            decode(&[SUBACK, 3, 0, 1, 1, 0xFF]),
+            Ok(Some((
+                Packet::SubAck {
+                    packet_id: 1,
+                    codes: &[1]
+                },
+                5
+            )))
+        );
+        assert_eq!(decode(&[PINGRESP, 0]), Ok(Some((Packet::PingResp, 2))));
+    }
+
+    #[test]
+    fn test_remote_command() {
+        assert_eq!(RemoteCommand::parse(b"reboot"), Some(RemoteCommand::Reboot));
+        assert_eq!(
+            RemoteCommand::parse(b" log-level 3\n"),
+            Some(RemoteCommand::LogLevel(3))
+        );
+        assert_eq!(RemoteCommand::parse(b"reboot now"), None);
+        assert_eq!(RemoteCommand::parse(b"log-level"), None);
+    }
+}
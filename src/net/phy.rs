@@ -0,0 +1,138 @@
+//! Management of the LAN8742A Ethernet PHY.
+//!
+//! [`Lan8742`] drives the PHY for the Ethernet driver like its generic one does,
+//! additionally publishing the negotiated speed and duplex as [`events::LINK`]
+//! and restarting autonegotiation on [request](renegotiate).
+
+#[cfg(feature = "cross")]
+use core::future::Future;
+#[cfg(feature = "cross")]
+use core::pin::Pin;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+#[cfg(feature = "cross")]
+use core::task::Context;
+
+#[cfg(feature = "cross")]
+use embassy_stm32::eth::StationManagement;
+#[cfg(feature = "cross")]
+use embassy_stm32::eth::PHY;
+#[cfg(feature = "cross")]
+use embassy_time::Duration;
+#[cfg(feature = "cross")]
+use embassy_time::Timer;
+
+#[cfg(feature = "cross")]
+use crate::system::events;
+use crate::system::events::Link;
+use crate::system::events::Speed;
+
+/// basic control register
+pub const BCR: u8 = 0;
+/// basic status register
+pub const BSR: u8 = 1;
+/// PHY special control/status register
+pub const PSCSR: u8 = 31;
+
+pub const BCR_RESET: u16 = 1 << 15;
+pub const BCR_100M: u16 = 1 << 13;
+pub const BCR_AN: u16 = 1 << 12;
+pub const BCR_AN_RESTART: u16 = 1 << 9;
+pub const BSR_LINK_UP: u16 = 1 << 2;
+pub const BSR_AN_DONE: u16 = 1 << 5;
+/// speed indication: bit 0 10 Mbit/s, bit 1 100 Mbit/s, bit 2 full duplex
+pub const PSCSR_SPEED: u16 = 0b111 << 2;
+
+static RENEGOTIATE: AtomicBool = AtomicBool::new(false);
+
+/// The LAN8742A at MDIO address `address`.
+#[cfg(feature = "cross")]
+pub struct Lan8742 {
+    address: u8,
+}
+
+/// Have the PHY restart autonegotiation the next time it is polled.
+pub fn renegotiate() {
+    RENEGOTIATE.store(true, Ordering::Relaxed);
+}
+
+/// The link described by the basic status and special control/status registers.
+pub fn link(bsr: u16, pscsr: u16) -> Link {
+    if bsr & (BSR_LINK_UP | BSR_AN_DONE) != BSR_LINK_UP | BSR_AN_DONE {
+        return Link::Down;
+    }
+    let indication = (pscsr & PSCSR_SPEED) >> 2;
+    let speed = match indication & 0b11 {
+        | 0b01 => Speed::Mbps10,
+        | 0b10 => Speed::Mbps100,
+        | _ => return Link::Down,
+    };
+    Link::Up {
+        speed,
+        full_duplex: indication & 0b100 != 0,
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Lan8742 {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub const fn new(address: u8) -> Self {
+        Self { address }
+    }
+}
+
+// Safety: resets and configures the PHY as the Ethernet driver expects
+#[cfg(feature = "cross")]
+unsafe impl PHY for Lan8742 {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        sm.smi_write(self.address, BCR, BCR_RESET);
+        while sm.smi_read(self.address, BCR) & BCR_RESET != 0 {}
+    }
+
+    fn phy_init<S: StationManagement>(&mut self, sm: &mut S) {
+        sm.smi_write(self.address, BCR, BCR_AN | BCR_AN_RESTART | BCR_100M);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> bool {
+        // only registers the waker, so the driver polls again
+        let _ = Pin::new(&mut Timer::after(Self::POLL_INTERVAL)).poll(cx);
+
+        if RENEGOTIATE.swap(false, Ordering::Relaxed) {
+            let bcr = sm.smi_read(self.address, BCR);
+            sm.smi_write(self.address, BCR, bcr | BCR_AN | BCR_AN_RESTART);
+        }
+        let bsr = sm.smi_read(self.address, BSR);
+        let pscsr = sm.smi_read(self.address, PSCSR);
+        let link = link(bsr, pscsr);
+        events::LINK.set(link);
+        link != Link::Down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link() {
+        let up = BSR_LINK_UP | BSR_AN_DONE;
+        assert_eq!(
+            link(up, 0b110 << 2),
+            Link::Up {
+                speed: Speed::Mbps100,
+                full_duplex: true
+            }
+        );
+        assert_eq!(
+            link(up, 0b001 << 2),
+            Link::Up {
+                speed: Speed::Mbps10,
+                full_duplex: false
+            }
+        );
+        // autonegotiation still running
+        assert_eq!(link(BSR_LINK_UP, 0b110 << 2), Link::Down);
+        assert_eq!(link(BSR_AN_DONE, 0b110 << 2), Link::Down);
+    }
+}
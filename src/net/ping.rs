@@ -0,0 +1,292 @@
+//! ICMP echo ([RFC 792](https://www.rfc-editor.org/rfc/rfc792)).
+//!
+//! smoltcp answers echo requests by itself; this module sends them.
+//! Every [`Pinger`] binds its own identifier, so concurrent pingers
+//! only ever see replies to their own requests.
+
+use core::fmt;
+use core::fmt::Display;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+use embassy_net::icmp::IcmpEndpoint;
+use embassy_net::icmp::IcmpSocket;
+use embassy_net::icmp::PacketMetadata;
+use embassy_net::IpAddress;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+use embassy_time::with_deadline;
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+/// ICMP header length
+pub const HEADER_LEN: usize = 8;
+/// payload bytes sent with every request
+pub const PAYLOAD_LEN: usize = 32;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const PACKET_LEN: usize = HEADER_LEN + PAYLOAD_LEN;
+
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(0x5354);
+
+/// Sends echo requests and matches their replies.
+pub struct Pinger<'d> {
+    socket: IcmpSocket<'d>,
+    ident: u16,
+    seq: u16,
+}
+
+/// Socket buffers of a [`Pinger`].
+pub struct Buffers {
+    pub rx_meta: [PacketMetadata; 4],
+    pub rx: [u8; 4 * PACKET_LEN],
+    pub tx_meta: [PacketMetadata; 1],
+    pub tx: [u8; PACKET_LEN],
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Reply {
+    pub seq: u16,
+    /// round-trip time
+    pub rtt: Duration,
+}
+
+/// Round-trip statistics of a series of pings.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub struct Stats {
+    pub sent: u32,
+    pub received: u32,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    /// sum of all round-trip times
+    pub total: Duration,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the socket could not be bound
+    Bind,
+    /// no route to the target, or no address yet
+    Send,
+    Timeout,
+}
+
+impl Buffers {
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 4],
+            rx: [0; 4 * PACKET_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 1],
+            tx: [0; PACKET_LEN],
+        }
+    }
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> Pinger<'d> {
+    pub fn new(stack: Stack<'d>, buffers: &'d mut Buffers) -> Result<Self, Error> {
+        let Buffers {
+            rx_meta,
+            rx,
+            tx_meta,
+            tx,
+        } = buffers;
+        let mut socket = IcmpSocket::new(stack, rx_meta, rx, tx_meta, tx);
+        let ident = NEXT_IDENT.fetch_add(1, Ordering::Relaxed);
+        socket.bind(IcmpEndpoint::Ident(ident)).map_err(|_| Error::Bind)?;
+        Ok(Self {
+            socket,
+            ident,
+            seq: 0,
+        })
+    }
+
+    /// Send an echo request to `target` and wait up to `timeout` for the reply.
+    pub async fn ping(
+        &mut self,
+        target: Ipv4Address,
+        timeout: Duration,
+    ) -> Result<Reply, Error> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut packet = [0; PACKET_LEN];
+        echo_request(&mut packet, self.ident, seq);
+        let start = Instant::now();
+        self.socket.send_to(&packet, target).await.map_err(|_| Error::Send)?;
+
+        let deadline = start + timeout;
+        loop {
+            let received = with_deadline(deadline, self.socket.recv_from(&mut packet))
+                .await
+                .map_err(|_| Error::Timeout)?;
+            // late replies to earlier requests are dropped, as are oversized ones
+            let Ok((len, from)) = received else {
+                continue;
+            };
+            if from == IpAddress::Ipv4(target)
+                && echo_reply(&packet[..len], self.ident) == Some(seq)
+            {
+                return Ok(Reply {
+                    seq,
+                    rtt: start.elapsed(),
+                });
+            }
+        }
+    }
+}
+
+impl Stats {
+    /// Record the outcome of one request, `None` if it timed out.
+    pub fn record(&mut self, rtt: Option<Duration>) {
+        self.sent += 1;
+        if let Some(rtt) = rtt {
+            self.received += 1;
+            self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+            self.max = Some(self.max.map_or(rtt, |max| max.max(rtt)));
+            self.total += rtt;
+        }
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        (self.received > 0).then(|| self.total / self.received)
+    }
+
+    /// Percentage of requests without reply.
+    pub fn loss(&self) -> u32 {
+        match self.sent {
+            | 0 => 0,
+            | sent => (sent - self.received) * 100 / sent,
+        }
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} received, {}% loss",
+            self.sent,
+            self.received,
+            self.loss()
+        )?;
+        if let (Some(min), Some(avg), Some(max)) = (self.min, self.average(), self.max) {
+            write!(
+                f,
+                "\nrtt min/avg/max: {}/{}/{} ms",
+                Millis(min),
+                Millis(avg),
+                Millis(max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Bind => write!(f, "no ICMP socket available"),
+            | Error::Send => write!(f, "sending failed"),
+            | Error::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Milliseconds with three decimals.
+pub struct Millis(pub Duration);
+
+impl Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = self.0.as_micros();
+        write!(f, "{}.{:03}", micros / 1000, micros % 1000)
+    }
+}
+
+/// Fill `packet` with an echo request, the payload being a byte counter.
+fn echo_request(packet: &mut [u8], ident: u16, seq: u16) {
+    let (header, payload) = packet.split_at_mut(HEADER_LEN);
+    header[..4].copy_from_slice(&[ECHO_REQUEST, 0, 0, 0]);
+    header[4..6].copy_from_slice(&ident.to_be_bytes());
+    header[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (byte, value) in payload.iter_mut().zip(0..) {
+        *byte = value;
+    }
+    let checksum = checksum(packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// The sequence number of an intact echo reply to `ident`.
+fn echo_reply(packet: &[u8], ident: u16) -> Option<u16> {
+    let header = packet.get(..HEADER_LEN)?;
+    let valid = header[0] == ECHO_REPLY
+        && header[1] == 0
+        && header[4..6] == ident.to_be_bytes()
+        && checksum(packet) == 0;
+    valid.then(|| u16::from_be_bytes([header[6], header[7]]))
+}
+
+/// Internet checksum (RFC 1071); `0` over a packet with a valid checksum.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| {
+            u32::from(word[0]) << 8 | u32::from(word.get(1).copied().unwrap_or(0))
+        })
+        .sum::<u32>();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo() {
+        let mut packet = [0; PACKET_LEN];
+        echo_request(&mut packet, 0x1234, 7);
+        assert_eq!(packet[..8], [8, 0, 0xF4, 0xC3, 0x12, 0x34, 0, 7]);
+        assert_eq!(checksum(&packet), 0);
+
+        // a reply only differs in type and checksum
+        packet[0] = ECHO_REPLY;
+        packet[2] += ECHO_REQUEST;
+        assert_eq!(echo_reply(&packet, 0x1234), Some(7));
+        assert_eq!(echo_reply(&packet, 0x1235), None);
+        packet[20] ^= 1;
+        assert_eq!(echo_reply(&packet, 0x1234), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        stats.record(Some(Duration::from_micros(1500)));
+        stats.record(None);
+        stats.record(Some(Duration::from_micros(500)));
+        stats.record(Some(Duration::from_micros(1000)));
+        let mut out = heapless::String::<96>::new();
+        fmt::write(&mut out, format_args!("{stats}")).unwrap();
+        assert_eq!(
+            out,
+            "4 sent, 3 received, 25% loss\nrtt min/avg/max: 0.500/1.000/1.500 ms"
+        );
+    }
+}
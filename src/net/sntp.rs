@@ -0,0 +1,162 @@
+//! Minimal SNTP client ([RFC 4330](https://www.rfc-editor.org/rfc/rfc4330)).
+//!
+//! Only whole seconds are kept, which is all the RTC counts anyway;
+//! the round trip delay is ignored for the same reason.
+
+use core::fmt;
+use core::fmt::Display;
+
+use embassy_net::udp::PacketMetadata;
+use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+use embassy_time::with_deadline;
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use crate::net::dns;
+
+pub const PORT: u16 = 123;
+/// server asked when none is given
+pub const POOL: &str = "pool.ntp.org";
+/// time to wait for a response before retrying
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+/// requests sent before giving up
+pub const ATTEMPTS: usize = 3;
+
+const PACKET_LEN: usize = 48;
+/// seconds from 1900-01-01, the NTP era 0 epoch, to 1970-01-01
+const UNIX_OFFSET: u64 = 2_208_988_800;
+/// leap indicator 0, version 4, mode 3 (client)
+const CLIENT: u8 = 0b00_100_011;
+const MODE_SERVER: u8 = 4;
+const ORIGINATE: usize = 24;
+const TRANSMIT: usize = 40;
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// no DNS server to resolve [`POOL`] with
+    NoDns,
+    Dns(dns::Error),
+    Send,
+    Timeout,
+    /// the server asked us to stop querying it
+    KissOfDeath,
+}
+
+/// Resolve [`POOL`] via the first configured DNS server.
+pub async fn pool(stack: Stack<'_>) -> Result<Ipv4Address, Error> {
+    let Some(&(server, _)) = dns::servers(stack).first() else {
+        return Err(Error::NoDns);
+    };
+    let mut buf = [0; dns::MAX_MESSAGE];
+    let answers = dns::query(stack, server, POOL, dns::Type::A, &mut buf)
+        .await
+        .map_err(Error::Dns)?;
+    answers
+        .filter_map(|record| match record {
+            | dns::Record::A(address) => Some(Ipv4Address(address.octets())),
+            | _ => None,
+        })
+        .next()
+        .ok_or(Error::Dns(dns::Error::Malformed))
+}
+
+/// Ask `server` for the time in seconds since 1970-01-01 00:00:00 UTC.
+pub async fn query(stack: Stack<'_>, server: Ipv4Address) -> Result<u64, Error> {
+    // the server echoes our transmit timestamp, which is only used to match responses
+    let cookie = Instant::now().as_ticks().to_be_bytes();
+    let mut request = [0; PACKET_LEN];
+    request[0] = CLIENT;
+    request[TRANSMIT..].copy_from_slice(&cookie);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0; 2 * PACKET_LEN];
+    let mut tx_buf = [0; PACKET_LEN];
+    let mut sock =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    sock.bind(0).expect("binding to an ephemeral port should succeed");
+    let endpoint = IpEndpoint::new(server.into(), PORT);
+
+    let mut buf = [0; PACKET_LEN];
+    for _ in 0..ATTEMPTS {
+        sock.send_to(&request, endpoint).await.map_err(|_| Error::Send)?;
+        let deadline = Instant::now() + TIMEOUT;
+        while let Ok(received) = with_deadline(deadline, sock.recv_from(&mut buf)).await {
+            match received {
+                | Ok((len, meta)) if meta.endpoint == endpoint => {
+                    if let Some(time) = response(&buf[..len], &cookie) {
+                        return time;
+                    }
+                }
+                | _ => {}
+            }
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// The transmit time of a response to the request carrying `cookie`,
+/// or `None` if `packet` is not one.
+fn response(packet: &[u8], cookie: &[u8; 8]) -> Option<Result<u64, Error>> {
+    let valid = packet.len() >= PACKET_LEN
+        && packet[0] & 0b111 == MODE_SERVER
+        && packet[ORIGINATE..ORIGINATE + 8] == *cookie;
+    if !valid {
+        return None;
+    }
+    let stratum = packet[1];
+    if stratum == 0 {
+        return Some(Err(Error::KissOfDeath));
+    }
+    let seconds = u32::from_be_bytes(packet[TRANSMIT..TRANSMIT + 4].try_into().unwrap());
+    // era 0 ends in 2036; later timestamps wrap around
+    let seconds = u64::from(seconds);
+    let seconds = if seconds >= UNIX_OFFSET {
+        seconds - UNIX_OFFSET
+    } else {
+        seconds + (1 << 32) - UNIX_OFFSET
+    };
+    Some(Ok(seconds))
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::NoDns => write!(f, "no DNS server configured"),
+            | Error::Dns(e) => write!(f, "resolving {POOL}: {e}"),
+            | Error::Send => write!(f, "sending request failed"),
+            | Error::Timeout => write!(f, "no response"),
+            | Error::KissOfDeath => write!(f, "server refused service"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response() {
+        let cookie = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = 0b00_100_100;
+        packet[1] = 2;
+        packet[ORIGINATE..ORIGINATE + 8].copy_from_slice(&cookie);
+        // 2024-02-29 12:34:56
+        let ntp = (1_709_210_096 + UNIX_OFFSET) as u32;
+        packet[TRANSMIT..TRANSMIT + 4].copy_from_slice(&ntp.to_be_bytes());
+        assert_eq!(response(&packet, &cookie), Some(Ok(1_709_210_096)));
+
+        assert_eq!(response(&packet, &[0; 8]), None);
+        assert_eq!(response(&packet[..PACKET_LEN - 1], &cookie), None);
+        packet[1] = 0;
+        assert_eq!(response(&packet, &cookie), Some(Err(Error::KissOfDeath)));
+    }
+}
@@ -0,0 +1,198 @@
+//! Traffic counters of the interface and of individual services.
+//!
+//! All counters wrap around on overflow.
+
+use core::fmt;
+use core::fmt::Display;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+use super::http::Object;
+use super::tap::Tap;
+
+/// Frames and bytes passing through the interface, counted as a [`Tap`].
+#[derive(Debug)]
+pub struct Interface {
+    rx_packets: AtomicU32,
+    rx_bytes: AtomicU32,
+    tx_packets: AtomicU32,
+    tx_bytes: AtomicU32,
+}
+
+/// Counters of one service, e.g. a [`tcp_server::Service`](super::tcp_server::Service).
+#[derive(Debug)]
+pub struct Socket {
+    pub name: &'static str,
+    rx_bytes: AtomicU32,
+    tx_bytes: AtomicU32,
+    connections: AtomicU32,
+    errors: AtomicU32,
+}
+
+/// A snapshot of [`Interface`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub rx_packets: u32,
+    pub rx_bytes: u32,
+    pub tx_packets: u32,
+    pub tx_bytes: u32,
+}
+
+/// A snapshot of [`Socket`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct SocketStats {
+    pub rx_bytes: u32,
+    pub tx_bytes: u32,
+    /// connections accepted
+    pub connections: u32,
+    /// failed accepts and connections ended by an error
+    pub errors: u32,
+}
+
+impl Interface {
+    pub const fn new() -> Self {
+        Self {
+            rx_packets: AtomicU32::new(0),
+            rx_bytes: AtomicU32::new(0),
+            tx_packets: AtomicU32::new(0),
+            tx_bytes: AtomicU32::new(0),
+        }
+    }
+
+    pub fn get(&self) -> InterfaceStats {
+        InterfaceStats {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Interface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tap for Interface {
+    fn receive(&self, frame: &[u8]) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(frame.len() as u32, Ordering::Relaxed);
+    }
+
+    fn transmit(&self, frame: &[u8]) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(frame.len() as u32, Ordering::Relaxed);
+    }
+}
+
+impl Socket {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            rx_bytes: AtomicU32::new(0),
+            tx_bytes: AtomicU32::new(0),
+            connections: AtomicU32::new(0),
+            errors: AtomicU32::new(0),
+        }
+    }
+
+    pub fn get(&self) -> SocketStats {
+        SocketStats {
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.rx_bytes.fetch_add(bytes as u32, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.tx_bytes.fetch_add(bytes as u32, Ordering::Relaxed);
+    }
+
+    pub fn connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Add `interface` and `sockets` to a `/status` object.
+pub fn status(
+    status: &mut Object<'_>,
+    interface: &Interface,
+    sockets: &[&Socket],
+) -> fmt::Result {
+    let stats = interface.get();
+    status.object("interface", |object| {
+        object.u64("rx_packets", stats.rx_packets.into())?;
+        object.u64("rx_bytes", stats.rx_bytes.into())?;
+        object.u64("tx_packets", stats.tx_packets.into())?;
+        object.u64("tx_bytes", stats.tx_bytes.into())
+    })?;
+    status.object("sockets", |object| {
+        for socket in sockets {
+            let stats = socket.get();
+            object.object(socket.name, |object| {
+                object.u64("rx_bytes", stats.rx_bytes.into())?;
+                object.u64("tx_bytes", stats.tx_bytes.into())?;
+                object.u64("connections", stats.connections.into())?;
+                object.u64("errors", stats.errors.into())
+            })?;
+        }
+        Ok(())
+    })
+}
+
+impl Display for InterfaceStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx {} packets, {} bytes; tx {} packets, {} bytes",
+            self.rx_packets, self.rx_bytes, self.tx_packets, self.tx_bytes
+        )
+    }
+}
+
+impl Display for SocketStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rx {} bytes, tx {} bytes, {} connections, {} errors",
+            self.rx_bytes, self.tx_bytes, self.connections, self.errors
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interface() {
+        let interface = Interface::new();
+        interface.receive(&[0; 60]);
+        interface.receive(&[0; 1514]);
+        interface.transmit(&[0; 42]);
+        assert_eq!(
+            interface.get(),
+            InterfaceStats {
+                rx_packets: 2,
+                rx_bytes: 1574,
+                tx_packets: 1,
+                tx_bytes: 42,
+            }
+        );
+    }
+}
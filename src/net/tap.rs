@@ -0,0 +1,144 @@
+//! Passive observation of the frames passing through a [`Driver`].
+//!
+//! Like [`arp::Guarded`](super::arp::Guarded), this sees traffic that smoltcp
+//! consumes internally, e.g. DHCP, without a socket of its own.
+
+use core::task::Context;
+
+use embassy_net::driver::Capabilities;
+use embassy_net::driver::Driver;
+use embassy_net::driver::HardwareAddress;
+use embassy_net::driver::LinkState;
+use embassy_net::driver::RxToken;
+use embassy_net::driver::TxToken;
+
+/// Observer of frames.
+pub trait Tap {
+    /// Called with every received frame before the stack processes it.
+    fn receive(&self, frame: &[u8]) {
+        let _ = frame;
+    }
+
+    /// Called with every frame after the stack has written it.
+    fn transmit(&self, frame: &[u8]) {
+        let _ = frame;
+    }
+}
+
+/// [`Driver`] adapter passing all frames to a [`Tap`].
+pub struct Tapped<'t, D, T> {
+    inner: D,
+    tap: &'t T,
+}
+
+pub struct TappedRxToken<'t, R, T> {
+    inner: R,
+    tap: &'t T,
+}
+
+pub struct TappedTxToken<'t, X, T> {
+    inner: X,
+    tap: &'t T,
+}
+
+impl<A: Tap, B: Tap> Tap for (A, B) {
+    fn receive(&self, frame: &[u8]) {
+        self.0.receive(frame);
+        self.1.receive(frame);
+    }
+
+    fn transmit(&self, frame: &[u8]) {
+        self.0.transmit(frame);
+        self.1.transmit(frame);
+    }
+}
+
+impl<T: Tap> Tap for &T {
+    fn receive(&self, frame: &[u8]) {
+        T::receive(self, frame)
+    }
+
+    fn transmit(&self, frame: &[u8]) {
+        T::transmit(self, frame)
+    }
+}
+
+impl<'t, D: Driver, T: Tap> Tapped<'t, D, T> {
+    pub fn new(inner: D, tap: &'t T) -> Self {
+        Self { inner, tap }
+    }
+}
+
+impl<D: Driver, T: Tap> Driver for Tapped<'_, D, T> {
+    type RxToken<'a>
+        = TappedRxToken<'a, D::RxToken<'a>, T>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TappedTxToken<'a, D::TxToken<'a>, T>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        cx: &mut Context,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(cx)?;
+        let rx = TappedRxToken {
+            inner: rx,
+            tap: self.tap,
+        };
+        let tx = TappedTxToken {
+            inner: tx,
+            tap: self.tap,
+        };
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        let tx = self.inner.transmit(cx)?;
+        Some(TappedTxToken {
+            inner: tx,
+            tap: self.tap,
+        })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+impl<R: RxToken, T: Tap> RxToken for TappedRxToken<'_, R, T> {
+    fn consume<U, F>(self, f: F) -> U
+    where
+        F: FnOnce(&mut [u8]) -> U,
+    {
+        let Self { inner, tap } = self;
+        inner.consume(|frame| {
+            tap.receive(frame);
+            f(frame)
+        })
+    }
+}
+
+impl<X: TxToken, T: Tap> TxToken for TappedTxToken<'_, X, T> {
+    fn consume<U, F>(self, len: usize, f: F) -> U
+    where
+        F: FnOnce(&mut [u8]) -> U,
+    {
+        let Self { inner, tap } = self;
+        inner.consume(len, |frame| {
+            let result = f(frame);
+            tap.transmit(frame);
+            result
+        })
+    }
+}
@@ -0,0 +1,229 @@
+//! Accept loop shared by the TCP services.
+//!
+//! A [`Service`] listens on a port, hands every accepted connection to a [`Handler`]
+//! and closes it afterwards. Each listener serves one client at a time,
+//! so the number of listeners bounds how many clients are served at once.
+//! The [`Policy`] bounds how long a peer may go silent and how much it may send per request,
+//! so a vanished or misbehaving client cannot occupy a listener indefinitely.
+//! Idle clients that still answer keep-alives are up to the [`Handler`].
+//! Failed accepts are retried with exponential backoff,
+//! so a stack without addresses or sockets does not spin.
+
+use core::cell::Cell;
+use core::fmt;
+use core::fmt::Display;
+
+use embassy_net::tcp;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embassy_time::Timer;
+use embedded_io_async::ErrorKind;
+use embedded_io_async::ErrorType;
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+
+use super::stats;
+
+/// delay before retrying the first failed accept
+pub const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// upper bound of the delay between failed accepts
+pub const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Serves accepted connections.
+#[allow(async_fn_in_trait)]
+pub trait Handler {
+    /// Talk to the client on `connection`.
+    ///
+    /// The socket is closed and flushed once this returns.
+    async fn handle(&mut self, connection: &mut Connection<'_, '_>) -> Result<(), Error>;
+}
+
+/// Limits of a service.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Policy {
+    /// time without anything from the peer, keep-alive ACKs included,
+    /// after which the connection is aborted as dead
+    pub dead_peer_timeout: Option<Duration>,
+    /// keep-alive interval, detecting clients that vanished without closing
+    pub keep_alive: Option<Duration>,
+    /// bytes a client may send per request, see [`Connection::next_request`]
+    pub max_request: usize,
+}
+
+/// Listeners sharing a [`Policy`] and [`stats::Socket`].
+///
+/// Run [`Service::serve`] once per listener, e.g. joined in a single task.
+#[derive(Debug)]
+pub struct Service<'s> {
+    policy: Policy,
+    stats: &'s stats::Socket,
+    active: Cell<usize>,
+}
+
+/// Socket buffers of one listener.
+pub struct Buffers<const SOCKET: usize> {
+    pub rx: [u8; SOCKET],
+    pub tx: [u8; SOCKET],
+}
+
+/// An accepted connection, enforcing [`Policy::max_request`] on reads.
+pub struct Connection<'s, 'b> {
+    socket: &'s mut TcpSocket<'b>,
+    stats: &'s stats::Socket,
+    max_request: usize,
+    /// bytes left to receive in the current request
+    remaining: usize,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    Tcp(tcp::Error),
+    /// the client exceeded [`Policy::max_request`]
+    RequestTooLarge,
+}
+
+impl<'s> Service<'s> {
+    pub const fn new(policy: Policy, stats: &'s stats::Socket) -> Self {
+        Self {
+            policy,
+            stats,
+            active: Cell::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Number of connections currently being served.
+    pub fn active(&self) -> usize {
+        self.active.get()
+    }
+
+    /// Accept connections on `port` one at a time and pass them to `handler`.
+    pub async fn serve<const SOCKET: usize>(
+        &self,
+        stack: Stack<'_>,
+        port: u16,
+        buffers: &mut Buffers<SOCKET>,
+        handler: &mut impl Handler,
+    ) -> ! {
+        let Buffers { rx, tx } = buffers;
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let mut socket = TcpSocket::new(stack, rx, tx);
+            socket.set_timeout(self.policy.dead_peer_timeout);
+            socket.set_keep_alive(self.policy.keep_alive);
+            if socket.accept(port).await.is_err() {
+                self.stats.error();
+                Timer::after(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+            backoff = MIN_BACKOFF;
+
+            self.stats.connected();
+            self.active.set(self.active.get() + 1);
+            let mut connection = Connection {
+                socket: &mut socket,
+                stats: self.stats,
+                max_request: self.policy.max_request,
+                remaining: self.policy.max_request,
+            };
+            if handler.handle(&mut connection).await.is_err() {
+                self.stats.error();
+            }
+            self.active.set(self.active.get() - 1);
+
+            socket.close();
+            let _ = socket.flush().await;
+        }
+    }
+}
+
+impl<const SOCKET: usize> Buffers<SOCKET> {
+    pub const fn new() -> Self {
+        Self {
+            rx: [0; SOCKET],
+            tx: [0; SOCKET],
+        }
+    }
+}
+
+impl<const SOCKET: usize> Default for Buffers<SOCKET> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'b> Connection<'_, 'b> {
+    /// Reset the request size budget, e.g. after a request has been answered.
+    pub fn next_request(&mut self) {
+        self.remaining = self.max_request;
+    }
+
+    pub fn socket(&mut self) -> &mut TcpSocket<'b> {
+        self.socket
+    }
+}
+
+impl ErrorType for Connection<'_, '_> {
+    type Error = Error;
+}
+
+impl Read for Connection<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.remaining == 0 {
+            return Err(Error::RequestTooLarge);
+        }
+        let len = buf.len().min(self.remaining);
+        let received = self.socket.read(&mut buf[..len]).await?;
+        self.remaining -= received;
+        self.stats.received(received);
+        Ok(received)
+    }
+}
+
+impl Write for Connection<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let sent = self.socket.write(buf).await?;
+        self.stats.sent(sent);
+        Ok(sent)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.socket.flush().await?)
+    }
+}
+
+impl From<tcp::Error> for Error {
+    fn from(value: tcp::Error) -> Self {
+        Self::Tcp(value)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Tcp(e) => write!(f, "tcp: {e:?}"),
+            | Error::RequestTooLarge => write!(f, "request too large"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            | Error::Tcp(e) => e.kind(),
+            | Error::RequestTooLarge => ErrorKind::OutOfMemory,
+        }
+    }
+}
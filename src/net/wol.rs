@@ -0,0 +1,86 @@
+//! Wake-on-LAN magic packets, broadcast over UDP.
+
+use core::fmt;
+use core::fmt::Display;
+
+use embassy_net::udp::PacketMetadata;
+use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+
+/// the discard port, as commonly used for magic packets
+pub const PORT: u16 = 9;
+/// six bytes of `0xFF` followed by the MAC address 16 times
+pub const PACKET_LEN: usize = 6 + 16 * 6;
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    Send,
+}
+
+/// The magic packet waking the station with address `mac`.
+pub fn magic_packet(mac: [u8; 6]) -> [u8; PACKET_LEN] {
+    let mut packet = [0xFF; PACKET_LEN];
+    for target in packet[6..].chunks_exact_mut(6) {
+        target.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Parse a MAC address of six hex bytes separated by `:` or `-`.
+pub fn parse_mac(text: &[u8]) -> Option<[u8; 6]> {
+    let text = core::str::from_utf8(text).ok()?;
+    let mut mac = [0; 6];
+    let mut bytes = text.split([':', '-']);
+    for byte in &mut mac {
+        let digits = bytes.next().filter(|digits| digits.len() == 2)?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+    bytes.next().is_none().then_some(mac)
+}
+
+/// Broadcast the magic packet for `mac` on the local network.
+pub async fn send(stack: Stack<'_>, mac: [u8; 6]) -> Result<(), Error> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0; 0];
+    let mut tx_buf = [0; PACKET_LEN];
+    let mut sock =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    sock.bind(0).expect("binding to an ephemeral port should succeed");
+    let broadcast = IpEndpoint::new(Ipv4Address([0xFF; 4]).into(), PORT);
+    sock.send_to(&magic_packet(mac), broadcast).await.map_err(|_| Error::Send)?;
+    // the packet is only queued until flushed
+    sock.flush().await;
+    Ok(())
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Send => write!(f, "failed to send magic packet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magic_packet() {
+        let mac = [0x02, 0x00, 0x5E, 0x10, 0x20, 0x30];
+        assert_eq!(parse_mac(b"02:00:5e:10:20:30"), Some(mac));
+        assert_eq!(parse_mac(b"02-00-5E-10-20-30"), Some(mac));
+        assert_eq!(parse_mac(b"02:00:5e:10:20"), None);
+        assert_eq!(parse_mac(b"02:00:5e:10:20:30:40"), None);
+        assert_eq!(parse_mac(b"2:00:5e:10:20:30"), None);
+
+        let packet = magic_packet(mac);
+        assert_eq!(packet[..6], [0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|target| target == mac));
+    }
+}
@@ -0,0 +1,309 @@
+//! Firmware updates staged in external flash.
+//!
+//! An update image is downloaded into the staging slot, prefixed by a [`Header`].
+//! Once [verified](verify), [`activate`] writes a swap request
+//! into the state sector; the bootloader is expected to check for it on boot,
+//! swap the staged image into place and erase the state sector afterwards.
+//!
+//! All multi-byte values are stored little-endian.
+
+#[cfg(feature = "cross")]
+use core::ffi::CStr;
+use core::fmt::Display;
+use core::mem::size_of;
+use core::range::Range;
+
+use bytemuck::Zeroable;
+#[cfg(feature = "cross")]
+use embassy_net::IpEndpoint;
+#[cfg(feature = "cross")]
+use embassy_net::Stack;
+use embedded_io_async::ErrorKind;
+use embedded_io_async::ErrorType;
+use embedded_io_async::Write;
+
+use crate::storage;
+use crate::storage::Storage;
+#[cfg(feature = "cross")]
+use crate::tftp;
+#[cfg(feature = "cross")]
+use crate::tftp::TransferError;
+use crate::util::hash;
+use crate::util::hash::Crc32;
+#[cfg(feature = "cross")]
+use crate::util::Cancel;
+
+/// magic number of a staged image header
+pub const MAGIC: u32 = u32::from_le_bytes(*b"OTA1");
+/// magic number of a swap request in the state sector
+pub const SWAP_MAGIC: u32 = u32::from_le_bytes(*b"SWAP");
+pub const HEADER_LEN: u32 = size_of::<Header>() as u32;
+
+/// Location of the update slots in flash.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Layout {
+    /// sector-aligned slot the update is downloaded into, header first
+    pub staging: Range<u32>,
+    /// address of the sector holding the swap request
+    pub state: u32,
+}
+
+/// Header preceding a staged image.
+#[repr(C)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Header {
+    pub magic: u32,
+    pub version: u32,
+    /// image length, excluding the header
+    pub len: u32,
+    /// CRC-32 (IEEE) of the image, excluding the header
+    pub crc32: u32,
+}
+
+/// Swap request read back from the state sector.
+#[repr(C)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SwapRequest {
+    pub magic: u32,
+    /// checksum of the image to be swapped in
+    pub crc32: u32,
+}
+
+/// Sequential [`Write`]r into the staging slot.
+pub struct Staging<'s, S> {
+    storage: &'s mut S,
+    layout: Layout,
+    written: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    BadMagic,
+    /// the image does not fit into the staging slot
+    TooLarge,
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl Layout {
+    fn staging_len(&self) -> u32 {
+        self.staging.end - self.staging.start
+    }
+}
+
+impl<'s, S: Storage> Staging<'s, S> {
+    /// Erase the staging slot and cancel any pending swap.
+    pub async fn begin(storage: &'s mut S, layout: Layout) -> Self {
+        cancel(storage, &layout).await;
+        if layout.staging_len() > 0 {
+            let last = layout.staging.end - 1;
+            storage.erase((layout.staging.start..=last).into()).await;
+        }
+        Self {
+            storage,
+            layout,
+            written: 0,
+        }
+    }
+
+    /// Number of bytes written so far, including the header.
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+}
+
+impl<S> ErrorType for Staging<'_, S> {
+    type Error = Error;
+}
+
+impl<S: Storage> Write for Staging<'_, S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = u32::try_from(buf.len()).map_err(|_| Error::TooLarge)?;
+        let end = self.written.checked_add(len).ok_or(Error::TooLarge)?;
+        if end > self.layout.staging_len() {
+            return Err(Error::TooLarge);
+        }
+        let address = self.layout.staging.start + self.written;
+        storage::program_pages(self.storage, buf, address).await;
+        self.written = end;
+        Ok(buf.len())
+    }
+}
+
+/// Download an update image from a TFTP `server` into the staging slot.
+///
+/// Returns the number of bytes staged. The image still has to be [verified](verify).
+#[cfg(feature = "cross")]
+pub async fn download<S: Storage>(
+    stack: Stack<'_>,
+    server: IpEndpoint,
+    filename: &CStr,
+    storage: &mut S,
+    layout: Layout,
+    progress: impl FnMut(&tftp::Progress),
+    cancel: &Cancel,
+) -> Result<u32, TransferError<Error>> {
+    let mut staging = Staging::begin(storage, layout).await;
+    tftp::fetch(stack, server, filename, &mut staging, progress, cancel).await?;
+    Ok(staging.written())
+}
+
+/// Check the header and checksum of the staged image.
+pub async fn verify<S: Storage>(
+    storage: &mut S,
+    layout: &Layout,
+) -> Result<Header, Error> {
+    let mut header = Header::zeroed();
+    storage.read(bytemuck::bytes_of_mut(&mut header), layout.staging.start).await;
+    if header.magic != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    if header.len > layout.staging_len().saturating_sub(HEADER_LEN) {
+        return Err(Error::TooLarge);
+    }
+
+    let start = layout.staging.start + HEADER_LEN;
+    let image = Range {
+        start,
+        end: start + header.len,
+    };
+    let mut buf = [0; 256];
+    let crc = hash::digest_storage(&mut Crc32::new(), storage, image, &mut buf).await;
+
+    match crc {
+        | actual if actual == header.crc32 => Ok(header),
+        | actual => Err(Error::CrcMismatch {
+            expected: header.crc32,
+            actual,
+        }),
+    }
+}
+
+/// Verify the staged image and request the bootloader to swap it in on next boot.
+pub async fn activate<S: Storage>(
+    storage: &mut S,
+    layout: &Layout,
+) -> Result<Header, Error> {
+    let header = verify(storage, layout).await?;
+    cancel(storage, layout).await;
+    let request = SwapRequest {
+        magic: SWAP_MAGIC,
+        crc32: header.crc32,
+    };
+    storage.program(bytemuck::bytes_of(&request), layout.state).await;
+    Ok(header)
+}
+
+/// The pending swap request, if any.
+pub async fn pending<S: Storage>(
+    storage: &mut S,
+    layout: &Layout,
+) -> Option<SwapRequest> {
+    let mut request = SwapRequest::zeroed();
+    storage.read(bytemuck::bytes_of_mut(&mut request), layout.state).await;
+    (request.magic == SWAP_MAGIC).then_some(request)
+}
+
+/// Cancel a pending swap request.
+pub async fn cancel<S: Storage>(storage: &mut S, layout: &Layout) {
+    let state = layout.state;
+    let last = state + (size_of::<SwapRequest>() as u32 - 1);
+    storage.erase((state..=last).into()).await;
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            | Error::BadMagic => write!(f, "no update image staged"),
+            | Error::TooLarge => write!(f, "update image exceeds staging slot"),
+            | Error::CrcMismatch { expected, actual } => write!(
+                f,
+                "update image checksum mismatch: expected {expected:08x}, got {actual:08x}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            | Error::TooLarge => ErrorKind::OutOfMemory,
+            | _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::storage::sim::MemFlash;
+    use crate::storage::sim::NoLatency;
+    use crate::util::hash::Hasher;
+
+    const SECTOR: u32 = MemFlash::<NoLatency>::SECTOR_SIZE;
+    const LAYOUT: Layout = Layout {
+        staging: Range {
+            start: SECTOR,
+            end: 3 * SECTOR,
+        },
+        state: 3 * SECTOR,
+    };
+
+    #[test]
+    fn test_stage_and_activate() {
+        let mut buf = [0; 4 * SECTOR as usize];
+        let mut flash = MemFlash::new(&mut buf);
+
+        let image = [0xA5; 300];
+        let mut crc = Crc32::new();
+        crc.update(&image);
+        let header = Header {
+            magic: MAGIC,
+            version: 2,
+            len: image.len() as u32,
+            crc32: crc.finish(),
+        };
+
+        block_on(async {
+            let mut staging = Staging::begin(&mut flash, LAYOUT).await;
+            staging.write_all(bytemuck::bytes_of(&header)).await.unwrap();
+            staging.write_all(&image).await.unwrap();
+            assert_eq!(staging.written(), HEADER_LEN + 300);
+
+            assert_eq!(verify(&mut flash, &LAYOUT).await, Ok(header));
+            assert_eq!(pending(&mut flash, &LAYOUT).await, None);
+            activate(&mut flash, &LAYOUT).await.unwrap();
+            assert_eq!(
+                pending(&mut flash, &LAYOUT).await.map(|request| request.crc32),
+                Some(header.crc32)
+            );
+
+            // corrupt the image
+            flash.program(&[0], SECTOR + HEADER_LEN).await;
+            assert!(matches!(
+                verify(&mut flash, &LAYOUT).await,
+                Err(Error::CrcMismatch { .. })
+            ));
+
+            Staging::begin(&mut flash, LAYOUT).await;
+            assert_eq!(pending(&mut flash, &LAYOUT).await, None);
+            assert_eq!(verify(&mut flash, &LAYOUT).await, Err(Error::BadMagic));
+        });
+    }
+}
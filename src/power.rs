@@ -0,0 +1,202 @@
+//! Stop mode while the system is idle.
+//!
+//! [`run`] stops the system once there has been no [input](events::INPUT)
+//! for a while and the published state allows it, see [`Quiet::allows_stop`].
+//! In Stop mode all clocks but the LSE are halted and the SRAMs are retained;
+//! the enabled [`Wake`] sources bring the system back, after which [`stop`]
+//! restores the clock tree and returns.
+//!
+//! The embassy time driver runs on a timer clocked from the APB,
+//! so time stands still while stopped and timers fire late by the time spent stopped.
+//! The RTC keeps going.
+//! SDRAM is not brought up by the [board](crate::board) yet;
+//! once it is, it has to enter self-refresh before stopping.
+
+#[cfg(feature = "cross")]
+use embassy_futures::select::select;
+#[cfg(feature = "cross")]
+use embassy_futures::select::Either;
+#[cfg(feature = "cross")]
+use embassy_time::Duration;
+#[cfg(feature = "cross")]
+use embassy_time::Timer;
+
+use crate::system::events;
+use crate::system::events::Display;
+use crate::system::events::Network;
+use crate::system::events::Storage;
+
+/// Sources that may wake the system from Stop mode.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Wake {
+    /// the user button, on EXTI line 0
+    pub button: bool,
+    /// a magic packet received by the Ethernet MAC, on EXTI line 19
+    pub ethernet: bool,
+}
+
+/// The parts of the system state that keep it from stopping.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Quiet {
+    pub display: Display,
+    pub network: Network,
+    pub storage: Storage,
+}
+
+impl Quiet {
+    /// The state as currently published in [`events`].
+    pub fn now() -> Self {
+        Self {
+            display: events::DISPLAY.get(),
+            network: events::NETWORK.get(),
+            storage: events::STORAGE.get(),
+        }
+    }
+
+    /// Whether the system may stop, waking from `wake`.
+    ///
+    /// Needs the display off and storage idle. The network has to be down,
+    /// unless a magic packet can wake the system, which then is all it answers.
+    pub fn allows_stop(&self, wake: Wake) -> bool {
+        let network = match self.network {
+            | Network::Down => true,
+            | Network::Pending | Network::Up => wake.ethernet,
+        };
+        self.display == Display::Off && self.storage != Storage::Busy && network
+    }
+}
+
+/// Stop whenever there has been no input for `idle`
+/// and the [state allows it](Quiet::allows_stop).
+///
+/// # Panics
+/// Panics if no `wake` source is enabled, which would stop the system for good,
+/// or if [`events::INPUT`] has no subscriptions left.
+#[cfg(feature = "cross")]
+pub async fn run(scb: &mut cortex_m::peripheral::SCB, idle: Duration, wake: Wake) -> ! {
+    assert!(wake != Wake::default(), "no wake source enabled");
+    let mut input = events::input().expect("too many input subscribers");
+    loop {
+        match select(Timer::after(idle), input.next_message_pure()).await {
+            | Either::First(()) if Quiet::now().allows_stop(wake) => {
+                stop(scb, wake);
+            }
+            | _ => {}
+        }
+    }
+}
+
+/// Enter Stop mode until one of the `wake` sources fires,
+/// returning those that did once the clocks are restored.
+///
+/// Blocks the executor, so the rest of the system has to be quiet.
+#[cfg(feature = "cross")]
+pub fn stop(scb: &mut cortex_m::peripheral::SCB, wake: Wake) -> Wake {
+    use embassy_stm32::pac::ETH;
+    use embassy_stm32::pac::EXTI;
+    use embassy_stm32::pac::PWR;
+    use embassy_stm32::pac::RCC;
+
+    const BUTTON_LINE: usize = 0;
+    const ETHERNET_LINE: usize = 19;
+
+    let lines = [(BUTTON_LINE, wake.button), (ETHERNET_LINE, wake.ethernet)];
+    for (line, _) in lines.into_iter().filter(|&(_, enabled)| enabled) {
+        EXTI.pr(0).write(|w| w.set_line(line, true));
+        EXTI.rtsr(0).modify(|w| w.set_line(line, true));
+        // as events, so waking does not depend on the interrupts being enabled
+        EXTI.emr(0).modify(|w| w.set_line(line, true));
+    }
+    if wake.ethernet {
+        // the MAC drops all other frames until the magic packet arrives
+        ETH.ethernet_mac().macpmtcsr().modify(|w| {
+            w.set_mpe(true);
+            w.set_pd(true);
+        });
+    }
+
+    // Stop mode exits on HSI, all PLLs off
+    let cfgr = RCC.cfgr().read();
+    let cr = RCC.cr().read();
+
+    PWR.cr1().modify(|w| {
+        w.set_pdds(false);
+        w.set_lpds(true);
+        w.set_fpds(true);
+    });
+    scb.set_sleepdeep();
+    // clear a stale event, so the second WFE sleeps
+    cortex_m::asm::sev();
+    cortex_m::asm::wfe();
+    cortex_m::asm::wfe();
+    scb.clear_sleepdeep();
+
+    if cr.hseon() {
+        RCC.cr().modify(|w| w.set_hseon(true));
+        while !RCC.cr().read().hserdy() {}
+    }
+    if cr.pllon() {
+        RCC.cr().modify(|w| w.set_pllon(true));
+        while !RCC.cr().read().pllrdy() {}
+    }
+    if cr.plli2son() {
+        RCC.cr().modify(|w| w.set_plli2son(true));
+        while !RCC.cr().read().plli2srdy() {}
+    }
+    if cr.pllsaion() {
+        RCC.cr().modify(|w| w.set_pllsaion(true));
+        while !RCC.cr().read().pllsairdy() {}
+    }
+    RCC.cfgr().modify(|w| w.set_sw(cfgr.sws()));
+    while RCC.cfgr().read().sws() != cfgr.sws() {}
+
+    let pending = EXTI.pr(0).read();
+    let woken = Wake {
+        button: wake.button && pending.line(BUTTON_LINE),
+        ethernet: wake.ethernet && pending.line(ETHERNET_LINE),
+    };
+    for (line, _) in lines {
+        EXTI.emr(0).modify(|w| w.set_line(line, false));
+    }
+    // the button line is left pending for its EXTI input, if it is waiting
+    EXTI.pr(0).write(|w| w.set_line(ETHERNET_LINE, true));
+    if wake.ethernet {
+        // a magic packet ends power down by itself, anything else does not
+        ETH.ethernet_mac().macpmtcsr().modify(|w| w.set_pd(false));
+    }
+    woken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_stop() {
+        let button = Wake {
+            button: true,
+            ethernet: false,
+        };
+        let ethernet = Wake {
+            button: true,
+            ethernet: true,
+        };
+
+        let mut quiet = Quiet::default();
+        assert!(quiet.allows_stop(button));
+        quiet.network = Network::Up;
+        assert!(!quiet.allows_stop(button));
+        assert!(quiet.allows_stop(ethernet));
+        quiet.storage = Storage::Busy;
+        assert!(!quiet.allows_stop(ethernet));
+        quiet.storage = Storage::Idle;
+        quiet.display = Display::On;
+        assert!(!quiet.allows_stop(ethernet));
+    }
+}
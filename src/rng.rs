@@ -0,0 +1,417 @@
+//! Random numbers from the hardware RNG, with a ChaCha20 fallback.
+//!
+//! Hardware output is checked with the continuous health tests of NIST SP 800-90B
+//! (repetition count and adaptive proportion) before it is handed out.
+//! When the hardware fails or its output fails a test, requests are served
+//! by a ChaCha20 DRBG instead, which is seeded from healthy hardware output
+//! and reseeded every [`RESEED_INTERVAL`] bytes of it.
+
+use core::fmt;
+use core::fmt::Display;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use crate::util::hash::Hasher;
+use crate::util::hash::Sha256;
+
+/// hardware bytes handed out between reseeds of the DRBG
+pub const RESEED_INTERVAL: usize = 64 << 10;
+/// bytes of hardware output the DRBG is (re)seeded with
+pub const SEED_LEN: usize = 32;
+
+/// assumed min-entropy of the hardware output, in bits per byte;
+/// the cutoffs below are for a false positive rate of 2^-20 at this entropy
+const MIN_ENTROPY: u32 = 4;
+/// identical consecutive bytes failing the repetition count test
+const REPETITION_CUTOFF: u32 = 1 + 20_u32.div_ceil(MIN_ENTROPY);
+const ADAPTIVE_WINDOW: u32 = 512;
+/// occurrences of a window's first byte failing the adaptive proportion test
+const ADAPTIVE_CUTOFF: u32 = 184;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574];
+const BLOCK_LEN: usize = 64;
+
+/// Source of raw entropy, e.g. the hardware RNG.
+#[allow(async_fn_in_trait)]
+pub trait Entropy {
+    type Error;
+
+    async fn fill(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+pub struct Rng<E> {
+    state: Mutex<CriticalSectionRawMutex, State<E>>,
+}
+
+struct State<E> {
+    source: E,
+    health: Health,
+    drbg: Option<ChaCha>,
+    /// hardware bytes handed out since the DRBG was last seeded
+    since_reseed: usize,
+    status: Status,
+}
+
+/// Where a request was served from.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Served {
+    Hardware,
+    Fallback,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the hardware failed before the fallback was ever seeded
+    Unseeded,
+}
+
+/// Counters of an [`Rng`].
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Status {
+    /// requests the hardware could not serve
+    pub source_errors: u32,
+    pub repetition_failures: u32,
+    pub proportion_failures: u32,
+    pub reseeds: u32,
+}
+
+/// A failed health test.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum Failure {
+    Repetition,
+    Proportion,
+}
+
+/// Continuous health tests of NIST SP 800-90B, section 4.4.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+struct Health {
+    last: u8,
+    repeats: u32,
+    window_first: u8,
+    window_count: u32,
+    window_seen: u32,
+}
+
+/// ChaCha20 keystream as DRBG, with fast key erasure after each request.
+#[derive(Clone)]
+pub struct ChaCha {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl<E: Entropy> Rng<E> {
+    pub const fn new(source: E) -> Self {
+        Self {
+            state: Mutex::new(State {
+                source,
+                health: Health::new(),
+                drbg: None,
+                since_reseed: 0,
+                status: Status {
+                    source_errors: 0,
+                    repetition_failures: 0,
+                    proportion_failures: 0,
+                    reseeds: 0,
+                },
+            }),
+        }
+    }
+
+    pub async fn fill(&self, buf: &mut [u8]) -> Result<Served, Error> {
+        let mut state = self.state.lock().await;
+        let state = &mut *state;
+        if state.drbg.is_none() || state.since_reseed >= RESEED_INTERVAL {
+            let mut seed = [0; SEED_LEN];
+            if state.hardware(&mut seed).await {
+                state.drbg.get_or_insert_with(ChaCha::unseeded).reseed(&seed);
+                state.since_reseed = 0;
+                state.status.reseeds += 1;
+            }
+        }
+        if state.hardware(buf).await {
+            state.since_reseed += buf.len();
+            return Ok(Served::Hardware);
+        }
+        let drbg = state.drbg.as_mut().ok_or(Error::Unseeded)?;
+        drbg.fill(buf);
+        Ok(Served::Fallback)
+    }
+
+    pub async fn next_u64(&self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        self.fill(&mut bytes).await?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub async fn status(&self) -> Status {
+        self.state.lock().await.status
+    }
+}
+
+impl<E: Entropy> State<E> {
+    /// Fill `buf` from the source. Returns whether the output passed the health tests.
+    async fn hardware(&mut self, buf: &mut [u8]) -> bool {
+        if self.source.fill(buf).await.is_err() {
+            self.status.source_errors += 1;
+            return false;
+        }
+        match self.health.check(buf) {
+            | Ok(()) => true,
+            | Err(Failure::Repetition) => {
+                self.status.repetition_failures += 1;
+                false
+            }
+            | Err(Failure::Proportion) => {
+                self.status.proportion_failures += 1;
+                false
+            }
+        }
+    }
+}
+
+impl Health {
+    const fn new() -> Self {
+        Self {
+            last: 0,
+            repeats: 0,
+            window_first: 0,
+            window_count: 0,
+            window_seen: 0,
+        }
+    }
+
+    fn check(&mut self, bytes: &[u8]) -> Result<(), Failure> {
+        let mut result = Ok(());
+        for &byte in bytes {
+            if let Err(failure) = self.sample(byte) {
+                // start over, so one failure is not reported for every later sample
+                *self = Self::new();
+                result = Err(failure);
+            }
+        }
+        result
+    }
+
+    fn sample(&mut self, byte: u8) -> Result<(), Failure> {
+        if self.repeats > 0 && byte == self.last {
+            self.repeats += 1;
+        } else {
+            self.last = byte;
+            self.repeats = 1;
+        }
+
+        if self.window_seen == 0 {
+            self.window_first = byte;
+            self.window_count = 0;
+        }
+        if byte == self.window_first {
+            self.window_count += 1;
+        }
+        self.window_seen = (self.window_seen + 1) % ADAPTIVE_WINDOW;
+
+        if self.repeats >= REPETITION_CUTOFF {
+            Err(Failure::Repetition)
+        } else if self.window_count >= ADAPTIVE_CUTOFF {
+            Err(Failure::Proportion)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl ChaCha {
+    pub fn new(seed: &[u8]) -> Self {
+        let mut chacha = Self::unseeded();
+        chacha.reseed(seed);
+        chacha
+    }
+
+    fn unseeded() -> Self {
+        Self {
+            key: [0; 8],
+            counter: 0,
+        }
+    }
+
+    /// Mix `seed` into the key.
+    pub fn reseed(&mut self, seed: &[u8]) {
+        let mut sha = Sha256::new();
+        for word in self.key {
+            sha.update(&word.to_le_bytes());
+        }
+        sha.update(seed);
+        self.rekey(&sha.finish());
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let mut block = [0; BLOCK_LEN];
+        for chunk in buf.chunks_mut(BLOCK_LEN) {
+            self.next_block(&mut block);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        // replace the key, so the output cannot be reconstructed from the state
+        self.next_block(&mut block);
+        self.rekey(&block[..32]);
+        block.fill(0);
+    }
+
+    fn rekey(&mut self, key: &[u8]) {
+        for (word, bytes) in self.key.iter_mut().zip(key.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        self.counter = 0;
+    }
+
+    fn next_block(&mut self, out: &mut [u8; BLOCK_LEN]) {
+        block(&self.key, self.counter, out);
+        self.counter += 1;
+    }
+}
+
+/// The ChaCha20 block with a 64-bit counter and a zero nonce.
+fn block(key: &[u32; 8], counter: u64, out: &mut [u8; BLOCK_LEN]) {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&SIGMA);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+
+    let mut x = input;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for ((bytes, x), input) in out.chunks_exact_mut(4).zip(x).zip(input) {
+        bytes.copy_from_slice(&x.wrapping_add(input).to_le_bytes());
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+#[cfg(feature = "cross")]
+impl<T: embassy_stm32::rng::Instance> Entropy for embassy_stm32::rng::Rng<'_, T> {
+    type Error = embassy_stm32::rng::Error;
+
+    async fn fill(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.async_fill_bytes(buf).await
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "source errors: {}, repetition failures: {}, proportion failures: {}, reseeds: {}",
+            self.source_errors,
+            self.repetition_failures,
+            self.proportion_failures,
+            self.reseeds
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Unseeded => write!(f, "no entropy available"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Counts up, then gets stuck.
+    struct Flaky {
+        next: u8,
+        healthy: usize,
+    }
+
+    impl Entropy for Flaky {
+        type Error = ();
+
+        async fn fill(&mut self, buf: &mut [u8]) -> Result<(), ()> {
+            for byte in buf {
+                if self.healthy > 0 {
+                    self.healthy -= 1;
+                    self.next = self.next.wrapping_add(1);
+                }
+                *byte = self.next;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chacha() {
+        // RFC 7539, appendix A.1, test vector 1
+        let mut out = [0; BLOCK_LEN];
+        block(&[0; 8], 0, &mut out);
+        assert_eq!(
+            out[..16],
+            *b"\x76\xb8\xe0\xad\xa0\xf1\x3d\x90\x40\x5d\x6a\xe5\x53\x86\xbd\x28"
+        );
+        assert_eq!(
+            out[48..],
+            *b"\x6a\x43\xb8\xf4\x15\x18\xa1\x1c\xc3\x87\xb6\x69\xb2\xee\x65\x86"
+        );
+    }
+
+    #[test]
+    fn test_fallback() {
+        let rng = Rng::new(Flaky {
+            next: 0,
+            healthy: SEED_LEN + 4,
+        });
+        block_on(async {
+            let mut buf = [0; 4];
+            assert_eq!(rng.fill(&mut buf).await, Ok(Served::Hardware));
+            assert_eq!(buf, [33, 34, 35, 36]);
+            // stuck output fails the repetition count test
+            let mut buf = [0; 8];
+            assert_eq!(rng.fill(&mut buf).await, Ok(Served::Fallback));
+            assert_ne!(buf, [36; 8]);
+            let status = rng.status().await;
+            assert_eq!(status.repetition_failures, 1);
+            assert_eq!(status.reseeds, 1);
+        });
+
+        let stuck = Rng::new(Flaky {
+            next: 0,
+            healthy: 0,
+        });
+        assert_eq!(block_on(stuck.next_u64()), Err(Error::Unseeded));
+    }
+}
@@ -0,0 +1,289 @@
+//! Calendar time kept by the hardware RTC.
+//!
+//! The RTC runs from the LSE in the backup domain, so it keeps counting
+//! across resets (and on battery, where fitted).
+//! A marker in a backup register tells whether the time was ever set.
+
+#[cfg(feature = "cross")]
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Display;
+use core::str;
+
+#[cfg(feature = "cross")]
+use embassy_stm32::rtc;
+#[cfg(feature = "cross")]
+use embassy_stm32::rtc::Rtc;
+#[cfg(feature = "cross")]
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+#[cfg(feature = "cross")]
+use embassy_sync::blocking_mutex::Mutex;
+
+/// backup register holding [`VALID`]
+#[cfg(feature = "cross")]
+const BACKUP_REGISTER: usize = 0;
+/// "RTC1"
+#[cfg(feature = "cross")]
+const VALID: u32 = 0x5254_4331;
+
+/// days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+const DAYS_PER_ERA: i64 = 146_097;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A UTC date and time.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// the RTC only counts years 2000 through 2099
+    Range,
+    /// the time was never set since the backup domain lost power
+    Unset,
+    #[cfg(feature = "cross")]
+    Rtc(rtc::RtcError),
+}
+
+/// Shared access to the RTC.
+#[cfg(feature = "cross")]
+pub struct Clock {
+    rtc: Mutex<CriticalSectionRawMutex, RefCell<Rtc>>,
+}
+
+impl DateTime {
+    pub const MIN: Self = Self::new(2000, 1, 1, 0, 0, 0);
+    pub const MAX: Self = Self::new(2099, 12, 31, 23, 59, 59);
+
+    pub const fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Convert seconds since 1970-01-01 00:00:00 UTC.
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / SECONDS_PER_DAY) as i64 + UNIX_EPOCH_DAYS;
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days - era * DAYS_PER_ERA;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+            - day_of_era / 146_096)
+            / 365;
+        let day_of_year =
+            day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // months starting in March
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        let time = seconds % SECONDS_PER_DAY;
+        Self::new(
+            year as u16,
+            month as u8,
+            day as u8,
+            (time / 3600) as u8,
+            (time / 60 % 60) as u8,
+            (time % 60) as u8,
+        )
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era =
+            year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * DAYS_PER_ERA + day_of_era - UNIX_EPOCH_DAYS) as u64;
+        days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    /// Day of the week, 0 being Monday.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((self.to_unix() / SECONDS_PER_DAY + 3) % 7) as u8
+    }
+
+    /// Parse `YYYY-MM-DD` and `HH:MM:SS`.
+    pub fn parse(date: &[u8], time: &[u8]) -> Option<Self> {
+        fn fields<const N: usize>(text: &[u8], separator: u8) -> Option<[u16; N]> {
+            let mut fields = [0; N];
+            let mut parts = text.split(|&byte| byte == separator);
+            for field in &mut fields {
+                let part = parts.next().filter(|part| (1..=4).contains(&part.len()))?;
+                *field = str::from_utf8(part).ok()?.parse().ok()?;
+            }
+            parts.next().is_none().then_some(fields)
+        }
+        let [year, month, day] = fields(date, b'-')?;
+        let [hour, minute, second] = fields(time, b':')?;
+        let date_time = Self::new(
+            year,
+            month.try_into().ok()?,
+            day.try_into().ok()?,
+            hour.try_into().ok()?,
+            minute.try_into().ok()?,
+            second.try_into().ok()?,
+        );
+        date_time.is_valid().then_some(date_time)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let leap = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        let days = match self.month {
+            | 2 if leap => 29,
+            | 2 => 28,
+            | 4 | 6 | 9 | 11 => 30,
+            | _ => 31,
+        };
+        (1..=12).contains(&self.month)
+            && (1..=days).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Clock {
+    pub fn new(rtc: Rtc) -> Self {
+        Self {
+            rtc: Mutex::new(RefCell::new(rtc)),
+        }
+    }
+
+    pub fn now(&self) -> Result<DateTime, Error> {
+        self.rtc.lock(|rtc| {
+            let rtc = rtc.borrow();
+            if rtc.read_backup_register(BACKUP_REGISTER) != Some(VALID) {
+                return Err(Error::Unset);
+            }
+            let now = rtc.now().map_err(Error::Rtc)?;
+            Ok(DateTime::new(
+                now.year(),
+                now.month(),
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second(),
+            ))
+        })
+    }
+
+    pub fn set(&self, time: DateTime) -> Result<(), Error> {
+        if !time.is_valid() || !(DateTime::MIN..=DateTime::MAX).contains(&time) {
+            return Err(Error::Range);
+        }
+        let weekday = match time.weekday() {
+            | 0 => rtc::DayOfWeek::Monday,
+            | 1 => rtc::DayOfWeek::Tuesday,
+            | 2 => rtc::DayOfWeek::Wednesday,
+            | 3 => rtc::DayOfWeek::Thursday,
+            | 4 => rtc::DayOfWeek::Friday,
+            | 5 => rtc::DayOfWeek::Saturday,
+            | _ => rtc::DayOfWeek::Sunday,
+        };
+        let time = rtc::DateTime::from(
+            time.year,
+            time.month,
+            time.day,
+            weekday,
+            time.hour,
+            time.minute,
+            time.second,
+        )
+        .map_err(|_| Error::Range)?;
+        self.rtc.lock(|rtc| {
+            let mut rtc = rtc.borrow_mut();
+            rtc.set_datetime(time).map_err(Error::Rtc)?;
+            rtc.write_backup_register(BACKUP_REGISTER, VALID);
+            Ok(())
+        })
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Range => write!(
+                f,
+                "time out of range ({} to {})",
+                DateTime::MIN,
+                DateTime::MAX
+            ),
+            | Error::Unset => write!(f, "time not set"),
+            #[cfg(feature = "cross")]
+            | Error::Rtc(e) => write!(f, "rtc: {e:?}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix() {
+        assert_eq!(DateTime::from_unix(0), DateTime::new(1970, 1, 1, 0, 0, 0));
+        let leap_day = DateTime::new(2024, 2, 29, 12, 34, 56);
+        assert_eq!(leap_day.to_unix(), 1_709_210_096);
+        assert_eq!(DateTime::from_unix(1_709_210_096), leap_day);
+        assert_eq!(DateTime::from_unix(DateTime::MAX.to_unix()), DateTime::MAX);
+        assert_eq!(DateTime::new(2000, 1, 1, 0, 0, 0).weekday(), 5);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            DateTime::parse(b"2024-10-16", b"08:05:00"),
+            Some(DateTime::new(2024, 10, 16, 8, 5, 0))
+        );
+        assert_eq!(DateTime::parse(b"2023-02-29", b"00:00:00"), None);
+        assert_eq!(DateTime::parse(b"2024-10-16", b"24:00:00"), None);
+        assert_eq!(DateTime::parse(b"2024-10", b"00:00:00"), None);
+        assert_eq!(DateTime::parse(b"2024-10-16-1", b"00:00:00"), None);
+    }
+}
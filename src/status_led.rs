@@ -0,0 +1,204 @@
+//! Status LEDs showing declarative blink patterns.
+//!
+//! Every LED gets an [`Assignment`] choosing its [`Pattern`] from the system [`State`],
+//! as published in [`events`].
+//! [`run`] plays the pattern and switches as soon as the state changes.
+//!
+//! Levels between off and full brightness are dimmed by the LED itself if it can,
+//! otherwise with software PWM over [`PWM_PERIOD`].
+
+use embassy_futures::select::select3;
+use embassy_futures::select::Either3;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Timer;
+
+use crate::system::events;
+use crate::system::events::Health;
+use crate::system::events::Network;
+
+pub const PWM_PERIOD: Duration = Duration::from_millis(10);
+
+/// An LED with 256 levels of brightness.
+pub trait Led {
+    fn set(&mut self, level: u8);
+
+    /// Whether the LED dims itself, e.g. driven by a timer channel.
+    fn dimmable(&self) -> bool {
+        false
+    }
+}
+
+/// One step of a [`Pattern`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Step {
+    pub level: u8,
+    pub duration: Duration,
+}
+
+/// Steps played in a loop.
+///
+/// An empty pattern keeps the LED off.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Pattern(pub &'static [Step]);
+
+/// The parts of the system state LEDs show.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub struct State {
+    pub network: Network,
+    pub health: Health,
+}
+
+/// Chooses the pattern of an LED.
+pub type Assignment = fn(&State) -> Pattern;
+
+const fn step(level: u8, millis: u64) -> Step {
+    Step {
+        level,
+        duration: Duration::from_millis(millis),
+    }
+}
+
+impl Pattern {
+    pub const OFF: Self = Self(&[]);
+    pub const SOLID: Self = Self(&[step(u8::MAX, 1000)]);
+    pub const FAST_BLINK: Self = Self(&[step(u8::MAX, 100), step(0, 100)]);
+    /// two beats fading out, then a pause
+    pub const HEARTBEAT: Self = Self(&[
+        step(u8::MAX, 100),
+        step(64, 100),
+        step(0, 100),
+        step(u8::MAX, 100),
+        step(128, 100),
+        step(32, 100),
+        step(0, 800),
+    ]);
+    /// `... --- ...` in Morse code
+    pub const SOS: Self = Self(&[
+        step(u8::MAX, 150),
+        step(0, 150),
+        step(u8::MAX, 150),
+        step(0, 150),
+        step(u8::MAX, 150),
+        step(0, 450),
+        step(u8::MAX, 450),
+        step(0, 150),
+        step(u8::MAX, 450),
+        step(0, 150),
+        step(u8::MAX, 450),
+        step(0, 450),
+        step(u8::MAX, 150),
+        step(0, 150),
+        step(u8::MAX, 150),
+        step(0, 150),
+        step(u8::MAX, 150),
+        step(0, 1050),
+    ]);
+
+    /// Time one loop of the pattern takes.
+    pub fn period(&self) -> Duration {
+        self.0.iter().map(|step| step.duration).fold(Duration::MIN, |a, b| a + b)
+    }
+}
+
+/// A heartbeat, or SOS on a fault.
+pub fn heartbeat(state: &State) -> Pattern {
+    match state.health {
+        | Health::Fault => Pattern::SOS,
+        | Health::Ok => Pattern::HEARTBEAT,
+    }
+}
+
+/// Fast blink while waiting for DHCP, solid once the network is up.
+pub fn network(state: &State) -> Pattern {
+    match state.network {
+        | Network::Down => Pattern::OFF,
+        | Network::Pending => Pattern::FAST_BLINK,
+        | Network::Up => Pattern::SOLID,
+    }
+}
+
+/// Drive `led` with the pattern `assignment` chooses.
+///
+/// # Panics
+/// Panics if the [`events`] have no subscriptions left.
+pub async fn run(led: &mut impl Led, assignment: Assignment) -> ! {
+    let mut network = events::NETWORK.subscribe().expect("no network subscription left");
+    let mut health = events::HEALTH.subscribe().expect("no health subscription left");
+    let mut state = State {
+        network: events::NETWORK.get(),
+        health: events::HEALTH.get(),
+    };
+    loop {
+        let pattern = assignment(&state);
+        match select3(play(led, pattern), network.changed(), health.changed()).await {
+            | Either3::First(never) => never,
+            | Either3::Second(changed) => state.network = changed,
+            | Either3::Third(changed) => state.health = changed,
+        }
+    }
+}
+
+async fn play(led: &mut impl Led, pattern: Pattern) -> ! {
+    loop {
+        if pattern.0.is_empty() {
+            led.set(0);
+            core::future::pending::<()>().await;
+        }
+        for step in pattern.0 {
+            show(led, step).await;
+        }
+    }
+}
+
+async fn show(led: &mut impl Led, step: &Step) {
+    if led.dimmable() || matches!(step.level, 0 | u8::MAX) {
+        led.set(step.level);
+        return Timer::after(step.duration).await;
+    }
+    let on = PWM_PERIOD * u32::from(step.level) / u32::from(u8::MAX);
+    let end = Instant::now() + step.duration;
+    while Instant::now() < end {
+        led.set(u8::MAX);
+        Timer::after(on).await;
+        led.set(0);
+        Timer::after(PWM_PERIOD - on).await;
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Led for embassy_stm32::gpio::Output<'_> {
+    fn set(&mut self, level: u8) {
+        self.set_level((level > u8::MAX / 2).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assignment() {
+        let mut state = State::default();
+        assert_eq!(network(&state), Pattern::OFF);
+        assert_eq!(heartbeat(&state), Pattern::HEARTBEAT);
+        state.network = Network::Pending;
+        assert_eq!(network(&state), Pattern::FAST_BLINK);
+        state.network = Network::Up;
+        state.health = Health::Fault;
+        assert_eq!(network(&state), Pattern::SOLID);
+        assert_eq!(heartbeat(&state), Pattern::SOS);
+
+        assert_eq!(Pattern::OFF.period(), Duration::MIN);
+        assert_eq!(Pattern::HEARTBEAT.period(), Duration::from_millis(1400));
+        // three dots, three dashes, three dots, a word gap
+        assert_eq!(Pattern::SOS.period(), Duration::from_millis(5100));
+    }
+}
@@ -0,0 +1,403 @@
+use core::convert::Infallible;
+use core::fmt;
+use core::fmt::Display;
+use core::range::Range;
+use core::range::RangeInclusive;
+
+use embedded_io_async::ErrorKind;
+use embedded_io_async::ErrorType;
+use embedded_io_async::Write;
+
+use crate::util::align::align_up;
+use crate::util::align::is_aligned_to;
+use crate::util::hash;
+use crate::util::hash::Crc32;
+use crate::util::hash::Hasher;
+
+pub mod sfdp;
+#[cfg(any(test, not(feature = "cross")))]
+pub mod sim;
+
+/// NOR-flash-like storage, as implemented by the QSPI `flash::Device`.
+///
+/// Addresses wrap on overflow of the storage capacity.
+#[allow(async_fn_in_trait)]
+pub trait Storage {
+    /// size of the smallest erasable unit
+    const SECTOR_SIZE: u32;
+    /// size of a program page
+    const PAGE_SIZE: u32;
+
+    fn capacity(&self) -> u32;
+
+    async fn read(&mut self, data: &mut [u8], address: u32);
+
+    /// Write some data. Cannot program 0s back to 1s.
+    ///
+    /// Like a NOR page program, may wrap around within a page; see [`program_pages`].
+    async fn program(&mut self, data: &[u8], address: u32);
+
+    /// Erase, i.e., change 0s back to 1s.
+    ///
+    /// Erases whole sectors; the erased range always contains `range` entirely.
+    async fn erase(&mut self, range: RangeInclusive<u32>);
+
+    /// JEDEC manufacturer, memory type and capacity ID, if available.
+    async fn jedec_id(&mut self) -> Option<[u8; 3]> {
+        None
+    }
+
+    /// Write-protect the blocks overlapping `range`, checking the result.
+    ///
+    /// The protection lasts until [unlocked](Storage::unlock_range) or power cycled.
+    async fn lock_range(
+        &mut self,
+        _range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+
+    /// Lift the write protection of the blocks overlapping `range`, checking the result.
+    async fn unlock_range(
+        &mut self,
+        _range: RangeInclusive<u32>,
+    ) -> Result<(), ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+
+    /// Whether the block holding `address` is write-protected.
+    async fn is_locked(&mut self, _address: u32) -> Result<bool, ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+
+    /// Size of the one-time programmable area, 0 if there is none.
+    fn otp_len(&self) -> u32 {
+        0
+    }
+
+    /// Read from the one-time programmable area.
+    async fn read_otp(
+        &mut self,
+        _data: &mut [u8],
+        _offset: u32,
+    ) -> Result<(), ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+
+    /// Write to the one-time programmable area, checking the result.
+    /// Like [`Storage::program`], cannot program 0s back to 1s, and never can erase.
+    async fn program_otp(
+        &mut self,
+        _data: &[u8],
+        _offset: u32,
+    ) -> Result<(), ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+
+    /// Whether the one-time programmable area is locked against programming for good.
+    async fn otp_locked(&mut self) -> Result<bool, ProtectError> {
+        Err(ProtectError::Unsupported)
+    }
+}
+
+/// Failure of the write protection or one-time programmable area of a [`Storage`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum ProtectError {
+    /// the storage has no such feature, or it is not enabled
+    Unsupported,
+    /// the access exceeds the OTP area
+    OutOfRange,
+    /// the OTP area is locked
+    OtpLocked,
+    /// the storage did not take the change, e.g. a permanently protected block
+    Failed,
+}
+
+/// [`Write`]r programming consecutive addresses.
+///
+/// The target range has to be erased beforehand.
+pub struct Programmer<'s, S> {
+    storage: &'s mut S,
+    address: u32,
+    written: u32,
+}
+
+/// [`Write`]r streaming into a range of storage, e.g. a download.
+///
+/// Sectors are erased as the data reaches them and the data is checksummed on the way,
+/// so it can be [verified](Sink::verify) once complete.
+/// Nothing is buffered, the data may be larger than RAM.
+pub struct Sink<'s, S> {
+    storage: &'s mut S,
+    range: Range<u32>,
+    written: u32,
+    /// end of the sectors erased so far
+    erased: u32,
+    crc: Crc32,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum SinkError {
+    /// the data does not fit into the range
+    TooLarge,
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+/// [`Write`]r comparing the data written to it against the storage contents.
+pub struct Verifier<'s, S> {
+    storage: &'s mut S,
+    address: u32,
+    compared: u32,
+    mismatches: u32,
+    first_mismatch: Option<u32>,
+}
+
+impl<'s, S: Storage> Programmer<'s, S> {
+    pub fn new(storage: &'s mut S, address: u32) -> Self {
+        Self {
+            storage,
+            address,
+            written: 0,
+        }
+    }
+
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+}
+
+impl<S> ErrorType for Programmer<'_, S> {
+    type Error = Infallible;
+}
+
+impl<S: Storage> Write for Programmer<'_, S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let address = self.address.wrapping_add(self.written);
+        program_pages(self.storage, buf, address).await;
+        self.written = self.written.wrapping_add(buf.len() as u32);
+        Ok(buf.len())
+    }
+}
+
+impl<'s, S: Storage> Sink<'s, S> {
+    /// # Panics
+    /// Panics if `range` does not start on a sector boundary.
+    pub fn new(storage: &'s mut S, range: Range<u32>) -> Self {
+        assert!(is_aligned_to(range.start, S::SECTOR_SIZE));
+        Self {
+            storage,
+            range,
+            written: 0,
+            erased: range.start,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+
+    /// CRC-32 of the data written so far.
+    pub fn crc32(&self) -> u32 {
+        self.crc.clone().finish()
+    }
+
+    /// Read the data back and compare its CRC-32 to the data written.
+    ///
+    /// Returns the CRC-32.
+    pub async fn verify(&mut self) -> Result<u32, SinkError> {
+        let expected = self.crc32();
+        let start = self.range.start;
+        let data = Range {
+            start,
+            end: start + self.written,
+        };
+        let mut buf = [0; 256];
+        let actual =
+            hash::digest_storage(&mut Crc32::new(), self.storage, data, &mut buf).await;
+        match actual == expected {
+            | true => Ok(actual),
+            | false => Err(SinkError::CrcMismatch { expected, actual }),
+        }
+    }
+}
+
+impl<S> ErrorType for Sink<'_, S> {
+    type Error = SinkError;
+}
+
+impl<S: Storage> Write for Sink<'_, S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = u32::try_from(buf.len()).map_err(|_| SinkError::TooLarge)?;
+        let written = self.written.checked_add(len).ok_or(SinkError::TooLarge)?;
+        if written > self.range.end - self.range.start {
+            return Err(SinkError::TooLarge);
+        }
+        let address = self.range.start + self.written;
+        let end = address + len;
+        if end > self.erased {
+            self.storage.erase((self.erased..=end - 1).into()).await;
+            self.erased = match align_up(end, S::SECTOR_SIZE) {
+                | (erased, false) => erased,
+                | (_, true) => u32::MAX,
+            };
+        }
+        program_pages(self.storage, buf, address).await;
+        self.crc.update(buf);
+        self.written = written;
+        Ok(buf.len())
+    }
+}
+
+impl<'s, S: Storage> Verifier<'s, S> {
+    pub fn new(storage: &'s mut S, address: u32) -> Self {
+        Self {
+            storage,
+            address,
+            compared: 0,
+            mismatches: 0,
+            first_mismatch: None,
+        }
+    }
+
+    pub fn compared(&self) -> u32 {
+        self.compared
+    }
+
+    /// Number of differing bytes.
+    pub fn mismatches(&self) -> u32 {
+        self.mismatches
+    }
+
+    /// Address of the first differing byte.
+    pub fn first_mismatch(&self) -> Option<u32> {
+        self.first_mismatch
+    }
+}
+
+impl<S> ErrorType for Verifier<'_, S> {
+    type Error = Infallible;
+}
+
+impl<S: Storage> Write for Verifier<'_, S> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut actual = [0; 64];
+        for expected in buf.chunks(actual.len()) {
+            let address = self.address.wrapping_add(self.compared);
+            let actual = &mut actual[..expected.len()];
+            self.storage.read(actual, address).await;
+
+            for (offset, (a, e)) in actual.iter().zip(expected).enumerate() {
+                if a != e {
+                    self.mismatches += 1;
+                    self.first_mismatch
+                        .get_or_insert(address.wrapping_add(offset as u32));
+                }
+            }
+            self.compared = self.compared.wrapping_add(expected.len() as u32);
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | SinkError::TooLarge => write!(f, "data exceeds the target range"),
+            | SinkError::CrcMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {expected:08x}, got {actual:08x}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for SinkError {}
+
+impl Display for ProtectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | ProtectError::Unsupported => write!(f, "not supported by the storage"),
+            | ProtectError::OutOfRange => write!(f, "exceeds the OTP area"),
+            | ProtectError::OtpLocked => write!(f, "OTP area is locked"),
+            | ProtectError::Failed => write!(f, "storage rejected the change"),
+        }
+    }
+}
+
+impl core::error::Error for ProtectError {}
+
+impl embedded_io_async::Error for SinkError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            | SinkError::TooLarge => ErrorKind::OutOfMemory,
+            | SinkError::CrcMismatch { .. } => ErrorKind::InvalidData,
+        }
+    }
+}
+
+/// Program `data` at `address`, split so that no program crosses a page boundary.
+pub async fn program_pages<S: Storage>(storage: &mut S, data: &[u8], address: u32) {
+    let mut data = data;
+    let mut address = address;
+    while !data.is_empty() {
+        let page_left = S::PAGE_SIZE - address % S::PAGE_SIZE;
+        let (page, rest) = data.split_at(data.len().min(page_left as usize));
+        storage.program(page, address).await;
+        address = address.wrapping_add(page.len() as u32);
+        data = rest;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::storage::sim::MemFlash;
+    use crate::storage::sim::NoLatency;
+
+    const SECTOR: u32 = MemFlash::<NoLatency>::SECTOR_SIZE;
+    const PAGE: u32 = MemFlash::<NoLatency>::PAGE_SIZE;
+
+    #[test]
+    fn test_sink() {
+        let mut buf = [0; 4 * SECTOR as usize];
+        let mut flash = MemFlash::new(&mut buf);
+        let data: [u8; 600] = core::array::from_fn(|i| i as u8);
+
+        block_on(async {
+            let mut sink = Sink::new(
+                &mut flash,
+                Range {
+                    start: SECTOR,
+                    end: 3 * SECTOR,
+                },
+            );
+            // the second write starts mid-page and is split at the page boundary
+            sink.write_all(&data[..PAGE as usize + 10]).await.unwrap();
+            sink.write_all(&data[PAGE as usize + 10..]).await.unwrap();
+            assert_eq!(sink.written(), 600);
+            assert_eq!(sink.verify().await, Ok(sink.crc32()));
+            let too_large = [0; 2 * SECTOR as usize];
+            assert_eq!(sink.write(&too_large).await, Err(SinkError::TooLarge));
+        });
+
+        let contents = flash.contents();
+        // only the sector reached was erased
+        assert!(contents[..SECTOR as usize].iter().all(|&b| b == 0));
+        assert_eq!(contents[SECTOR as usize..][..600], data);
+        assert!(contents[SECTOR as usize + 600..2 * SECTOR as usize]
+            .iter()
+            .all(|&b| b == 0xFF));
+        assert!(contents[2 * SECTOR as usize..].iter().all(|&b| b == 0));
+        assert_eq!(flash.stats().programs, 4);
+    }
+}
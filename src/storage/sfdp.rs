@@ -0,0 +1,248 @@
+//! Serial Flash Discoverable Parameters ([JESD216]), describing the geometry of NOR flash.
+//!
+//! Only the basic flash parameter table is used, located through the first
+//! parameter header. Parsed apart from the QSPI driver, which only builds for the
+//! target, so the parsing is tested on the host.
+//!
+//! [JESD216]: https://www.jedec.org/standards-documents/docs/jesd216b
+
+use core::fmt;
+use core::fmt::Display;
+use core::range::Range;
+
+pub const SIGNATURE: [u8; 4] = *b"SFDP";
+/// bytes of the SFDP header and the first parameter header
+pub const HEADER_LEN: usize = 16;
+/// bytes of the basic flash parameter table used, as of JESD216B
+pub const BASIC_TABLE_LEN: usize = 16 * 4;
+
+const BASIC_TABLE_ID: u16 = 0xFF00;
+
+/// Capacity and erase and program granularity of a NOR flash.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Geometry {
+    /// bytes
+    pub capacity: u32,
+    pub page_size: u32,
+    /// supported erase sizes, smallest first
+    pub erase: [Option<Erase>; 4],
+    /// `None` for tables predating JESD216A
+    pub quad_enable: Option<QuadEnable>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Erase {
+    /// bytes
+    pub size: u32,
+    pub instruction: u8,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Error {
+    /// no SFDP signature or no basic flash parameter table
+    Missing,
+    /// the table ends before a field that has to be read
+    Truncated,
+    /// the capacity or an erase size exceeds 32 bits
+    Overflow,
+    /// fewer than 2 bytes, which leaves no address bits
+    TooSmall,
+}
+
+/// How to set the quad enable bit, see JESD216B 6.4.18.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum QuadEnable {
+    /// no QE bit, quad mode depends on the instruction
+    None,
+    /// bit 1 of SR2, written as second byte of WRSR; writing one byte clears it
+    Sr2Bit1,
+    /// bit 6 of SR1
+    Sr1Bit6,
+    /// bit 7 of SR2, read with 3Fh and written with 3Eh
+    Sr2Bit7,
+    /// bit 1 of SR2, written as second byte of WRSR
+    Sr2Bit1Keep,
+    /// bit 1 of SR2, read with 35h
+    Sr2Bit1Read35,
+    /// bit 1 of SR2, read with 35h and written with 31h
+    Sr2Bit1Write31,
+}
+
+/// The address range of the basic flash parameter table, given the first
+/// [`HEADER_LEN`] bytes of SFDP data.
+pub fn basic_table(header: &[u8; HEADER_LEN]) -> Option<Range<u32>> {
+    let (sfdp, parameter) = header.split_at(8);
+    if sfdp[..4] != SIGNATURE {
+        return None;
+    }
+    let id = u16::from_be_bytes([parameter[7], parameter[0]]);
+    if id != BASIC_TABLE_ID {
+        return None;
+    }
+    let len = u32::from(parameter[3]) * 4;
+    let start = u32::from_le_bytes([parameter[4], parameter[5], parameter[6], 0]);
+    Some(Range {
+        start,
+        end: start + len,
+    })
+}
+
+impl Geometry {
+    /// Parse a basic flash parameter table of at least 9 dwords.
+    pub fn parse(table: &[u8]) -> Result<Self, Error> {
+        let dword = |n: usize| {
+            let bytes = table.get(4 * (n - 1)..4 * n).ok_or(Error::Truncated)?;
+            Ok(u32::from_le_bytes(
+                bytes.try_into().expect("a dword is 4 bytes"),
+            ))
+        };
+
+        let density = dword(2)?;
+        let bits = match density & (1 << 31) {
+            | 0 => u64::from(density) + 1,
+            | _ => 1u64.checked_shl(density & !(1 << 31)).ok_or(Error::Overflow)?,
+        };
+        let capacity = u32::try_from(bits / 8).map_err(|_| Error::Overflow)?;
+        // the QSPI is configured with log2(capacity) - 1 address bits
+        if capacity < 2 {
+            return Err(Error::TooSmall);
+        }
+
+        let mut erase = [None; 4];
+        let types = [dword(8)?, dword(9)?]
+            .into_iter()
+            .flat_map(|types| [types as u16, (types >> 16) as u16]);
+        for (slot, erase_type) in erase.iter_mut().zip(types) {
+            let [exponent, instruction] = erase_type.to_le_bytes();
+            if exponent != 0 {
+                let size = 1u32.checked_shl(exponent.into()).ok_or(Error::Overflow)?;
+                *slot = Some(Erase { size, instruction });
+            }
+        }
+        // tables without erase types only describe 4 KiB erases
+        if erase.iter().all(Option::is_none) && dword(1)? & 0b11 == 0b01 {
+            let instruction = (dword(1)? >> 8) as u8;
+            erase[0] = Some(Erase {
+                size: 4 << 10,
+                instruction,
+            });
+        }
+        erase.sort_by_key(|erase| erase.map_or(u32::MAX, |erase| erase.size));
+
+        let page_size = match dword(11) {
+            | Ok(dword) => 1 << ((dword >> 4) & 0xF),
+            | Err(_) => 256,
+        };
+        let quad_enable = match dword(15).ok().map(|dword| (dword >> 20) & 0b111) {
+            | Some(0) => Some(QuadEnable::None),
+            | Some(1) => Some(QuadEnable::Sr2Bit1),
+            | Some(2) => Some(QuadEnable::Sr1Bit6),
+            | Some(3) => Some(QuadEnable::Sr2Bit7),
+            | Some(4) => Some(QuadEnable::Sr2Bit1Keep),
+            | Some(5) => Some(QuadEnable::Sr2Bit1Read35),
+            | Some(6) => Some(QuadEnable::Sr2Bit1Write31),
+            | _ => None,
+        };
+
+        Ok(Self {
+            capacity,
+            page_size,
+            erase,
+            quad_enable,
+        })
+    }
+
+    /// Supported erase sizes, smallest first.
+    pub fn erase_sizes(&self) -> impl Iterator<Item = Erase> + '_ {
+        self.erase.iter().flatten().copied()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Error::Missing => write!(f, "no SFDP basic flash parameter table"),
+            | Error::Truncated => write!(f, "SFDP table truncated"),
+            | Error::Overflow => write!(f, "SFDP sizes exceed 32 bits"),
+            | Error::TooSmall => write!(f, "SFDP capacity below 2 bytes"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut header = [0; HEADER_LEN];
+        header[..8].copy_from_slice(b"SFDP\x06\x01\x00\xFF");
+        header[8..].copy_from_slice(&[0x00, 0x06, 0x01, 16, 0x30, 0x00, 0x00, 0xFF]);
+        assert_eq!(
+            basic_table(&header),
+            Some(Range {
+                start: 0x30,
+                end: 0x70
+            })
+        );
+        header[0] = b's';
+        assert_eq!(basic_table(&header), None);
+
+        let mut table = [0; BASIC_TABLE_LEN];
+        let mut set = |n: usize, dword: u32| {
+            table[4 * (n - 1)..4 * n].copy_from_slice(&dword.to_le_bytes());
+        };
+        set(1, 0xFF20_20E5);
+        // 512 Mbit
+        set(2, (512 << 20) - 1);
+        // 64 KiB with D8h, 4 KiB with 20h, 32 KiB with 52h
+        set(8, 0x200C_D810);
+        set(9, 0x0000_520F);
+        // 256 byte pages
+        set(11, 0x0000_0081);
+        set(15, 0x0020_0000);
+
+        let geometry = Geometry::parse(&table).unwrap();
+        assert_eq!(geometry.capacity, 64 << 20);
+        assert_eq!(geometry.page_size, 256);
+        assert_eq!(geometry.quad_enable, Some(QuadEnable::Sr1Bit6));
+        let sizes = [(4 << 10, 0x20), (32 << 10, 0x52), (64 << 10, 0xD8)];
+        assert!(geometry
+            .erase_sizes()
+            .map(|erase| (erase.size, erase.instruction))
+            .eq(sizes));
+
+        // JESD216 without revision A: no page size or quad enable method
+        let geometry = Geometry::parse(&table[..9 * 4]).unwrap();
+        assert_eq!(geometry.page_size, 256);
+        assert_eq!(geometry.quad_enable, None);
+        // nor erase types, only the 4 KiB erase of the first dword
+        table[28..36].fill(0);
+        let geometry = Geometry::parse(&table[..9 * 4]).unwrap();
+        assert!(geometry.erase_sizes().eq([Erase {
+            size: 4 << 10,
+            instruction: 0x20,
+        }]));
+        assert_eq!(Geometry::parse(&table[..8 * 4]), Err(Error::Truncated));
+
+        // 1 byte would leave no address bits, 0 bytes none at all
+        for bits in [7, 0] {
+            table[4..8].copy_from_slice(&u32::to_le_bytes(bits));
+            assert_eq!(Geometry::parse(&table), Err(Error::TooSmall));
+        }
+        table[4..8].copy_from_slice(&u32::to_le_bytes(15));
+        assert_eq!(Geometry::parse(&table).map(|g| g.capacity), Ok(2));
+        table[4..8].copy_from_slice(&u32::to_le_bytes(1 << 31 | 35));
+        assert_eq!(Geometry::parse(&table), Err(Error::Overflow));
+    }
+}
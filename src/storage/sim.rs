@@ -0,0 +1,215 @@
+//! In-memory [`Storage`] for host-side tests.
+//!
+//! Models the NOR flash semantics of the QSPI `flash::Device`:
+//! programming can only clear bits and wraps around within its page,
+//! erasing works on whole sectors, and addresses wrap around the capacity.
+
+use core::ops;
+use core::range::RangeInclusive;
+
+use embedded_hal_async::delay::DelayNs;
+
+use super::Storage;
+
+/// Simulated NOR flash backed by a RAM buffer.
+pub struct MemFlash<'a, D = NoLatency> {
+    data: &'a mut [u8],
+    latency: Latency,
+    delay: D,
+    stats: Stats,
+}
+
+/// Simulated operation latencies; all zero by default.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Latency {
+    pub read_byte_ns: u32,
+    pub program_page_us: u32,
+    pub erase_sector_us: u32,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Stats {
+    pub reads: u32,
+    pub programs: u32,
+    pub erased_sectors: u32,
+    /// number of programmed bytes that tried to change 0s back to 1s
+    pub overprogrammed: u32,
+}
+
+/// [`DelayNs`] that does not delay at all.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+pub struct NoLatency;
+
+impl<'a> MemFlash<'a> {
+    /// `data` is used as-is, i.e., not erased first.
+    ///
+    /// Its length must be a power of two and a multiple of the sector size.
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self::with_latency(data, Latency::default(), NoLatency)
+    }
+}
+
+impl<'a, D: DelayNs> MemFlash<'a, D> {
+    pub fn with_latency(data: &'a mut [u8], latency: Latency, delay: D) -> Self {
+        assert!(data.len().is_power_of_two());
+        assert!(u32::try_from(data.len()).is_ok());
+        assert!(data.len() >= Self::SECTOR_SIZE as usize);
+
+        Self {
+            data,
+            latency,
+            delay,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Raw view of the simulated flash contents.
+    pub fn contents(&self) -> &[u8] {
+        self.data
+    }
+
+    fn index(&self, address: u32) -> usize {
+        address as usize & (self.data.len() - 1)
+    }
+}
+
+impl<D: DelayNs> Storage for MemFlash<'_, D> {
+    const SECTOR_SIZE: u32 = 4 << 10;
+    const PAGE_SIZE: u32 = 256;
+
+    fn capacity(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    async fn read(&mut self, data: &mut [u8], address: u32) {
+        self.stats.reads += 1;
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = self.data[self.index(address.wrapping_add(offset as u32))];
+        }
+        let ns = self.latency.read_byte_ns.saturating_mul(data.len() as u32);
+        self.delay.delay_ns(ns).await;
+    }
+
+    async fn program(&mut self, data: &[u8], address: u32) {
+        self.stats.programs += 1;
+        let page = address & !(Self::PAGE_SIZE - 1);
+        // like the chip, only keep the last page worth of data
+        let skip = data.len().saturating_sub(Self::PAGE_SIZE as usize);
+        for (offset, byte) in data.iter().enumerate().skip(skip) {
+            let column = (address as usize + offset) % Self::PAGE_SIZE as usize;
+            let index = self.index(page.wrapping_add(column as u32));
+            let cell = &mut self.data[index];
+            if *byte & !*cell != 0 {
+                self.stats.overprogrammed += 1;
+            }
+            *cell &= *byte;
+        }
+        let pages = (data.len() as u32).div_ceil(Self::PAGE_SIZE);
+        let us = self.latency.program_page_us.saturating_mul(pages);
+        self.delay.delay_us(us).await;
+    }
+
+    async fn erase(&mut self, range: RangeInclusive<u32>) {
+        let range: ops::RangeInclusive<u32> = range.into();
+        if range.is_empty() {
+            return;
+        }
+        let first = range.start() / Self::SECTOR_SIZE;
+        let last = range.end() / Self::SECTOR_SIZE;
+
+        for sector in first..=last {
+            let start = self.index(sector * Self::SECTOR_SIZE);
+            self.data[start..start + Self::SECTOR_SIZE as usize].fill(0xFF);
+            self.stats.erased_sectors += 1;
+            self.delay.delay_us(self.latency.erase_sector_us).await;
+        }
+    }
+}
+
+impl DelayNs for NoLatency {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    const SECTOR: usize = MemFlash::<NoLatency>::SECTOR_SIZE as usize;
+    const PAGE: usize = MemFlash::<NoLatency>::PAGE_SIZE as usize;
+
+    #[test]
+    fn test_program_clears_bits_only() {
+        let mut buf = [0xFF; 2 * SECTOR];
+        let mut flash = MemFlash::new(&mut buf);
+
+        block_on(flash.program(&[0b1010_1010, 0x0F], 10));
+        block_on(flash.program(&[0b0101_0101, 0xF0], 10));
+
+        let mut read = [0; 2];
+        block_on(flash.read(&mut read, 10));
+        assert_eq!(read, [0, 0]);
+        assert_eq!(flash.stats().overprogrammed, 2);
+    }
+
+    #[test]
+    fn test_erase_whole_sectors() {
+        let mut buf = [0x00; 4 * SECTOR];
+        let mut flash = MemFlash::new(&mut buf);
+
+        let start = SECTOR as u32 + 1;
+        block_on(flash.erase(RangeInclusive::from(start..=start + SECTOR as u32)));
+
+        assert!(flash.contents()[..SECTOR].iter().all(|&b| b == 0x00));
+        assert!(flash.contents()[SECTOR..3 * SECTOR].iter().all(|&b| b == 0xFF));
+        assert!(flash.contents()[3 * SECTOR..].iter().all(|&b| b == 0x00));
+        assert_eq!(flash.stats().erased_sectors, 2);
+    }
+
+    #[test]
+    fn test_erase_empty_range() {
+        let mut buf = [0x00; 2 * SECTOR];
+        let mut flash = MemFlash::new(&mut buf);
+
+        let start = SECTOR as u32 + 1;
+        block_on(flash.erase(RangeInclusive::from(start..=start - 1)));
+
+        assert!(flash.contents().iter().all(|&b| b == 0x00));
+        assert_eq!(flash.stats().erased_sectors, 0);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut buf = [0xFF; SECTOR];
+        let mut flash = MemFlash::new(&mut buf);
+
+        // programs wrap within the last page
+        block_on(flash.program(&[1, 2, 3, 4], SECTOR as u32 - 2));
+        assert_eq!(flash.contents()[SECTOR - PAGE..][..2], [3, 4]);
+        assert_eq!(flash.contents()[SECTOR - 2..], [1, 2]);
+        assert_eq!(flash.contents()[0], 0xFF);
+
+        // reads wrap around the capacity
+        block_on(flash.program(&[5, 6], 0));
+        let mut read = [0; 4];
+        block_on(flash.read(&mut read, u32::MAX - 1));
+        assert_eq!(read, [1, 2, 5, 6]);
+    }
+}
@@ -0,0 +1,5 @@
+//! System-wide state shared between tasks.
+
+pub mod events;
+pub mod panic;
+pub mod supervisor;
@@ -0,0 +1,220 @@
+//! Typed registry of system state and events.
+//!
+//! Each domain has a static [`State`] that any task can set and up to
+//! [`SUBSCRIBERS`] tasks can follow, so a new consumer only needs the static,
+//! not another parameter threaded through `main`.
+//! Momentary occurrences without a lasting state, like button presses,
+//! go through [`INPUT`] instead.
+
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub;
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::watch;
+use embassy_sync::watch::Watch;
+
+use crate::util::lease::Overlong;
+
+/// subscriptions a [`State`] or [`INPUT`] can have
+pub const SUBSCRIBERS: usize = 8;
+/// input events buffered for slow subscribers
+pub const INPUT_CAPACITY: usize = 8;
+/// publishers [`INPUT`] can have
+pub const INPUT_PUBLISHERS: usize = 4;
+
+pub static NETWORK: State<Network> = State::new();
+pub static LINK: State<Link> = State::new();
+pub static DISPLAY: State<Display> = State::new();
+pub static STORAGE: State<Storage> = State::new();
+pub static HEALTH: State<Health> = State::new();
+/// the latest lease held for longer than its resource's threshold
+pub static OVERLONG: State<Option<Overlong>> = State::new();
+pub static INPUT: PubSubChannel<
+    CriticalSectionRawMutex,
+    Input,
+    INPUT_CAPACITY,
+    SUBSCRIBERS,
+    INPUT_PUBLISHERS,
+> = PubSubChannel::new();
+
+pub type Subscription<'s, T> =
+    watch::Receiver<'s, CriticalSectionRawMutex, T, SUBSCRIBERS>;
+pub type InputSubscription = pubsub::Subscriber<
+    'static,
+    CriticalSectionRawMutex,
+    Input,
+    INPUT_CAPACITY,
+    SUBSCRIBERS,
+    INPUT_PUBLISHERS,
+>;
+
+/// The latest value of some state, starting out as the default.
+pub struct State<T: Clone> {
+    watch: Watch<CriticalSectionRawMutex, T, SUBSCRIBERS>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum Network {
+    #[default]
+    Down,
+    /// waiting for a DHCP lease
+    Pending,
+    Up,
+}
+
+/// The Ethernet link as negotiated by the PHY.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum Link {
+    #[default]
+    Down,
+    Up {
+        speed: Speed,
+        full_duplex: bool,
+    },
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Speed {
+    Mbps10,
+    Mbps100,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum Display {
+    #[default]
+    Off,
+    On,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum Storage {
+    #[default]
+    Idle,
+    /// a program or erase is in progress
+    Busy,
+    Failed,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub enum Health {
+    #[default]
+    Ok,
+    /// the firmware panicked, e.g. on the previous boot
+    Fault,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Input {
+    /// the user button
+    ButtonPressed,
+    ButtonReleased,
+}
+
+impl<T: Clone + Default> State<T> {
+    pub const fn new() -> Self {
+        Self {
+            watch: Watch::new(),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.watch.try_get().unwrap_or_default()
+    }
+
+    /// Set the state, waking subscribers if it changed.
+    pub fn set(&self, value: T)
+    where
+        T: PartialEq,
+    {
+        if self.watch.try_get().as_ref() != Some(&value) {
+            self.watch.sender().send(value);
+        }
+    }
+
+    /// Follow changes of the state, or `None` if there are [`SUBSCRIBERS`] already.
+    pub fn subscribe(&self) -> Option<Subscription<'_, T>> {
+        self.watch.receiver()
+    }
+}
+
+impl<T: Clone + Default> Default for State<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Link::Down => write!(f, "down"),
+            | Link::Up { speed, full_duplex } => {
+                let speed = match speed {
+                    | Speed::Mbps10 => 10,
+                    | Speed::Mbps100 => 100,
+                };
+                let duplex = if *full_duplex { "full" } else { "half" };
+                write!(f, "up, {speed} Mbit/s {duplex} duplex")
+            }
+        }
+    }
+}
+
+/// [`Leased`](crate::util::lease::Leased) hook publishing to [`OVERLONG`].
+pub fn overlong(overlong: &Overlong) {
+    OVERLONG.set(Some(*overlong));
+}
+
+/// Follow [`INPUT`], or `None` if there are [`SUBSCRIBERS`] already.
+pub fn input() -> Option<InputSubscription> {
+    INPUT.subscriber().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let state = State::<Network>::new();
+        assert_eq!(state.get(), Network::Down);
+        let mut a = state.subscribe().unwrap();
+        let mut b = state.subscribe().unwrap();
+        state.set(Network::Pending);
+        state.set(Network::Up);
+        assert_eq!(state.get(), Network::Up);
+        // subscribers see the latest value, not every one
+        assert_eq!(block_on(a.changed()), Network::Up);
+        assert_eq!(block_on(b.changed()), Network::Up);
+
+        // setting the same value again is not a change
+        state.set(Network::Up);
+        assert_eq!(a.try_changed(), None);
+
+        let subscriptions: [_; SUBSCRIBERS - 2] =
+            core::array::from_fn(|_| state.subscribe());
+        assert!(subscriptions.iter().all(Option::is_some));
+        assert!(state.subscribe().is_none());
+    }
+}
@@ -0,0 +1,88 @@
+//! Panic messages kept across the reset that follows a panic.
+//!
+//! The panic handler [records](record) the message in the backup SRAM,
+//! where it stays until the next boot [takes](take) it out again,
+//! so every panic is reported once.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::str;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::String;
+
+use crate::mem::backup;
+
+/// bytes of a panic message that are kept; longer ones are cut off
+pub const MESSAGE_LEN: usize = 128;
+
+pub type Message = String<MESSAGE_LEN>;
+
+static PREVIOUS: Mutex<CriticalSectionRawMutex, RefCell<Option<Message>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Store the message of `info` under [`backup::Key::PANIC`].
+///
+/// Meant for the panic handler, so failures are ignored.
+pub fn record(info: &PanicInfo<'_>) {
+    let mut message = Message::new();
+    // a message too long to fit ends at the last piece that did
+    let _ = write!(message, "{info}");
+    let _ = backup::store_bytes(backup::Key::PANIC, message.as_bytes());
+}
+
+/// Move the message of the previous run's panic out of the backup SRAM,
+/// keeping it for [`previous`].
+///
+/// Returns whether the previous run panicked.
+pub fn take() -> bool {
+    let mut buf = [0; MESSAGE_LEN];
+    let Some(data) = backup::load_bytes(backup::Key::PANIC, &mut buf) else {
+        return false;
+    };
+    let text = match str::from_utf8(data) {
+        | Ok(text) => text,
+        | Err(e) => str::from_utf8(&data[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    let mut message = Message::new();
+    message
+        .push_str(text)
+        .expect("the message should fit, as it was loaded into as many bytes");
+    backup::remove(backup::Key::PANIC);
+    PREVIOUS.lock(|previous| *previous.borrow_mut() = Some(message));
+    true
+}
+
+/// The message of the previous run's panic, if it was [taken](take).
+pub fn previous() -> Option<Message> {
+    PREVIOUS.lock(|previous| previous.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::SyncUnsafeCell;
+
+    use super::*;
+
+    static MEMORY: SyncUnsafeCell<[u8; 256]> = SyncUnsafeCell::new([0; 256]);
+
+    #[test]
+    fn test_take() {
+        // Safety: no other test uses the memory
+        backup::install(backup::Store::new(unsafe { &mut *MEMORY.get() }));
+        assert!(!take());
+        backup::store_bytes(backup::Key::PANIC, b"panicked at src/main.rs:1:1:\noops")
+            .unwrap();
+
+        assert!(take());
+        assert_eq!(
+            previous().as_deref(),
+            Some("panicked at src/main.rs:1:1:\noops")
+        );
+        // reported once
+        assert_eq!(backup::load_bytes(backup::Key::PANIC, &mut [0; 8]), None);
+        assert!(!take());
+    }
+}
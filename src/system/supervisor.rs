@@ -0,0 +1,249 @@
+//! Restarting long-running workers when they exit.
+//!
+//! A [`Service`] is a static handle naming a worker and recording its [`Status`],
+//! so the CLI can list every supervised service.
+//! [`Service::supervise`] runs the worker, restarts it with exponential backoff
+//! whenever it exits and records why.
+//!
+//! Panics cannot be caught: the firmware halts and the panic is only seen on the
+//! next boot, e.g. as [`Health::Fault`](super::events::Health::Fault)
+//! and the message [kept](super::panic) across the reset.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Write;
+use core::future::Future;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Timer;
+use heapless::String;
+
+/// length error messages are truncated to
+pub const MESSAGE: usize = 48;
+
+/// A supervised worker.
+pub struct Service {
+    pub name: &'static str,
+    status: Mutex<CriticalSectionRawMutex, RefCell<Status>>,
+}
+
+/// When and how fast to restart a worker.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Policy {
+    pub restart: Restart,
+    /// delay before the first restart
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// runs lasting at least this long reset the backoff
+    pub stable: Duration,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Restart {
+    Always,
+    /// stop once the worker returns `Ok`
+    OnFailure,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum State {
+    /// not supervised yet
+    Idle,
+    Running {
+        since: Instant,
+    },
+    /// waiting to restart
+    Backoff {
+        until: Instant,
+    },
+    Stopped,
+}
+
+/// A snapshot of a [`Service`].
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq, Eq)]
+pub struct Status {
+    pub state: State,
+    pub restarts: u32,
+    pub failures: u32,
+    /// error of the last failure
+    pub error: Option<String<MESSAGE>>,
+}
+
+/// Exponential backoff between restarts.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+struct Backoff {
+    policy: Policy,
+    next: Duration,
+}
+
+impl Policy {
+    pub const DEFAULT: Self = Self {
+        restart: Restart::Always,
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(5 * 60),
+        stable: Duration::from_secs(60),
+    };
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Service {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            status: Mutex::new(RefCell::new(Status {
+                state: State::Idle,
+                restarts: 0,
+                failures: 0,
+                error: None,
+            })),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock(|status| status.borrow().clone())
+    }
+
+    /// Run the worker `start` creates, restarting it according to `policy`.
+    pub async fn supervise<F, E>(&self, policy: Policy, mut start: impl FnMut() -> F) -> !
+    where
+        F: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let mut backoff = Backoff::new(policy);
+        loop {
+            let since = Instant::now();
+            self.update(|status| status.state = State::Running { since });
+            let result = start().await;
+            let failed = result.is_err();
+            self.update(|status| {
+                if let Err(e) = result {
+                    status.failures += 1;
+                    let mut message = String::new();
+                    // truncated messages are good enough
+                    let _ = write!(Truncate(&mut message), "{e}");
+                    status.error = Some(message);
+                }
+            });
+
+            if !failed && policy.restart == Restart::OnFailure {
+                self.update(|status| status.state = State::Stopped);
+                core::future::pending::<()>().await;
+            }
+
+            let delay = backoff.next(Instant::now() - since);
+            let until = Instant::now() + delay;
+            self.update(|status| status.state = State::Backoff { until });
+            Timer::at(until).await;
+            self.update(|status| status.restarts += 1);
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Status)) {
+        self.status.lock(|status| f(&mut status.borrow_mut()));
+    }
+}
+
+impl Backoff {
+    fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            next: policy.initial_backoff,
+        }
+    }
+
+    /// The delay before restarting a worker that ran for `ran`.
+    fn next(&mut self, ran: Duration) -> Duration {
+        if ran >= self.policy.stable {
+            self.next = self.policy.initial_backoff;
+        }
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.policy.max_backoff);
+        delay
+    }
+}
+
+/// Writes as much as fits.
+struct Truncate<'s>(&'s mut String<MESSAGE>);
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.push(c).map_err(|()| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = Instant::now();
+        match self.state {
+            | State::Idle => write!(f, "idle")?,
+            | State::Running { since } => {
+                write!(f, "running for {} s", (now - since).as_secs())?
+            }
+            | State::Backoff { until } => write!(
+                f,
+                "restarting in {} s",
+                until.saturating_duration_since(now).as_secs()
+            )?,
+            | State::Stopped => write!(f, "stopped")?,
+        }
+        write!(
+            f,
+            ", {} restarts, {} failures",
+            self.restarts, self.failures
+        )?;
+        match &self.error {
+            | Some(error) => write!(f, ", last error: {error}"),
+            | None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = Policy {
+            restart: Restart::Always,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            stable: Duration::from_secs(60),
+        };
+        let mut backoff = Backoff::new(policy);
+        let quick = Duration::from_secs(1);
+        let delays = [1, 2, 4, 5, 5].map(Duration::from_secs);
+        for delay in delays {
+            assert_eq!(backoff.next(quick), delay);
+        }
+        // a stable run starts over
+        assert_eq!(backoff.next(policy.stable), policy.initial_backoff);
+        assert_eq!(backoff.next(quick), Duration::from_secs(2));
+
+        let mut message = String::new();
+        assert!(write!(Truncate(&mut message), "{:x<1$}", "", MESSAGE + 1).is_err());
+        assert_eq!(message.len(), MESSAGE);
+    }
+}
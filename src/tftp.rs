@@ -1,70 +1,250 @@
+//! TFTP client ([RFC 1350](https://www.rfc-editor.org/rfc/rfc1350)), octet mode only.
+//!
+//! Requests can carry [`Options`] ([RFC 2347](https://www.rfc-editor.org/rfc/rfc2347)):
+//! a larger block size ([RFC 2348](https://www.rfc-editor.org/rfc/rfc2348)),
+//! the transfer size and the retransmission timeout
+//! ([RFC 2349](https://www.rfc-editor.org/rfc/rfc2349)).
+//! Servers ignoring the options get the RFC 1350 defaults;
+//! servers refusing them get the request again, without options.
+//!
+//! Transfers report their [`Progress`] after every block and can be aborted
+//! through a [`Cancel`] token, which is checked before sending each packet.
+
+mod packet;
+pub mod server;
+
 use core::error::Error;
 use core::ffi::CStr;
 use core::fmt::Debug;
 use core::fmt::Display;
+use core::range::Range;
 
+use embassy_net::udp::PacketMetadata;
 use embassy_net::udp::RecvError;
 use embassy_net::udp::SendError;
 use embassy_net::udp::UdpMetadata;
 use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Stack;
+use embassy_time::with_deadline;
+use embassy_time::Duration;
+use embassy_time::Instant;
 use embedded_io_async::Read;
 use embedded_io_async::Write;
-use ttftp::client::download;
-use ttftp::client::upload;
-use ttftp::client::upload::*;
-use ttftp::client::FilenameError;
-use ttftp::client::TransferError as TtftpError;
-use ttftp::Mode;
-
-pub async fn upload<'filename, F: Read>(
-    filename: &'filename CStr,
-    file: F,
-    sock: &UdpSocket<'_>,
+use packet::ErrorCode;
+use packet::Fields;
+use packet::Packet;
+use packet::ACK;
+use packet::DATA;
+use packet::HEADER_LEN;
+use packet::RRQ;
+use packet::WRQ;
+
+use crate::storage::Sink;
+use crate::storage::SinkError;
+use crate::storage::Storage;
+use crate::util::Cancel;
+
+/// well-known TFTP server port
+pub const PORT: u16 = 69;
+/// data bytes per block without the blksize option
+pub const BLOCK_SIZE: usize = 512;
+pub const PACKET_SIZE: usize = HEADER_LEN + BLOCK_SIZE;
+/// largest block fitting an Ethernet frame, after the IPv4, UDP and TFTP headers
+pub const MAX_BLOCK_SIZE: usize = 1500 - 20 - 8 - HEADER_LEN;
+/// time to wait for a reply before retransmitting, unless negotiated
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+/// retransmissions before a transfer is aborted
+pub const RETRIES: usize = 5;
+
+/// Transfer options, see the [module docs](self).
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[derive(Default)]
+pub struct Options {
+    /// data bytes per block
+    pub blksize: Option<u16>,
+    /// file size in bytes; read requests ask for it with 0
+    pub tsize: Option<u32>,
+    /// retransmission timeout in seconds
+    pub timeout: Option<u8>,
+}
+
+/// Progress of a transfer, reported after every block.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Progress {
+    /// bytes
+    pub transferred: u32,
+    /// size of the file in bytes, if known
+    pub total: Option<u32>,
+    pub retransmits: u32,
+}
+
+/// A file written by [`download_to_flash`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Flashed {
+    pub len: u32,
+    /// CRC-32 (IEEE) of the file, as read back
+    pub crc32: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum TransferError<File> {
+    /// the filename does not fit into a request
+    Filename,
+    /// the server aborted the transfer with an error code
+    Server(u16),
+    /// the server stopped answering
+    Timeout,
+    /// the server acknowledged options that were not requested
+    Options,
+    Cancelled,
+    Send(SendError),
+    Recv(RecvError),
+    File(File),
+}
+
+/// The server end of a transfer.
+struct Peer<'s, 'd> {
+    sock: &'s UdpSocket<'d>,
     remote: UdpMetadata,
-    file_buf: &mut [u8; ttftp::BLOCK_SIZE],
-    rx: &mut [u8; ttftp::PACKET_SIZE],
-    tx: &mut [u8; ttftp::PACKET_SIZE],
-) -> Result<(), TransferError<'filename, 'static, F::Error>> {
-    assert!(sock.payload_recv_capacity() >= ttftp::PACKET_SIZE);
+    cancel: &'s Cancel,
+    /// whether `remote` is the port the server answered from yet
+    bound: bool,
+    timeout: Duration,
+    /// length of the reply that ended the negotiation
+    last: usize,
+    retransmits: u32,
+}
 
-    let mut file = file;
-    let mut buf_offset = 0;
+impl Options {
+    pub const NONE: Self = Self {
+        blksize: None,
+        tsize: None,
+        timeout: None,
+    };
+    /// what [`fetch`] asks for
+    pub const REQUEST: Self = Self {
+        blksize: Some(MAX_BLOCK_SIZE as u16),
+        tsize: Some(0),
+        timeout: None,
+    };
+    const BLKSIZE: &str = "blksize";
+    const TSIZE: &str = "tsize";
+    const TIMEOUT: &str = "timeout";
 
-    let mut state;
-    let send;
-    (state, send) = upload::new(tx, filename, Mode::Octect)?;
+    pub fn block_size(&self) -> usize {
+        self.blksize.map_or(BLOCK_SIZE, usize::from)
+    }
 
-    loop {
-        sock.send_to(&tx[..send], remote).await?;
-        let received = loop {
-            let (received, sender) = sock.recv_from(rx).await?;
-            if sender.endpoint == remote.endpoint {
-                break received;
-            }
+    pub fn timeout(&self) -> Duration {
+        self.timeout.map_or(TIMEOUT, |secs| Duration::from_secs(secs.into()))
+    }
+
+    /// Whether `reply` is a valid answer to these options.
+    ///
+    /// Servers may leave out options and lower the block size, but not add options.
+    fn accepts(&self, reply: &Options) -> bool {
+        let blksize = match (self.blksize, reply.blksize) {
+            | (_, None) => true,
+            | (Some(requested), Some(blksize)) => blksize <= requested,
+            | (None, Some(_)) => false,
         };
+        let tsize = self.tsize.is_some() || reply.tsize.is_none();
+        let timeout = reply.timeout.is_none() || reply.timeout == self.timeout;
+        blksize && tsize && timeout
+    }
 
-        let buf_len = buf_offset
-            + fill_buf(&mut file, &mut file_buf[buf_offset..])
-                .await
-                .map_err(TransferError::File)?;
-        let (result, send) =
-            state.process(&rx[..received], tx, file_buf[..buf_len].iter().copied());
+    /// Parse name and value fields, ignoring unknown options.
+    fn parse<'a>(fields: impl Iterator<Item = &'a [u8]>) -> Option<Self> {
+        let mut options = Self::NONE;
+        let mut fields = fields;
+        while let Some(name) = fields.next() {
+            let value = core::str::from_utf8(fields.next()?).ok()?;
+            if name.eq_ignore_ascii_case(Self::BLKSIZE.as_bytes()) {
+                options.blksize = Some(value.parse().ok().filter(|&b| b >= 8)?);
+            } else if name.eq_ignore_ascii_case(Self::TSIZE.as_bytes()) {
+                options.tsize = Some(value.parse().ok()?);
+            } else if name.eq_ignore_ascii_case(Self::TIMEOUT.as_bytes()) {
+                options.timeout = Some(value.parse().ok().filter(|&t| t >= 1)?);
+            }
+        }
+        Some(options)
+    }
 
-        if let Some(send) = send {
-            sock.send_to(&tx[..send], remote).await?;
+    fn write(&self, fields: &mut Fields<'_>) -> Option<()> {
+        if let Some(blksize) = self.blksize {
+            fields.option(Self::BLKSIZE, blksize.into())?;
+        }
+        if let Some(tsize) = self.tsize {
+            fields.option(Self::TSIZE, tsize)?;
         }
+        if let Some(timeout) = self.timeout {
+            fields.option(Self::TIMEOUT, timeout.into())?;
+        }
+        Some(())
+    }
+}
+
+/// Send `file` to `remote` as `filename`.
+///
+/// `options.tsize` should be the size of the file, if known.
+/// Returns the options the server agreed to.
+///
+/// # Panics
+/// Panics if `tx` cannot hold a block of the requested size.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload<F: Read>(
+    filename: &CStr,
+    file: F,
+    sock: &UdpSocket<'_>,
+    remote: UdpMetadata,
+    options: Options,
+    rx: &mut [u8],
+    tx: &mut [u8],
+    mut progress: impl FnMut(&Progress),
+    cancel: &Cancel,
+) -> Result<Options, TransferError<F::Error>> {
+    assert!(tx.len() >= HEADER_LEN + options.block_size());
+
+    let mut file = file;
+    let total = options.tsize;
+    let ack = |packet: &Packet| *packet == Packet::Ack { block: 0 };
+    let (mut peer, options) =
+        negotiate(sock, remote, WRQ, filename, options, rx, tx, ack, cancel).await?;
 
-        let consumed;
-        (state, consumed) = match result.map_err(TtftpError::strip)? {
-            | AckReceived::NextBlock(awaiting_ack, consumed) => (awaiting_ack, consumed),
-            | AckReceived::TransferComplete => break,
-            | AckReceived::Retransmission(awaiting_ack) => (awaiting_ack, 0),
+    let block_size = options.block_size();
+    let mut block: u16 = 1;
+    let mut transferred: u32 = 0;
+    loop {
+        let payload = &mut tx[HEADER_LEN..][..block_size];
+        let len = match fill_buf(&mut file, payload).await {
+            | Ok(len) => len,
+            | Err(e) => {
+                peer.abort(tx, ErrorCode::Undefined).await;
+                return Err(TransferError::File(e));
+            }
         };
+        packet::header(tx, DATA, block);
+        peer.exchange(&tx[..HEADER_LEN + len], rx, |packet| {
+            *packet == Packet::Ack { block }
+        })
+        .await?;
+        transferred = transferred.wrapping_add(len as u32);
+        progress(&peer.progress(transferred, total));
 
-        buf_offset = buf_len - consumed;
+        if len < block_size {
+            return Ok(options);
+        }
+        block = block.wrapping_add(1);
     }
-
-    Ok(())
 }
 
 async fn fill_buf<F: Read>(file: F, buf: &mut [u8]) -> Result<usize, F::Error> {
@@ -79,104 +259,342 @@ async fn fill_buf<F: Read>(file: F, buf: &mut [u8]) -> Result<usize, F::Error> {
     Ok(written)
 }
 
-pub async fn download<'filename, F: Write>(
-    filename: &'filename CStr,
+/// Receive `filename` from `remote` into `file`.
+///
+/// Returns the options the server agreed to.
+///
+/// # Panics
+/// Panics if `rx` cannot hold a block of the requested size.
+#[allow(clippy::too_many_arguments)]
+pub async fn download<F: Write>(
+    filename: &CStr,
     file: F,
     sock: &UdpSocket<'_>,
     remote: UdpMetadata,
-    rx: &mut [u8; ttftp::PACKET_SIZE],
-    tx: &mut [u8; ttftp::PACKET_SIZE],
-) -> Result<(), TransferError<'filename, 'static, F::Error>> {
-    assert!(sock.payload_recv_capacity() >= ttftp::PACKET_SIZE);
+    options: Options,
+    rx: &mut [u8],
+    tx: &mut [u8],
+    mut progress: impl FnMut(&Progress),
+    cancel: &Cancel,
+) -> Result<Options, TransferError<F::Error>> {
+    assert!(rx.len() >= HEADER_LEN + options.block_size());
+    assert!(sock.payload_recv_capacity() >= HEADER_LEN + options.block_size());
 
     let mut file = file;
+    let first_block = |packet: &Packet| matches!(packet, Packet::Data { block: 1, .. });
+    let (mut peer, options) = negotiate(
+        sock,
+        remote,
+        RRQ,
+        filename,
+        options,
+        rx,
+        tx,
+        first_block,
+        cancel,
+    )
+    .await?;
 
-    let mut state;
-    let send;
-    (state, send) = download::new(tx, filename, Mode::Octect)?;
+    let mut received = match Packet::parse(&rx[..peer.last]) {
+        // the server ignored the options and started right away
+        | Some(Packet::Data { .. }) => peer.last,
+        | _ => {
+            packet::header(tx, ACK, 0);
+            peer.exchange(&tx[..HEADER_LEN], rx, first_block).await?
+        }
+    };
 
+    let block_size = options.block_size();
+    let mut block: u16 = 1;
+    let mut transferred: u32 = 0;
     loop {
-        sock.send_to(&tx[..send], remote).await?;
-        let received = loop {
-            let (received, sender) = sock.recv_from(rx).await?;
-            if sender == remote {
-                break received;
-            }
-        };
-
-        let (result, send) = state.process(&rx[..received], tx);
-
-        if let Some(send) = send {
-            sock.send_to(&tx[..send], remote).await?;
+        let data = &rx[HEADER_LEN..received];
+        let last = data.len() < block_size;
+        if let Err(e) = file.write_all(data).await {
+            peer.abort(tx, ErrorCode::DiskFull).await;
+            return Err(TransferError::File(e));
         }
+        transferred = transferred.wrapping_add(data.len() as u32);
+        progress(&peer.progress(transferred, options.tsize));
 
-        state = match result.map_err(TtftpError::strip)? {
-            | download::BlockReceived::Intermediate(awaiting_data, block) => {
-                file.write_all(block).await.map_err(TransferError::File)?;
-                awaiting_data
-            }
-            | download::BlockReceived::Final(block) => {
-                file.write_all(block).await.map_err(TransferError::File)?;
-                break;
-            }
-            | download::BlockReceived::Retransmission(awaiting_data) => awaiting_data,
+        packet::header(tx, ACK, block);
+        if last {
+            peer.send(&tx[..HEADER_LEN]).await?;
+            return Ok(options);
         }
+        let next = block.wrapping_add(1);
+        received = peer
+            .exchange(
+                &tx[..HEADER_LEN],
+                rx,
+                |packet| matches!(packet, Packet::Data { block, .. } if *block == next),
+            )
+            .await?;
+        block = next;
     }
+}
+
+/// [`download`] `filename` from `server` using a temporary socket,
+/// asking for [`Options::REQUEST`].
+pub async fn fetch<F: Write>(
+    stack: Stack<'_>,
+    server: IpEndpoint,
+    filename: &CStr,
+    file: F,
+    progress: impl FnMut(&Progress),
+    cancel: &Cancel,
+) -> Result<Options, TransferError<F::Error>> {
+    const RX_PACKET: usize = HEADER_LEN + MAX_BLOCK_SIZE;
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buf = [0; 2 * RX_PACKET];
+    let mut tx_buf = [0; 2 * PACKET_SIZE];
+    let mut rx = [0; RX_PACKET];
+    let mut tx = [0; PACKET_SIZE];
+
+    let mut sock =
+        UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    sock.bind(0).expect("binding to an ephemeral port should succeed");
 
-    todo!()
+    let options = Options::REQUEST;
+    download(
+        filename,
+        file,
+        &sock,
+        server.into(),
+        options,
+        &mut rx,
+        &mut tx,
+        progress,
+        cancel,
+    )
+    .await
 }
 
-#[derive(Debug)]
-#[derive(Clone, Copy)]
-#[derive(PartialEq, Eq)]
-pub enum TransferError<'filename, 'rx, File> {
-    Filename(FilenameError<'filename>),
-    Tftp(TtftpError<'rx>),
-    Send(SendError),
-    Recv(RecvError),
-    File(File),
+/// [`fetch`] `filename` straight into `range` of `storage`, through a [`Sink`].
+///
+/// Only one block is held in RAM at a time. Sectors are erased as they are reached;
+/// once complete, the file is read back and checked against the CRC-32 computed
+/// while receiving it, a mismatch failing with [`SinkError::CrcMismatch`].
+///
+/// # Panics
+/// Panics if `range` does not start on a sector boundary.
+pub async fn download_to_flash<S: Storage>(
+    stack: Stack<'_>,
+    server: IpEndpoint,
+    filename: &CStr,
+    storage: &mut S,
+    range: Range<u32>,
+    progress: impl FnMut(&Progress),
+    cancel: &Cancel,
+) -> Result<Flashed, TransferError<SinkError>> {
+    let mut sink = Sink::new(storage, range);
+    fetch(stack, server, filename, &mut sink, progress, cancel).await?;
+    let crc32 = sink.verify().await.map_err(TransferError::File)?;
+    Ok(Flashed {
+        len: sink.written(),
+        crc32,
+    })
 }
 
-impl<File> Display for TransferError<'_, '_, File> {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(
-            f,
-            "file transfer failed: {}",
-            match self {
-                | TransferError::Filename(_) => "bad filename",
-                | TransferError::Tftp(_) => "TTFTP",
-                | TransferError::Send(_) => "UDP send",
-                | TransferError::Recv(_) => "UDP receive",
-                | TransferError::File(_) => "file read or write",
+/// Send a request and wait for the OACK or, if the server ignores the options,
+/// the first packet of the transfer as recognised by `started`.
+///
+/// Returns the server end and the agreed options. The reply is left in `rx`.
+#[allow(clippy::too_many_arguments)]
+async fn negotiate<'s, 'd, E>(
+    sock: &'s UdpSocket<'d>,
+    remote: UdpMetadata,
+    opcode: u16,
+    filename: &CStr,
+    options: Options,
+    rx: &mut [u8],
+    tx: &mut [u8],
+    started: impl Fn(&Packet) -> bool,
+    cancel: &'s Cancel,
+) -> Result<(Peer<'s, 'd>, Options), TransferError<E>> {
+    let mut requested = options;
+    loop {
+        let len = packet::request(tx, opcode, filename.to_bytes(), &requested)
+            .ok_or(TransferError::Filename)?;
+        let mut peer = Peer::new(sock, remote, cancel);
+        let received = match peer
+            .exchange(&tx[..len], rx, |packet| {
+                matches!(packet, Packet::OptionAck { .. }) || started(packet)
+            })
+            .await
+        {
+            | Err(TransferError::Server(code))
+                if code == ErrorCode::Options as u16 && requested != Options::NONE =>
+            {
+                requested = Options::NONE;
+                continue;
             }
-        )
+            | result => result?,
+        };
+        peer.last = received;
+
+        let Some(Packet::OptionAck { options }) = Packet::parse(&rx[..received]) else {
+            return Ok((peer, Options::NONE));
+        };
+        if !requested.accepts(&options) {
+            peer.abort(tx, ErrorCode::Options).await;
+            return Err(TransferError::Options);
+        }
+        peer.timeout = options.timeout();
+        return Ok((peer, options));
     }
 }
 
-impl<File: Debug> Error for TransferError<'_, '_, File> {}
+impl<'s, 'd> Peer<'s, 'd> {
+    fn new(sock: &'s UdpSocket<'d>, remote: UdpMetadata, cancel: &'s Cancel) -> Self {
+        Self {
+            sock,
+            remote,
+            cancel,
+            bound: false,
+            timeout: TIMEOUT,
+            last: 0,
+            retransmits: 0,
+        }
+    }
 
-impl<'filename, File> From<FilenameError<'filename>>
-    for TransferError<'filename, 'static, File>
-{
-    fn from(filename: FilenameError<'filename>) -> Self {
-        TransferError::Filename(filename)
+    fn progress(&self, transferred: u32, total: Option<u32>) -> Progress {
+        Progress {
+            transferred,
+            total,
+            retransmits: self.retransmits,
+        }
+    }
+
+    /// Send `packet`, unless the transfer is cancelled.
+    async fn send<E>(&self, packet: &[u8]) -> Result<(), TransferError<E>> {
+        if self.cancel.is_cancelled() {
+            let mut buf = [0; HEADER_LEN + 1];
+            self.abort(&mut buf, ErrorCode::Undefined).await;
+            return Err(TransferError::Cancelled);
+        }
+        Ok(self.sock.send_to(packet, self.remote).await?)
+    }
+
+    /// Tell the server the transfer is aborted.
+    async fn abort(&self, buf: &mut [u8], code: ErrorCode) {
+        let len = packet::error(buf, code);
+        let _ = self.sock.send_to(&buf[..len], self.remote).await;
+    }
+
+    /// Send `packet` and wait for a reply satisfying `expected`,
+    /// retransmitting on timeout.
+    ///
+    /// The first reply binds the peer to the port the server answers from.
+    /// Returns the length of the reply in `rx`.
+    async fn exchange<E>(
+        &mut self,
+        packet: &[u8],
+        rx: &mut [u8],
+        expected: impl Fn(&Packet) -> bool,
+    ) -> Result<usize, TransferError<E>> {
+        for attempt in 0..=RETRIES {
+            if attempt > 0 {
+                self.retransmits += 1;
+            }
+            self.send(packet).await?;
+
+            let deadline = Instant::now() + self.timeout;
+            while let Ok(result) = with_deadline(deadline, self.sock.recv_from(rx)).await
+            {
+                let (received, sender) = result?;
+                let from_server = match self.bound {
+                    | true => sender.endpoint == self.remote.endpoint,
+                    | false => sender.endpoint.addr == self.remote.endpoint.addr,
+                };
+                if !from_server {
+                    continue;
+                }
+                match Packet::parse(&rx[..received]) {
+                    | Some(Packet::Error { code, .. }) => {
+                        return Err(TransferError::Server(code))
+                    }
+                    | Some(packet) if expected(&packet) => {
+                        self.remote.endpoint = sender.endpoint;
+                        self.bound = true;
+                        return Ok(received);
+                    }
+                    // duplicates and garbage
+                    | _ => {}
+                }
+            }
+        }
+        Err(TransferError::Timeout)
     }
 }
 
-impl<'rx, File> From<TtftpError<'rx>> for TransferError<'static, 'rx, File> {
-    fn from(tftp: TtftpError<'rx>) -> Self {
-        TransferError::Tftp(tftp)
+impl<File> Display for TransferError<File> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "file transfer failed: ")?;
+        match self {
+            | TransferError::Filename => write!(f, "filename too long"),
+            | TransferError::Server(code) => write!(f, "server error {code}"),
+            | TransferError::Timeout => write!(f, "timeout"),
+            | TransferError::Options => write!(f, "bad option acknowledgement"),
+            | TransferError::Cancelled => write!(f, "cancelled"),
+            | TransferError::Send(_) => write!(f, "UDP send"),
+            | TransferError::Recv(_) => write!(f, "UDP receive"),
+            | TransferError::File(_) => write!(f, "file read or write"),
+        }
     }
 }
 
-impl<File> From<SendError> for TransferError<'static, 'static, File> {
+impl<File: Debug> Error for TransferError<File> {}
+
+impl<File> From<SendError> for TransferError<File> {
     fn from(send: SendError) -> Self {
         TransferError::Send(send)
     }
 }
 
-impl<File> From<RecvError> for TransferError<'static, 'static, File> {
+impl<File> From<RecvError> for TransferError<File> {
     fn from(recv: RecvError) -> Self {
         TransferError::Recv(recv)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let requested = Options {
+            blksize: Some(1428),
+            tsize: Some(0),
+            timeout: Some(1),
+        };
+        assert!(requested.accepts(&Options::NONE));
+        assert!(requested.accepts(&Options {
+            blksize: Some(1024),
+            tsize: Some(4096),
+            timeout: Some(1),
+        }));
+        // larger blocks than requested
+        assert!(!requested.accepts(&Options {
+            blksize: Some(1432),
+            ..Options::NONE
+        }));
+        // a different timeout
+        assert!(!requested.accepts(&Options {
+            timeout: Some(2),
+            ..Options::NONE
+        }));
+        // an option that was not requested
+        assert!(!Options::NONE.accepts(&Options {
+            tsize: Some(4096),
+            ..Options::NONE
+        }));
+
+        assert_eq!(Options::NONE.block_size(), BLOCK_SIZE);
+        assert_eq!(requested.block_size(), 1428);
+        assert_eq!(requested.timeout(), Duration::from_secs(1));
+        assert_eq!(Options::NONE.timeout(), TIMEOUT);
+    }
+}
@@ -0,0 +1,276 @@
+//! TFTP wire format, shared by the client and the [server](super::server).
+
+use core::str;
+
+use super::Options;
+
+pub const RRQ: u16 = 1;
+pub const WRQ: u16 = 2;
+pub const DATA: u16 = 3;
+pub const ACK: u16 = 4;
+pub const ERROR: u16 = 5;
+/// option acknowledgement, see [RFC 2347](https://www.rfc-editor.org/rfc/rfc2347)
+pub const OACK: u16 = 6;
+
+pub const HEADER_LEN: usize = 4;
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Packet<'a> {
+    Read {
+        filename: &'a str,
+        mode: &'a str,
+        options: Options,
+    },
+    Write {
+        filename: &'a str,
+        mode: &'a str,
+        options: Options,
+    },
+    Data {
+        block: u16,
+        data: &'a [u8],
+    },
+    Ack {
+        block: u16,
+    },
+    Error {
+        code: u16,
+        message: &'a str,
+    },
+    OptionAck {
+        options: Options,
+    },
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+#[repr(u16)]
+pub enum ErrorCode {
+    Undefined = 0,
+    NotFound = 1,
+    AccessViolation = 2,
+    DiskFull = 3,
+    IllegalOperation = 4,
+    /// the options of a request were refused
+    Options = 8,
+}
+
+pub fn header(buf: &mut [u8], opcode: u16, arg: u16) {
+    buf[0..2].copy_from_slice(&opcode.to_be_bytes());
+    buf[2..4].copy_from_slice(&arg.to_be_bytes());
+}
+
+/// Write an error packet with an empty message. Returns its length.
+pub fn error(buf: &mut [u8], code: ErrorCode) -> usize {
+    header(buf, ERROR, code as u16);
+    buf[HEADER_LEN] = 0;
+    HEADER_LEN + 1
+}
+
+/// Write an RRQ or WRQ in octet mode. Returns its length, or `None` if it does not fit.
+pub fn request(
+    buf: &mut [u8],
+    opcode: u16,
+    filename: &[u8],
+    options: &Options,
+) -> Option<usize> {
+    let mut writer = Fields { buf, len: 0 };
+    writer.push(&opcode.to_be_bytes())?;
+    writer.field(filename)?;
+    writer.field(b"octet")?;
+    options.write(&mut writer)?;
+    Some(writer.len)
+}
+
+/// Writes NUL-terminated fields.
+pub struct Fields<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Fields<'_> {
+    fn push(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    pub fn field(&mut self, field: &[u8]) -> Option<()> {
+        if field.contains(&0) {
+            return None;
+        }
+        self.push(field)?;
+        self.push(&[0])
+    }
+
+    /// An option with a decimal value.
+    pub fn option(&mut self, name: &str, value: u32) -> Option<()> {
+        let mut digits = [0; 10];
+        let mut start = digits.len();
+        let mut rest = value;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        self.field(name.as_bytes())?;
+        self.field(&digits[start..])
+    }
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(packet: &'a [u8]) -> Option<Self> {
+        let opcode = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]);
+        let body = &packet[2..];
+        let arg = || Some(u16::from_be_bytes([*body.first()?, *body.get(1)?]));
+
+        let packet = match opcode {
+            | RRQ | WRQ => {
+                let mut fields = terminated(body)?;
+                let filename = str::from_utf8(fields.next()?).ok()?;
+                let mode = str::from_utf8(fields.next()?).ok()?;
+                let options = Options::parse(fields)?;
+                match opcode {
+                    | RRQ => Packet::Read {
+                        filename,
+                        mode,
+                        options,
+                    },
+                    | _ => Packet::Write {
+                        filename,
+                        mode,
+                        options,
+                    },
+                }
+            }
+            | DATA => Packet::Data {
+                block: arg()?,
+                data: &body[2..],
+            },
+            | ACK => Packet::Ack { block: arg()? },
+            | ERROR => {
+                let message = body.get(2..)?.split(|&b| b == 0).next()?;
+                Packet::Error {
+                    code: arg()?,
+                    message: str::from_utf8(message).ok()?,
+                }
+            }
+            | OACK => Packet::OptionAck {
+                options: Options::parse(terminated(body)?)?,
+            },
+            | _ => return None,
+        };
+        Some(packet)
+    }
+}
+
+/// NUL-terminated fields, or `None` if the last one is not terminated.
+fn terminated(body: &[u8]) -> Option<impl Iterator<Item = &[u8]>> {
+    let body = match body {
+        | [] => body,
+        | [fields @ .., 0] => fields,
+        | _ => return None,
+    };
+    Some(body.split(|&b| b == 0).filter(move |_| !body.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            Packet::parse(b"\x00\x01log\x00octet\x00"),
+            Some(Packet::Read {
+                filename: "log",
+                mode: "octet",
+                options: Options::NONE,
+            })
+        );
+        assert_eq!(
+            Packet::parse(
+                b"\x00\x02fw.bin\x00octet\x00BLKSIZE\x001024\x00tsize\x000\x00"
+            ),
+            Some(Packet::Write {
+                filename: "fw.bin",
+                mode: "octet",
+                options: Options {
+                    blksize: Some(1024),
+                    tsize: Some(0),
+                    timeout: None,
+                },
+            })
+        );
+        // unknown options are ignored
+        assert_eq!(
+            Packet::parse(b"\x00\x06windowsize\x004\x00timeout\x003\x00"),
+            Some(Packet::OptionAck {
+                options: Options {
+                    blksize: None,
+                    tsize: None,
+                    timeout: Some(3),
+                },
+            })
+        );
+        assert_eq!(Packet::parse(b"\x00\x01log\x00octet"), None);
+        assert_eq!(Packet::parse(b"\x00\x01log\x00octet\x00blksize\x00"), None);
+        assert_eq!(
+            Packet::parse(b"\x00\x01log\x00octet\x00blksize\x00many\x00"),
+            None
+        );
+        assert_eq!(Packet::parse(b"\x00\x09"), None);
+
+        let options = Options {
+            blksize: Some(1428),
+            tsize: Some(0),
+            timeout: Some(1),
+        };
+        let mut buf = [0; 64];
+        let len = request(&mut buf, RRQ, b"fw.bin", &options).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x00\x01fw.bin\x00octet\x00blksize\x001428\x00tsize\x000\x00timeout\x001\x00"
+        );
+        assert_eq!(
+            Packet::parse(&buf[..len]),
+            Some(Packet::Read {
+                filename: "fw.bin",
+                mode: "octet",
+                options,
+            })
+        );
+        assert_eq!(request(&mut buf[..16], RRQ, b"fw.bin", &options), None);
+        assert_eq!(request(&mut buf, RRQ, b"fw\0.bin", &Options::NONE), None);
+    }
+
+    #[test]
+    fn test_parse_transfer() {
+        assert_eq!(
+            Packet::parse(b"\x00\x03\x01\x02abc"),
+            Some(Packet::Data {
+                block: 0x0102,
+                data: b"abc"
+            })
+        );
+        assert_eq!(
+            Packet::parse(b"\x00\x04\x00\x07"),
+            Some(Packet::Ack { block: 7 })
+        );
+        assert_eq!(
+            Packet::parse(b"\x00\x05\x00\x01nope\x00"),
+            Some(Packet::Error {
+                code: 1,
+                message: "nope"
+            })
+        );
+        assert_eq!(Packet::parse(b"\x00\x04\x00"), None);
+    }
+}
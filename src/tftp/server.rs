@@ -0,0 +1,426 @@
+//! Minimal TFTP server ([RFC 1350](https://www.rfc-editor.org/rfc/rfc1350)).
+//!
+//! Serves one transfer at a time, octet mode only.
+//! Only files listed in the whitelist passed to [`serve`] are accessible,
+//! each with its own access mode and size limit.
+//! Files are provided by a [`Filesystem`], e.g., flash regions via [`Regions`].
+
+use embassy_net::udp::RecvError;
+use embassy_net::udp::SendError;
+use embassy_net::udp::UdpMetadata;
+use embassy_net::udp::UdpSocket;
+use embassy_time::with_deadline;
+use embassy_time::Instant;
+
+use super::packet;
+use super::packet::ErrorCode;
+use super::packet::Packet;
+use super::packet::ACK;
+use super::packet::DATA;
+use super::packet::HEADER_LEN;
+use super::BLOCK_SIZE;
+use super::PACKET_SIZE;
+use super::RETRIES;
+use super::TIMEOUT;
+use crate::ota;
+use crate::storage;
+use crate::storage::Storage;
+use crate::util::align::align_up;
+
+/// Files served over TFTP.
+#[allow(async_fn_in_trait)]
+pub trait Filesystem {
+    type Error;
+
+    /// Read from `path` at `offset`.
+    ///
+    /// Returns fewer than `buf.len()` bytes only at the end of the file.
+    async fn read(
+        &mut self,
+        path: &str,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+
+    /// Prepare `path` for being rewritten, e.g., by erasing it.
+    async fn truncate(&mut self, path: &str) -> Result<(), Self::Error>;
+
+    async fn write(
+        &mut self,
+        path: &str,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), Self::Error>;
+}
+
+/// A whitelisted file.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub path: &'a str,
+    pub access: Access,
+    /// reads are truncated and writes rejected beyond this size
+    pub max_size: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Access {
+    /// RRQ only
+    Read,
+    /// WRQ only
+    Write,
+    ReadWrite,
+}
+
+/// Flash regions exposed as files.
+///
+/// A rewritten region is erased sector by sector as the data reaches it,
+/// so a transfer starts without waiting for the whole region to be erased.
+/// Sectors past the end of the new contents keep their old data.
+pub struct Regions<'r, S> {
+    storage: S,
+    regions: &'r [Region<'r>],
+    /// path of the region being rewritten and the end of its sectors erased so far
+    erased: Option<(&'r str, u32)>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Region<'a> {
+    pub path: &'a str,
+    /// should be sector-aligned, as writes erase whole sectors
+    pub start: u32,
+    pub len: u32,
+    /// update slots whose pending swap is cancelled when this region is rewritten,
+    /// for the region holding their staging slot
+    pub ota: Option<&'a ota::Layout>,
+}
+
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum RegionError {
+    NotFound,
+    OutOfBounds,
+}
+
+/// Transfer failures, reported to the peer as TFTP errors where possible.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+enum ServeError<E> {
+    Filesystem(E),
+    TooLarge,
+    Timeout,
+    /// the peer aborted the transfer
+    Aborted,
+    Send(SendError),
+    Recv(RecvError),
+}
+
+impl Access {
+    fn allows(self, write: bool) -> bool {
+        match self {
+            | Access::Read => !write,
+            | Access::Write => write,
+            | Access::ReadWrite => true,
+        }
+    }
+}
+
+impl<'r, S: Storage> Regions<'r, S> {
+    pub fn new(storage: S, regions: &'r [Region<'r>]) -> Self {
+        Self {
+            storage,
+            regions,
+            erased: None,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    fn region(&self, path: &str) -> Result<Region<'r>, RegionError> {
+        self.regions
+            .iter()
+            .find(|region| region.path == path)
+            .copied()
+            .ok_or(RegionError::NotFound)
+    }
+}
+
+impl<S: Storage> Filesystem for Regions<'_, S> {
+    type Error = RegionError;
+
+    async fn read(
+        &mut self,
+        path: &str,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let region = self.region(path)?;
+        let available = region.len.saturating_sub(offset) as usize;
+        let len = buf.len().min(available);
+        self.storage.read(&mut buf[..len], region.start + offset).await;
+        Ok(len)
+    }
+
+    async fn truncate(&mut self, path: &str) -> Result<(), Self::Error> {
+        let region = self.region(path)?;
+        // the staged image is about to change under the swap request
+        if let Some(layout) = region.ota {
+            ota::cancel(&mut self.storage, layout).await;
+        }
+        self.erased = Some((region.path, region.start));
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        path: &str,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        let region = self.region(path)?;
+        let end =
+            offset.checked_add(data.len() as u32).ok_or(RegionError::OutOfBounds)?;
+        if end > region.len {
+            return Err(RegionError::OutOfBounds);
+        }
+        let (address, end) = (region.start + offset, region.start + end);
+        match &mut self.erased {
+            | Some((rewritten, erased)) if *rewritten == path && end > *erased => {
+                self.storage.erase((*erased..=end - 1).into()).await;
+                *erased = match align_up(end, S::SECTOR_SIZE) {
+                    | (erased, false) => erased,
+                    | (_, true) => u32::MAX,
+                };
+            }
+            | _ => {}
+        }
+        storage::program_pages(&mut self.storage, data, address).await;
+        Ok(())
+    }
+}
+
+/// Serve whitelisted files on `sock`, one transfer at a time.
+///
+/// `sock` should be bound to port 69.
+/// Requests arriving during a transfer are rejected.
+pub async fn serve<F: Filesystem>(
+    sock: &UdpSocket<'_>,
+    fs: &mut F,
+    whitelist: &[Entry<'_>],
+    rx: &mut [u8; PACKET_SIZE],
+    tx: &mut [u8; PACKET_SIZE],
+) -> ! {
+    assert!(sock.payload_recv_capacity() >= PACKET_SIZE);
+
+    loop {
+        let Ok((received, remote)) = sock.recv_from(rx).await else {
+            continue;
+        };
+        let (filename, mode, write) = match Packet::parse(&rx[..received]) {
+            // options are ignored, clients fall back to the defaults
+            | Some(Packet::Read { filename, mode, .. }) => (filename, mode, false),
+            | Some(Packet::Write { filename, mode, .. }) => (filename, mode, true),
+            | _ => {
+                let _ = reply_error(sock, remote, tx, ErrorCode::IllegalOperation).await;
+                continue;
+            }
+        };
+
+        let Some(entry) = whitelist.iter().find(|entry| entry.path == filename) else {
+            let _ = reply_error(sock, remote, tx, ErrorCode::NotFound).await;
+            continue;
+        };
+        if !entry.access.allows(write) {
+            let _ = reply_error(sock, remote, tx, ErrorCode::AccessViolation).await;
+            continue;
+        }
+        if !mode.eq_ignore_ascii_case("octet") {
+            let _ = reply_error(sock, remote, tx, ErrorCode::Undefined).await;
+            continue;
+        }
+
+        let result = if write {
+            receive_file(sock, fs, entry, remote, rx, tx).await
+        } else {
+            send_file(sock, fs, entry, remote, rx, tx).await
+        };
+
+        let code = match result {
+            | Ok(()) | Err(ServeError::Aborted) => continue,
+            | Err(ServeError::TooLarge) => ErrorCode::DiskFull,
+            | Err(_) => ErrorCode::Undefined,
+        };
+        let _ = reply_error(sock, remote, tx, code).await;
+    }
+}
+
+async fn send_file<F: Filesystem>(
+    sock: &UdpSocket<'_>,
+    fs: &mut F,
+    entry: &Entry<'_>,
+    remote: UdpMetadata,
+    rx: &mut [u8; PACKET_SIZE],
+    tx: &mut [u8; PACKET_SIZE],
+) -> Result<(), ServeError<F::Error>> {
+    let mut block: u16 = 1;
+    let mut offset: u32 = 0;
+    loop {
+        let limit = entry.max_size.saturating_sub(offset) as usize;
+        let payload = &mut tx[HEADER_LEN..][..BLOCK_SIZE.min(limit)];
+        let len =
+            fs.read(entry.path, offset, payload).await.map_err(ServeError::Filesystem)?;
+        packet::header(tx, DATA, block);
+
+        exchange(sock, remote, &tx[..HEADER_LEN + len], rx, |packet| {
+            *packet == Packet::Ack { block }
+        })
+        .await?;
+
+        if len < BLOCK_SIZE {
+            return Ok(());
+        }
+        offset += len as u32;
+        block = block.wrapping_add(1);
+    }
+}
+
+async fn receive_file<F: Filesystem>(
+    sock: &UdpSocket<'_>,
+    fs: &mut F,
+    entry: &Entry<'_>,
+    remote: UdpMetadata,
+    rx: &mut [u8; PACKET_SIZE],
+    tx: &mut [u8; PACKET_SIZE],
+) -> Result<(), ServeError<F::Error>> {
+    fs.truncate(entry.path).await.map_err(ServeError::Filesystem)?;
+
+    let mut block: u16 = 0;
+    let mut offset: u32 = 0;
+    loop {
+        packet::header(tx, ACK, block);
+        let next = block.wrapping_add(1);
+        let received = exchange(
+            sock,
+            remote,
+            &tx[..HEADER_LEN],
+            rx,
+            |packet| matches!(packet, Packet::Data { block, .. } if *block == next),
+        )
+        .await?;
+        let data = &rx[HEADER_LEN..received];
+
+        if offset + data.len() as u32 > entry.max_size {
+            return Err(ServeError::TooLarge);
+        }
+        fs.write(entry.path, offset, data).await.map_err(ServeError::Filesystem)?;
+
+        offset += data.len() as u32;
+        block = next;
+
+        if data.len() < BLOCK_SIZE {
+            packet::header(tx, ACK, block);
+            sock.send_to(&tx[..HEADER_LEN], remote).await.map_err(ServeError::Send)?;
+            return Ok(());
+        }
+    }
+}
+
+/// Send `packet` and wait for a reply from `remote` satisfying `expected`,
+/// retransmitting on timeout.
+///
+/// Returns the length of the reply in `rx`.
+async fn exchange<E>(
+    sock: &UdpSocket<'_>,
+    remote: UdpMetadata,
+    packet: &[u8],
+    rx: &mut [u8; PACKET_SIZE],
+    expected: impl Fn(&Packet) -> bool,
+) -> Result<usize, ServeError<E>> {
+    for _ in 0..=RETRIES {
+        sock.send_to(packet, remote).await.map_err(ServeError::Send)?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        while let Ok(result) = with_deadline(deadline, sock.recv_from(rx)).await {
+            let (received, sender) = result.map_err(ServeError::Recv)?;
+            if sender.endpoint != remote.endpoint {
+                let mut busy = [0; HEADER_LEN + 1];
+                let _ = reply_error(sock, sender, &mut busy, ErrorCode::Undefined).await;
+                continue;
+            }
+            match Packet::parse(&rx[..received]) {
+                | Some(Packet::Error { .. }) => return Err(ServeError::Aborted),
+                | Some(packet) if expected(&packet) => return Ok(received),
+                // duplicates and garbage
+                | _ => {}
+            }
+        }
+    }
+    Err(ServeError::Timeout)
+}
+
+async fn reply_error(
+    sock: &UdpSocket<'_>,
+    remote: UdpMetadata,
+    buf: &mut [u8],
+    code: ErrorCode,
+) -> Result<(), SendError> {
+    let len = packet::error(buf, code);
+    sock.send_to(&buf[..len], remote).await
+}
+
+#[cfg(test)]
+mod tests {
+    use core::range::Range;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::storage::sim::MemFlash;
+    use crate::storage::sim::NoLatency;
+
+    const SECTOR: u32 = MemFlash::<NoLatency>::SECTOR_SIZE;
+    const LAYOUT: ota::Layout = ota::Layout {
+        staging: Range {
+            start: 0,
+            end: 3 * SECTOR,
+        },
+        state: 3 * SECTOR,
+    };
+    const REGIONS: [Region; 1] = [Region {
+        path: "update.bin",
+        start: 0,
+        len: 3 * SECTOR,
+        ota: Some(&LAYOUT),
+    }];
+
+    #[test]
+    fn test_rewrite_erases_lazily() {
+        let mut buf = [0; 4 * SECTOR as usize];
+        let mut fs = Regions::new(MemFlash::new(&mut buf), &REGIONS);
+
+        block_on(async {
+            fs.truncate("update.bin").await.unwrap();
+            // only the swap request has been erased
+            assert_eq!(fs.storage.stats().erased_sectors, 1);
+
+            let block = [0xA5; BLOCK_SIZE];
+            for offset in (0..SECTOR + 1).step_by(BLOCK_SIZE) {
+                fs.write("update.bin", offset, &block).await.unwrap();
+            }
+        });
+        let flash = fs.into_inner();
+        assert_eq!(flash.stats().erased_sectors, 3);
+        assert_eq!(flash.stats().overprogrammed, 0);
+        // the sector past the end of the new contents was not touched
+        assert_eq!(flash.contents()[2 * SECTOR as usize], 0);
+    }
+}
@@ -0,0 +1,189 @@
+pub mod align;
+pub mod hash;
+pub mod lease;
+pub mod profile;
+pub mod uid;
+
+use core::fmt;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use embassy_futures::yield_now;
+use embassy_time::with_timeout;
+use embassy_time::Duration;
+use embassy_time::TimeoutError;
+use embassy_time::Timer;
+
+/// A cancellation token, checked by long-running operations between steps.
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Cancel {
+    cancelled: AtomicBool,
+}
+
+/// [`fmt::Write`] into a byte buffer, failing once it is full.
+pub struct Cursor<'b> {
+    buf: &'b mut [u8],
+    written: usize,
+}
+
+/// How [`until_with`] waits between polls.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub enum Strategy {
+    /// yield to the executor, polling again as soon as other tasks let it
+    Yield,
+    /// wait a fixed interval
+    Every(Duration),
+    /// wait `initial`, doubling the interval after every poll up to `max`
+    Backoff { initial: Duration, max: Duration },
+}
+
+/// Runs a closure when dropped, unless [defused](DropGuard::defuse) first.
+#[must_use = "the closure runs immediately if the guard is not held"]
+pub struct DropGuard<F: FnOnce()> {
+    on_drop: Option<F>,
+}
+
+/// Create a [`DropGuard`] running `on_drop` at the end of its scope.
+pub fn drop_guard<F: FnOnce()>(on_drop: F) -> DropGuard<F> {
+    DropGuard {
+        on_drop: Some(on_drop),
+    }
+}
+
+/// Poll `poll` until it returns a value, yielding in between.
+pub async fn until<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+    until_with(async || poll(), Strategy::Yield).await
+}
+
+/// Poll `poll` until it returns a value, waiting in between as `strategy` says.
+///
+/// `poll` may wait itself, e.g. for the bus transfer reading a status register.
+pub async fn until_with<T>(
+    mut poll: impl AsyncFnMut() -> Option<T>,
+    strategy: Strategy,
+) -> T {
+    let mut attempt = 0;
+    loop {
+        if let Some(value) = poll().await {
+            return value;
+        }
+        match strategy.delay(attempt) {
+            | None => yield_now().await,
+            | Some(delay) => Timer::after(delay).await,
+        }
+        attempt = attempt.saturating_add(1);
+    }
+}
+
+/// Like [`until_with`], giving up after `timeout`.
+pub async fn until_timeout<T>(
+    poll: impl AsyncFnMut() -> Option<T>,
+    strategy: Strategy,
+    timeout: Duration,
+) -> Result<T, TimeoutError> {
+    with_timeout(timeout, until_with(poll, strategy)).await
+}
+
+impl Strategy {
+    /// The time to wait after the `attempt`th poll failed, counting from 0,
+    /// or `None` to only yield.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        match *self {
+            | Strategy::Yield => None,
+            | Strategy::Every(interval) => Some(interval),
+            | Strategy::Backoff { initial, max } => {
+                let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+                Some(initial.checked_mul(factor).map_or(max, |delay| delay.min(max)))
+            }
+        }
+    }
+}
+
+impl<'b> Cursor<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, written: 0 }
+    }
+
+    /// Number of bytes written so far, at the start of the buffer.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+}
+
+impl fmt::Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.written + s.len();
+        let dst = self.buf.get_mut(self.written..end).ok_or(fmt::Error)?;
+        dst.copy_from_slice(s.as_bytes());
+        self.written = end;
+        Ok(())
+    }
+}
+
+impl Cancel {
+    pub const fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    /// Ask the operation holding the token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl<F: FnOnce()> DropGuard<F> {
+    /// Disarm the guard without running its closure.
+    pub fn defuse(mut self) {
+        self.on_drop = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for DropGuard<F> {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Strategy::Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+        };
+        let delays = (0..6).map(|attempt| backoff.delay(attempt).unwrap().as_millis());
+        assert!(delays.eq([1, 2, 4, 8, 10, 10]));
+        assert_eq!(backoff.delay(40), Some(Duration::from_millis(10)));
+        assert_eq!(Strategy::Yield.delay(0), None);
+    }
+
+    #[test]
+    fn test_until_timeout() {
+        let mut polls = 0;
+        let poll = async || {
+            polls += 1;
+            (polls == 3).then_some(polls)
+        };
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            block_on(until_timeout(poll, Strategy::Yield, timeout)),
+            Ok(3)
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Power-of-two alignment of addresses.
+//!
+//! Kept apart from the QSPI driver, which only builds for the target,
+//! so the arithmetic is tested on the host.
+
+use core::ops;
+use core::range::RangeInclusive;
+
+/// Returns the aligned address alongside a `bool` indicating whether the result is wrapped.
+///
+/// `alignment` must be a power of two
+pub const fn align_up(address: u32, alignment: u32) -> (u32, bool) {
+    assert!(alignment.is_power_of_two());
+    if is_aligned_to(address, alignment) {
+        (address, false)
+    } else {
+        (address & !(alignment - 1)).overflowing_add(alignment)
+    }
+}
+
+/// `alignment` must be a power of two
+pub const fn align_down(address: u32, alignment: u32) -> u32 {
+    assert!(alignment.is_power_of_two());
+    address & !(alignment - 1)
+}
+
+/// `alignment` must be a power of two
+pub const fn is_aligned_to(address: u32, alignment: u32) -> bool {
+    assert!(alignment.is_power_of_two());
+    address & (alignment - 1) == 0
+}
+
+/// Blocks covering a range, each the [best fit](best_fit) at its address,
+/// e.g. the erase blocks of a flash range.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Blocks {
+    range: RangeInclusive<u32>,
+    /// where the next block starts, `None` once `range` is covered
+    next: Option<u32>,
+}
+
+/// The alignment whose block around `address` covers the least outside of `target`.
+///
+/// Ties go to the first of `alignments`, which must not be empty.
+pub fn best_fit(address: u32, target: RangeInclusive<u32>, alignments: &[u32]) -> u32 {
+    let target = target.into();
+    alignments
+        .iter()
+        .map(|&a| {
+            let block = align_down(address, a)..=align_up(address, a).0.wrapping_sub(1);
+            (a, waste(&block, &target))
+        })
+        .min_by_key(|(_, waste)| *waste)
+        .expect("alignments should not be empty")
+        .0
+}
+
+impl Blocks {
+    pub fn new(range: RangeInclusive<u32>) -> Self {
+        // an empty range has no blocks
+        let next = range.contains(&range.start).then_some(range.start);
+        Self { range, next }
+    }
+
+    /// The address and size of the next block, picked among `sizes`.
+    pub fn next_block(&mut self, sizes: &[u32]) -> Option<(u32, u32)> {
+        let address = self.next?;
+        let block = best_fit(address.wrapping_add(1), self.range, sizes);
+        let (next, wrapped) = align_up(address.wrapping_add(1), block);
+        self.next = (!wrapped && self.range.contains(&next)).then_some(next);
+        Some((address, block))
+    }
+}
+
+/// Bytes of `pick` outside of `target`.
+fn waste(pick: &ops::RangeInclusive<u32>, target: &ops::RangeInclusive<u32>) -> u32 {
+    if pick.is_empty() || target.contains(pick.start()) && target.contains(pick.end()) {
+        0
+    } else if target.contains(pick.end()) {
+        target.start() - pick.start()
+    } else if target.contains(pick.start()) {
+        pick.end() - target.end()
+    } else {
+        (pick.end() - pick.start()).saturating_add(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift, so the tests cover many addresses without a dependency
+    fn addresses() -> impl Iterator<Item = u32> {
+        let mut state = 0x2545_f491_u32;
+        core::iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        })
+        .take(4096)
+        .chain([0, 1, u32::MAX, u32::MAX - 4095])
+    }
+
+    #[test]
+    fn test_align() {
+        for address in addresses() {
+            for shift in 0..32 {
+                let alignment = 1 << shift;
+                let down = align_down(address, alignment);
+                assert!(is_aligned_to(down, alignment));
+                assert!(down <= address && address - down < alignment);
+
+                let (up, wrapped) = align_up(address, alignment);
+                assert!(is_aligned_to(up, alignment));
+                if wrapped {
+                    assert_eq!(up, 0);
+                } else {
+                    assert!(up >= address && up - address < alignment);
+                }
+                assert_eq!(up == address, is_aligned_to(address, alignment));
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        const SIZES: [u32; 2] = [64 << 10, 4 << 10];
+        let mut blocks = Blocks::new((0xf000..=0x2_0fff).into());
+        let all = core::iter::from_fn(|| blocks.next_block(&SIZES));
+        assert!(all.eq([(0xf000, 0x1000), (0x1_0000, 0x1_0000), (0x2_0000, 0x1000)]));
+
+        // what `start_erase` is given for a zero-length erase
+        let start = 0x1000;
+        let mut empty = Blocks::new((start..=start - 1).into());
+        assert_eq!(empty.next_block(&SIZES), None);
+    }
+
+    #[test]
+    fn test_best_fit() {
+        const ALIGNMENTS: [u32; 3] = [4 << 10, 32 << 10, 64 << 10];
+        let target = 0x1_2000..=0x2_ffff;
+        assert_eq!(waste(&(0x2_0000..=0x2_ffff), &target), 0);
+        assert_eq!(waste(&(0x1_0000..=0x1_ffff), &target), 0x2000);
+        assert_eq!(waste(&(0x2_0000..=0x3_7fff), &target), 0x8000);
+        assert_eq!(waste(&(0x3_0000..=0x3_0fff), &target), 0x1000);
+
+        // the block best_fit picks never wastes more than the others
+        for address in addresses() {
+            let start = align_down(address, 4 << 10);
+            let target = start..=start.saturating_add(0x1_7fff);
+            let fit = best_fit(address, target.clone().into(), &ALIGNMENTS);
+            let block =
+                |a| align_down(address, a)..=align_up(address, a).0.wrapping_sub(1);
+            for alignment in ALIGNMENTS {
+                assert!(waste(&block(fit), &target) <= waste(&block(alignment), &target));
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! CRC-32 and SHA-256 hashing.
+//!
+//! CRC-32 is available both in software ([`Crc32`])
+//! and backed by the CRC peripheral ([`HwCrc32`]).
+//! [`digest`] and [`digest_storage`] hash large regions in chunks,
+//! yielding to the executor in between.
+
+use core::ops;
+use core::range::Range;
+
+use embassy_futures::yield_now;
+#[cfg(feature = "cross")]
+use embassy_stm32::crc;
+#[cfg(feature = "cross")]
+use embassy_stm32::peripherals::CRC;
+#[cfg(feature = "cross")]
+use embassy_stm32::Peripheral;
+
+use crate::storage::Storage;
+
+/// bytes hashed between yields
+pub const CHUNK_SIZE: usize = 4 << 10;
+
+const CRC32_POLY: u32 = 0x04C1_1DB7;
+const CRC32_POLY_REFLECTED: u32 = 0xEDB8_8320;
+
+#[rustfmt::skip]
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+#[rustfmt::skip]
+const SHA256_H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental hash function.
+pub trait Hasher {
+    type Output;
+
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of all data fed so far and resets the hasher.
+    fn finish(&mut self) -> Self::Output;
+}
+
+/// Bitwise CRC-32 (IEEE 802.3).
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+/// CRC-32 (IEEE 802.3) computed by the CRC peripheral.
+#[cfg(feature = "cross")]
+pub struct HwCrc32<'d> {
+    crc: crc::Crc<'d>,
+    value: u32,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    /// bytes buffered in `block`
+    buffered: usize,
+    /// total bytes fed
+    len: u64,
+}
+
+/// Hash `data` in chunks of [`CHUNK_SIZE`], yielding in between.
+pub async fn digest<H: Hasher>(hasher: &mut H, data: &[u8]) -> H::Output {
+    for chunk in data.chunks(CHUNK_SIZE) {
+        hasher.update(chunk);
+        yield_now().await;
+    }
+    hasher.finish()
+}
+
+/// Hash `range` of `storage` in chunks of `buf.len()`.
+pub async fn digest_storage<H: Hasher, S: Storage>(
+    hasher: &mut H,
+    storage: &mut S,
+    range: Range<u32>,
+    buf: &mut [u8],
+) -> H::Output {
+    let range: ops::Range<u32> = range.into();
+    let mut address = range.start;
+    while address < range.end {
+        let len = buf.len().min((range.end - address) as usize);
+        let chunk = &mut buf[..len];
+        storage.read(chunk, address).await;
+        hasher.update(chunk);
+        address += len as u32;
+    }
+    hasher.finish()
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(!0)
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Crc32 {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= u32::from(byte);
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (CRC32_POLY_REFLECTED & mask);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> u32 {
+        let crc = !self.0;
+        *self = Self::new();
+        crc
+    }
+}
+
+#[cfg(feature = "cross")]
+impl<'d> HwCrc32<'d> {
+    pub fn new(peri: impl Peripheral<P = CRC> + 'd) -> Self {
+        let config = crc::Config::new(
+            crc::InputReverseConfig::Byte,
+            true,
+            crc::PolySize::Width32,
+            !0,
+            CRC32_POLY,
+        )
+        .expect("CRC-32 configuration should be valid");
+        Self {
+            crc: crc::Crc::new(peri, config),
+            value: !0,
+        }
+    }
+}
+
+#[cfg(feature = "cross")]
+impl Hasher for HwCrc32<'_> {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        self.value = self.crc.feed_bytes(data);
+    }
+
+    fn finish(&mut self) -> u32 {
+        let crc = !self.value;
+        self.crc.reset();
+        self.value = !0;
+        crc
+    }
+}
+
+impl Sha256 {
+    pub const fn new() -> Self {
+        Self {
+            state: SHA256_H,
+            block: [0; 64],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 =
+                w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 =
+                w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (k, w) in SHA256_K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(*k).wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Sha256 {
+    type Output = [u8; 32];
+
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        let mut data = data;
+        while !data.is_empty() {
+            let take = data.len().min(64 - self.buffered);
+            self.block[self.buffered..][..take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered == 64 {
+                Self::compress(&mut self.state, &self.block);
+                self.buffered = 0;
+            }
+        }
+    }
+
+    fn finish(&mut self) -> [u8; 32] {
+        let bits = self.len.wrapping_mul(8);
+
+        self.block[self.buffered] = 0x80;
+        self.block[self.buffered + 1..].fill(0);
+        if self.buffered >= 56 {
+            Self::compress(&mut self.state, &self.block);
+            self.block.fill(0);
+        }
+        self.block[56..].copy_from_slice(&bits.to_be_bytes());
+        Self::compress(&mut self.state, &self.block);
+
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        *self = Self::new();
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+        assert_eq!(crc.finish(), 0);
+    }
+
+    #[test]
+    fn test_sha256() {
+        let mut sha = Sha256::new();
+        assert_eq!(sha.finish()[..4], [0xe3, 0xb0, 0xc4, 0x42], "empty input");
+
+        sha.update(b"abc");
+        assert_eq!(
+            sha.finish(),
+            [
+                0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde,
+                0x5d, 0xae, 0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c,
+                0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+            ]
+        );
+
+        // 56 bytes, forcing an extra padding block
+        let input = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let digest = block_on(digest(&mut sha, input));
+        assert_eq!(digest[..4], [0x24, 0x8d, 0x6a, 0x61]);
+        assert_eq!(digest[28..], [0x19, 0xdb, 0x06, 0xc1]);
+    }
+}
@@ -0,0 +1,357 @@
+use core::cell::Cell;
+use core::fmt;
+use core::fmt::Display;
+use core::ops::Deref;
+use core::ops::DerefMut;
+
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::mutex::MutexGuard;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Timer;
+
+/// A shared resource (DMA2D, flash, DSI, ...) handed out through [`Lease`]s.
+///
+/// Keeps track of who currently holds the resource and since when,
+/// and collects contention statistics.
+/// Leases held for longer than `threshold` are counted
+/// and reported to the `on_overlong` hook, if any, once per lease:
+/// when [checked](Leased::check) while still held, or else when released.
+pub struct Leased<M: RawMutex, T> {
+    name: &'static str,
+    threshold: Duration,
+    on_overlong: Option<fn(&Overlong)>,
+    resource: Mutex<M, T>,
+    state: blocking_mutex::Mutex<M, Cell<State>>,
+}
+
+/// Exclusive access to a [`Leased`] resource.
+///
+/// Releases the resource and updates its statistics on drop.
+pub struct Lease<'a, M: RawMutex, T> {
+    guard: MutexGuard<'a, M, T>,
+    leased: &'a Leased<M, T>,
+    owner: &'static str,
+    acquired: Instant,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct LeaseStats {
+    /// number of leases handed out
+    pub leases: u32,
+    /// number of leases that had to wait for a previous holder
+    pub contended: u32,
+    /// number of leases held for longer than the threshold
+    pub overlong: u32,
+    /// longest time a lease was held, alongside its owner
+    pub longest_hold: Option<(&'static str, Duration)>,
+    /// longest time a lease had to wait for the resource
+    pub longest_wait: Duration,
+}
+
+/// Report of a lease that was held for longer than its resource's threshold.
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Overlong {
+    pub resource: &'static str,
+    pub owner: &'static str,
+    pub held: Duration,
+    pub threshold: Duration,
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    holder: Option<(&'static str, Instant)>,
+    /// whether the current lease has been reported as overlong already
+    reported: bool,
+    stats: LeaseStats,
+}
+
+impl<M: RawMutex, T> Leased<M, T> {
+    pub const fn new(
+        name: &'static str,
+        threshold: Duration,
+        on_overlong: Option<fn(&Overlong)>,
+        resource: T,
+    ) -> Self {
+        Self {
+            name,
+            threshold,
+            on_overlong,
+            resource: Mutex::new(resource),
+            state: blocking_mutex::Mutex::new(Cell::new(State {
+                holder: None,
+                reported: false,
+                stats: LeaseStats {
+                    leases: 0,
+                    contended: 0,
+                    overlong: 0,
+                    longest_hold: None,
+                    longest_wait: Duration::from_ticks(0),
+                },
+            })),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub const fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Wait for the resource to become available and lease it to `owner`.
+    pub async fn lease(&self, owner: &'static str) -> Lease<'_, M, T> {
+        match self.resource.try_lock() {
+            | Ok(guard) => self.grant(guard, owner, None),
+            | Err(_) => {
+                let start = Instant::now();
+                let guard = self.resource.lock().await;
+                self.grant(guard, owner, Some(start.elapsed()))
+            }
+        }
+    }
+
+    /// Lease the resource to `owner` if it is available right now.
+    pub fn try_lease(&self, owner: &'static str) -> Option<Lease<'_, M, T>> {
+        let guard = self.resource.try_lock().ok()?;
+        Some(self.grant(guard, owner, None))
+    }
+
+    /// The current holder of the resource alongside how long it has been holding it.
+    pub fn holder(&self) -> Option<(&'static str, Duration)> {
+        self.state
+            .lock(|state| state.get().holder)
+            .map(|(owner, acquired)| (owner, acquired.elapsed()))
+    }
+
+    pub fn stats(&self) -> LeaseStats {
+        self.state.lock(|state| state.get().stats)
+    }
+
+    /// Report the current lease if it has been held for longer than the threshold,
+    /// so a stuck lease is noticed before it is released, if ever.
+    pub fn check(&self) -> Option<Overlong> {
+        let mut overlong = None;
+        self.update(|state| {
+            let Some((owner, acquired)) = state.holder else {
+                return;
+            };
+            let held = acquired.elapsed();
+            if !state.reported && held > self.threshold {
+                state.reported = true;
+                state.stats.overlong = state.stats.overlong.saturating_add(1);
+                overlong = Some(self.overlong(owner, held));
+            }
+        });
+        if let (Some(overlong), Some(on_overlong)) = (&overlong, self.on_overlong) {
+            on_overlong(overlong);
+        }
+        overlong
+    }
+
+    /// [Check](Leased::check) the current lease every `interval`.
+    pub async fn watch(&self, interval: Duration) -> ! {
+        loop {
+            Timer::after(interval).await;
+            self.check();
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.update(|state| state.stats = LeaseStats::default());
+    }
+
+    fn grant<'a>(
+        &'a self,
+        guard: MutexGuard<'a, M, T>,
+        owner: &'static str,
+        waited: Option<Duration>,
+    ) -> Lease<'a, M, T> {
+        let acquired = Instant::now();
+        self.update(|state| {
+            state.holder = Some((owner, acquired));
+            state.reported = false;
+            state.stats.leases = state.stats.leases.saturating_add(1);
+            if let Some(waited) = waited {
+                state.stats.contended = state.stats.contended.saturating_add(1);
+                state.stats.longest_wait = state.stats.longest_wait.max(waited);
+            }
+        });
+
+        Lease {
+            guard,
+            leased: self,
+            owner,
+            acquired,
+        }
+    }
+
+    fn overlong(&self, owner: &'static str, held: Duration) -> Overlong {
+        Overlong {
+            resource: self.name,
+            owner,
+            held,
+            threshold: self.threshold,
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        self.state.lock(|state| {
+            let mut value = state.get();
+            f(&mut value);
+            state.set(value);
+        })
+    }
+}
+
+impl Display for LeaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} leases, {} contended (longest wait {} ms), {} overlong",
+            self.leases,
+            self.contended,
+            self.longest_wait.as_millis(),
+            self.overlong
+        )?;
+        if let Some((owner, held)) = self.longest_hold {
+            write!(f, ", longest hold {} ms by {owner}", held.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Overlong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} held by {} for {} ms (threshold {} ms)",
+            self.resource,
+            self.owner,
+            self.held.as_millis(),
+            self.threshold.as_millis()
+        )
+    }
+}
+
+impl<M: RawMutex, T> Lease<'_, M, T> {
+    pub const fn owner(&self) -> &'static str {
+        self.owner
+    }
+
+    /// How long this lease has been held so far.
+    pub fn held(&self) -> Duration {
+        self.acquired.elapsed()
+    }
+}
+
+impl<M: RawMutex, T> Deref for Lease<'_, M, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<M: RawMutex, T> DerefMut for Lease<'_, M, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<M: RawMutex, T> Drop for Lease<'_, M, T> {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        let owner = self.owner;
+        let mut overlong = false;
+
+        self.leased.update(|state| {
+            state.holder = None;
+            overlong = held > self.leased.threshold && !state.reported;
+            if overlong {
+                state.stats.overlong = state.stats.overlong.saturating_add(1);
+            }
+            if state.stats.longest_hold.is_none_or(|(_, longest)| held > longest) {
+                state.stats.longest_hold = Some((owner, held));
+            }
+        });
+
+        if let (true, Some(on_overlong)) = (overlong, self.leased.on_overlong) {
+            on_overlong(&self.leased.overlong(owner, held));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+    use core::sync::atomic::AtomicU32;
+    use core::sync::atomic::Ordering;
+
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_time::MockDriver;
+    use heapless::String;
+
+    use super::*;
+
+    static REPORTS: AtomicU32 = AtomicU32::new(0);
+
+    fn report(_: &Overlong) {
+        REPORTS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_overlong() {
+        let driver = MockDriver::get();
+        driver.reset();
+        let threshold = Duration::from_millis(10);
+        let leased = Leased::<NoopRawMutex, _>::new("dma2d", threshold, Some(report), ());
+
+        let lease = block_on(leased.lease("screen"));
+        assert!(leased.try_lease("cli").is_none());
+        driver.advance(Duration::from_millis(4));
+        assert_eq!(lease.held(), Duration::from_millis(4));
+        assert_eq!(leased.holder(), Some(("screen", Duration::from_millis(4))));
+        assert_eq!(leased.check(), None);
+
+        // reported while still held, and only once
+        driver.advance(Duration::from_millis(8));
+        let overlong = leased.check().unwrap();
+        assert_eq!(overlong.owner, "screen");
+        assert_eq!(overlong.held, Duration::from_millis(12));
+        assert_eq!(leased.check(), None);
+        drop(lease);
+
+        let stats = leased.stats();
+        assert_eq!(stats.leases, 1);
+        assert_eq!(stats.overlong, 1);
+        assert_eq!(
+            stats.longest_hold,
+            Some(("screen", Duration::from_millis(12)))
+        );
+        let mut line = String::<96>::new();
+        write!(line, "{stats}").unwrap();
+        assert_eq!(
+            line,
+            "1 leases, 0 contended (longest wait 0 ms), 1 overlong, longest hold 12 ms by screen"
+        );
+        assert_eq!(REPORTS.load(Ordering::Relaxed), 1);
+        assert_eq!(leased.holder(), None);
+
+        // reported on release, if not checked in time
+        let lease = leased.try_lease("cli").unwrap();
+        driver.advance(Duration::from_millis(11));
+        drop(lease);
+        assert_eq!(leased.stats().overlong, 2);
+        assert_eq!(REPORTS.load(Ordering::Relaxed), 2);
+    }
+}
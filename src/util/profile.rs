@@ -0,0 +1,240 @@
+//! Cycle-accurate profiling scopes based on the DWT cycle counter.
+//!
+//! Every [`scope!`] call site owns a static [`Slot`] accumulating
+//! hit count and min/avg/max cycles spent in the scope.
+//! Slots register themselves on first use, so [`report`] lists every
+//! scope that has been entered at least once.
+//!
+//! Cycle counts wrap after 2^32 cycles, i.e., scopes must be shorter than that.
+
+use core::cell::Cell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::DCB;
+#[cfg(feature = "cross")]
+use cortex_m::peripheral::DWT;
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// Profile the rest of the enclosing block under `name`.
+///
+/// ```ignore
+/// fn layout() {
+///     profile::scope!("text layout");
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! scope {
+    ($name:expr) => {
+        let _scope = {
+            static SLOT: $crate::util::profile::Slot =
+                $crate::util::profile::Slot::new($name);
+            SLOT.enter()
+        };
+    };
+}
+pub use crate::scope;
+
+/// head of the registered slots, most recently registered first
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+
+/// Statistics of a single profiling scope.
+pub struct Slot {
+    name: &'static str,
+    stats: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Stats>>,
+    registered: AtomicBool,
+    next: AtomicPtr<Slot>,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Stats {
+    pub hits: u32,
+    /// total cycles spent in the scope
+    pub total: u64,
+    pub min: u32,
+    pub max: u32,
+}
+
+/// Guard recording the cycles spent until it is dropped.
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct Scope {
+    slot: &'static Slot,
+    start: u32,
+}
+
+/// Start the cycle counter.
+#[cfg(feature = "cross")]
+pub fn enable(dcb: &mut DCB, dwt: &mut DWT) {
+    dcb.enable_trace();
+    // the Cortex-M7 DWT is locked out of reset
+    DWT::unlock();
+    dwt.enable_cycle_counter();
+}
+
+#[cfg(feature = "cross")]
+fn now() -> u32 {
+    DWT::cycle_count()
+}
+
+// no cycle counter on the host
+#[cfg(not(feature = "cross"))]
+fn now() -> u32 {
+    0
+}
+
+/// Write a table of all registered scopes to `out`.
+pub fn report(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<24} {:>10} {:>10} {:>10} {:>10}",
+        "scope", "hits", "min", "avg", "max"
+    )?;
+    for slot in slots() {
+        let stats = slot.stats();
+        match stats.avg() {
+            | Some(avg) => writeln!(
+                out,
+                "{:<24} {:>10} {:>10} {:>10} {:>10}",
+                slot.name, stats.hits, stats.min, avg, stats.max
+            )?,
+            | None => writeln!(out, "{:<24} {:>10}", slot.name, 0)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reset the statistics of all registered scopes.
+pub fn reset() {
+    for slot in slots() {
+        slot.reset();
+    }
+}
+
+fn slots() -> impl Iterator<Item = &'static Slot> {
+    let head = SLOTS.load(Ordering::Acquire);
+    // Safety: only `&'static Slot`s are ever registered
+    core::iter::successors(unsafe { head.as_ref() }, |slot| unsafe {
+        slot.next.load(Ordering::Acquire).as_ref()
+    })
+}
+
+impl Slot {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            stats: blocking_mutex::Mutex::new(Cell::new(Stats {
+                hits: 0,
+                total: 0,
+                min: 0,
+                max: 0,
+            })),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.lock(Cell::get)
+    }
+
+    pub fn reset(&self) {
+        self.stats.lock(|stats| stats.set(Stats::default()));
+    }
+
+    pub fn enter(&'static self) -> Scope {
+        self.register();
+        Scope {
+            slot: self,
+            start: now(),
+        }
+    }
+
+    pub fn record(&self, cycles: u32) {
+        self.stats.lock(|stats| {
+            let mut updated = stats.get();
+            updated.record(cycles);
+            stats.set(updated);
+        });
+    }
+
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let this = ptr::from_ref(self).cast_mut();
+        let mut head = SLOTS.load(Ordering::Relaxed);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match SLOTS.compare_exchange_weak(
+                head,
+                this,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                | Ok(_) => break,
+                | Err(current) => head = current,
+            }
+        }
+    }
+}
+
+impl Stats {
+    pub fn record(&mut self, cycles: u32) {
+        self.min = match self.hits {
+            | 0 => cycles,
+            | _ => self.min.min(cycles),
+        };
+        self.max = self.max.max(cycles);
+        self.total += u64::from(cycles);
+        self.hits = self.hits.saturating_add(1);
+    }
+
+    /// Average cycles per hit, if the scope was ever hit.
+    pub fn avg(&self) -> Option<u64> {
+        self.total.checked_div(u64::from(self.hits))
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.slot.record(now().wrapping_sub(self.start));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.avg(), None);
+
+        for cycles in [30, 10, 20] {
+            stats.record(cycles);
+        }
+        assert_eq!(
+            stats,
+            Stats {
+                hits: 3,
+                total: 60,
+                min: 10,
+                max: 30,
+            }
+        );
+        assert_eq!(stats.avg(), Some(20));
+    }
+}
@@ -0,0 +1,94 @@
+//! The 96-bit unique device ID and the network identity derived from it.
+//!
+//! Every board gets its own stable MAC address and hostname this way,
+//! so several boards can share a network without configuration.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::util::hash::Hasher;
+use crate::util::hash::Sha256;
+
+/// location of the unique ID on the STM32F76x
+pub const ADDRESS: u32 = 0x1FF0_F420;
+
+/// Unique device ID, as read from [`ADDRESS`].
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq, Eq)]
+pub struct Uid(pub [u8; 12]);
+
+impl Uid {
+    #[cfg(feature = "cross")]
+    pub fn read() -> Self {
+        let words = ADDRESS as *const u32;
+        let mut uid = [0; 12];
+        for (i, chunk) in uid.chunks_exact_mut(4).enumerate() {
+            // Safety: the unique ID is readable system memory
+            let word = unsafe { core::ptr::read_volatile(words.add(i)) };
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Self(uid)
+    }
+
+    /// A locally administered unicast MAC address.
+    ///
+    /// The ID itself is mostly wafer position and lot number,
+    /// so it is hashed to spread boards from one lot over the address space.
+    pub fn mac(&self) -> [u8; 6] {
+        let mut sha = Sha256::new();
+        sha.update(&self.0);
+        let digest = sha.finish();
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&digest[..6]);
+        // see https://en.wikipedia.org/wiki/MAC_address#IEEE_802c_local_MAC_address_usage
+        mac[0] = mac[0] & !0b11 | 0b10;
+        mac
+    }
+
+    /// `<prefix>-<last three bytes of the MAC in hex>`
+    pub fn hostname<const N: usize>(
+        &self,
+        prefix: &str,
+    ) -> Result<String<N>, fmt::Error> {
+        let [.., a, b, c] = self.mac();
+        let mut hostname = String::new();
+        write!(hostname, "{prefix}-{a:02x}{b:02x}{c:02x}")?;
+        Ok(hostname)
+    }
+}
+
+/// Hex digits, most significant first, the way ST's tools show it.
+impl Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0.iter().rev() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac() {
+        let a = Uid(*b"\x30\x00\x2f\x00\x0b\x51\x34\x36\x39\x38\x38\x32");
+        let b = Uid(*b"\x31\x00\x2f\x00\x0b\x51\x34\x36\x39\x38\x38\x32");
+        let mac = a.mac();
+        assert_eq!(mac, a.mac());
+        assert_ne!(mac, b.mac());
+        // locally administered, unicast
+        assert_eq!(mac[0] & 0b11, 0b10);
+
+        let [.., x, y, z] = mac;
+        let mut expected = String::<32>::new();
+        write!(expected, "STM32F7-DISCO-{x:02x}{y:02x}{z:02x}").unwrap();
+        assert_eq!(a.hostname::<32>("STM32F7-DISCO"), Ok(expected));
+        assert!(a.hostname::<8>("STM32F7-DISCO").is_err());
+    }
+}